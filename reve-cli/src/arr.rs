@@ -0,0 +1,149 @@
+//! `reve arr`: upscales the files Sonarr and Radarr import, as their custom script.
+//!
+//! The file is upscaled by a `reve upscale` child process next to the original, then
+//! replaces it under the name the app gave it, so its library keeps pointing at the file.
+//! The outputs are tagged as upscaled by reve, so a later event on the same file is skipped.
+
+use clap::Parser;
+use reve_shared::arr::ArrEvent;
+use reve_shared::config::ConfigData;
+use reve_shared::toml_config::TomlConfig;
+use reve_shared::ReveError;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::{env, fs};
+
+/// Variables whose presence tells that Sonarr or Radarr ran reve.
+const EVENT_KEYS: [&str; 2] = ["sonarr_eventtype", "radarr_eventtype"];
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve arr",
+    bin_name = "reve arr",
+    about = "upscale the file Sonarr or Radarr imported, run as their custom script"
+)]
+pub struct ArrArgs {
+    /// named or device profile the file is upscaled with, the one of [arr] by default
+    #[clap(long)]
+    profile: Option<String>,
+}
+
+/// Returns whether reve was run as a custom script of Sonarr or Radarr, which give it no
+/// arguments.
+pub fn is_custom_script() -> bool {
+    env::vars_os().any(|(key, _)| key.to_str().is_some_and(is_event_key))
+}
+
+/// Returns whether `key` is the variable of the event Sonarr or Radarr run reve for, which
+/// is upper case on Windows.
+fn is_event_key(key: &str) -> bool {
+    EVENT_KEYS
+        .iter()
+        .any(|event| key.eq_ignore_ascii_case(event))
+}
+
+pub fn run(args: ArrArgs) -> Result<(), ReveError> {
+    let vars = env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+    let Some(event) = ArrEvent::from_vars(vars)? else {
+        return Err(ReveError::InvalidArgument(String::from(
+            "reve arr is run by Sonarr or Radarr, which set sonarr_eventtype or radarr_eventtype",
+        )));
+    };
+    match event {
+        ArrEvent::Test(app) => println!("reve is ready to upscale the imports of {:?}", app),
+        ArrEvent::Other(app, event) => {
+            println!("nothing to upscale on the {} event of {:?}", event, app)
+        }
+        ArrEvent::Import { path, .. } => import(&path, args.profile)?,
+    }
+    Ok(())
+}
+
+/// Upscales the imported file at `path` and replaces it with the output. A failed upscale
+/// exits with the status of the child, which already printed its error.
+fn import(path: &str, profile: Option<String>) -> Result<(), ReveError> {
+    let original = Path::new(path);
+    let upscaled = upscaled_path(original)?;
+    let profile = match profile {
+        Some(profile) => Some(profile),
+        None => {
+            let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+            let (_, file) = ConfigData::load_with_file(&exe_dir)?;
+            file.as_ref()
+                .map(TomlConfig::arr)
+                .transpose()?
+                .flatten()
+                .and_then(|arr| arr.profile)
+        }
+    };
+
+    if upscaled.exists() {
+        // Written by a run stopped before replacing the original
+        fs::remove_file(&upscaled)?;
+    }
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(["upscale", "--progress", "plain", "--skip-upscaled", "-i"])
+        .arg(path)
+        .arg("-o")
+        .arg(&upscaled);
+    if let Some(profile) = &profile {
+        command.args(["--profile", profile.as_str()]);
+    }
+    let status = command.stdin(Stdio::null()).status()?;
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    if !upscaled.exists() {
+        println!("{} was already upscaled by reve", path);
+        return Ok(());
+    }
+    fs::rename(&upscaled, original)?;
+    println!("replaced {} with its upscale", path);
+    Ok(())
+}
+
+/// Returns the path the upscale of the import at `original` is written to before replacing
+/// it, hidden next to it so replacing it is a rename on the same file system.
+fn upscaled_path(original: &Path) -> Result<PathBuf, ReveError> {
+    let (Some(file_name), Some("mp4" | "mkv")) = (
+        original.file_name().and_then(|name| name.to_str()),
+        original
+            .extension()
+            .and_then(|extension| extension.to_str()),
+    ) else {
+        return Err(ReveError::InvalidArgument(format!(
+            "only mp4 and mkv imports can be replaced by their upscale, not {:?}",
+            original
+        )));
+    };
+    Ok(original.with_file_name(format!(".reve-{}", file_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_event_key() {
+        assert!(is_event_key("sonarr_eventtype"));
+        assert!(is_event_key("RADARR_EVENTTYPE"));
+        assert!(!is_event_key("sonarr_series_title"));
+        assert!(!is_event_key("lidarr_eventtype"));
+    }
+
+    #[test]
+    fn test_upscaled_path() {
+        assert_eq!(
+            upscaled_path(Path::new("/tv/Show/Season 1/Show - S01E01.mkv")).unwrap(),
+            Path::new("/tv/Show/Season 1/.reve-Show - S01E01.mkv")
+        );
+        assert_eq!(
+            upscaled_path(Path::new("/movies/Movie (1999).mp4")).unwrap(),
+            Path::new("/movies/.reve-Movie (1999).mp4")
+        );
+        assert!(upscaled_path(Path::new("/movies/Movie (1999).avi")).is_err());
+        assert!(upscaled_path(Path::new("/movies/mkv")).is_err());
+    }
+}
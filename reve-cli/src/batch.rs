@@ -0,0 +1,586 @@
+//! `reve batch`: upscales the videos of a folder one after the other, each by a `reve upscale`
+//! child process given the same options.
+//!
+//! With `--jobs` several children run at once, sharing the gpu, each in the temp folder of its
+//! own job. Their json progress is followed on a bar per video instead of their dashboards.
+//!
+//! A video that fails is reported at the end without stopping the others. Outputs written to
+//! the folder by an earlier run are skipped with `--skip-upscaled`, which every upscale gets.
+//! The videos are upscaled by name, or in the order of `--sort`, and `--limit` upscales only
+//! the first ones.
+//!
+//! The videos found are kept in a manifest in `reve.db`, identified by the folder and the
+//! options, with the status and output of each. Running the same batch again resumes the
+//! video it was interrupted on, then upscales the ones left and retries the failed ones,
+//! skipping the upscaled ones. `--restart` forgets the manifest.
+
+use crate::absolute_path;
+use crate::progress::truncate;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use colored::Colorize;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use reve_shared::binaries::Binaries;
+use reve_shared::config::ConfigData;
+use reve_shared::selection::{Candidate, Selection};
+use reve_shared::state::{self, BatchFile, QueueStatus};
+use reve_shared::toml_config::TomlConfig;
+use reve_shared::{ffprobe, paths};
+use reve_shared::{is_video_path, Args, ProgressEvent, ReveError, UPSCALED_TAG};
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use std::{env, fs, thread};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve batch",
+    bin_name = "reve batch",
+    about = "upscale every video of a folder with the same options"
+)]
+pub struct BatchArgs {
+    /// folder of the videos to upscale
+    folder: String,
+
+    /// also upscale the videos of its subfolders
+    #[clap(short, long)]
+    recursive: bool,
+
+    /// videos upscaled at once, each by its own reve upscale sharing the gpu
+    #[clap(short, long, value_parser = clap::value_parser!(u8).range(1..=16), default_value_t = 1)]
+    jobs: u8,
+
+    #[clap(flatten)]
+    selection: Selection,
+
+    /// forget the videos upscaled by an earlier run of this batch and upscale them again
+    #[clap(long)]
+    restart: bool,
+
+    /// options of reve upscale given to every video, after --, like `-- -s 2 --output-dir out`
+    #[clap(last = true)]
+    options: Vec<OsString>,
+}
+
+pub fn run(args: BatchArgs) -> Result<(), ReveError> {
+    if let Some(option) = args
+        .options
+        .iter()
+        .find(|option| *option == "-i" || *option == "--inputpath")
+    {
+        return Err(ReveError::InvalidArgument(format!(
+            "{} is given by the folder, each of its videos is an input",
+            option.to_string_lossy()
+        )));
+    }
+    let mut found = Vec::new();
+    find(Path::new(&args.folder), args.recursive, &mut found)?;
+    found.sort();
+    if found.is_empty() {
+        return Err(ReveError::InvalidArgument(format!(
+            "no video in {}",
+            args.folder
+        )));
+    }
+
+    let exe = env::current_exe()?;
+    let exe_dir = exe.parent().unwrap().to_path_buf();
+    let (config, file) = ConfigData::load_with_file(&exe_dir)?;
+    let manifest = Manifest {
+        work_dir: config.work_dir(&exe_dir)?,
+        batch: batch_id(&args.folder, args.recursive, &args.options),
+        options: &args.options,
+        config: &config,
+        file: file.as_ref(),
+    };
+    if args.restart && state::remove_batch(&manifest.work_dir, &manifest.batch)? {
+        println!(
+            "{}",
+            "upscaled videos forgotten, upscaling them again".yellow()
+        );
+    }
+    let found: Vec<String> = found.iter().map(absolute_path).collect();
+    state::add_batch_files(&manifest.work_dir, &manifest.batch, &found)?;
+    let files = state::batch_files(&manifest.work_dir, &manifest.batch)?;
+    let videos = left_to_upscale(&files, &found);
+    if videos.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "every video of {} is upscaled, --restart upscales them again",
+                args.folder
+            )
+            .green()
+        );
+        return Ok(());
+    }
+    let videos = select(videos, &args.selection, &config.binaries())?;
+
+    let outcomes = if args.jobs == 1 {
+        upscale_each(&exe, &videos, &manifest)?
+    } else {
+        upscale_parallel(&exe, &videos, &manifest, args.jobs as usize)
+    };
+    if outcomes.contains(&Some(Outcome::Interrupted)) {
+        return Err(ReveError::Interrupted);
+    }
+    let failed: Vec<&PathBuf> = videos
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| **outcome == Some(Outcome::Failed))
+        .map(|(video, _)| video)
+        .collect();
+
+    if failed.is_empty() {
+        println!("{}", format!("{} videos upscaled", videos.len()).green());
+        return Ok(());
+    }
+    for video in &failed {
+        eprintln!("{} {}", "failed:".bright_red(), video.display());
+    }
+    Err(ReveError::Batch(format!(
+        "{} of {} videos could not be upscaled",
+        failed.len(),
+        videos.len()
+    )))
+}
+
+/// Returns the id of the manifest of the batch of `folder` with `options`.
+fn batch_id(folder: &str, recursive: bool, options: &[OsString]) -> String {
+    let options: Vec<String> = recursive
+        .then(|| "--recursive".to_string())
+        .into_iter()
+        .chain(
+            options
+                .iter()
+                .map(|option| option.to_string_lossy().into_owned()),
+        )
+        .collect();
+    paths::job_id(&absolute_path(folder), &options.join("\0"))
+}
+
+/// Returns the videos of the manifest `files` still `found` that are left to upscale: the one
+/// interrupted first, then the pending ones and the failed ones, retried last.
+fn left_to_upscale(files: &[BatchFile], found: &[String]) -> Vec<PathBuf> {
+    let mut left: Vec<&BatchFile> = files
+        .iter()
+        .filter(|file| {
+            matches!(
+                file.status,
+                QueueStatus::Running | QueueStatus::Pending | QueueStatus::Failed
+            ) && found.contains(&file.path)
+        })
+        .collect();
+    left.sort_by_key(|file| match file.status {
+        QueueStatus::Running => 0,
+        QueueStatus::Pending => 1,
+        _ => 2,
+    });
+    left.into_iter()
+        .map(|file| PathBuf::from(&file.path))
+        .collect()
+}
+
+/// Manifest of the batch in `reve.db`, recording how the upscale of each video ended.
+struct Manifest<'a> {
+    work_dir: PathBuf,
+    batch: String,
+    options: &'a [OsString],
+    config: &'a ConfigData,
+    file: Option<&'a TomlConfig>,
+}
+
+impl Manifest<'_> {
+    fn start(&self, video: &Path) -> Result<(), ReveError> {
+        self.update(video, QueueStatus::Running, None)
+    }
+
+    /// Records the `outcome` of the upscale of `video`, with its output once upscaled. An
+    /// interrupted video stays running, to be resumed first.
+    fn finish(&self, video: &Path, outcome: Outcome) -> Result<(), ReveError> {
+        match outcome {
+            Outcome::Upscaled => {
+                let output = self.output(video);
+                self.update(video, QueueStatus::Done, output.as_deref())
+            }
+            Outcome::Failed => self.update(video, QueueStatus::Failed, None),
+            Outcome::Interrupted => Ok(()),
+        }
+    }
+
+    fn update(
+        &self,
+        video: &Path,
+        status: QueueStatus,
+        output: Option<&str>,
+    ) -> Result<(), ReveError> {
+        state::update_batch_file(
+            &self.work_dir,
+            &self.batch,
+            &video.to_string_lossy(),
+            status,
+            output,
+        )
+    }
+
+    /// Returns the output `reve upscale` wrote for `video`, parsing the options like it does.
+    /// A video skipped as an output of reve has none.
+    fn output(&self, video: &Path) -> Option<String> {
+        let mut argv: Vec<OsString> = vec!["reve".into(), "-i".into(), video.into()];
+        argv.extend(self.options.iter().cloned());
+        let (command, file_ids) =
+            ConfigData::upscale_command(Args::command(), &mut argv, self.file).ok()?;
+        let matches = command.try_get_matches_from(argv).ok()?;
+        let mut args = Args::from_arg_matches(&matches).ok()?;
+        self.config.complete_args(&mut args, &matches, &file_ids);
+        let output = absolute_path(args.resolve_output_path().ok()?);
+        Path::new(&output).is_file().then_some(output)
+    }
+}
+
+/// How the upscale of a video ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+    Upscaled,
+    Failed,
+    Interrupted,
+}
+
+impl Outcome {
+    fn of(status: ExitStatus) -> Outcome {
+        match status.code() {
+            Some(0) => Outcome::Upscaled,
+            // ctrl+c reaches the child as well, which keeps its temp folder to resume
+            Some(130) | None => Outcome::Interrupted,
+            Some(_) => Outcome::Failed,
+        }
+    }
+}
+
+/// Returns the arguments of the `reve upscale` of `video` with `options`, which skips the
+/// outputs of reve unless `options` already ask it to.
+fn upscale_args(video: &Path, options: &[OsString]) -> Vec<OsString> {
+    let mut args: Vec<OsString> = vec!["upscale".into(), "-i".into(), video.into()];
+    args.extend(options.iter().cloned());
+    if !options.iter().any(|option| option == "--skip-upscaled") {
+        args.push("--skip-upscaled".into());
+    }
+    args
+}
+
+/// Upscales `videos` one after the other, each child showing its own dashboard, and returns
+/// how each ended, `None` for the ones left when one was interrupted.
+fn upscale_each(
+    exe: &Path,
+    videos: &[PathBuf],
+    manifest: &Manifest,
+) -> Result<Vec<Option<Outcome>>, ReveError> {
+    let mut outcomes = vec![None; videos.len()];
+    for (i, video) in videos.iter().enumerate() {
+        println!(
+            "{}",
+            format!("[{}/{}] {}", i + 1, videos.len(), video.display()).green()
+        );
+        manifest.start(video)?;
+        let outcome = Outcome::of(
+            Command::new(exe)
+                .args(upscale_args(video, manifest.options))
+                .status()?,
+        );
+        manifest.finish(video, outcome)?;
+        outcomes[i] = Some(outcome);
+        if outcome == Outcome::Interrupted {
+            break;
+        }
+    }
+    Ok(outcomes)
+}
+
+/// Upscales `videos` `jobs` at a time and returns how each ended, `None` for the ones left
+/// when one was interrupted. The others go on when one fails.
+fn upscale_parallel(
+    exe: &Path,
+    videos: &[PathBuf],
+    manifest: &Manifest,
+    jobs: usize,
+) -> Vec<Option<Outcome>> {
+    let multi = MultiProgress::new();
+    let next = AtomicUsize::new(0);
+    let interrupted = AtomicBool::new(false);
+    let outcomes = Mutex::new(vec![None; videos.len()]);
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(videos.len()) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= videos.len() || interrupted.load(Ordering::Relaxed) {
+                    break;
+                }
+                let video = &videos[index];
+                let _ = multi.println(
+                    format!("[{}/{}] {}", index + 1, videos.len(), video.display())
+                        .green()
+                        .to_string(),
+                );
+                let outcome = manifest
+                    .start(video)
+                    .and_then(|_| follow(exe, video, manifest.options, &multi))
+                    .and_then(|outcome| manifest.finish(video, outcome).map(|_| outcome))
+                    .unwrap_or_else(|err| {
+                        let _ = multi.println(format!("{} {}", "error:".bright_red(), err));
+                        Outcome::Failed
+                    });
+                if outcome == Outcome::Interrupted {
+                    interrupted.store(true, Ordering::Relaxed);
+                }
+                outcomes.lock().unwrap()[index] = Some(outcome);
+            });
+        }
+    });
+    outcomes.into_inner().unwrap()
+}
+
+/// Upscales `video` in a child whose json progress is followed on a bar of `multi`, printing
+/// its errors when it fails.
+fn follow(
+    exe: &Path,
+    video: &Path,
+    options: &[OsString],
+    multi: &MultiProgress,
+) -> Result<Outcome, ReveError> {
+    let mut child = Command::new(exe)
+        .args(upscale_args(video, options))
+        .args(["--progress", "json"])
+        // The children would take turns reading the p + enter of pausing
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr = child.stderr.take().unwrap();
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+
+    let bar = multi.add(ProgressBar::new(0));
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{elapsed_precise}] [{wide_bar:.green/white}] {pos:>4}/{len:4} {prefix:<24} {msg}",
+            )
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let name = video
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    bar.set_prefix(truncate(&name, 24));
+    for line in BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map_while(Result::ok)
+    {
+        if let Ok(ProgressEvent::Job {
+            segments_done,
+            segments_total,
+            eta,
+        }) = serde_json::from_str(&line)
+        {
+            bar.set_length(segments_total as u64);
+            bar.set_position(segments_done as u64);
+            bar.set_message(
+                eta.map(|eta| format!("{:.0} min left", eta / 60.0))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    let outcome = Outcome::of(child.wait()?);
+    bar.finish_and_clear();
+    let errors = errors.join().unwrap_or_default();
+    if outcome == Outcome::Failed && !errors.trim().is_empty() {
+        let _ = multi.println(format!("{}: {}", name, errors.trim()));
+    }
+    Ok(outcome)
+}
+
+/// Returns `videos` in the order of `--sort`, up to `--limit` of them, without the ones the
+/// filters of `selection` skip. With a limit the outputs of reve are left out first, so they
+/// do not take the place of videos to upscale.
+fn select(
+    mut videos: Vec<PathBuf>,
+    selection: &Selection,
+    binaries: &Binaries,
+) -> Result<Vec<PathBuf>, ReveError> {
+    if selection.sort.is_none() && selection.limit.is_none() && !selection.filters() {
+        return Ok(videos);
+    }
+    if selection.limit.is_some() {
+        videos.retain(|video| {
+            !matches!(
+                ffprobe::format_tag(binaries, video, UPSCALED_TAG),
+                Ok(Some(_))
+            )
+        });
+    }
+    let mut candidates = Vec::with_capacity(videos.len());
+    let mut kept = Vec::with_capacity(videos.len());
+    for video in videos {
+        if let Some(candidate) = candidate(binaries, &video, selection)? {
+            candidates.push(candidate);
+            kept.push(video);
+        }
+    }
+    let videos = kept;
+    let mut order = selection.order(&candidates);
+    if let Some(limit) = selection.limit {
+        order.truncate(limit as usize);
+    }
+    Ok(order
+        .into_iter()
+        .map(|index| videos[index].clone())
+        .collect())
+}
+
+/// Returns the size and modification time of `video`, and its probe when `selection` needs
+/// it, or `None` when its filters skip the video, printing why. A video ffprobe can not read
+/// has no probe and is kept, its upscale reports the error.
+fn candidate(
+    binaries: &Binaries,
+    video: &Path,
+    selection: &Selection,
+) -> Result<Option<Candidate>, ReveError> {
+    let metadata = fs::metadata(video)?;
+    let size = metadata.len();
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as i64);
+    let unprobed = Candidate {
+        size,
+        modified,
+        ..Candidate::default()
+    };
+    if !selection.needs_probe() {
+        return Ok(Some(unprobed));
+    }
+    let Ok(candidate) = ffprobe::probe_json(binaries, video)
+        .and_then(|json| Candidate::from_probe(size, modified, &json))
+    else {
+        return Ok(Some(unprobed));
+    };
+    if let Some(reason) = selection.skip_reason(&candidate.probe) {
+        println!("{} {}: {}", "skipped".yellow(), video.display(), reason);
+        return Ok(None);
+    }
+    Ok(Some(candidate))
+}
+
+/// Adds the videos of `folder` to `videos`, and the ones of its subfolders when `recursive`.
+fn find(folder: &Path, recursive: bool, videos: &mut Vec<PathBuf>) -> Result<(), ReveError> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                find(&path, recursive, videos)?;
+            }
+        } else if is_video_path(&path) {
+            videos.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(options: &[&str]) -> Vec<OsString> {
+        options.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_upscale_args() {
+        let video = Path::new("/videos/movie.mkv");
+        assert_eq!(
+            upscale_args(video, &options(&["-s", "2"])),
+            options(&[
+                "upscale",
+                "-i",
+                "/videos/movie.mkv",
+                "-s",
+                "2",
+                "--skip-upscaled"
+            ])
+        );
+        assert_eq!(
+            upscale_args(video, &options(&["--skip-upscaled", "-s", "2"])),
+            options(&[
+                "upscale",
+                "-i",
+                "/videos/movie.mkv",
+                "--skip-upscaled",
+                "-s",
+                "2"
+            ])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_outcome() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let exited = |code: i32| Outcome::of(ExitStatus::from_raw(code << 8));
+        assert_eq!(exited(0), Outcome::Upscaled);
+        assert_eq!(exited(1), Outcome::Failed);
+        assert_eq!(exited(130), Outcome::Interrupted);
+        // Killed by SIGINT before it could exit
+        assert_eq!(Outcome::of(ExitStatus::from_raw(2)), Outcome::Interrupted);
+    }
+
+    #[test]
+    fn test_left_to_upscale() {
+        let file = |path: &str, status: QueueStatus| BatchFile {
+            path: path.to_string(),
+            status,
+            output: None,
+        };
+        let files = [
+            file("/videos/ep1.mkv", QueueStatus::Failed),
+            file("/videos/ep2.mkv", QueueStatus::Done),
+            file("/videos/ep3.mkv", QueueStatus::Pending),
+            file("/videos/ep4.mkv", QueueStatus::Running),
+            file("/videos/ep5.mkv", QueueStatus::Pending),
+            file("/videos/gone.mkv", QueueStatus::Pending),
+        ];
+        let found: Vec<String> = (1..=5)
+            .map(|episode| format!("/videos/ep{}.mkv", episode))
+            .collect();
+        assert_eq!(
+            left_to_upscale(&files, &found),
+            [
+                "/videos/ep4.mkv",
+                "/videos/ep3.mkv",
+                "/videos/ep5.mkv",
+                "/videos/ep1.mkv"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_batch_id() {
+        let folder = env::current_dir().unwrap().join("videos");
+        let id = batch_id("videos", false, &options(&["-s", "2"]));
+        assert_eq!(
+            id,
+            batch_id(&folder.to_string_lossy(), false, &options(&["-s", "2"]))
+        );
+        assert_ne!(id, batch_id("videos", true, &options(&["-s", "2"])));
+        assert_ne!(id, batch_id("videos", false, &options(&["-s", "4"])));
+        assert_ne!(id, batch_id("videos", false, &options(&["-s2"])));
+        assert_ne!(id, batch_id("other", false, &options(&["-s", "2"])));
+    }
+}
@@ -0,0 +1,167 @@
+//! `reve benchmark`: upscales a short sample of a video through the same stages as `reve
+//! upscale` and prints how fast each one ran, to compare models, tile sizes and gpus before a
+//! long run.
+//!
+//! The sample is a single segment, so the stages run one after the other and each is timed
+//! alone. Its output is deleted once measured.
+
+use crate::{absolute_path, control, logging};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use colored::Colorize;
+use reve_shared::config::ConfigData;
+use reve_shared::job::{JobProgress, UpscaleJob};
+use reve_shared::{paths, rebuild_temp, Args, ProgressFormat, ReveError, Segment, Stage};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve benchmark",
+    bin_name = "reve benchmark",
+    about = "measure how fast each stage upscales a sample of a video"
+)]
+pub struct BenchmarkArgs {
+    /// video to take the sample from
+    #[clap(short = 'i', long)]
+    inputpath: String,
+
+    /// upscale ratio (2, 3, 4)
+    #[clap(short, long, default_value_t = 2)]
+    scale: u8,
+
+    /// start of the sample, in seconds or hh:mm:ss
+    #[clap(long, default_value = "0")]
+    start: String,
+
+    /// length of the sample, in seconds
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..3600), default_value_t = 10)]
+    seconds: u32,
+
+    /// options of reve upscale to measure, after --, like `-- --tile 256 --codec av1`
+    #[clap(last = true)]
+    options: Vec<OsString>,
+}
+
+/// Time each stage of the sample took.
+#[derive(Default)]
+struct Timings {
+    started: Mutex<HashMap<Stage, Instant>>,
+    elapsed: Mutex<HashMap<Stage, Duration>>,
+}
+
+impl JobProgress for Timings {
+    fn stage_started(&self, stage: Stage, _segment: &Segment) {
+        self.started.lock().unwrap().insert(stage, Instant::now());
+    }
+
+    fn stage_finished(&self, stage: Stage, _segment: &Segment) {
+        if let Some(started) = self.started.lock().unwrap().remove(&stage) {
+            *self.elapsed.lock().unwrap().entry(stage).or_default() += started.elapsed();
+        }
+    }
+}
+
+pub fn run(args: BenchmarkArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, file) = ConfigData::load_with_file(&exe_dir)?;
+    let work_dir = config.work_dir(&exe_dir)?;
+    let output = work_dir
+        .join("benchmark.mkv")
+        .to_string_lossy()
+        .into_owned();
+
+    // Parsed like the options of reve upscale, so reve.toml and its profiles apply. No video
+    // has a thousand frames a second, the sample is a single segment
+    let input = absolute_path(&args.inputpath);
+    let (scale, seconds) = (args.scale.to_string(), args.seconds.to_string());
+    let segment_size = (args.seconds * 1000).to_string();
+    let mut argv: Vec<OsString> = [
+        "reve",
+        "-i",
+        input.as_str(),
+        "-s",
+        scale.as_str(),
+        "--start",
+        args.start.as_str(),
+        "--duration",
+        seconds.as_str(),
+        "--segmentsize",
+        segment_size.as_str(),
+        "--no-resume",
+        output.as_str(),
+    ]
+    .into_iter()
+    .map(OsString::from)
+    .chain(args.options)
+    .collect();
+    let (command, file_ids) =
+        ConfigData::upscale_command(Args::command(), &mut argv, file.as_ref())?;
+    let matches = command
+        .try_get_matches_from(argv)
+        .map_err(|err| ReveError::InvalidArgument(err.to_string()))?;
+    let mut upscale_args = Args::from_arg_matches(&matches)
+        .map_err(|err| ReveError::InvalidArgument(err.to_string()))?;
+    config.complete_args(&mut upscale_args, &matches, &file_ids);
+    upscale_args.progress = ProgressFormat::Plain;
+    upscale_args.validate()?;
+
+    logging::init(
+        &work_dir.join("reve.log"),
+        upscale_args.log_level,
+        ProgressFormat::Plain,
+    )?;
+    env::set_current_dir(&work_dir)?;
+    paths::set_job(&paths::job_id(&upscale_args.inputpath, &output));
+    paths::set_frames_dir(upscale_args.ramdisk.as_deref());
+    rebuild_temp(false)?;
+    let mut job = UpscaleJob::new(upscale_args)?;
+    let video = job.video();
+    let frames = video.frame_count;
+    println!(
+        "{}",
+        format!(
+            "sample of {} frames, {}x{} upscaled x{}",
+            frames, video.width, video.height, video.upscale_ratio
+        )
+        .green()
+    );
+    let frame_rate = video.frame_rate as f64;
+
+    control::listen(&job.control());
+    let timings = Timings::default();
+    let result = job.run(&timings);
+    let _ = fs::remove_file(&output);
+    result?;
+
+    let elapsed = timings.elapsed.into_inner().unwrap();
+    let mut slowest: Option<(Stage, f64)> = None;
+    for stage in [Stage::Export, Stage::Upscale, Stage::Merge] {
+        let seconds = elapsed.get(&stage).map_or(0.0, Duration::as_secs_f64);
+        let fps = frames as f64 / seconds.max(0.001);
+        println!(
+            "{:>8} {:>9.2} fps {:>8.1}s",
+            stage.to_string(),
+            fps,
+            seconds
+        );
+        if slowest.is_none_or(|(_, slowest)| fps < slowest) {
+            slowest = Some((stage, fps));
+        }
+    }
+    // The stages of a full upscale overlap, the slowest one sets its pace
+    if let Some((stage, fps)) = slowest {
+        println!(
+            "{}",
+            format!(
+                "{} is the slowest stage, an hour of this video takes about {:.1} hours",
+                stage,
+                frame_rate / fps
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use reve_shared::control::JobControl;
+use std::io::{self, BufRead};
+use std::thread;
+use tracing::{info, warn};
+
+/// Listens for pause and resume commands on stdin and, on unix, toggles on SIGUSR1.
+///
+/// Commands are read a line at a time (`p`/`pause`, `r`/`resume`), so they can be typed in the
+/// terminal or written to stdin by a wrapper through a pipe.
+///
+/// Ctrl+c (or SIGTERM) stops the job cleanly, a second one exits right away.
+pub fn listen(control: &JobControl) {
+    let stop_control = control.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        if stop_control.is_stopped() {
+            std::process::exit(130);
+        }
+        info!("stopping, press ctrl+c again to exit right away");
+        stop_control.stop();
+    }) {
+        warn!("could not handle ctrl+c: {}", err);
+    }
+
+    let stdin_control = control.clone();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            match line.trim() {
+                "p" | "pause" => {
+                    info!("pausing after the current segment, type r and press enter to resume");
+                    stdin_control.set_paused(true);
+                }
+                "r" | "resume" => {
+                    info!("resuming");
+                    stdin_control.set_paused(false);
+                }
+                "" => {}
+                command => warn!("unknown command {:?}, valid: p/pause, r/resume", command),
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    {
+        use signal_hook::consts::SIGUSR1;
+        use signal_hook::iterator::Signals;
+
+        match Signals::new([SIGUSR1]) {
+            Ok(mut signals) => {
+                let signal_control = control.clone();
+                thread::spawn(move || {
+                    for _ in signals.forever() {
+                        signal_control.toggle();
+                        info!(
+                            "{}",
+                            if signal_control.is_paused() {
+                                "pausing after the current segment"
+                            } else {
+                                "resuming"
+                            }
+                        );
+                    }
+                });
+            }
+            Err(err) => warn!("could not listen for SIGUSR1: {}", err),
+        }
+    }
+}
@@ -0,0 +1,864 @@
+//! `reve daemon`: upscales the videos copied to the watch folders of `reve.toml`, meant to
+//! run as a service.
+//!
+//! The watch folders are scanned every `interval` seconds and the videos not modified since
+//! are added to the queue of `reve.db`, which outlives a restart. They are upscaled one at a
+//! time by a `reve upscale` child process whose json progress is followed. Files without a
+//! video stream or that ffprobe can not read are left out of it, with their reason. Nothing
+//! is asked on the terminal, events are logged with their file as a field, and `reve daemon
+//! status` reads the progress and the queue from the loopback control address.
+//!
+//! With `--sort` the pending files are sorted after each scan, the order being kept in the
+//! queue, and `--limit` stops the daemon once it upscaled that many files. The videos outside
+//! `--min-height`, `--max-height` and `--max-width` or in a codec of `--codec-filter` are not
+//! queued, and are looked at again by the next scans.
+
+use crate::{absolute_path, library, logging};
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use reve_shared::binaries::Binaries;
+use reve_shared::config::ConfigData;
+use reve_shared::control::JobControl;
+use reve_shared::selection::Selection;
+use reve_shared::state::{self, QueueStatus, QueuedFile, UpscaleStats};
+use reve_shared::toml_config::{Library, TomlConfig, WatchFolder};
+use reve_shared::{
+    ffprobe, is_video_path, LogLevel, ProgressEvent, ProgressFormat, ReveError, Stage,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, fs, thread};
+use tracing::{debug, error, info, warn};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve daemon",
+    bin_name = "reve daemon",
+    about = "upscale the videos copied to the watch folders of reve.toml, as a service"
+)]
+pub struct DaemonArgs {
+    #[clap(subcommand)]
+    command: Option<DaemonCommand>,
+
+    /// scan the watch folders once and print what each holds, without upscaling
+    #[clap(long)]
+    scan_only: bool,
+
+    #[clap(flatten)]
+    selection: Selection,
+
+    /// verbosity of reve.log, written next to the temp folder
+    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+}
+
+#[derive(Subcommand, Debug)]
+enum DaemonCommand {
+    /// print the progress and the queue of the running daemon
+    Status {
+        /// print the status as json
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+/// Upscale the daemon is running.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct Current {
+    file: String,
+    segments_done: u32,
+    segments_total: u32,
+    eta: Option<f64>,
+}
+
+/// Answer to the `status` command of the control address.
+#[derive(Serialize, Deserialize, Debug)]
+struct Status {
+    current: Option<Current>,
+    queue: Vec<QueuedFile>,
+}
+
+pub fn run(args: DaemonArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, file) = ConfigData::load_with_file(&exe_dir)?;
+    let daemon = file
+        .as_ref()
+        .map(TomlConfig::daemon)
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| {
+            ReveError::InvalidArgument(String::from("reve.toml has no [daemon] table"))
+        })?;
+    let address = control_address(&daemon.control)?;
+    if let Some(DaemonCommand::Status { json }) = args.command {
+        return print_status(address, json);
+    }
+    if daemon.watch.is_empty() {
+        return Err(ReveError::InvalidArgument(String::from(
+            "[daemon] has no [[daemon.watch]] folder",
+        )));
+    }
+
+    let rescan = library::settings(file.as_ref())?.filter(|library| library.rescan);
+    let binaries = file.as_ref().map_or_else(Binaries::default, |file| {
+        Binaries::new(
+            file.get_str("ffmpeg-path"),
+            file.get_str("ffprobe-path"),
+            file.get_str("realesrgan-path"),
+        )
+    });
+    let work_dir = config.work_dir(&exe_dir)?;
+    logging::init(
+        &work_dir.join("reve.log"),
+        args.log_level,
+        ProgressFormat::Plain,
+    )?;
+    let watch: Vec<WatchFolder> = daemon
+        .watch
+        .into_iter()
+        .map(|folder| WatchFolder {
+            folder: absolute_path(&folder.folder),
+            output_dir: folder.output_dir.map(absolute_path),
+            ..folder
+        })
+        .collect();
+    env::set_current_dir(&work_dir)?;
+
+    // With the termination feature of ctrlc the handler also gets the SIGTERM of a service
+    // manager. The upscale is stopped by the same signal, so it resumes when the daemon
+    // starts again
+    let stop = JobControl::new();
+    let handler_stop = stop.clone();
+    if let Err(err) = ctrlc::set_handler(move || handler_stop.stop()) {
+        warn!("could not handle ctrl+c and SIGTERM: {}", err);
+    }
+    if args.scan_only {
+        if !scan(
+            &watch,
+            daemon.interval,
+            &binaries,
+            &args.selection,
+            &stop,
+            true,
+        ) {
+            println!(
+                "{}",
+                "scan stopped, run reve daemon --scan-only again to resume it".yellow()
+            );
+        }
+        return print_report(&watch);
+    }
+
+    let requeued = state::requeue_running()?;
+    if requeued > 0 {
+        info!(
+            files = requeued,
+            "resuming the upscales of the previous run"
+        );
+    }
+    let current = Arc::new(Mutex::new(None));
+    serve(address, current.clone())?;
+
+    info!(folders = watch.len(), control = %address, "daemon started");
+    let mut upscaled = 0;
+    while !stop.is_stopped() {
+        scan(
+            &watch,
+            daemon.interval,
+            &binaries,
+            &args.selection,
+            &stop,
+            false,
+        );
+        if let Some(sort) = args.selection.sort {
+            if let Err(err) = state::sort_queue(Some(sort)) {
+                warn!("could not sort the queue: {}", err);
+            }
+        }
+        // A file that can not be upscaled, or a busy reve.db, must not stop the service
+        while !stop.is_stopped() {
+            if args.selection.limit.is_some_and(|limit| upscaled >= limit) {
+                info!(files = upscaled, "limit reached, daemon stopped");
+                return Ok(());
+            }
+            let file = match state::dequeue() {
+                Ok(Some(file)) => file,
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("could not read the queue: {}", err);
+                    break;
+                }
+            };
+            upscaled += 1;
+            if let Err(err) = upscale(&file, &current, &stop, rescan.as_ref()) {
+                *current.lock().unwrap() = None;
+                let message = err.to_string();
+                error!(file = %file.path, error = %message, "upscale failed");
+                let stats = UpscaleStats {
+                    finished: Some(unix_time()),
+                    ..UpscaleStats::default()
+                };
+                finish(&file, Some(&message), &stats);
+            }
+        }
+        for _ in 0..daemon.interval {
+            if stop.is_stopped() {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+    info!("daemon stopped");
+    Ok(())
+}
+
+/// Parses the control address, which must be a loopback one since the status holds the
+/// paths of the queue.
+fn control_address(address: &str) -> Result<SocketAddr, ReveError> {
+    address
+        .parse::<SocketAddr>()
+        .ok()
+        .filter(|address| address.ip().is_loopback())
+        .ok_or_else(|| {
+            ReveError::InvalidArgument(format!(
+                "the control address of [daemon] must be a loopback address like \
+                 127.0.0.1:7431, not {:?}",
+                address
+            ))
+        })
+}
+
+/// Files probed at once by a scan, which mostly waits on the disk or the network share.
+const PROBE_THREADS: usize = 4;
+
+/// Settled video of a watch folder, not in the queue yet.
+struct Found<'a> {
+    path: String,
+    size: u64,
+    /// Modification time, in milliseconds since the epoch.
+    modified: i64,
+    folder: &'a WatchFolder,
+}
+
+/// Queues the videos of the watch folders not modified for `interval` seconds, which are no
+/// longer being copied, returning `false` if `stop` interrupted the scan.
+///
+/// Each folder scanned is recorded in `reve.db`, so an interrupted scan resumes with the next
+/// one. `progress` prints the files probed on the terminal.
+fn scan(
+    watch: &[WatchFolder],
+    interval: u64,
+    binaries: &Binaries,
+    selection: &Selection,
+    stop: &JobControl,
+    progress: bool,
+) -> bool {
+    let (files, scanned) = match state::queued().and_then(|files| Ok((files, state::scanned()?))) {
+        Ok(queue) => queue,
+        Err(err) => {
+            warn!("could not read the queue: {}", err);
+            return false;
+        }
+    };
+    let queued = queued_paths(files);
+    for folder in watch {
+        if scanned.contains(&folder.folder) {
+            continue;
+        }
+        if !scan_folder(
+            folder, interval, binaries, selection, &queued, stop, progress,
+        ) {
+            return false;
+        }
+        if let Err(err) = state::mark_scanned(&folder.folder) {
+            warn!(folder = %folder.folder, "could not record the folder scanned: {}", err);
+        }
+    }
+    if let Err(err) = state::finish_scan() {
+        warn!("could not record the end of the scan: {}", err);
+    }
+    true
+}
+
+/// Returns the paths of the `queue` a scan does not look at again. The files left out are
+/// probed again once changed, a copy that was not finished may have been left out.
+fn queued_paths(queue: Vec<QueuedFile>) -> HashSet<String> {
+    queue
+        .into_iter()
+        .filter(|file| !matches!(file.status, QueueStatus::NoVideo | QueueStatus::Error))
+        .map(|file| file.path)
+        .collect()
+}
+
+/// Scans `folder`, returning `false` if `stop` interrupted it. The videos without a video
+/// stream or that ffprobe can not read are left out with their reason, and the ones the
+/// filters of `selection` skip are not queued.
+///
+/// Only the files new or changed since their last probe are probed, a few at a time, and the
+/// outputs are cached in `reve.db` for the next scans.
+fn scan_folder(
+    folder: &WatchFolder,
+    interval: u64,
+    binaries: &Binaries,
+    selection: &Selection,
+    queued: &HashSet<String>,
+    stop: &JobControl,
+    progress: bool,
+) -> bool {
+    let found = find(folder, interval, queued);
+    let mut probes: Vec<Option<Result<String, String>>> = found
+        .iter()
+        .map(|file| {
+            state::cached_probe(&file.path, file.size, file.modified).unwrap_or_else(|err| {
+                warn!(file = %file.path, "could not read the cached probe: {}", err);
+                None
+            })
+        })
+        .collect();
+    let unprobed: Vec<usize> = (0..found.len())
+        .filter(|&index| probes[index].is_none())
+        .collect();
+    let paths: Vec<&str> = unprobed
+        .iter()
+        .map(|&index| found[index].path.as_str())
+        .collect();
+    let shown = progress.then_some(folder.folder.as_str());
+    let done: Vec<(usize, Result<String, String>)> = unprobed
+        .into_iter()
+        .zip(probe_all(binaries, &paths, stop, shown))
+        .filter_map(|(index, probe)| Some((index, probe?)))
+        .collect();
+    let probed = done.len();
+    let rows: Vec<_> = done
+        .iter()
+        .map(|(index, probe)| {
+            let file = &found[*index];
+            (file.path.as_str(), file.size, file.modified, probe)
+        })
+        .collect();
+    // Written at once, a folder of thousands of files is one transaction rather than one each
+    if let Err(err) = state::save_probes(&rows) {
+        warn!(folder = %folder.folder, "could not cache the probes: {}", err);
+    }
+    for (index, probe) in done {
+        probes[index] = Some(probe);
+    }
+    // The probes done are cached, the next scan goes on from them
+    if stop.is_stopped() {
+        return false;
+    }
+
+    let (mut queued, mut left_out) = (0, 0);
+    for (file, probe) in found.iter().zip(probes) {
+        // Not probed, it is queued or left out by the next scan
+        let Some(probe) = probe else {
+            continue;
+        };
+        let skipped = probe
+            .as_ref()
+            .ok()
+            .filter(|_| selection.filters())
+            .and_then(|json| ffprobe::summary(json).ok())
+            .and_then(|summary| selection.skip_reason(&summary));
+        let streams = probe
+            .and_then(|json| ffprobe::video_stream_count(&json).map_err(|err| err.to_string()));
+        let (status, reason) = match streams {
+            Ok(0) => (QueueStatus::NoVideo, String::from("no video stream")),
+            Ok(_) => {
+                if let Some(reason) = skipped {
+                    // Not recorded, the filters may change when the daemon restarts
+                    debug!(file = %file.path, reason = %reason, "skipped");
+                    continue;
+                }
+                match state::enqueue(
+                    &file.path,
+                    file.folder.output_dir.as_deref(),
+                    file.folder.profile.as_deref(),
+                ) {
+                    Ok(true) => {
+                        info!(file = %file.path, "queued");
+                        queued += 1;
+                    }
+                    Ok(false) => {}
+                    Err(err) => warn!(file = %file.path, "could not queue: {}", err),
+                }
+                continue;
+            }
+            Err(reason) => (QueueStatus::Error, reason),
+        };
+        match state::leave_out(&file.path, status, &reason) {
+            Ok(true) => {
+                warn!(file = %file.path, reason = %reason, "left out");
+                left_out += 1;
+            }
+            Ok(false) => {}
+            Err(err) => warn!(file = %file.path, "could not record the file left out: {}", err),
+        }
+    }
+    if !found.is_empty() {
+        info!(
+            folder = %folder.folder,
+            videos = found.len(),
+            probed,
+            queued,
+            left_out,
+            "folder scanned"
+        );
+    }
+    true
+}
+
+/// Returns the settled videos of `folder` which are not in `queued`.
+fn find<'a>(folder: &'a WatchFolder, interval: u64, queued: &HashSet<String>) -> Vec<Found<'a>> {
+    let entries = match fs::read_dir(&folder.folder) {
+        Ok(entries) => entries,
+        Err(err) => {
+            warn!(folder = %folder.folder, "could not read the watch folder: {}", err);
+            return Vec::new();
+        }
+    };
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        let Ok(modified) = metadata.modified() else {
+            continue;
+        };
+        let settled = modified
+            .elapsed()
+            .is_ok_and(|age| age >= Duration::from_secs(interval));
+        if !settled || !metadata.is_file() || !is_video_path(&path) {
+            continue;
+        }
+        let path = path.to_string_lossy().into_owned();
+        if queued.contains(&path) {
+            continue;
+        }
+        found.push(Found {
+            path,
+            size: metadata.len(),
+            modified: modified
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as i64),
+            folder,
+        });
+    }
+    found
+}
+
+/// Runs ffprobe on `paths` from [`PROBE_THREADS`] threads, returning the outputs or errors in
+/// the order of `paths`, `None` for the files left when `stop` is set. The count of files
+/// probed is printed after the name `folder` when given.
+fn probe_all(
+    binaries: &Binaries,
+    paths: &[&str],
+    stop: &JobControl,
+    folder: Option<&str>,
+) -> Vec<Option<Result<String, String>>> {
+    if paths.is_empty() {
+        return Vec::new();
+    }
+    let done = AtomicUsize::new(0);
+    let chunk_size = paths.len().div_ceil(PROBE_THREADS);
+    let probes = thread::scope(|scope| {
+        let chunks: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let done = &done;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| {
+                            if stop.is_stopped() {
+                                return None;
+                            }
+                            let probe =
+                                ffprobe::probe_json(binaries, path).map_err(|err| err.to_string());
+                            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                            if let Some(folder) = folder {
+                                print!("\r{}: {}/{} probed", folder, done, paths.len());
+                                let _ = io::stdout().flush();
+                            }
+                            Some(probe)
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        chunks
+            .into_iter()
+            .flat_map(|chunk| chunk.join().unwrap())
+            .collect()
+    });
+    if folder.is_some() {
+        println!();
+    }
+    probes
+}
+
+/// Prints the files of each watch folder by their status in the queue, then the ones that
+/// failed or were left out.
+fn print_report(watch: &[WatchFolder]) -> Result<(), ReveError> {
+    let queue = state::queued()?;
+    println!(
+        "{:>8} {:>8} {:>8} {:>8}  folder",
+        "pending", "done", "failed", "left out"
+    );
+    for folder in watch {
+        let [pending, done, failed, left_out] = folder_counts(&queue, &folder.folder);
+        println!(
+            "{:>8} {:>8} {:>8} {:>8}  {}",
+            pending, done, failed, left_out, folder.folder
+        );
+    }
+    print_problems(&queue);
+    Ok(())
+}
+
+/// Returns the pending, done, failed and left out files of `queue` found in `folder`,
+/// without the ones of its subfolders.
+fn folder_counts(queue: &[QueuedFile], folder: &str) -> [usize; 4] {
+    let files: Vec<&QueuedFile> = queue
+        .iter()
+        .filter(|file| Path::new(&file.path).parent() == Some(Path::new(folder)))
+        .collect();
+    let count = |statuses: &[QueueStatus]| {
+        files
+            .iter()
+            .filter(|file| statuses.contains(&file.status))
+            .count()
+    };
+    [
+        count(&[QueueStatus::Pending, QueueStatus::Running]),
+        count(&[QueueStatus::Done]),
+        count(&[QueueStatus::Failed]),
+        count(&[QueueStatus::NoVideo, QueueStatus::Error]),
+    ]
+}
+
+/// Prints the files of `queue` that failed or were left out, with their reason.
+fn print_problems(queue: &[QueuedFile]) {
+    for file in queue {
+        let label = match file.status {
+            QueueStatus::Failed => "failed",
+            QueueStatus::NoVideo => "no video",
+            QueueStatus::Error => "unreadable",
+            _ => continue,
+        };
+        println!(
+            "{} {}: {}",
+            label.red(),
+            file.path,
+            file.error.as_deref().unwrap_or_default()
+        );
+    }
+}
+
+/// Upscales `file` in a child process, following its progress in `current`, and records the
+/// statistics of the upscale in the queue.
+///
+/// Outputs already upscaled by reve are skipped, so the ones written to a watch folder are
+/// not upscaled again. The media server of `rescan` is asked to rescan once an output lands.
+fn upscale(
+    file: &QueuedFile,
+    current: &Mutex<Option<Current>>,
+    stop: &JobControl,
+    rescan: Option<&Library>,
+) -> Result<(), ReveError> {
+    info!(file = %file.path, "upscaling");
+    let started = Instant::now();
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(["upscale", "--progress", "json", "--skip-upscaled", "-i"])
+        .arg(&file.path);
+    if let Some(output_dir) = &file.output_dir {
+        command.arg("--output-dir").arg(output_dir);
+    }
+    if let Some(profile) = &file.profile {
+        command.args(["--profile", profile.as_str()]);
+    }
+    let mut child = command
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stderr = child.stderr.take().unwrap();
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+    *current.lock().unwrap() = Some(Current {
+        file: file.path.clone(),
+        ..Current::default()
+    });
+    let mut output = None;
+    // Frames realesrgan upscaled and the seconds it took, over the segments
+    let (mut frames, mut elapsed) = (0, 0.0);
+    for line in BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map_while(Result::ok)
+    {
+        match serde_json::from_str(&line) {
+            Ok(ProgressEvent::Job {
+                segments_done,
+                segments_total,
+                eta,
+            }) => {
+                if let Some(current) = current.lock().unwrap().as_mut() {
+                    current.segments_done = segments_done;
+                    current.segments_total = segments_total;
+                    current.eta = eta;
+                }
+            }
+            Ok(ProgressEvent::SegmentFinished {
+                stage: Stage::Upscale,
+                frames: segment_frames,
+                elapsed: segment_elapsed,
+                ..
+            }) => {
+                frames += segment_frames;
+                elapsed += segment_elapsed;
+            }
+            Ok(ProgressEvent::Done { output: upscaled }) => {
+                info!(file = %file.path, output = %upscaled, "upscaled");
+                output = Some(upscaled);
+            }
+            _ => {}
+        }
+    }
+    let status = child.wait()?;
+    *current.lock().unwrap() = None;
+    let errors = errors.join().unwrap_or_default();
+    let stats = UpscaleStats {
+        started: None,
+        finished: Some(unix_time()),
+        duration: Some(started.elapsed().as_secs_f64()),
+        fps: (elapsed > 0.0).then(|| frames as f64 / elapsed),
+        output_size: output
+            .as_ref()
+            .and_then(|output| fs::metadata(output).ok())
+            .map(|metadata| metadata.len()),
+        output,
+    };
+
+    if status.success() {
+        let upscaled = stats.output.is_some();
+        finish(file, None, &stats);
+        if let Some(library) = rescan.filter(|_| upscaled) {
+            match library::rescan(library) {
+                Ok(()) => info!(file = %file.path, "asked the media server to rescan"),
+                Err(err) => {
+                    warn!(file = %file.path, "could not ask the media server to rescan: {}", err)
+                }
+            }
+        }
+    } else if stop.is_stopped() {
+        // Left running in the queue, requeued when the daemon starts again
+        info!(file = %file.path, "upscale stopped");
+    } else {
+        let message = match errors.trim() {
+            "" => format!("reve exited with {}", status),
+            errors => errors.to_string(),
+        };
+        error!(file = %file.path, error = %message, "upscale failed");
+        finish(file, Some(&message), &stats);
+    }
+    Ok(())
+}
+
+/// Marks `file` done in the queue, or failed with `error`, with the statistics of its
+/// upscale. A failure to write it is logged, the file being left running and upscaled again
+/// when the daemon restarts.
+fn finish(file: &QueuedFile, error: Option<&str>, stats: &UpscaleStats) {
+    if let Err(err) = state::finish_queued(&file.path, error, stats) {
+        warn!(file = %file.path, "could not record the end of the upscale: {}", err);
+    }
+}
+
+/// Returns the current time in seconds since the epoch.
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
+
+/// Answers the `status` command on `address` from a thread.
+fn serve(address: SocketAddr, current: Arc<Mutex<Option<Current>>>) -> Result<(), ReveError> {
+    let listener = TcpListener::bind(address).map_err(|err| {
+        ReveError::InvalidArgument(format!(
+            "could not listen on {}, is another daemon running? {}",
+            address, err
+        ))
+    })?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(err) = answer(stream, &current) {
+                warn!("control connection failed: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn answer(stream: TcpStream, current: &Mutex<Option<Current>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+    let reply = match command.trim() {
+        "status" => {
+            let status = Status {
+                current: current.lock().unwrap().clone(),
+                queue: state::queued().map_err(|err| io::Error::other(err.to_string()))?,
+            };
+            serde_json::to_string(&status)?
+        }
+        command => {
+            serde_json::json!({ "error": format!("unknown command {:?}", command) }).to_string()
+        }
+    };
+    writeln!(&stream, "{}", reply)
+}
+
+fn print_status(address: SocketAddr, json: bool) -> Result<(), ReveError> {
+    let mut stream =
+        TcpStream::connect_timeout(&address, Duration::from_secs(5)).map_err(|err| {
+            ReveError::InvalidArgument(format!("no daemon answers on {}: {}", address, err))
+        })?;
+    writeln!(stream, "status")?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if json {
+        print!("{}", reply);
+        return Ok(());
+    }
+    let status: Status = serde_json::from_str(&reply).map_err(|err| ReveError::Io(err.into()))?;
+
+    match &status.current {
+        Some(current) => println!(
+            "{} {} ({}/{} segments{})",
+            "upscaling".green(),
+            current.file,
+            current.segments_done,
+            current.segments_total,
+            current
+                .eta
+                .map(|eta| format!(", {:.0} min left", eta / 60.0))
+                .unwrap_or_default()
+        ),
+        None => println!("{}", "waiting for videos".green()),
+    }
+    let count = |wanted| {
+        status
+            .queue
+            .iter()
+            .filter(|file| file.status == wanted)
+            .count()
+    };
+    println!(
+        "queue: {} pending, {} done, {} failed, {} left out",
+        count(QueueStatus::Pending),
+        count(QueueStatus::Done),
+        count(QueueStatus::Failed),
+        count(QueueStatus::NoVideo) + count(QueueStatus::Error)
+    );
+    print_problems(&status.queue);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(path: &str, status: QueueStatus) -> QueuedFile {
+        QueuedFile {
+            path: path.to_string(),
+            output_dir: None,
+            profile: None,
+            status,
+            error: None,
+            priority: 0,
+            stats: UpscaleStats::default(),
+        }
+    }
+
+    #[test]
+    fn test_control_address() {
+        assert_eq!(
+            control_address("127.0.0.1:7431").unwrap(),
+            SocketAddr::from(([127, 0, 0, 1], 7431))
+        );
+        assert!(control_address("[::1]:7431").is_ok());
+        assert!(control_address("0.0.0.0:7431").is_err());
+        assert!(control_address("localhost").is_err());
+    }
+
+    #[test]
+    fn test_queued_paths() {
+        let paths = queued_paths(vec![
+            queued("/inbox/ep1.mkv", QueueStatus::Done),
+            queued("/inbox/ep2.mkv", QueueStatus::Pending),
+            queued("/inbox/song.mkv", QueueStatus::NoVideo),
+            queued("/inbox/broken.mkv", QueueStatus::Error),
+            queued("/inbox/ep3.mkv", QueueStatus::Failed),
+        ]);
+        let mut paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["/inbox/ep1.mkv", "/inbox/ep2.mkv", "/inbox/ep3.mkv"]
+        );
+    }
+
+    #[test]
+    fn test_folder_counts() {
+        let queue = [
+            queued("/inbox/ep1.mkv", QueueStatus::Done),
+            queued("/inbox/ep2.mkv", QueueStatus::Running),
+            queued("/inbox/ep3.mkv", QueueStatus::Pending),
+            queued("/inbox/song.mkv", QueueStatus::NoVideo),
+            queued("/inbox/broken.mkv", QueueStatus::Error),
+            queued("/inbox/ep4.mkv", QueueStatus::Failed),
+            // Neither a subfolder nor a folder sharing the prefix are counted
+            queued("/inbox/season2/ep1.mkv", QueueStatus::Pending),
+            queued("/inbox2/ep1.mkv", QueueStatus::Pending),
+        ];
+        assert_eq!(folder_counts(&queue, "/inbox"), [2, 1, 1, 2]);
+        assert_eq!(folder_counts(&queue, "/inbox/season2"), [1, 0, 0, 0]);
+        assert_eq!(folder_counts(&queue, "/other"), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_find() {
+        let dir = env::temp_dir().join(format!("reve-daemon-{}", std::process::id()));
+        fs::create_dir_all(dir.join("season2")).unwrap();
+        for file in ["ep1.mkv", "ep2.mp4", "notes.txt", "season2/ep3.mkv"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        let folder = WatchFolder {
+            folder: dir.to_string_lossy().into_owned(),
+            output_dir: None,
+            profile: None,
+        };
+        let queued: HashSet<String> = [dir.join("ep2.mp4").to_string_lossy().into_owned()].into();
+
+        let found = find(&folder, 0, &queued);
+        let paths: Vec<&str> = found.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, [dir.join("ep1.mkv").to_string_lossy()]);
+        assert_eq!(found[0].size, 0);
+        // Still being copied
+        assert!(find(&folder, 3600, &HashSet::new()).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
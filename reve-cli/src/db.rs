@@ -0,0 +1,357 @@
+//! `reve db`: reads the queue of `reve.db` in the work dir.
+//!
+//! `list` counts the files of the queue by status and lists them with the upscales saved to be
+//! resumed, `status` shows one of them, `reset` queues a file again and `prune` removes the
+//! files that no longer exist. `sort` sets the order the pending files are upscaled in. The
+//! time and speed of the upscales are printed from the statistics the daemon records.
+//!
+//! `export` writes the queue as json or csv, which `import` adds to the queue of another
+//! machine, or of the same one from a backup.
+//!
+//! `duplicates` fingerprints the queued files, caching the fingerprints in `reve.db`, and
+//! lists the ones holding the same content under different paths, like a movie copied to two
+//! watch folders.
+
+use crate::absolute_path;
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use reve_shared::config::ConfigData;
+use reve_shared::fingerprint::fingerprint;
+use reve_shared::queue_export;
+use reve_shared::selection::SortKey;
+use reve_shared::state::{self, QueueStatus};
+use reve_shared::ReveError;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use std::{env, fs};
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve db",
+    bin_name = "reve db",
+    about = "read, reset, prune, export and import the queue of reve.db"
+)]
+pub struct DbArgs {
+    #[clap(subcommand)]
+    command: DbCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// count the files of the queue by status and list them with the upscales to resume
+    List,
+    /// print the status of a file in the queue and of its saved upscale
+    Status {
+        /// path of the file
+        file: String,
+    },
+    /// queue a file again, failed or done, clearing its error
+    Reset {
+        /// path of the file
+        file: String,
+    },
+    /// remove the files that no longer exist from the queue and its caches
+    Prune,
+    /// set the order the daemon upscales the pending files in, from their cached probes
+    Sort {
+        /// order of the files, the order they were found in when not given
+        #[clap(value_enum)]
+        key: Option<SortKey>,
+    },
+    /// list the queued files holding the same content under different paths
+    Duplicates,
+    /// write the queue to a file, or to stdout
+    Export {
+        #[clap(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+
+        /// file written, stdout by default
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// add the files of an exported queue to the queue, replacing the ones with the same path
+    Import {
+        /// json or csv file, read as csv when its extension is csv
+        path: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+pub fn run(args: DbArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, _) = ConfigData::load_with_file(&exe_dir)?;
+    let work_dir = config.work_dir(&exe_dir)?;
+    match args.command {
+        DbCommand::List => {
+            env::set_current_dir(work_dir)?;
+            list()
+        }
+        DbCommand::Status { file } => {
+            let file = absolute_path(file);
+            env::set_current_dir(work_dir)?;
+            status(&file)
+        }
+        DbCommand::Reset { file } => {
+            let file = absolute_path(file);
+            env::set_current_dir(work_dir)?;
+            match state::queued_file(&file)? {
+                None => Err(not_queued(&file)),
+                Some(queued) if queued.status == QueueStatus::Running => {
+                    Err(ReveError::InvalidArgument(format!(
+                        "{} is being upscaled by reve daemon",
+                        file
+                    )))
+                }
+                Some(_) => {
+                    state::reset_queued(&file)?;
+                    println!("{}", format!("{} queued again", file).green());
+                    Ok(())
+                }
+            }
+        }
+        DbCommand::Prune => {
+            env::set_current_dir(work_dir)?;
+            let pruned = state::prune()?;
+            println!(
+                "{}",
+                format!("{} files that no longer exist removed", pruned).green()
+            );
+            Ok(())
+        }
+        DbCommand::Sort { key } => {
+            env::set_current_dir(work_dir)?;
+            let sorted = state::sort_queue(key)?;
+            let order = match key.and_then(|key| key.to_possible_value()) {
+                Some(key) => format!("sorted by {}", key.get_name()),
+                None => String::from("back in the order they were found"),
+            };
+            println!("{}", format!("{} pending files {}", sorted, order).green());
+            Ok(())
+        }
+        DbCommand::Duplicates => {
+            env::set_current_dir(work_dir)?;
+            duplicates()
+        }
+        DbCommand::Export { format, output } => {
+            let output = output.map(absolute_path);
+            env::set_current_dir(work_dir)?;
+            let files = state::queued()?;
+            let exported = match format {
+                ExportFormat::Json => queue_export::to_json(&files)?,
+                ExportFormat::Csv => queue_export::to_csv(&files),
+            };
+            match output {
+                Some(output) => {
+                    fs::write(&output, exported)?;
+                    println!("{} files exported to {}", files.len(), output);
+                }
+                None => print!("{}", exported),
+            }
+            Ok(())
+        }
+        DbCommand::Import { path } => {
+            let text = fs::read_to_string(&path)?;
+            let is_csv = Path::new(&path)
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+            let files = if is_csv {
+                queue_export::from_csv(&text)?
+            } else {
+                queue_export::from_json(&text)?
+            };
+            env::set_current_dir(work_dir)?;
+            let imported = state::import(&files)?;
+            println!(
+                "{}",
+                format!("{} files imported to the queue", imported).green()
+            );
+            Ok(())
+        }
+    }
+}
+
+fn not_queued(file: &str) -> ReveError {
+    ReveError::InvalidArgument(format!("{} is not in the queue of reve.db", file))
+}
+
+/// Name of `status` for the user.
+fn label(status: QueueStatus) -> &'static str {
+    match status {
+        QueueStatus::Pending => "pending",
+        QueueStatus::Running => "running",
+        QueueStatus::Done => "done",
+        QueueStatus::Failed => "failed",
+        QueueStatus::NoVideo | QueueStatus::Error => "left out",
+    }
+}
+
+fn list() -> Result<(), ReveError> {
+    let files = state::queued()?;
+    let count = |wanted: &str| {
+        files
+            .iter()
+            .filter(|file| label(file.status) == wanted)
+            .count()
+    };
+    println!(
+        "queue: {} pending, {} running, {} done, {} failed, {} left out",
+        count("pending"),
+        count("running"),
+        count("done"),
+        count("failed"),
+        count("left out")
+    );
+    let upscaled: Vec<_> = files
+        .iter()
+        .filter(|file| file.status == QueueStatus::Done)
+        .filter_map(|file| Some((file, file.stats.duration?)))
+        .collect();
+    if !upscaled.is_empty() {
+        let total: f64 = upscaled.iter().map(|(_, duration)| duration).sum();
+        println!("{} files upscaled in {}", upscaled.len(), hours(total));
+        let slowest = upscaled
+            .iter()
+            .filter_map(|(file, _)| Some((file, file.stats.fps?)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((file, fps)) = slowest {
+            println!("slowest: {} at {:.1} fps", file.path, fps);
+        }
+    }
+    for file in &files {
+        let shown = format!("{:>8}", label(file.status));
+        let shown = match file.status {
+            QueueStatus::Done => shown.green(),
+            QueueStatus::Pending | QueueStatus::Running => shown.normal(),
+            _ => shown.red(),
+        };
+        println!("{}  {}", shown, file.path);
+    }
+
+    let jobs = state::saved_jobs()?;
+    if !jobs.is_empty() {
+        println!("upscales to resume:");
+    }
+    for job in jobs {
+        println!(
+            "  {} -> {} ({}/{} segments merged)",
+            job.input, job.output, job.merged, job.segments
+        );
+    }
+    Ok(())
+}
+
+fn status(file: &str) -> Result<(), ReveError> {
+    let queued = state::queued_file(file)?;
+    let job = state::saved_jobs()?
+        .into_iter()
+        .find(|job| job.input == file);
+    if queued.is_none() && job.is_none() {
+        return Err(not_queued(file));
+    }
+    if let Some(queued) = queued {
+        println!("{}: {}", file, label(queued.status));
+        if let Some(error) = &queued.error {
+            println!("  {}", error.red());
+        }
+        if let Some(output_dir) = &queued.output_dir {
+            println!("  output dir: {}", output_dir);
+        }
+        if let Some(profile) = &queued.profile {
+            println!("  profile: {}", profile);
+        }
+        let stats = &queued.stats;
+        if let Some(duration) = stats.duration {
+            let fps = stats
+                .fps
+                .map(|fps| format!(" at {:.1} fps", fps))
+                .unwrap_or_default();
+            println!("  upscaled in {}{}", hours(duration), fps);
+        }
+        if let Some(output) = &stats.output {
+            let size = stats
+                .output_size
+                .map(|size| format!(" ({} MB)", size / 1_000_000))
+                .unwrap_or_default();
+            println!("  output: {}{}", output, size);
+        }
+    }
+    if let Some(job) = job {
+        println!(
+            "saved upscale to {}: {}/{} segments merged, resumed by reve resume -i {}",
+            job.output, job.merged, job.segments, job.input
+        );
+    }
+    Ok(())
+}
+
+/// Returns `seconds` as hours and minutes.
+fn hours(seconds: f64) -> String {
+    let minutes = (seconds / 60.0).round() as u64;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+fn duplicates() -> Result<(), ReveError> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for file in state::queued()? {
+        if matches!(file.status, QueueStatus::NoVideo | QueueStatus::Error) {
+            continue;
+        }
+        // Upscaled and removed, or moved since it was queued
+        let Ok(metadata) = fs::metadata(&file.path) else {
+            continue;
+        };
+        let size = metadata.len();
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        let hash = match state::cached_fingerprint(&file.path, size, modified)? {
+            Some(hash) => hash,
+            None => {
+                let hash = fingerprint(&file.path)?;
+                state::save_fingerprint(&file.path, size, modified, &hash)?;
+                hash
+            }
+        };
+        groups.entry(hash).or_default().push(file.path);
+    }
+
+    let mut found = 0;
+    for (hash, paths) in groups.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        println!("{}", hash[..12].yellow());
+        for path in paths {
+            println!("  {}", path);
+        }
+        found += 1;
+    }
+    if found == 0 {
+        println!("{}", "no queued file has a duplicate".green());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label(QueueStatus::Running), "running");
+        assert_eq!(label(QueueStatus::NoVideo), label(QueueStatus::Error));
+        assert_eq!(label(QueueStatus::Error), "left out");
+    }
+
+    #[test]
+    fn test_hours() {
+        assert_eq!(hours(0.0), "0h 00m");
+        assert_eq!(hours(89.0), "0h 01m");
+        assert_eq!(hours(5430.0), "1h 31m");
+        assert_eq!(hours(36000.0), "10h 00m");
+    }
+}
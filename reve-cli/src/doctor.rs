@@ -0,0 +1,202 @@
+//! `reve doctor`: checks the tools, models, gpus and folders reve needs, printing a report
+//! to paste in a bug report.
+//!
+//! Every check runs even after one fails, so the report shows the whole setup.
+
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use reve_shared::binaries::{Binaries, TESTED_FFMPEG};
+use reve_shared::config::ConfigData;
+use reve_shared::disk::free_space;
+use reve_shared::encoder::{self, Codec};
+use reve_shared::{gpu, models, state, ReveError};
+use std::env::consts::{ARCH, OS};
+use std::path::Path;
+use std::{env, fs};
+
+/// Free space of the work dir below which a 4k segment may not fit.
+const LOW_SPACE: u64 = 20_000_000_000;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve doctor",
+    bin_name = "reve doctor",
+    about = "check the tools, models, gpus and folders reve needs, for a bug report"
+)]
+pub struct DoctorArgs {}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// Report printed as the checks run, counting the problems found.
+#[derive(Default)]
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn line(&mut self, outcome: Outcome, check: &str, detail: impl AsRef<str>) {
+        // Padded before coloring, the escape codes would count as width
+        let label = match outcome {
+            Outcome::Ok => format!("{:<8}", "ok").green(),
+            Outcome::Warning => format!("{:<8}", "warning").yellow(),
+            Outcome::Error => format!("{:<8}", "error").red(),
+        };
+        if outcome != Outcome::Ok {
+            self.problems += 1;
+        }
+        println!("{} {:<12} {}", label, check, detail.as_ref());
+    }
+}
+
+pub fn run(_args: DoctorArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let mut report = Report::default();
+    println!("reve {} on {} {}", env!("CARGO_PKG_VERSION"), OS, ARCH);
+
+    let config = match ConfigData::load_with_file(&exe_dir) {
+        Ok((config, file)) => {
+            let source = match (&file, ConfigData::path()) {
+                (Some(file), _) => file.path.display().to_string(),
+                (None, Some(path)) if path.exists() => path.display().to_string(),
+                _ => String::from("defaults, no reve.toml"),
+            };
+            report.line(Outcome::Ok, "config", source);
+            config
+        }
+        Err(err) => {
+            report.line(Outcome::Error, "config", err.to_string());
+            ConfigData::default()
+        }
+    };
+    let binaries = config.binaries();
+
+    match binaries.check() {
+        Ok(version) => {
+            report.line(Outcome::Ok, "tools", version);
+            check_ffmpeg_version(&mut report, &binaries);
+        }
+        Err(err) => report.line(Outcome::Error, "tools", err.to_string()),
+    }
+    for codec in Codec::value_variants() {
+        match encoder::check_encoder(&binaries, *codec) {
+            Ok(()) => report.line(Outcome::Ok, "encoder", codec.name()),
+            Err(err) => report.line(Outcome::Warning, "encoder", err.to_string()),
+        }
+    }
+    check_models(&mut report, &binaries.realesrgan);
+    match gpu::list(&binaries) {
+        Ok(gpus) if gpus.is_empty() => {
+            report.line(Outcome::Error, "gpu", "realesrgan found no vulkan device")
+        }
+        Ok(gpus) => {
+            for gpu in gpus {
+                report.line(Outcome::Ok, "gpu", format!("{}  {}", gpu.id, gpu.name));
+            }
+        }
+        Err(err) => report.line(Outcome::Error, "gpu", err.to_string()),
+    }
+
+    match config.work_dir(&exe_dir) {
+        Ok(work_dir) => check_work_dir(&mut report, &work_dir),
+        Err(err) => report.line(Outcome::Error, "work dir", err.to_string()),
+    }
+
+    println!();
+    match report.problems {
+        0 => println!("{}", "no problem found".green()),
+        problems => println!(
+            "{} problems found, paste this report in a bug report",
+            problems
+        ),
+    }
+    Ok(())
+}
+
+/// Checks that ffmpeg is not older than the releases reve is tested with.
+fn check_ffmpeg_version(report: &mut Report, binaries: &Binaries) {
+    match binaries.ffmpeg_version() {
+        Ok(Some(version)) if version < TESTED_FFMPEG => report.line(
+            Outcome::Warning,
+            "ffmpeg",
+            format!(
+                "{} is older than {}, the oldest release reve is tested with",
+                version, TESTED_FFMPEG
+            ),
+        ),
+        Ok(Some(version)) => report.line(Outcome::Ok, "ffmpeg", version.to_string()),
+        Ok(None) => report.line(Outcome::Ok, "ffmpeg", "git build, taken as recent"),
+        Err(err) => report.line(Outcome::Error, "ffmpeg", err.to_string()),
+    }
+}
+
+/// Checks that the `models` folder next to realesrgan holds the files of every model.
+fn check_models(report: &mut Report, realesrgan: &Path) {
+    let Some(dir) = realesrgan
+        .is_file()
+        .then(|| realesrgan.with_file_name("models"))
+    else {
+        report.line(
+            Outcome::Warning,
+            "models",
+            "realesrgan is looked up in PATH, its models folder was not checked",
+        );
+        return;
+    };
+    for model in models::files() {
+        let missing: Vec<String> = ["param", "bin"]
+            .iter()
+            .map(|extension| format!("{}.{}", model, extension))
+            .filter(|file| !dir.join(file).is_file())
+            .collect();
+        if missing.is_empty() {
+            report.line(Outcome::Ok, "model", model);
+        } else {
+            report.line(
+                Outcome::Warning,
+                "model",
+                format!("{} missing in {}", missing.join(" and "), dir.display()),
+            );
+        }
+    }
+}
+
+/// Checks that the work dir is writable, has room for the frames and a sound `reve.db`.
+fn check_work_dir(report: &mut Report, work_dir: &Path) {
+    let probe = work_dir.join("reve-doctor.tmp");
+    match fs::write(&probe, "reve").and_then(|()| fs::remove_file(&probe)) {
+        Ok(()) => report.line(Outcome::Ok, "work dir", work_dir.display().to_string()),
+        Err(err) => report.line(
+            Outcome::Error,
+            "work dir",
+            format!("{} is not writable: {}", work_dir.display(), err),
+        ),
+    }
+    match free_space(work_dir) {
+        Ok(available) => report.line(
+            if available < LOW_SPACE {
+                Outcome::Warning
+            } else {
+                Outcome::Ok
+            },
+            "free space",
+            format!("{} GB", available / 1_000_000_000),
+        ),
+        Err(err) => report.line(Outcome::Error, "free space", err.to_string()),
+    }
+    match state::integrity_problems(work_dir) {
+        Ok(problems) if problems.is_empty() => {
+            report.line(Outcome::Ok, "database", "reve.db is sound")
+        }
+        Ok(problems) => report.line(
+            Outcome::Error,
+            "database",
+            format!("reve.db is damaged: {}", problems.join("; ")),
+        ),
+        Err(err) => report.line(Outcome::Error, "database", err.to_string()),
+    }
+}
@@ -0,0 +1,32 @@
+//! `reve gpus`: lists the vulkan devices `--gpu-id` selects from.
+
+use clap::Parser;
+use colored::Colorize;
+use reve_shared::config::ConfigData;
+use reve_shared::{gpu, ReveError};
+use std::env;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve gpus",
+    bin_name = "reve gpus",
+    about = "list the vulkan devices realesrgan can upscale on, selected with --gpu"
+)]
+pub struct GpusArgs {}
+
+pub fn run(_args: GpusArgs) -> Result<(), ReveError> {
+    let (config, _) = ConfigData::load_with_file(env::current_exe()?.parent().unwrap())?;
+    let selected = config.gpu_id;
+    for gpu in gpu::list(&config.binaries())? {
+        let default = if selected == Some(gpu.id) {
+            " (gpu-id of reve.toml)"
+        } else {
+            ""
+        };
+        println!("{}  {}{}", gpu.id.to_string().green(), gpu.name, default);
+    }
+    if selected.is_none() {
+        println!("\nrealesrgan picks a device unless --gpu <id> is given");
+    }
+    Ok(())
+}
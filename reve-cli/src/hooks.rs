@@ -0,0 +1,185 @@
+//! `--on-complete`, `--on-error` and `--on-file-done` hooks.
+//!
+//! A hook starting with `http://` or `https://` is posted the event as JSON, any other hook
+//! is run with the system shell and gets it in `REVE_*` environment variables. A failing hook
+//! is logged and never fails the job.
+
+use serde::Serialize;
+use std::process::Command;
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    Complete,
+    Error,
+    FileDone,
+}
+
+impl HookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            HookEvent::Complete => "complete",
+            HookEvent::Error => "error",
+            HookEvent::FileDone => "file_done",
+        }
+    }
+}
+
+/// Job metadata passed to a hook.
+#[derive(Serialize)]
+pub struct HookInfo<'a> {
+    pub event: HookEvent,
+    pub input: &'a str,
+    pub output: &'a str,
+    /// Seconds since the job started.
+    pub duration: f64,
+    /// Exit status of reve: 0 once done, 130 when stopped, 1 on errors.
+    pub status: i32,
+    pub error: Option<String>,
+}
+
+impl HookInfo<'_> {
+    /// Returns a one-line summary, used as the `content` of posted events so chat webhooks
+    /// show something readable, and by notifications.
+    pub fn message(&self) -> String {
+        match self.event {
+            HookEvent::Complete => {
+                format!("reve finished {} in {:.0}s", self.output, self.duration)
+            }
+            HookEvent::FileDone => format!("reve wrote {}", self.output),
+            HookEvent::Error => format!(
+                "reve failed on {} after {:.0}s: {}",
+                self.input,
+                self.duration,
+                self.error.as_deref().unwrap_or("stopped")
+            ),
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("REVE_EVENT", self.event.name().to_string()),
+            ("REVE_INPUT", self.input.to_string()),
+            ("REVE_OUTPUT", self.output.to_string()),
+            ("REVE_DURATION", format!("{:.0}", self.duration)),
+            ("REVE_STATUS", self.status.to_string()),
+            ("REVE_ERROR", self.error.clone().unwrap_or_default()),
+        ]
+    }
+}
+
+fn is_url(hook: &str) -> bool {
+    hook.starts_with("http://") || hook.starts_with("https://")
+}
+
+/// Runs `hook` for `info`, if one was given.
+pub fn run(hook: Option<&str>, info: &HookInfo) {
+    let Some(hook) = hook else {
+        return;
+    };
+    info!("running {} hook", info.event.name());
+    let result = if is_url(hook) {
+        post(hook, info)
+    } else {
+        shell(hook, info)
+    };
+    if let Err(err) = result {
+        warn!("{} hook failed: {}", info.event.name(), err);
+    }
+}
+
+fn post(url: &str, info: &HookInfo) -> Result<(), String> {
+    let mut body = serde_json::to_value(info).unwrap();
+    body["content"] = info.message().into();
+    ureq::post(url)
+        .timeout(Duration::from_secs(30))
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+fn shell(command: &str, info: &HookInfo) -> Result<(), String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .envs(info.env())
+        .status()
+        .map_err(|err| err.to_string())?;
+    if !status.success() {
+        return Err(format!("{:?} exited with {}", command, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(event: HookEvent, error: Option<&str>) -> HookInfo<'static> {
+        HookInfo {
+            event,
+            input: "/videos/movie.mkv",
+            output: "/videos/movie_2x.mkv",
+            duration: 125.4,
+            status: if event == HookEvent::Error { 1 } else { 0 },
+            error: error.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://discord.com/api/webhooks/1/abc"));
+        assert!(is_url("http://localhost:8080/done"));
+        assert!(!is_url("notify-send done"));
+        assert!(!is_url("./http://script.sh"));
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(
+            info(HookEvent::Complete, None).message(),
+            "reve finished /videos/movie_2x.mkv in 125s"
+        );
+        assert_eq!(
+            info(HookEvent::FileDone, None).message(),
+            "reve wrote /videos/movie_2x.mkv"
+        );
+        assert_eq!(
+            info(HookEvent::Error, Some("ffmpeg exited with 1")).message(),
+            "reve failed on /videos/movie.mkv after 125s: ffmpeg exited with 1"
+        );
+        assert_eq!(
+            info(HookEvent::Error, None).message(),
+            "reve failed on /videos/movie.mkv after 125s: stopped"
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let env = info(HookEvent::FileDone, None).env();
+        let value = |key: &str| {
+            env.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(value("REVE_EVENT"), Some("file_done"));
+        assert_eq!(value("REVE_OUTPUT"), Some("/videos/movie_2x.mkv"));
+        assert_eq!(value("REVE_DURATION"), Some("125"));
+        assert_eq!(value("REVE_STATUS"), Some("0"));
+        assert_eq!(value("REVE_ERROR"), Some(""));
+        // Posted events name the event like the variable
+        let json = serde_json::to_value(info(HookEvent::FileDone, None)).unwrap();
+        assert_eq!(json["event"], "file_done");
+    }
+}
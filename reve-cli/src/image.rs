@@ -0,0 +1,118 @@
+//! `reve image`: upscales still images with the models and tools the video upscaling uses.
+
+use crate::{absolute_path, logging};
+use clap::Parser;
+use reve_shared::config::ConfigData;
+use reve_shared::{model_validation, models, still, LogLevel, ProgressFormat, ReveError};
+use std::env;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve image",
+    bin_name = "reve image",
+    about = "upscale png/jpg/webp images, keeping their EXIF data in png and jpg outputs"
+)]
+pub struct ImageArgs {
+    /// input images, file names may contain * and ? (photos/*.jpg)
+    #[clap(short = 'i', long, num_args = 1.., required = true)]
+    input: Vec<String>,
+
+    /// output image for a single input, else the folder the images are written to (defaults
+    /// to {stem}_x{scale} next to each input)
+    #[clap(short = 'o', long)]
+    output: Option<String>,
+
+    /// realesrgan model family, upscaling with its weights for the selected scale
+    #[clap(short = 'n', long, value_parser = model_validation, default_value = models::DEFAULT_FAMILY)]
+    model: String,
+
+    /// upscale ratio (2, 3, 4)
+    #[clap(short = 's', long, value_parser = clap::value_parser!(u8).range(2..5), default_value_t = 2)]
+    scale: u8,
+
+    /// format of the generated outputs (png, jpg, webp), the input's by default
+    #[clap(long, value_parser = ["png", "jpg", "webp"])]
+    format: Option<String>,
+
+    /// realesrgan tile size (>= 32, its default when not given)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(32..))]
+    tile: Option<u32>,
+
+    /// vulkan device used by realesrgan, see `reve gpus` (auto when not given)
+    #[clap(long, visible_alias = "gpu", value_name = "ID")]
+    gpu_id: Option<u32>,
+
+    /// replace outputs that already exist instead of skipping their input
+    #[clap(long)]
+    overwrite: bool,
+
+    /// verbosity of reve.log, written next to the temp folder
+    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+}
+
+pub fn run(args: ImageArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, _) = ConfigData::load_with_file(&exe_dir)?;
+    let binaries = config.binaries();
+    let work_dir = config.work_dir(&exe_dir)?;
+    logging::init(
+        &work_dir.join("reve.log"),
+        args.log_level,
+        ProgressFormat::Bar,
+    )?;
+
+    let inputs = still::expand_inputs(&args.input)?;
+    let single = inputs.len() == 1 && args.input.len() == 1;
+    let output = args.output.as_ref().map(absolute_path);
+    if let Some(output) = &output {
+        if single && !still::is_supported(Path::new(output)) {
+            return Err(ReveError::InvalidArgument(String::from(
+                "valid output formats: png/jpg/webp",
+            )));
+        }
+        if !single && !Path::new(output).is_dir() {
+            return Err(ReveError::InvalidArgument(format!(
+                "output folder {:?} not found",
+                output
+            )));
+        }
+    }
+
+    for (i, input) in inputs.iter().enumerate() {
+        let output_path = still::output_path(
+            input,
+            output.as_deref().map(Path::new),
+            single,
+            args.scale,
+            args.format.as_deref(),
+        );
+        if output_path.exists() && !args.overwrite {
+            info!(
+                "skipped {}: {} exists",
+                input.display(),
+                output_path.display()
+            );
+            continue;
+        }
+        info!(
+            "[{}/{}] upscaling {} to {}",
+            i + 1,
+            inputs.len(),
+            input.display(),
+            output_path.display()
+        );
+        still::upscale_image(
+            &binaries,
+            input,
+            &output_path,
+            &args.model,
+            args.scale,
+            args.tile,
+            args.gpu_id,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! `reve init`: asks for the settings of every run and writes them to `reve.toml`.
+
+use clap::{Parser, ValueEnum};
+use colored::Colorize;
+use dialoguer::{Confirm, Input, Select};
+use reve_shared::config::ConfigData;
+use reve_shared::encoder::{self, Codec};
+use reve_shared::toml_config::TomlConfig;
+use reve_shared::{gpu, models, ReveError};
+use std::env;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve init",
+    bin_name = "reve init",
+    about = "choose the gpu, codec, model, temp folder and crf of every run, written to reve.toml"
+)]
+pub struct InitArgs {
+    /// write reve.toml next to the executable instead of the configuration directory
+    #[clap(long)]
+    portable: bool,
+}
+
+pub fn run(args: InitArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, file) = ConfigData::load_with_file(&exe_dir)?;
+    let binaries = config.binaries();
+    let mut file = match (file, args.portable) {
+        (Some(file), false) => file,
+        (Some(file), true) if file.path.parent() == Some(exe_dir.as_path()) => file,
+        (_, true) => TomlConfig::new(exe_dir.join(reve_shared::toml_config::FILE_NAME)),
+        (None, false) => TomlConfig::new(TomlConfig::user_path().ok_or_else(|| {
+            ReveError::InvalidArgument(String::from("no configuration directory, use --portable"))
+        })?),
+    };
+    if file.path.is_file()
+        && !Confirm::new()
+            .with_prompt(format!("replace the settings of {}?", file.path.display()))
+            .default(true)
+            .interact()?
+    {
+        return Ok(());
+    }
+
+    // Laptops may get the integrated gpu by default
+    if let Ok(gpus) = gpu::list(&binaries) {
+        for gpu in gpus {
+            println!("{}  {}", gpu.id.to_string().green(), gpu.name);
+        }
+    }
+    let gpu_id: String = Input::new()
+        .with_prompt("vulkan device of realesrgan (empty for its default)")
+        .allow_empty(true)
+        .with_initial_text(config.gpu_id.map(|id| id.to_string()).unwrap_or_default())
+        .validate_with(|id: &String| {
+            if id.is_empty() || id.parse::<u32>().is_ok() {
+                Ok(())
+            } else {
+                Err("expected the number of the device")
+            }
+        })
+        .interact_text()?;
+    file.set("gpu-id", gpu_id.parse::<u32>().ok().map(i64::from));
+
+    let codecs = Codec::value_variants();
+    let codec = loop {
+        let names: Vec<String> = codecs.iter().map(value_name).collect();
+        let index = Select::new()
+            .with_prompt("codec")
+            .items(&names)
+            .default(
+                codecs
+                    .iter()
+                    .position(|codec| *codec == config.codec)
+                    .unwrap_or(0),
+            )
+            .interact()?;
+        match encoder::check_encoder(&binaries, codecs[index]) {
+            Ok(()) => break codecs[index],
+            Err(err @ ReveError::MissingBinary(_)) => {
+                println!("{} {}, the codec is not checked", "warning:".yellow(), err);
+                break codecs[index];
+            }
+            Err(err) => println!("{} {}", "error:".bright_red(), err),
+        }
+    };
+    file.set("codec", Some(value_name(&codec)));
+
+    let families = models::families();
+    let index = Select::new()
+        .with_prompt("model")
+        .items(&families)
+        .default(
+            families
+                .iter()
+                .position(|family| *family == config.model)
+                .unwrap_or(0),
+        )
+        .interact()?;
+    let model = families[index];
+    file.set("model", Some(model));
+    // Families with only x4 weights cannot upscale by the default scale
+    let scales = models::scales(model);
+    file.set(
+        "scale",
+        (!scales.contains(&2)).then(|| i64::from(scales[0])),
+    );
+
+    let temp_dir: String = Input::new()
+        .with_prompt("folder holding the temp folder (empty for next to reve)")
+        .allow_empty(true)
+        .with_initial_text(config.temp_dir.clone().unwrap_or_default())
+        .validate_with(|dir: &String| {
+            if dir.is_empty() || Path::new(dir).is_dir() {
+                Ok(())
+            } else {
+                Err("folder not found")
+            }
+        })
+        .interact_text()?;
+    file.set("temp-dir", (!temp_dir.is_empty()).then_some(temp_dir));
+
+    let crf: u8 = Input::new()
+        .with_prompt("video constant rate factor (51-0)")
+        .default(config.crf)
+        .validate_with(|crf: &u8| {
+            if *crf <= 51 {
+                Ok(())
+            } else {
+                Err("crf must be 51 or less")
+            }
+        })
+        .interact_text()?;
+    file.set("crf", Some(i64::from(crf)));
+
+    file.save()?;
+    println!("settings written to {}", file.path.display());
+
+    match binaries.check() {
+        Ok(version) => println!("{} {}", "ok:".green(), version),
+        Err(err) => println!(
+            "{} {}\nrun {} to install the tools",
+            "warning:".yellow(),
+            err,
+            "reve setup".green()
+        ),
+    }
+    Ok(())
+}
+
+fn value_name(value: &impl ValueEnum) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
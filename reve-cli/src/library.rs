@@ -0,0 +1,239 @@
+//! `reve library`: lists the videos of the Jellyfin or Plex server of `reve.toml` below a
+//! resolution and queues them for `reve daemon`.
+//!
+//! The paths are the ones of the server, so reve should run on it or see its files at the
+//! same paths.
+
+use crate::absolute_path;
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use reve_shared::config::ConfigData;
+use reve_shared::library::{self, LibraryItem, PlexSection};
+use reve_shared::toml_config::{Library, MediaServer, TomlConfig};
+use reve_shared::{state, ReveError};
+use std::env;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve library",
+    bin_name = "reve library",
+    about = "queue the videos of the Jellyfin or Plex library of reve.toml below a resolution"
+)]
+pub struct LibraryArgs {
+    #[clap(subcommand)]
+    command: LibraryCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum LibraryCommand {
+    /// list the videos below the height
+    List {
+        #[clap(flatten)]
+        filter: Filter,
+    },
+    /// add the videos below the height to the queue of reve daemon
+    Queue {
+        #[clap(flatten)]
+        filter: Filter,
+
+        /// folder of the outputs (next to the inputs by default)
+        #[clap(long)]
+        output_dir: Option<String>,
+
+        /// named or device profile the videos are upscaled with
+        #[clap(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct Filter {
+    /// height in lines the videos are below
+    #[clap(long, default_value_t = 1080)]
+    below: u32,
+}
+
+pub fn run(args: LibraryArgs) -> Result<(), ReveError> {
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, file) = ConfigData::load_with_file(&exe_dir)?;
+    let library = settings(file.as_ref())?.ok_or_else(|| {
+        ReveError::InvalidArgument(String::from("reve.toml has no [library] table"))
+    })?;
+    match args.command {
+        LibraryCommand::List { filter } => {
+            for item in below(&library, filter.below)? {
+                println!(
+                    "{}  {}  {}",
+                    format!("{:>5}p", item.height).yellow(),
+                    item.title,
+                    item.path
+                );
+            }
+        }
+        LibraryCommand::Queue {
+            filter,
+            output_dir,
+            profile,
+        } => {
+            let items = below(&library, filter.below)?;
+            let output_dir = output_dir.map(absolute_path);
+            // The queue is the one of the daemon, in the reve.db of the work dir
+            env::set_current_dir(config.work_dir(&exe_dir)?)?;
+            let mut queued = 0;
+            for item in items {
+                if state::enqueue(&item.path, output_dir.as_deref(), profile.as_deref())? {
+                    println!("queued {}", item.path);
+                    queued += 1;
+                }
+            }
+            println!(
+                "{}",
+                format!("{} videos queued for reve daemon", queued).green()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns the `[library]` table of `file`.
+pub fn settings(file: Option<&TomlConfig>) -> Result<Option<Library>, ReveError> {
+    Ok(file.map(TomlConfig::library).transpose()?.flatten())
+}
+
+/// Asks the server to scan its libraries, so an output of `reve daemon` shows up in them.
+pub fn rescan(library: &Library) -> Result<(), ReveError> {
+    match library.server {
+        MediaServer::Jellyfin => {
+            let url = library.jellyfin_refresh_url();
+            ureq::post(&url)
+                .timeout(Duration::from_secs(30))
+                .set(library.token_header(), &library.token)
+                .call()
+                .map_err(|err| ReveError::Library(format!("{}: {}", url, err)))?;
+        }
+        MediaServer::Plex => {
+            for section in plex_sections(library)? {
+                get(library, &library.plex_refresh_url(&section))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the videos of the library analyzed by the server whose height is below `height`.
+fn below(library: &Library, height: u32) -> Result<Vec<LibraryItem>, ReveError> {
+    Ok(keep_below(items(library)?, height))
+}
+
+/// Returns the `items` whose height is below `height`, without the ones not analyzed yet.
+fn keep_below(mut items: Vec<LibraryItem>, height: u32) -> Vec<LibraryItem> {
+    items.retain(|item| item.height > 0 && item.height < height);
+    items
+}
+
+fn items(library: &Library) -> Result<Vec<LibraryItem>, ReveError> {
+    let mut items = Vec::new();
+    match library.server {
+        MediaServer::Jellyfin => {
+            let parents: Vec<Option<&str>> = if library.sections.is_empty() {
+                vec![None]
+            } else {
+                library
+                    .sections
+                    .iter()
+                    .map(|id| Some(id.as_str()))
+                    .collect()
+            };
+            for parent in parents {
+                let answer = get(library, &library.jellyfin_items_url(parent))?;
+                items.extend(library::parse_jellyfin_items(&answer).map_err(ReveError::Library)?);
+            }
+        }
+        MediaServer::Plex => {
+            for section in plex_sections(library)? {
+                let answer = get(library, &library.plex_items_url(&section))?;
+                items.extend(library::parse_plex_items(&answer).map_err(ReveError::Library)?);
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Returns the movie and show sections of Plex listed in `sections`, all of them when empty.
+fn plex_sections(library: &Library) -> Result<Vec<PlexSection>, ReveError> {
+    let answer = get(library, &library.plex_sections_url())?;
+    let sections = library::parse_plex_sections(&answer).map_err(ReveError::Library)?;
+    Ok(keep_sections(sections, &library.sections))
+}
+
+/// Returns the `sections` whose key is in `wanted`, all of them when it is empty.
+fn keep_sections(mut sections: Vec<PlexSection>, wanted: &[String]) -> Vec<PlexSection> {
+    sections.retain(|section| wanted.is_empty() || wanted.contains(&section.key));
+    sections
+}
+
+fn get(library: &Library, url: &str) -> Result<String, ReveError> {
+    ureq::get(url)
+        .timeout(Duration::from_secs(30))
+        .set(library.token_header(), &library.token)
+        .set("Accept", "application/json")
+        .call()
+        .map_err(|err| ReveError::Library(format!("{}: {}", url, err)))?
+        .into_string()
+        .map_err(|err| ReveError::Library(format!("{}: {}", url, err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_below() {
+        let item = |title: &str, height: u32| LibraryItem {
+            title: title.to_string(),
+            path: format!("/media/{}.mkv", title),
+            height,
+        };
+        let items = vec![
+            item("dvd", 480),
+            item("unanalyzed", 0),
+            item("hd", 720),
+            item("full hd", 1080),
+            item("uhd", 2160),
+        ];
+        let titles = |items: Vec<LibraryItem>| -> Vec<String> {
+            items.into_iter().map(|item| item.title).collect()
+        };
+        assert_eq!(titles(keep_below(items.clone(), 1080)), ["dvd", "hd"]);
+        assert_eq!(titles(keep_below(items, 480)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_keep_sections() {
+        let section = |key: &str, item_type: u8| PlexSection {
+            key: key.to_string(),
+            item_type,
+        };
+        let sections = vec![section("1", 1), section("2", 4), section("5", 1)];
+        assert_eq!(keep_sections(sections.clone(), &[]), sections);
+        assert_eq!(
+            keep_sections(sections, &["5".to_string(), "9".to_string()]),
+            [section("5", 1)]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        #[derive(Parser)]
+        struct Command {
+            #[clap(flatten)]
+            filter: Filter,
+        }
+        assert_eq!(Command::parse_from(["list"]).filter.below, 1080);
+        assert_eq!(
+            Command::parse_from(["list", "--below", "720"]).filter.below,
+            720
+        );
+    }
+}
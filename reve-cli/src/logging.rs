@@ -0,0 +1,52 @@
+use reve_shared::{LogLevel, ProgressFormat, ReveError};
+use std::fs::OpenOptions;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use tracing::Level;
+use tracing_subscriber::filter::{filter_fn, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Off => LevelFilter::OFF,
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    }
+}
+
+/// Appends timestamped events at `level` and above to `log_path`.
+///
+/// With progress bars or plain progress lines, info and warning messages are also printed to
+/// the terminal. Errors are left out there since they are reported by `exit_with_error`.
+pub fn init(log_path: &Path, level: LogLevel, progress: ProgressFormat) -> Result<(), ReveError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let file_layer = fmt::layer()
+        .with_ansi(false)
+        .with_writer(Mutex::new(file))
+        .with_filter(level_filter(level));
+    let console_layer =
+        matches!(progress, ProgressFormat::Bar | ProgressFormat::Plain).then(|| {
+            fmt::layer()
+                .without_time()
+                .with_level(false)
+                .with_target(false)
+                .with_writer(io::stderr)
+                .with_filter(filter_fn(|metadata| {
+                    matches!(*metadata.level(), Level::INFO | Level::WARN)
+                }))
+        });
+
+    tracing_subscriber::registry()
+        .with(file_layer)
+        .with(console_layer)
+        .init();
+    Ok(())
+}
@@ -1,16 +1,41 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use clearscreen::clear;
 use colored::Colorize;
-use dialoguer::Confirm;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use hooks::{HookEvent, HookInfo};
 use path_clean::PathClean;
+use progress::{emit, CliProgress};
+use reve_shared::config::ConfigData;
+use reve_shared::job::UpscaleJob;
+use reve_shared::toml_config::TomlConfig;
 use reve_shared::*;
 use std::env;
-use std::fs;
-use std::io::BufRead;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::thread;
+use std::time::Instant;
+use tracing::{error, info};
+
+mod arr;
+mod batch;
+mod benchmark;
+mod control;
+mod daemon;
+mod db;
+mod doctor;
+mod gpus;
+mod hooks;
+mod image;
+mod init;
+mod library;
+mod logging;
+mod notify;
+mod profile;
+mod progress;
+mod resume;
+mod setup;
+mod worker;
 
 fn absolute_path(path: impl AsRef<Path>) -> String {
     let path = path.as_ref();
@@ -22,345 +47,381 @@ fn absolute_path(path: impl AsRef<Path>) -> String {
             .expect("could not get current path")
             .join(path)
     }
-        .clean();
+    .clean();
 
-    absolute_path.into_os_string().into_string().unwrap()
+    let absolute_path = absolute_path.into_os_string().into_string().unwrap();
+    paths::native(&absolute_path).into_owned()
 }
 
-fn main() {
-    let current_exe_path = env::current_exe().unwrap();
-
-    let args_path = current_exe_path
-        .parent()
-        .unwrap()
-        .join("temp\\args.temp")
-        .into_os_string()
-        .into_string()
-        .unwrap();
+/// Prints a user-friendly message for `err` and exits.
+fn exit_with_error(err: ReveError) -> ! {
+    error!("{}", err);
+    let hint = match &err {
+        ReveError::MissingBinary(name) => format!(
+            "run {} or make sure {} is installed and available in PATH or next to reve",
+            "reve setup".green(),
+            name
+        ),
+        ReveError::Ffmpeg(_) => String::from("check that the input video is not corrupted"),
+        ReveError::Upscaler(_) => {
+            String::from("try a smaller --tile size or check that the gpu supports vulkan")
+        }
+        ReveError::Probe(_) => String::from("the input file may not contain a video stream"),
+        ReveError::InvalidArgument(_) => format!("for more information try {}", "--help".green()),
+        ReveError::InsufficientSpace { .. } => String::from(
+            "free up space on the temp folder's drive (or the --ramdisk), or try a smaller \
+             --segmentsize",
+        ),
+        ReveError::Interrupted => String::from("run reve again to resume"),
+        ReveError::StaleJob(_) => format!(
+            "run {} to continue it with its options, or pass {} to start over",
+            "reve resume".green(),
+            "--no-resume".green()
+        ),
+        ReveError::Download(_) => format!(
+            "check the connection or pass a local manifest with {}",
+            "--manifest".green()
+        ),
+        ReveError::Worker(_) => String::from(
+            "check the worker's reve.log, then run reve again to publish the job again",
+        ),
+        ReveError::Library(_) => {
+            String::from("check the url and token of the [library] table of reve.toml")
+        }
+        ReveError::Batch(_) => String::from("their errors are in reve.log"),
+        ReveError::Db(_) => format!(
+            "run {} to check reve.db, which another reve may be holding",
+            "reve doctor".green()
+        ),
+        ReveError::Io(_) => String::from(
+            "if temporary files are the problem, try deleting the temp folder manually",
+        ),
+    };
+    eprintln!(
+        "{} {}\n\n{}",
+        "error:".to_string().bright_red(),
+        err,
+        hint.yellow()
+    );
+    std::process::exit(1);
+}
 
-    let mut args;
-    let mut video;
-    if Path::new(&args_path).exists() {
+/// Clears the terminal and prints `message` above the progress bars, only when they are
+/// shown so logs stay readable and json progress stays the only output.
+fn show_banner(progress: ProgressFormat, message: impl fmt::Display) {
+    if progress == ProgressFormat::Bar {
         clear().unwrap();
-        println!("{}", "found existing temporary files.".to_string().red());
-
-        if !Confirm::new()
-            .with_prompt("resume upscaling previous video?")
-            .default(true)
-            .show_default(true)
-            .interact()
-            .unwrap()
-        {
-            if !Confirm::new()
-                .with_prompt("all progress will be lost. do you want to continue?")
-                .default(true)
-                .show_default(true)
-                .interact()
-                .unwrap()
-            {
-                // Abort remove
-                std::process::exit(1);
-            }
+        println!("{}", message);
+    }
+}
 
-            // Remove and start new
-            args = Args::parse();
-            args.inputpath = absolute_path(PathBuf::from_str(&args.inputpath).unwrap());
-            println!("{} loaded", args.inputpath);
-            args.outputpath = absolute_path(PathBuf::from_str(&args.outputpath).unwrap());
+/// Exits without error if `--skip-upscaled` is set and the input was upscaled by reve.
+fn skip_if_upscaled(args: &Args) {
+    if let Some(tag) = args
+        .upscaled_by_reve()
+        .unwrap_or_else(|err| exit_with_error(err))
+    {
+        info!(
+            "skipped {}: already upscaled by reve ({})",
+            args.inputpath, tag
+        );
+        std::process::exit(0);
+    }
+}
 
-            env::set_current_dir(current_exe_path.parent().unwrap()).unwrap();
-            rebuild_temp(false);
+/// reve's commands, the options of `upscale` being taken without one.
+#[derive(Parser)]
+#[clap(
+    name = "reve",
+    bin_name = "reve",
+    author = "ONdraid <ondraid.png@gmail.com>",
+    about = "Real-ESRGAN video upscaler with resumability",
+    long_about = None,
+    after_help = "reve -i <INPUTPATH> [OPTIONS] upscales without naming the command, as before \
+                  commands existed"
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: CliCommand,
+}
 
-            let serialized_args = serde_json::to_string(&args).unwrap();
-            fs::write(&args_path, serialized_args).expect("Unable to write file");
-            video = Video::new(
-                &args.inputpath,
-                &args.outputpath,
-                args.segmentsize,
-                args.scale,
-            );
-            let serialized_video = serde_json::to_string(&video).unwrap();
-            fs::write("temp\\video.temp", serialized_video).unwrap();
-            clear().unwrap();
-            println!(
-                "{}",
-                "deleted all temporary files, parsing console input"
-                    .to_string()
-                    .green()
-            );
-        } else {
-            // Resume upscale
-            env::set_current_dir(current_exe_path.parent().unwrap()).unwrap();
-            let args_json = fs::read_to_string(&args_path).unwrap();
-            args = serde_json::from_str(&args_json).unwrap();
-            let video_json = fs::read_to_string("temp\\video.temp").unwrap();
-            video = serde_json::from_str(&video_json).unwrap();
+#[derive(Subcommand)]
+enum CliCommand {
+    /// upscale a video, the command of options given without one
+    Upscale(Box<Args>),
+    Batch(batch::BatchArgs),
+    Benchmark(benchmark::BenchmarkArgs),
+    Resume(resume::ResumeArgs),
+    Image(image::ImageArgs),
+    Init(init::InitArgs),
+    Gpus(gpus::GpusArgs),
+    Profile(profile::ProfileArgs),
+    Setup(setup::SetupArgs),
+    Worker(worker::WorkerArgs),
+    Daemon(daemon::DaemonArgs),
+    Library(library::LibraryArgs),
+    Arr(arr::ArrArgs),
+    Db(db::DbArgs),
+    Doctor(doctor::DoctorArgs),
+}
 
-            rebuild_temp(true);
-            clear().unwrap();
-            println!("{}", "resuming upscale".to_string().green());
+/// Returns `argv` with the command of a command line without one: `upscale` for options, as
+/// before commands existed, and `arr` for Sonarr and Radarr, which run their custom scripts
+/// without arguments.
+fn with_command(mut argv: Vec<OsString>) -> Vec<OsString> {
+    match argv.get(1).and_then(|arg| arg.to_str()) {
+        None if arr::is_custom_script() => argv.push(OsString::from("arr")),
+        Some(arg) if arg.starts_with('-') && arg != "-h" && arg != "--help" => {
+            argv.insert(1, OsString::from("upscale"))
         }
-    } else {
-        // Start new
-        args = Args::parse();
-        args.inputpath = absolute_path(PathBuf::from_str(&args.inputpath).unwrap());
-        println!("{} loaded", args.inputpath);
-        args.outputpath = absolute_path(PathBuf::from_str(&args.outputpath).unwrap());
-        env::set_current_dir(current_exe_path.parent().unwrap()).unwrap();
-
-        rebuild_temp(false);
-        let serialized_args = serde_json::to_string(&args).unwrap();
-        fs::write(&args_path, serialized_args).expect("Unable to write file");
-        video = Video::new(
-            &args.inputpath,
-            &args.outputpath,
-            args.segmentsize,
-            args.scale,
-        );
-        let serialized_video = serde_json::to_string(&video).unwrap();
-        fs::write("temp\\video.temp", serialized_video).unwrap();
+        _ => {}
     }
+    argv
+}
 
-    // Validation
-    {
-        let in_extension = Path::new(&args.inputpath).extension().unwrap();
-        let out_extension = Path::new(&args.outputpath).extension().unwrap();
+fn main() {
+    let mut argv = with_command(env::args_os().collect());
+    let exe_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let load_config =
+        || ConfigData::load_with_file(&exe_dir).unwrap_or_else(|err| exit_with_error(err));
+
+    // reve.toml and its named profiles give the defaults of the options of an upscale, so
+    // they are set on its command before parsing
+    let mut command = Cli::command();
+    let mut upscale_config = None;
+    if argv.get(1).is_some_and(|arg| arg == "upscale") {
+        let (config, file) = load_config();
+        let upscale_command = command.find_subcommand("upscale").unwrap().clone();
+        let (upscale_command, file_ids) =
+            ConfigData::upscale_command(upscale_command, &mut argv, file.as_ref())
+                .unwrap_or_else(|err| exit_with_error(err));
+        command = command.mut_subcommand("upscale", |_| upscale_command);
+        upscale_config = Some((config, file, file_ids));
+    }
+    let matches = command.get_matches_from(argv);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    let result = match cli.command {
+        CliCommand::Upscale(mut args) => {
+            let (config, file, file_ids) = upscale_config.unwrap();
+            let (_, upscale_matches) = matches.subcommand().unwrap();
+            config.complete_args(&mut args, upscale_matches, &file_ids);
+            upscale(*args, false, &config, file.as_ref(), &exe_dir);
+            return;
+        }
+        CliCommand::Resume(resume_args) => {
+            let (config, file) = load_config();
+            let work_dir = config
+                .work_dir(&exe_dir)
+                .unwrap_or_else(|err| exit_with_error(err));
+            let args = resume::saved_args(resume_args, &work_dir)
+                .unwrap_or_else(|err| exit_with_error(err));
+            upscale(args, true, &config, file.as_ref(), &exe_dir);
+            return;
+        }
+        CliCommand::Batch(args) => batch::run(args),
+        CliCommand::Benchmark(args) => benchmark::run(args),
+        CliCommand::Image(args) => image::run(args),
+        CliCommand::Init(args) => init::run(args),
+        CliCommand::Gpus(args) => gpus::run(args),
+        CliCommand::Profile(args) => profile::run(args),
+        CliCommand::Setup(args) => setup::run(args),
+        CliCommand::Worker(args) => worker::run(args),
+        CliCommand::Daemon(args) => daemon::run(args),
+        CliCommand::Library(args) => library::run(args),
+        CliCommand::Arr(args) => arr::run(args),
+        CliCommand::Db(args) => db::run(args),
+        CliCommand::Doctor(args) => doctor::run(args),
+    };
+    result.unwrap_or_else(|err| exit_with_error(err));
+}
 
-        if in_extension == "mkv" && out_extension != "mkv" {
-            clear().unwrap();
-            println!(
-                "{} Invalid value {} for '{}': mkv file can only be exported as mkv file\n\nFor more information try {}",
-                "error:".to_string().bright_red(),
-                format!("\"{}\"", args.inputpath).yellow(),
-                "--outputpath <OUTPUTPATH>".to_string().yellow(),
-                "--help".to_string().green()
-            );
-            std::process::exit(1);
+/// Runs the upscale of `args`, `resume_command` when `reve resume` took them from the saved
+/// state.
+fn upscale(
+    mut args: Args,
+    resume_command: bool,
+    config: &ConfigData,
+    file: Option<&TomlConfig>,
+    exe_dir: &Path,
+) {
+    let work_dir = config
+        .work_dir(exe_dir)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let log_path = work_dir.join("reve.log");
+    args.resolve_progress(io::stdout().is_terminal());
+    logging::init(&log_path, args.log_level, args.progress)
+        .unwrap_or_else(|err| exit_with_error(err));
+    args.inputpath = absolute_path(PathBuf::from_str(&args.inputpath).unwrap());
+    info!("{} loaded", args.inputpath);
+    args.share = args.share.as_ref().map(absolute_path);
+    args.validate().unwrap_or_else(|err| exit_with_error(err));
+    skip_if_upscaled(&args);
+    let output_path = absolute_path(
+        args.resolve_output_path()
+            .unwrap_or_else(|err| exit_with_error(err)),
+    );
+    args.outputpath = Some(output_path);
+
+    // Every input and output pair gets its own temp folder, so instances upscaling other
+    // videos do not touch it
+    env::set_current_dir(&work_dir).unwrap();
+    paths::set_job(&paths::job_id(
+        &args.inputpath,
+        args.outputpath.as_deref().unwrap(),
+    ));
+    paths::set_frames_dir(args.ramdisk.as_deref());
+    let saved = if resume_command || args.no_resume {
+        None
+    } else {
+        state::load_args().unwrap_or_else(|err| {
+            exit_with_error(ReveError::StaleJob(format!(
+                "its options could not be read ({})",
+                err
+            )))
+        })
+    };
+
+    let resume = if resume_command {
+        true
+    } else if let Some(saved) = saved {
+        // Resuming with other options would mix the segments of both runs in the output
+        let changes = saved.changed_options(&args);
+        if !changes.is_empty() {
+            let changes: Vec<String> = changes
+                .into_iter()
+                .map(|(option, saved, given)| format!("  {}: {} -> {}", option, saved, given))
+                .collect();
+            exit_with_error(ReveError::StaleJob(format!(
+                "options changed since it started (saved -> given):\n{}",
+                changes.join("\n")
+            )));
         }
-    }
+        info!("found temporary files of the same upscale, resuming it");
+        true
+    } else {
+        if args.no_resume {
+            info!("--no-resume given, a previous run of the same upscale is discarded");
+        }
+        false
+    };
 
-    if video.segments.is_empty() {
-        video.segments.push(Segment {
-            index: video.segment_count - 1,
-            size: get_last_segment_size(video.frame_count, args.segmentsize),
-        });
-    } else if video.segments[0].index > 0 {
-        video.segments.insert(
-            0,
-            Segment {
-                index: video.segments[0].index - 1,
-                size: args.segmentsize,
-            },
-        );
+    let mut job: UpscaleJob;
+    if resume {
+        // The output options are the saved ones, `reve resume` took them from the saved state
+        // and a run with others was refused above
+        job = UpscaleJob::resume(args.clone()).unwrap_or_else(|err| exit_with_error(err));
+
+        rebuild_temp(true).unwrap_or_else(|err| exit_with_error(err));
+        show_banner(args.progress, "resuming upscale".to_string().green());
+    } else {
+        // Start new
+        rebuild_temp(false).unwrap_or_else(|err| exit_with_error(err));
+        job = UpscaleJob::new(args.clone()).unwrap_or_else(|err| exit_with_error(err));
     }
-    let _ = fs::remove_file(format!(
-        "temp\\video_parts\\{}.mp4",
-        video.segments[0].index
-    ));
 
-    clear().unwrap();
-    println!(
-        "{}",
+    show_banner(
+        args.progress,
         format!(
-            "total segments: {}, last segment size: {} (ctrl+c to exit)",
-            video.segment_count,
-            video.segments.last().unwrap().size
+            "total segments: {}, last segment size: {} (p + enter to pause, ctrl+c to exit)",
+            job.video().segment_count,
+            job.video().segment_frames(job.video().segment_count - 1)
         )
-            .red()
+        .red(),
     );
 
-    {
-        let mut export_handle = thread::spawn(move || {});
-        let mut merge_handle = thread::spawn(move || {});
-        let mut remove_handle = thread::spawn(move || {});
-        let info_style = "[info][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} processed segments       eta: {eta:<7}";
-        let expo_style = "[expo][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} exporting segment        {per_sec:<12}";
-        let upsc_style = "[upsc][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} upscaling segment        {per_sec:<12}";
-        let merg_style = "[merg][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} merging segment          {per_sec:<12}";
-
-        let m = MultiProgress::new();
-        let pb = m.add(ProgressBar::new(video.segment_count as u64));
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template(info_style)
-                .unwrap()
-                .progress_chars("#>-"),
-        );
-        let mut last_pb = pb.clone();
-
-        // Initial export
-        if !video.segments.is_empty() {
-            let index = video.segments[0].index;
-
-            let progress_bar =
-                m.insert_after(&last_pb, ProgressBar::new(video.segments[0].size as u64));
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template(expo_style)
-                    .unwrap()
-                    .progress_chars("#>-"),
+    let progress = CliProgress::new(args.progress, job.video());
+    control::listen(&job.control());
+    let started = Instant::now();
+    let result = job.run(&progress);
+    progress.clear();
+
+    let hook_info = |event, status, error| HookInfo {
+        event,
+        input: &args.inputpath,
+        output: &job.video().output_path,
+        duration: started.elapsed().as_secs_f64(),
+        status,
+        error,
+    };
+    match &result {
+        Ok(()) => {
+            hooks::run(
+                args.on_file_done.as_deref(),
+                &hook_info(HookEvent::FileDone, 0, None),
             );
-            last_pb = progress_bar.clone();
-
-            let reader = video.export_segment(index as usize).unwrap();
-            let mut count: i32 = -1;
-            reader
-                .lines()
-                .filter_map(|line| line.ok())
-                .filter(|line| line.contains("AVIOContext"))
-                .for_each(|_| {
-                    count += 1;
-                    progress_bar.set_position(count as u64);
-                });
-            m.clear().unwrap();
+            let info = hook_info(HookEvent::Complete, 0, None);
+            hooks::run(args.on_complete.as_deref(), &info);
+            notify::send(&args.notify, file, &info);
         }
+        Err(ReveError::Interrupted) => {
+            let info = hook_info(HookEvent::Error, 130, None);
+            hooks::run(args.on_error.as_deref(), &info);
+            notify::send(&args.notify, file, &info);
+        }
+        Err(err) => {
+            let info = hook_info(HookEvent::Error, 1, Some(err.to_string()));
+            hooks::run(args.on_error.as_deref(), &info);
+            notify::send(&args.notify, file, &info);
+        }
+    }
 
-        for _ in 0..video.segments.len() {
-            export_handle.join().unwrap();
-            if video.segments.len() == 1 {
-                export_handle = thread::spawn(move || {});
-            } else {
-                let index = video.segments[1].index;
-
-                let progress_bar =
-                    m.insert_after(&last_pb, ProgressBar::new(video.segments[1].size as u64));
-                progress_bar.set_style(
-                    ProgressStyle::default_bar()
-                        .template(expo_style)
-                        .unwrap()
-                        .progress_chars("#>-"),
-                );
-                last_pb = progress_bar.clone();
-
-                let reader = video.export_segment(index as usize).unwrap();
-                export_handle = thread::spawn(move || {
-                    let mut count: i32 = -1;
-                    reader
-                        .lines()
-                        .filter_map(|line| line.ok())
-                        .filter(|line| line.contains("AVIOContext"))
-                        .for_each(|_| {
-                            count += 1;
-                            progress_bar.set_position(count as u64);
-                        });
-                });
-            }
-
-            let input_directory = format!("temp\\tmp_frames\\{}", video.segments[0].index);
-
-            {
-                let progress_bar =
-                    m.insert_after(&last_pb, ProgressBar::new(video.segments[0].size as u64));
-                progress_bar.set_style(
-                    ProgressStyle::default_bar()
-                        .template(upsc_style)
-                        .unwrap()
-                        .progress_chars("#>-"),
-                );
-                last_pb = progress_bar.clone();
-
-                let reader = video
-                    .upscale_segment(video.segments[0].index as usize)
-                    .unwrap();
-                let mut count = 0;
-                reader
-                    .lines()
-                    .filter_map(|line| line.ok())
-                    .filter(|line| line.contains("done"))
-                    .for_each(|_| {
-                        count += 1;
-                        progress_bar.set_position(count);
-                    });
+    match result {
+        Ok(()) => {}
+        Err(ReveError::Interrupted) => {
+            if args.progress == ProgressFormat::Json {
+                emit(&ProgressEvent::Stopped);
             }
-
-            thread::spawn(move || {
-                fs::remove_dir_all(&input_directory).unwrap();
-            });
-
-            merge_handle.join().unwrap();
-            let path_to_remove =
-                format!("temp\\out_frames\\{}", video.segments[0].index as i32 - 1);
-            remove_handle = thread::spawn(move || {
-                let _ = fs::remove_dir_all(&path_to_remove);
-            });
-
-            let progress_bar =
-                m.insert_after(&last_pb, ProgressBar::new(video.segments[0].size as u64));
-            progress_bar.set_style(
-                ProgressStyle::default_bar()
-                    .template(merg_style)
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            last_pb = progress_bar.clone();
-
-            let input = format!(
-                "temp\\out_frames\\{}\\frame%08d.png",
-                video.segments[0].index
+            // stdout only holds the json events with --progress json
+            eprintln!(
+                "{}",
+                "upscale stopped, it is safe to resume by running reve again"
+                    .to_string()
+                    .yellow()
             );
-            let output = format!("temp\\video_parts\\{}.mp4", video.segments[0].index);
-            let frame_rate = format!("{}/1", video.frame_rate);
-            let crf = args.crf.to_string();
-
-            // TODO: move this away
-            let args = vec![
-                "-v",
-                "verbose",
-                "-f",
-                "image2",
-                "-framerate",
-                &frame_rate,
-                "-i",
-                &input,
-                "-c:v",
-                "libx265",
-                "-pix_fmt",
-                "yuv420p10le",
-                "-crf",
-                &crf,
-                "-preset",
-                &args.preset,
-                "-x265-params",
-                &args.x265params,
-                &output,
-            ];
-
-            let reader = video.merge_segment(args).unwrap();
-            merge_handle = thread::spawn(move || {
-                let mut count = 0;
-                reader
-                    .lines()
-                    .filter_map(|line| line.ok())
-                    .filter(|line| line.contains("AVIOContext"))
-                    .for_each(|_| {
-                        count += 1;
-                        progress_bar.set_position(count);
-                    });
-            });
-            video.segments.remove(0);
-
-            let serialized_video = serde_json::to_string(&video).unwrap();
-            fs::write("temp\\video.temp", serialized_video).unwrap();
-            pb.set_position((video.segment_count - video.segments.len() as u32 - 1) as u64);
+            std::process::exit(130);
         }
-        merge_handle.join().unwrap();
-        remove_handle.join().unwrap();
+        Err(err) => exit_with_error(err),
+    }
 
-        m.clear().unwrap();
+    if args.progress == ProgressFormat::Json {
+        emit(&ProgressEvent::Done {
+            output: job.video().output_path.clone(),
+        });
     }
+}
 
-    println!("merging video segments");
-    video.concatenate_segments();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Validation
-    {
-        let p = Path::new(&args.outputpath);
-        if p.exists() && fs::File::open(p).unwrap().metadata().unwrap().len() != 0 {
-            rebuild_temp(false);
-        } else {
-            panic!("final file validation error: try running again")
+    #[test]
+    fn test_with_command() {
+        let argv = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            with_command(argv(&["reve", "-i", "movie.mkv", "-s", "2"])),
+            argv(&["reve", "upscale", "-i", "movie.mkv", "-s", "2"])
+        );
+        assert_eq!(
+            with_command(argv(&["reve", "--inputpath", "movie.mkv"])),
+            argv(&["reve", "upscale", "--inputpath", "movie.mkv"])
+        );
+        for args in [
+            &["reve", "batch", "videos"][..],
+            &["reve", "--help"],
+            &["reve", "-h"],
+            &["reve", "upscal", "-i", "movie.mkv"],
+        ] {
+            assert_eq!(with_command(argv(args)), argv(args));
+        }
+        if !arr::is_custom_script() {
+            assert_eq!(with_command(argv(&["reve"])), argv(&["reve"]));
         }
-    }
 
-    println!("done!");
+        let cli = Cli::try_parse_from(with_command(argv(&["reve", "-i", "assets/test.mp4"])));
+        assert!(matches!(cli.unwrap().command, CliCommand::Upscale(args) if args.scale == 2));
+        // A mistyped command is not taken for an upscale
+        assert!(Cli::try_parse_from(with_command(argv(&["reve", "upscal"]))).is_err());
+    }
 }
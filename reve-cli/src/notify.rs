@@ -0,0 +1,148 @@
+//! `--notify` desktop and mail notifications, sent when the job completes or fails.
+//!
+//! Mails are sent with the `[smtp]` table of `reve.toml`. Like hooks, a notification that
+//! cannot be sent is logged and never fails the job.
+
+use crate::hooks::{HookEvent, HookInfo};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use reve_shared::toml_config::{SmtpSecurity, TomlConfig};
+use reve_shared::Notify;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Sends `info` to every target of `--notify`.
+pub fn send(targets: &[Notify], file: Option<&TomlConfig>, info: &HookInfo) {
+    for target in targets {
+        let result = match target {
+            Notify::Desktop => desktop(info),
+            Notify::Email(address) => email(address, file, info),
+        };
+        match result {
+            Ok(()) => info!("sent the {:?} notification", target),
+            Err(err) => warn!("could not send the {:?} notification: {}", target, err),
+        }
+    }
+}
+
+fn title(info: &HookInfo) -> &'static str {
+    match info.event {
+        HookEvent::Error if info.status == 130 => "reve stopped",
+        HookEvent::Error => "reve failed",
+        _ => "reve finished",
+    }
+}
+
+fn desktop(info: &HookInfo) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .appname("reve")
+        .summary(title(info))
+        .body(&info.message())
+        .show()
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+fn email(address: &str, file: Option<&TomlConfig>, info: &HookInfo) -> Result<(), String> {
+    let smtp = file
+        .map(TomlConfig::smtp)
+        .transpose()
+        .map_err(|err| err.to_string())?
+        .flatten()
+        .ok_or("reve.toml has no [smtp] table")?;
+    let from = smtp
+        .from
+        .as_deref()
+        .or(smtp.username.as_deref())
+        .ok_or("[smtp] needs a from or username")?;
+
+    let message = Message::builder()
+        .from(
+            from.parse::<Mailbox>()
+                .map_err(|err| format!("invalid sender {:?}: {}", from, err))?,
+        )
+        .to(address
+            .parse::<Mailbox>()
+            .map_err(|err| format!("invalid address {:?}: {}", address, err))?)
+        .subject(format!("{}: {}", title(info), info.input))
+        .body(body(info))
+        .map_err(|err| err.to_string())?;
+
+    let mut transport = match smtp.security {
+        SmtpSecurity::Tls => SmtpTransport::relay(&smtp.host),
+        SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&smtp.host),
+        SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(&smtp.host)),
+    }
+    .map_err(|err| err.to_string())?
+    .timeout(Some(Duration::from_secs(30)));
+    if let Some(port) = smtp.port {
+        transport = transport.port(port);
+    }
+    if let (Some(username), Some(password)) = (smtp.username, smtp.password) {
+        transport = transport.credentials(Credentials::new(username, password));
+    }
+    transport
+        .build()
+        .send(&message)
+        .map_err(|err| err.to_string())?;
+    Ok(())
+}
+
+/// Returns the text of the mail of `info`.
+fn body(info: &HookInfo) -> String {
+    let mut body = format!(
+        "{}\n\ninput: {}\noutput: {}\nduration: {:.0}s\n",
+        info.message(),
+        info.input,
+        info.output,
+        info.duration
+    );
+    if let Some(error) = &info.error {
+        body.push_str(&format!("\nerror:\n{}\n", error));
+    }
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(event: HookEvent, status: i32) -> HookInfo<'static> {
+        HookInfo {
+            event,
+            input: "movie.mkv",
+            output: "movie_2x.mkv",
+            duration: 60.0,
+            status,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_title() {
+        assert_eq!(title(&info(HookEvent::Complete, 0)), "reve finished");
+        assert_eq!(title(&info(HookEvent::FileDone, 0)), "reve finished");
+        assert_eq!(title(&info(HookEvent::Error, 1)), "reve failed");
+        assert_eq!(title(&info(HookEvent::Error, 130)), "reve stopped");
+    }
+
+    #[test]
+    fn test_email() {
+        assert_eq!(
+            body(&info(HookEvent::Complete, 0)),
+            "reve finished movie_2x.mkv in 60s\n\ninput: movie.mkv\noutput: movie_2x.mkv\n\
+             duration: 60s\n"
+        );
+        let failed = HookInfo {
+            error: Some(String::from("no space left on device")),
+            ..info(HookEvent::Error, 1)
+        };
+        assert!(body(&failed).ends_with("\nerror:\nno space left on device\n"));
+        // Without reve.toml nothing is sent
+        assert_eq!(
+            email("me@example.com", None, &failed).unwrap_err(),
+            "reve.toml has no [smtp] table"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! `reve profile`: lists the device profiles and the named profiles of `reve.toml`.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use reve_shared::encoder::Profile;
+use reve_shared::toml_config::TomlConfig;
+use reve_shared::ReveError;
+use std::env;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve profile",
+    bin_name = "reve profile",
+    about = "list the profiles --profile selects"
+)]
+pub struct ProfileArgs {
+    #[clap(subcommand)]
+    command: ProfileCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ProfileCommand {
+    /// list the device profiles and the profiles of reve.toml
+    List,
+    /// print the options a profile sets
+    Show {
+        /// name of the profile
+        name: String,
+    },
+}
+
+pub fn run(args: ProfileArgs) -> Result<(), ReveError> {
+    let file = TomlConfig::load(env::current_exe()?.parent().unwrap())?;
+    match args.command {
+        ProfileCommand::List => {
+            for profile in Profile::value_variants() {
+                let value = profile.to_possible_value().unwrap();
+                println!(
+                    "{}{}",
+                    format!("{:<16}", value.get_name()).green(),
+                    value
+                        .get_help()
+                        .map(|help| help.to_string())
+                        .unwrap_or_default()
+                );
+            }
+            match &file {
+                Some(file) => {
+                    for (name, options) in file.profiles() {
+                        let keys: Vec<&str> = options.keys().map(String::as_str).collect();
+                        println!("{}{}", format!("{:<16}", name).green(), keys.join(", "));
+                    }
+                }
+                None => println!("\nno reve.toml found, named profiles are read from it"),
+            }
+        }
+        ProfileCommand::Show { name } => {
+            if let Some(options) = file.as_ref().and_then(|file| file.show_profile(&name)) {
+                print!("{}", options);
+            } else if let Ok(profile) = Profile::from_str(&name, false) {
+                println!(
+                    "codec = \"{}\"\npix-fmt = \"{}\"",
+                    profile.codec().to_possible_value().unwrap().get_name(),
+                    profile.pix_fmt().to_possible_value().unwrap().get_name()
+                );
+            } else {
+                return Err(ReveError::InvalidArgument(format!(
+                    "unknown profile {:?}, see reve profile list",
+                    name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+use indicatif::{
+    FormattedDuration, HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use reve_shared::job::JobProgress;
+use reve_shared::{disk, paths, ProgressEvent, ProgressFormat, ReveError, Segment, Stage, Video};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Writes a progress event as a single json line to stdout.
+pub fn emit(event: &ProgressEvent) {
+    println!("{}", serde_json::to_string(event).unwrap());
+}
+
+/// Progress of one stage (export, upscale, merge) of a segment.
+///
+/// Drives the bar of the stage and, in json mode, mirrors every update as an event.
+pub struct StageProgress {
+    bar: ProgressBar,
+    format: ProgressFormat,
+    stage: Stage,
+    segment: u32,
+    frames: u32,
+    started: Instant,
+}
+
+impl StageProgress {
+    pub fn new(
+        bar: ProgressBar,
+        format: ProgressFormat,
+        stage: Stage,
+        segment: u32,
+        frames: u32,
+    ) -> Self {
+        bar.reset();
+        bar.set_length(frames as u64);
+        bar.set_message(format!("segment {}", segment));
+        if format == ProgressFormat::Json {
+            emit(&ProgressEvent::SegmentStarted {
+                stage,
+                segment,
+                frames,
+            });
+        }
+        Self {
+            bar,
+            format,
+            stage,
+            segment,
+            frames,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn set_position(&self, pos: u64) {
+        self.bar.set_position(pos);
+        if self.format == ProgressFormat::Json {
+            let elapsed = self.started.elapsed().as_secs_f64();
+            emit(&ProgressEvent::Frames {
+                stage: self.stage,
+                segment: self.segment,
+                done: pos,
+                total: self.frames,
+                fps: if elapsed > 0.0 {
+                    pos as f64 / elapsed
+                } else {
+                    0.0
+                },
+            });
+        }
+    }
+
+    /// Returns the frames per second of the stage so far.
+    pub fn fps(&self) -> f64 {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.bar.position() as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    pub fn finish(&self) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::SegmentFinished {
+                stage: self.stage,
+                segment: self.segment,
+                frames: self.bar.position(),
+                elapsed: self.started.elapsed().as_secs_f64(),
+            });
+        }
+    }
+
+    /// Empties the bar of a failed attempt, the retry starts it again.
+    pub fn abandon(&self) {
+        self.bar.set_position(0);
+    }
+}
+
+/// Figures of the stats line of the dashboard.
+#[derive(Default)]
+struct Stats {
+    upscale_fps: Option<f64>,
+    encode_fps: Option<f64>,
+    temp_used: u64,
+    gpu: Option<String>,
+    /// Seconds left, only shown in the plain progress lines.
+    eta: Option<f64>,
+}
+
+impl Stats {
+    fn line(&self) -> String {
+        let fps = |fps: Option<f64>| fps.map_or(String::from("-"), |fps| format!("{:.1}", fps));
+        format!(
+            "upscale {} fps | encode {} fps | temp {} | gpu {}",
+            fps(self.upscale_fps),
+            fps(self.encode_fps),
+            HumanBytes(self.temp_used),
+            self.gpu.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+/// Dashboard of an upscale job, mirrored as json events in json mode.
+///
+/// Its lines stay in place for the whole job: the segments of the input, one bar per stage
+/// showing the segment it works on, and a line of stats.
+pub struct CliProgress {
+    multi: MultiProgress,
+    format: ProgressFormat,
+    job: ProgressBar,
+    stage_bars: HashMap<Stage, ProgressBar>,
+    stages: Mutex<HashMap<(Stage, u32), StageProgress>>,
+    stats_bar: ProgressBar,
+    stats: Mutex<Stats>,
+}
+
+impl CliProgress {
+    pub fn new(format: ProgressFormat, video: &Video) -> Self {
+        let info_style = "[info][{elapsed_precise}] [{wide_bar:.green/white}] {pos:>7}/{len:7} {prefix:<24} eta: {msg:<8}";
+
+        let multi = MultiProgress::new();
+        if format != ProgressFormat::Bar {
+            multi.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        let job = multi.add(ProgressBar::new(video.segment_count as u64));
+        job.set_style(
+            ProgressStyle::default_bar()
+                .template(info_style)
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        let name = Path::new(&video.path)
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+        job.set_prefix(truncate(&name, 24));
+        job.set_position((video.segment_count - video.segments.len() as u32) as u64);
+
+        let stage_bars = [
+            (Stage::Export, "[expo][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} exporting {msg:<14} {per_sec:<12}"),
+            (Stage::Upscale, "[upsc][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} upscaling {msg:<14} {per_sec:<12}"),
+            (Stage::Merge, "[merg][{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos:>7}/{len:7} merging {msg:<16} {per_sec:<12}"),
+        ]
+        .into_iter()
+        .map(|(stage, style)| {
+            let bar = multi.add(ProgressBar::new(0));
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(style)
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            (stage, bar)
+        })
+        .collect();
+
+        let stats = Stats::default();
+        let stats_bar = multi.add(ProgressBar::new(0));
+        stats_bar.set_style(
+            ProgressStyle::default_bar()
+                .template("[stat] {msg}")
+                .unwrap(),
+        );
+        stats_bar.set_message(stats.line());
+        Self {
+            multi,
+            format,
+            job,
+            stage_bars,
+            stages: Mutex::new(HashMap::new()),
+            stats_bar,
+            stats: Mutex::new(stats),
+        }
+    }
+
+    /// Changes the stats and redraws their line.
+    fn update_stats(&self, update: impl FnOnce(&mut Stats)) {
+        let mut stats = self.stats.lock().unwrap();
+        update(&mut stats);
+        self.stats_bar.set_message(stats.line());
+    }
+
+    pub fn clear(&self) {
+        self.multi.clear().unwrap();
+    }
+}
+
+/// Shortens `text` to `width` characters, ending it with `~` when cut.
+pub fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut text: String = text.chars().take(width - 1).collect();
+    text.push('~');
+    text
+}
+
+impl JobProgress for CliProgress {
+    fn stage_started(&self, stage: Stage, segment: &Segment) {
+        let progress = StageProgress::new(
+            self.stage_bars[&stage].clone(),
+            self.format,
+            stage,
+            segment.index,
+            segment.size,
+        );
+        self.stages
+            .lock()
+            .unwrap()
+            .insert((stage, segment.index), progress);
+    }
+
+    fn stage_frames(&self, stage: Stage, segment: &Segment, frames_done: u64) {
+        if let Some(progress) = self.stages.lock().unwrap().get(&(stage, segment.index)) {
+            progress.set_position(frames_done);
+        }
+    }
+
+    fn stage_finished(&self, stage: Stage, segment: &Segment) {
+        if let Some(progress) = self.stages.lock().unwrap().remove(&(stage, segment.index)) {
+            if stage == Stage::Merge {
+                self.update_stats(|stats| stats.encode_fps = Some(progress.fps()));
+            }
+            progress.finish();
+        }
+    }
+
+    fn segment_done(&self, segments_done: u32, segments_total: u32, eta: Option<f64>) {
+        self.job.set_position(segments_done as u64);
+        self.update_stats(|stats| stats.temp_used = disk::dir_size(paths::temp()));
+        if self.format == ProgressFormat::Plain {
+            let stats = self.stats.lock().unwrap();
+            let eta = stats.eta.map_or(String::from("-"), |eta| {
+                FormattedDuration(Duration::from_secs_f64(eta)).to_string()
+            });
+            info!(
+                "{}/{} segments done, eta {} | {}",
+                segments_done,
+                segments_total,
+                eta,
+                stats.line()
+            );
+        }
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Job {
+                segments_done,
+                segments_total,
+                eta,
+            });
+        }
+    }
+
+    fn eta(&self, fps: f64, eta: f64) {
+        self.job
+            .set_message(FormattedDuration(Duration::from_secs_f64(eta)).to_string());
+        self.update_stats(|stats| {
+            stats.upscale_fps = Some(fps);
+            stats.eta = Some(eta);
+        });
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Eta { fps, eta });
+        }
+    }
+
+    fn paused(&self, segment: &Segment) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Paused {
+                segment: segment.index,
+            });
+        }
+    }
+
+    fn resumed(&self, segment: &Segment) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Resumed {
+                segment: segment.index,
+            });
+        }
+    }
+
+    fn outside_schedule(&self, segment: &Segment, opens_in: u64) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Scheduled {
+                segment: segment.index,
+                opens_in,
+            });
+        }
+    }
+
+    fn low_disk_space(&self, required: u64, available: u64) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::LowDiskSpace {
+                required,
+                available,
+            });
+        }
+    }
+
+    fn gpu_hot(&self, temperature: u8, max_temperature: u8) {
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::GpuHot {
+                temperature,
+                max_temperature,
+            });
+        }
+    }
+
+    fn gpu(&self, name: &str) {
+        if self.stats.lock().unwrap().gpu.as_deref() != Some(name) {
+            self.update_stats(|stats| stats.gpu = Some(name.to_string()));
+        }
+    }
+
+    fn stage_failed(&self, stage: Stage, segment: &Segment, attempt: u8, err: &ReveError) {
+        if let Some(progress) = self.stages.lock().unwrap().remove(&(stage, segment.index)) {
+            progress.abandon();
+        }
+        if self.format == ProgressFormat::Json {
+            emit(&ProgressEvent::Retry {
+                stage,
+                segment: segment.index,
+                attempt,
+                error: err.to_string(),
+            });
+        }
+    }
+}
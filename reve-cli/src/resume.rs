@@ -0,0 +1,175 @@
+//! `reve resume`: continues an interrupted upscale with the options it was started with.
+
+use crate::absolute_path;
+use clap::Parser;
+use reve_shared::{state, Args, LogLevel, ProgressFormat, ReveError, UpscalerThreads};
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve resume",
+    bin_name = "reve resume",
+    about = "continue an interrupted upscale with the options it was started with"
+)]
+pub struct ResumeArgs {
+    /// input of the upscale to resume, needed when several were interrupted
+    #[clap(short = 'i', long)]
+    inputpath: Option<String>,
+
+    /// output of the upscale to resume, when the input was upscaled to several outputs
+    outputpath: Option<String>,
+
+    /// progress output format, bars switch to plain when stdout is not a terminal
+    #[clap(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    progress: ProgressFormat,
+
+    /// print only errors, same as --progress quiet
+    #[clap(short = 'q', long, conflicts_with = "progress")]
+    quiet: bool,
+
+    /// verbosity of reve.log, written next to the temp folder
+    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+
+    /// vulkan device used by realesrgan, see `reve gpus` (the saved one when not given)
+    #[clap(long, visible_alias = "gpu", value_name = "ID")]
+    gpu_id: Option<u32>,
+
+    /// realesrgan threads as load:proc:save (the saved ones when not given)
+    #[clap(long)]
+    upscaler_threads: Option<UpscalerThreads>,
+}
+
+/// Returns the saved options of the interrupted upscale `args` selects among the jobs of
+/// `work_dir`, with the output, gpu and thread options of this run.
+pub fn saved_args(args: ResumeArgs, work_dir: &Path) -> Result<Args, ReveError> {
+    let input = args.inputpath.as_ref().map(absolute_path);
+    let output = args.outputpath.as_ref().map(absolute_path);
+    let saved = select(
+        state::saved_args(work_dir)?,
+        input.as_deref(),
+        output.as_deref(),
+    )?;
+    Ok(args.apply(saved))
+}
+
+/// Returns the upscale of `jobs` from `input` to `output`, which may be left out when a
+/// single one matches.
+fn select(jobs: Vec<Args>, input: Option<&str>, output: Option<&str>) -> Result<Args, ReveError> {
+    let mut jobs: Vec<Args> = jobs
+        .into_iter()
+        .filter(|saved| {
+            input.is_none_or(|input| input == saved.inputpath)
+                && output.is_none_or(|output| saved.outputpath.as_deref() == Some(output))
+        })
+        .collect();
+
+    match jobs.len() {
+        0 => Err(ReveError::InvalidArgument(match input {
+            Some(input) => format!("no interrupted upscale of {} to resume", input),
+            None => String::from("no interrupted upscale to resume"),
+        })),
+        1 => Ok(jobs.remove(0)),
+        _ => {
+            let jobs: Vec<String> = jobs
+                .iter()
+                .map(|job| {
+                    format!(
+                        "  {} -> {}",
+                        job.inputpath,
+                        job.outputpath.as_deref().unwrap_or_default()
+                    )
+                })
+                .collect();
+            Err(ReveError::InvalidArgument(format!(
+                "several interrupted upscales, select one with -i and the output path:\n{}",
+                jobs.join("\n")
+            )))
+        }
+    }
+}
+
+impl ResumeArgs {
+    /// Returns the `saved` options with the output, gpu and thread options of this run.
+    fn apply(self, mut saved: Args) -> Args {
+        saved.progress = self.progress;
+        saved.quiet = self.quiet;
+        saved.log_level = self.log_level;
+        // Run options, the job applies them to the saved video when it resumes
+        if self.gpu_id.is_some() {
+            saved.gpu_id = self.gpu_id;
+        }
+        if self.upscaler_threads.is_some() {
+            saved.upscaler_threads = self.upscaler_threads;
+        }
+        saved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(input: &str, output: &str) -> Args {
+        let mut args = Args::parse_from(["reve", "-i", "assets/test.mp4", "-s", "2"]);
+        args.inputpath = input.to_string();
+        args.outputpath = Some(output.to_string());
+        args
+    }
+
+    #[test]
+    fn test_select() {
+        let jobs = || {
+            vec![
+                job("/videos/movie.mkv", "/videos/movie_2x.mkv"),
+                job("/videos/movie.mkv", "/videos/movie_4k.mkv"),
+                job("/videos/show.mkv", "/videos/show_2x.mkv"),
+            ]
+        };
+        let output = |result: Result<Args, ReveError>| result.unwrap().outputpath.unwrap();
+        assert_eq!(
+            output(select(jobs(), Some("/videos/show.mkv"), None)),
+            "/videos/show_2x.mkv"
+        );
+        assert_eq!(
+            output(select(
+                jobs(),
+                Some("/videos/movie.mkv"),
+                Some("/videos/movie_4k.mkv")
+            )),
+            "/videos/movie_4k.mkv"
+        );
+        let several = select(jobs(), Some("/videos/movie.mkv"), None)
+            .unwrap_err()
+            .to_string();
+        assert!(several.contains("several interrupted upscales"));
+        assert!(several.contains("/videos/movie.mkv -> /videos/movie_4k.mkv"));
+        assert!(select(jobs(), None, None).is_err());
+        assert_eq!(
+            select(jobs(), Some("/videos/other.mkv"), None)
+                .unwrap_err()
+                .to_string(),
+            "no interrupted upscale of /videos/other.mkv to resume"
+        );
+        assert_eq!(
+            output(select(jobs()[..1].to_vec(), None, None)),
+            "/videos/movie_2x.mkv"
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut saved = job("/videos/movie.mkv", "/videos/movie_2x.mkv");
+        saved.gpu_id = Some(1);
+        let args = ResumeArgs::parse_from(["reve resume", "-q"]);
+        let resumed = args.apply(saved.clone());
+        assert!(resumed.quiet);
+        assert_eq!(resumed.gpu_id, Some(1));
+
+        let args = ResumeArgs::parse_from(["reve resume", "--gpu", "0", "--progress", "json"]);
+        let resumed = args.apply(saved);
+        assert_eq!(resumed.gpu_id, Some(0));
+        assert_eq!(resumed.progress, ProgressFormat::Json);
+        assert_eq!(resumed.scale, 2);
+    }
+}
@@ -0,0 +1,206 @@
+//! `reve setup`: installs pinned builds of ffmpeg, realesrgan-ncnn-vulkan and its models.
+//!
+//! The builds are listed in a manifest published with every release, giving the url and
+//! sha256 of each archive for every platform. Archives are verified before anything is
+//! extracted into [`binaries::managed_dir`], which the tool lookup prefers.
+
+use clap::Parser;
+use colored::Colorize;
+use reve_shared::{binaries, ReveError};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env::consts::{ARCH, OS};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::info;
+
+const MANIFEST_URL: &str = concat!(
+    "https://github.com/ONdraid/reve/releases/download/v",
+    env!("CARGO_PKG_VERSION"),
+    "/dependencies.json"
+);
+
+/// Records the sha256 of the archives installed in the managed folder.
+const INSTALLED_FILE: &str = "installed.json";
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve setup",
+    bin_name = "reve setup",
+    about = "download verified builds of ffmpeg, realesrgan-ncnn-vulkan and its models"
+)]
+pub struct SetupArgs {
+    /// dependency manifest (url or path), the one of this release by default
+    #[clap(long)]
+    manifest: Option<String>,
+
+    /// download archives that are already installed again
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    /// Archives to install, by `{os}-{arch}` (windows-x86_64, macos-aarch64, ...).
+    platforms: HashMap<String, Vec<Archive>>,
+}
+
+/// A zip archive holding some of the dependencies.
+#[derive(Deserialize)]
+struct Archive {
+    name: String,
+    url: String,
+    sha256: String,
+    /// Entries of the archive to install, mapped to their path in the managed folder. An
+    /// entry ending with `/` installs the folder's content.
+    files: BTreeMap<String, String>,
+}
+
+pub fn run(args: SetupArgs) -> Result<(), ReveError> {
+    let dir = binaries::managed_dir()
+        .ok_or_else(|| ReveError::Download(String::from("could not find the local data folder")))?;
+    fs::create_dir_all(&dir)?;
+
+    let manifest = load_manifest(args.manifest.as_deref().unwrap_or(MANIFEST_URL))?;
+    let platform = format!("{}-{}", OS, ARCH);
+    let archives = manifest.platforms.get(&platform).ok_or_else(|| {
+        ReveError::Download(format!("no dependencies are published for {}", platform))
+    })?;
+
+    let installed_path = dir.join(INSTALLED_FILE);
+    let mut installed: BTreeMap<String, String> = fs::read_to_string(&installed_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    for archive in archives {
+        if !args.force && installed.get(&archive.name) == Some(&archive.sha256) {
+            println!("{} is up to date", archive.name);
+            continue;
+        }
+        println!("downloading {}", archive.name);
+        let download = dir.join(format!("{}.download", archive.name));
+        let result = fetch(archive, &download).and_then(|()| extract(archive, &download, &dir));
+        let _ = fs::remove_file(&download);
+        result?;
+        installed.insert(archive.name.clone(), archive.sha256.clone());
+        fs::write(&installed_path, serde_json::to_string(&installed).unwrap())?;
+        info!("installed {} from {}", archive.name, archive.url);
+    }
+
+    println!(
+        "{}",
+        format!("dependencies installed in {}", dir.display()).green()
+    );
+    Ok(())
+}
+
+fn load_manifest(source: &str) -> Result<Manifest, ReveError> {
+    let content = if source.starts_with("https://") || source.starts_with("http://") {
+        get(source)?
+            .into_string()
+            .map_err(|err| ReveError::Download(format!("{}: {}", source, err)))?
+    } else {
+        fs::read_to_string(source)?
+    };
+    serde_json::from_str(&content)
+        .map_err(|err| ReveError::Download(format!("invalid manifest {}: {}", source, err)))
+}
+
+fn get(url: &str) -> Result<ureq::Response, ReveError> {
+    ureq::get(url)
+        .call()
+        .map_err(|err| ReveError::Download(format!("{}: {}", url, err)))
+}
+
+/// Downloads `archive` to `path`, failing if its sha256 is not the pinned one.
+fn fetch(archive: &Archive, path: &Path) -> Result<(), ReveError> {
+    let mut reader = get(&archive.url)?.into_reader();
+    let mut file = File::create(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        file.write_all(&buffer[..read])?;
+    }
+
+    let sha256: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if !sha256.eq_ignore_ascii_case(&archive.sha256) {
+        return Err(ReveError::Download(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            archive.url, archive.sha256, sha256
+        )));
+    }
+    Ok(())
+}
+
+/// Extracts the entries of `archive` listed in its manifest into `dir`.
+fn extract(archive: &Archive, path: &Path, dir: &Path) -> Result<(), ReveError> {
+    let invalid = |message: String| ReveError::Download(format!("{}: {}", archive.name, message));
+    let mut zip = zip::ZipArchive::new(File::open(path)?)
+        .map_err(|err| invalid(format!("invalid archive: {}", err)))?;
+
+    for index in 0..zip.len() {
+        let mut entry = zip
+            .by_index(index)
+            .map_err(|err| invalid(format!("invalid archive: {}", err)))?;
+        let Some(target) = install_path(entry.name(), &archive.files) else {
+            continue;
+        };
+        let target = dir.join(target);
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&target)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = entry.unix_mode().unwrap_or(0o644) & 0o777;
+            // Binaries are listed one by one, folders only hold models
+            let mode = if archive.files.contains_key(entry.name()) {
+                mode | 0o755
+            } else {
+                mode
+            };
+            fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+        }
+    }
+
+    for (entry, target) in &archive.files {
+        if !entry.ends_with('/') && !dir.join(target).is_file() {
+            return Err(invalid(format!("the archive has no {}", entry)));
+        }
+    }
+    Ok(())
+}
+
+/// Returns where the archive entry `name` is installed, relative to the managed folder.
+///
+/// Entries that are not listed, or whose target would leave the managed folder, are skipped.
+fn install_path(name: &str, files: &BTreeMap<String, String>) -> Option<PathBuf> {
+    let path = files.iter().find_map(|(entry, target)| {
+        if let Some(folder) = entry.strip_suffix('/') {
+            let rest = name.strip_prefix(folder)?.strip_prefix('/')?;
+            Some(Path::new(target).join(rest))
+        } else {
+            (name == entry).then(|| PathBuf::from(target))
+        }
+    })?;
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
@@ -0,0 +1,62 @@
+//! `reve worker`: upscales the segments of jobs published to a shared folder with `--share`.
+//!
+//! Segments are processed in a `worker` folder of the work dir, so a worker can run next to
+//! a local job on the same machine.
+
+use crate::{absolute_path, control, logging};
+use clap::Parser;
+use reve_shared::config::ConfigData;
+use reve_shared::control::JobControl;
+use reve_shared::{remote, LogLevel, ProgressFormat, ReveError};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+#[clap(
+    name = "reve worker",
+    bin_name = "reve worker",
+    about = "upscale the segments of jobs published to a shared folder with --share"
+)]
+pub struct WorkerArgs {
+    /// shared folder the jobs are published to
+    #[clap(value_parser)]
+    share: String,
+
+    /// name of the worker in the share (defaults to worker-{process id})
+    #[clap(long, value_parser = remote::worker_name_validation)]
+    name: Option<String>,
+
+    /// verbosity of reve.log, written next to the temp folder
+    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+}
+
+pub fn run(args: WorkerArgs) -> Result<(), ReveError> {
+    if !Path::new(&args.share).is_dir() {
+        return Err(ReveError::InvalidArgument(format!(
+            "shared folder {:?} not found",
+            args.share
+        )));
+    }
+    let share = absolute_path(&args.share);
+    let name = args
+        .name
+        .unwrap_or_else(|| format!("worker-{}", std::process::id()));
+
+    let exe_dir = env::current_exe()?.parent().unwrap().to_path_buf();
+    let (config, _) = ConfigData::load_with_file(&exe_dir)?;
+    let binaries = config.binaries();
+    let work_dir = config.work_dir(&exe_dir)?.join("worker");
+    fs::create_dir_all(&work_dir)?;
+    logging::init(
+        &work_dir.join("reve.log"),
+        args.log_level,
+        ProgressFormat::Bar,
+    )?;
+    env::set_current_dir(&work_dir)?;
+
+    let control = JobControl::new();
+    control::listen(&control);
+    remote::work(Path::new(&share), &name, &binaries, &control, &())
+}
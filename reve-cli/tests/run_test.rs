@@ -1,20 +1,13 @@
 use std::fs;
 use std::io::ErrorKind;
+use std::path::Path;
 use std::process::Command;
 
 #[test]
 fn run_verify() {
-    match fs::remove_file("target\\debug\\temp\\parts.txt") {
-        Ok(()) => "ok",
-        Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-        Err(_e) => "other",
-    };
-    match fs::remove_file("target\\debug\\temp\\temp.mp4") {
-        Ok(()) => "ok",
-        Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
-        Err(_e) => "other",
-    };
-    match fs::remove_file("target\\debug\\temp\\args.temp") {
+    // Jobs keep their files in a folder of temp named after the input and output, a
+    // leftover one would make reve ask to resume
+    match fs::remove_dir_all(Path::new("target").join("debug").join("temp")) {
         Ok(()) => "ok",
         Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
         Err(_e) => "other",
@@ -24,8 +17,11 @@ fn run_verify() {
         Err(_e) if _e.kind() == ErrorKind::NotFound => "not found",
         Err(_e) => "other",
     };
-    Command::new("target\\debug\\reve")
-        .args(["-i", "assets\\test.mp4", "-s", "2", "out.mp4"])
+    let input = Path::new("assets").join("test.mp4");
+    Command::new(env!("CARGO_BIN_EXE_reve-cli"))
+        .arg("-i")
+        .arg(&input)
+        .args(["-s", "2", "out.mp4"])
         .output()
         .unwrap();
     match fs::remove_file("out.mp4") {
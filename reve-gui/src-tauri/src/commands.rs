@@ -1,106 +1,333 @@
-use std::io::{self, Write};
+use std::{collections::HashMap, env, fs, sync::Mutex, time::Instant};
 
-use tauri::api::process::{Command, CommandEvent};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{CommandFactory, FromArgMatches};
+use reve_shared::{
+    config::ConfigData,
+    job::{JobProgress, UpscaleJob},
+    paths, preview, rebuild_temp, state, Args, ProgressEvent, ReveError, Segment, Stage,
+};
+use serde::Serialize;
+use tauri::{Manager, State, Window};
 
-use crate::utils;
+use crate::{
+    queue::{QueueItem, QueueState, QueueStatus},
+    utils,
+};
 
-enum UpscaleTypes {
-    General,
-    Digital,
+/// Forwards the progress of an upscale job to the frontend as events:
+///
+/// - `progress://segment` when a stage of a segment starts or finishes
+/// - `progress://frames` for every frame exported, upscaled or merged
+/// - `progress://eta` after every merged segment
+struct WindowProgress {
+    window: Window,
+    started: Mutex<HashMap<(Stage, u32), Instant>>,
 }
 
-impl UpscaleTypes {
-    /// Returns the model to be used in the upscale.
-    fn upscale_type_as_str(&self) -> &str {
-        match self {
-            UpscaleTypes::General => "realesr-animevideov3",
-            UpscaleTypes::Digital => "realesr-animevideov3",
+impl WindowProgress {
+    fn new(window: Window) -> Self {
+        Self {
+            window,
+            started: Mutex::new(HashMap::new()),
         }
     }
+
+    fn elapsed(&self, stage: Stage, segment: &Segment) -> f64 {
+        self.started
+            .lock()
+            .unwrap()
+            .get(&(stage, segment.index))
+            .map_or(0.0, |started| started.elapsed().as_secs_f64())
+    }
 }
 
-/// Upscales a single image.
-///
-/// Currently the upscale_factor is not used, but it is kept for future use.
+impl JobProgress for WindowProgress {
+    fn stage_started(&self, stage: Stage, segment: &Segment) {
+        self.started
+            .lock()
+            .unwrap()
+            .insert((stage, segment.index), Instant::now());
+        let _ = self.window.emit(
+            "progress://segment",
+            ProgressEvent::SegmentStarted {
+                stage,
+                segment: segment.index,
+                frames: segment.size,
+            },
+        );
+    }
+
+    fn stage_frames(&self, stage: Stage, segment: &Segment, frames_done: u64) {
+        let elapsed = self.elapsed(stage, segment);
+        let _ = self.window.emit(
+            "progress://frames",
+            ProgressEvent::Frames {
+                stage,
+                segment: segment.index,
+                done: frames_done,
+                total: segment.size,
+                fps: if elapsed > 0.0 {
+                    frames_done as f64 / elapsed
+                } else {
+                    0.0
+                },
+            },
+        );
+    }
+
+    fn stage_finished(&self, stage: Stage, segment: &Segment) {
+        let elapsed = self.elapsed(stage, segment);
+        self.started.lock().unwrap().remove(&(stage, segment.index));
+        let _ = self.window.emit(
+            "progress://segment",
+            ProgressEvent::SegmentFinished {
+                stage,
+                segment: segment.index,
+                frames: segment.size as u64,
+                elapsed,
+            },
+        );
+    }
+
+    fn segment_done(&self, segments_done: u32, segments_total: u32, eta: Option<f64>) {
+        let _ = self.window.emit(
+            "progress://eta",
+            ProgressEvent::Job {
+                segments_done,
+                segments_total,
+                eta,
+            },
+        );
+    }
+}
+
+/// Plans a new job for `path`, or resumes the one left in temp if it was upscaling the same
+/// video to the same output.
+fn prepare_job(
+    path: &str,
+    save_path: &str,
+    upscale_factor: &str,
+    upscale_type: &str,
+) -> Result<UpscaleJob, ReveError> {
+    // Long paths only open in their extended-length form on Windows
+    let (path, save_path) = (paths::native(path), paths::native(save_path));
+    let (path, save_path): (&str, &str) = (&path, &save_path);
+    let config = ConfigData::load()?;
+    let exe_path = env::current_exe()?;
+    env::set_current_dir(
+        config.work_dir(exe_path.parent().expect("executable has a parent folder"))?,
+    )?;
+    paths::set_job(&paths::job_id(path, save_path));
+
+    // A state saved by an older version that cannot be read is planned again
+    if let Some(args) = state::load_args()
+        .ok()
+        .flatten()
+        .filter(|args| args.inputpath == path && args.outputpath.as_deref() == Some(save_path))
+    {
+        paths::set_frames_dir(args.ramdisk.as_deref());
+        let job = UpscaleJob::resume(args)?;
+        rebuild_temp(true)?;
+        return Ok(job);
+    }
+
+    let matches = Args::command()
+        .try_get_matches_from([
+            "reve",
+            "-i",
+            path,
+            "-s",
+            upscale_factor,
+            "--model",
+            upscale_type,
+            save_path,
+        ])
+        .map_err(|err| ReveError::InvalidArgument(err.to_string()))?;
+    let mut args = Args::from_arg_matches(&matches)
+        .map_err(|err| ReveError::InvalidArgument(err.to_string()))?;
+    config.apply(&mut args, &matches);
+    args.validate()?;
+    paths::set_frames_dir(args.ramdisk.as_deref());
+    rebuild_temp(false)?;
+    UpscaleJob::new(args)
+}
+
+/// Starts upscaling a video segment by segment, resuming the previous run if it was
+/// interrupted.
 ///
-/// The comment part of this function is for the Windows version of the program.
-/// When building it for Windows, you need to comment the Linux line and uncomment the Windows line.
+/// Returns right away, the progress is emitted as events (see `WindowProgress`) and the run
+/// ends with `progress://done` carrying the output path or `progress://error`.
 #[tauri::command]
 pub async fn upscale_single_video(
+    window: Window,
     path: String,
     save_path: String,
     upscale_factor: String,
     upscale_type: String,
-) -> Result<String, String> {
+) -> Result<(), String> {
     let upscale_information = format!(
-        "Upscaling image: {} with the following configuration:
+        "Upscaling video: {} with the following configuration:
         -> Save path: {}
         -> Upscale factor: {}
         -> Upscale type: {}\n",
         &path, &save_path, &upscale_factor, &upscale_type
     );
     println!("{}", &upscale_information);
+    utils::write_log(&upscale_information);
 
-    let command = tauri::async_runtime::spawn(async move {
-        let upscale_type_model = match upscale_type.as_str() {
-            "digital" => UpscaleTypes::Digital,
-            _ => UpscaleTypes::General,
+    tauri::async_runtime::spawn_blocking(move || {
+        let result = prepare_job(&path, &save_path, &upscale_factor, &upscale_type)
+            .and_then(|mut job| job.run(&WindowProgress::new(window.clone())));
+        let _ = match result {
+            Ok(()) => window.emit("progress://done", save_path),
+            Err(err) => window.emit("progress://error", error_message(&err)),
         };
+    });
+    Ok(())
+}
 
-        let upscale_string = upscale_type_model.upscale_type_as_str();
-
-        let (mut rx, mut _child) = match Command::new("realesrgan-ncnn-vulkan.exe")
-            .args([
-                "-i",
-                &path,
-                "-o",
-                &save_path,
-                "-m",
-                "models",
-                "-n",
-                (upscale_string.to_owned() + "-x" + &upscale_factor.to_owned()).as_str(),
-                "-s",
-                &upscale_factor.to_owned(),
-            ])
-            .spawn()
-        {
-            Ok((rx, child)) => (rx, child),
-            Err(err) => {
-                return Err(format!(
-                    "Failed to spawn process \"realesrgan-ncnn-vulkan.exe\": {}",
-                    err
-                ));
-            }
-        };
+/// Before and after images of a preview frame, as base64 encoded png files.
+#[derive(Serialize)]
+pub struct FramePreview {
+    original: String,
+    upscaled: String,
+}
 
-        let logger = utils::Logger::new();
-        let mut command_buffer = Vec::new();
-        write!(&mut command_buffer, "{}", upscale_information).expect("Failed to write to buffer");
-
-        while let Some(event) = rx.recv().await {
-            match event {
-                CommandEvent::Stderr(data) | CommandEvent::Stdout(data) => {
-                    write!(&mut command_buffer, "{}", data).expect("Failed to write to buffer");
-                    println!("{}", data);
-                }
-                CommandEvent::Terminated(process) => {
-                    if process.code.expect("Failed to get process exit code") != 0 {
-                        // This flush is needed to make sure the output is printed before the error is returned.
-                        io::stdout().flush().expect("Failed to flush stdout");
-                        utils::write_log(String::from_utf8_lossy(&command_buffer).as_ref());
-                        return Err(format!("Process exited with non-zero exit code.\nFor more information run the app from a terminal and check the output.\nOr check the log file located at {}", logger.log_file_path())
-                        );
-                    }
-                }
-                _ => (),
-            }
-        }
-        utils::write_log(String::from_utf8_lossy(&command_buffer).as_ref());
-        Ok(String::from("Upscaling finished successfully"))
+/// Upscales the frame shown at `timestamp` seconds into the video with `model` and `scale`,
+/// so they can be compared before starting the whole upscale.
+#[tauri::command]
+pub async fn preview_frame(
+    path: String,
+    timestamp: f64,
+    model: String,
+    scale: u8,
+) -> Result<FramePreview, String> {
+    let command = tauri::async_runtime::spawn_blocking(move || {
+        let dir = env::temp_dir().join("reve-gui-preview");
+        let binaries = ConfigData::load()?.binaries();
+        let preview = preview::preview_frame(&binaries, &path, timestamp, &model, scale, &dir)?;
+        Ok::<_, ReveError>(FramePreview {
+            original: STANDARD.encode(fs::read(preview.original)?),
+            upscaled: STANDARD.encode(fs::read(preview.upscaled)?),
+        })
     });
 
     match command.await {
-        Ok(result) => result,
+        Ok(result) => result.map_err(|err| error_message(&err)),
         Err(err) => Err(format!("Failed while await for command: {}", err)),
     }
 }
+
+/// Logs `err` and returns the message shown to the user.
+fn error_message(err: &ReveError) -> String {
+    utils::write_log(&err.to_string());
+    let logger = utils::Logger::new();
+    format!(
+        "{}\nFor more information check the log file located at {}",
+        err,
+        logger.log_file_path()
+    )
+}
+
+/// Returns the queued videos in the order they will be upscaled.
+#[tauri::command]
+pub fn queue_list(queue: State<'_, QueueState>) -> Vec<QueueItem> {
+    queue.lock().unwrap().items().to_vec()
+}
+
+/// Adds a video to the end of the queue.
+#[tauri::command]
+pub fn queue_add(
+    queue: State<'_, QueueState>,
+    path: String,
+    save_path: String,
+    upscale_factor: String,
+    upscale_type: String,
+) -> Result<Vec<QueueItem>, String> {
+    let mut queue = queue.lock().unwrap();
+    queue.push(path, save_path, upscale_factor, upscale_type);
+    queue.save().map_err(|err| err.to_string())?;
+    Ok(queue.items().to_vec())
+}
+
+/// Moves the video at `from` to position `to` in the queue.
+#[tauri::command]
+pub fn queue_move(
+    queue: State<'_, QueueState>,
+    from: usize,
+    to: usize,
+) -> Result<Vec<QueueItem>, String> {
+    let mut queue = queue.lock().unwrap();
+    queue.move_item(from, to)?;
+    queue.save().map_err(|err| err.to_string())?;
+    Ok(queue.items().to_vec())
+}
+
+/// Removes the video at `index` from the queue.
+#[tauri::command]
+pub fn queue_remove(queue: State<'_, QueueState>, index: usize) -> Result<Vec<QueueItem>, String> {
+    let mut queue = queue.lock().unwrap();
+    queue.remove(index)?;
+    queue.save().map_err(|err| err.to_string())?;
+    Ok(queue.items().to_vec())
+}
+
+/// Sets the status of a queued video, saves the queue and tells the frontend.
+fn update_item(window: &Window, item: &QueueItem, status: QueueStatus) {
+    let queue = window.state::<QueueState>();
+    let mut queue = queue.lock().unwrap();
+    queue.set_status(item.id(), status);
+    if let Err(err) = queue.save() {
+        utils::write_log(&format!("Failed to save the queue: {}", err));
+    }
+    let _ = window.emit("queue://changed", queue.items().to_vec());
+}
+
+/// Upscales the pending videos of the queue one after the other.
+///
+/// A failed video is marked as such and the queue moves on to the next one. Emits
+/// `queue://changed` with the whole queue whenever a video starts or ends.
+#[tauri::command]
+pub async fn queue_run(window: Window) -> Result<(), String> {
+    let already_running = window
+        .state::<QueueState>()
+        .lock()
+        .unwrap()
+        .items()
+        .iter()
+        .any(|item| item.status == QueueStatus::Running);
+    if already_running {
+        return Err(String::from("The queue is already running"));
+    }
+
+    let command = tauri::async_runtime::spawn_blocking(move || loop {
+        let item = {
+            let queue = window.state::<QueueState>();
+            let mut queue = queue.lock().unwrap();
+            let Some(item) = queue.start_next() else {
+                return;
+            };
+            let _ = queue.save();
+            let _ = window.emit("queue://changed", queue.items().to_vec());
+            item
+        };
+
+        let result = prepare_job(
+            &item.path,
+            &item.save_path,
+            &item.upscale_factor,
+            &item.upscale_type,
+        )
+        .and_then(|mut job| job.run(&WindowProgress::new(window.clone())));
+        let status = match result {
+            Ok(()) => QueueStatus::Done,
+            Err(err) => QueueStatus::Failed(error_message(&err)),
+        };
+        update_item(&window, &item, status);
+    });
+
+    command
+        .await
+        .map_err(|err| format!("Failed while await for command: {}", err))
+}
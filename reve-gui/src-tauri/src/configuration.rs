@@ -1,53 +1,8 @@
 use std::{error::Error, path::PathBuf};
 
-use serde::{Deserialize, Serialize};
+pub use reve_shared::config::{ConfigData, CONFIG_FOLDER};
 
-pub const CONFIG_FOLDER: &str = "reve-gui";
 pub const LOG_FILE: &str = "reve-gui.log";
-const CONFIG_FILE: &str = "reve-gui-config.json";
-
-#[derive(Serialize, Deserialize, Clone)]
-pub struct ConfigData {
-    #[serde(rename = "application-logs")]
-    application_logs: bool,
-
-    #[serde(rename = "default-upscale-type")]
-    default_upscale_type: String,
-}
-
-impl ConfigData {
-    /// Returns a default configuration.
-    pub fn default() -> ConfigData {
-        Self {
-            application_logs: false,
-            default_upscale_type: String::from("realesr-animevideov3"),
-        }
-    }
-
-    /// Validates the `ConfigData` struct.
-    fn validate_config(&self) -> Result<(), Box<dyn Error>> {
-        if [
-            String::from("realesr-animevideov3"),
-            String::from("realesr-realvideo"),
-        ]
-        .contains(&self.get_default_upscale_type())
-        {
-            Ok(())
-        } else {
-            Err("Invalid default upscale type".into())
-        }
-    }
-
-    /// Returns the value of the application-logs key in the `ConfigData`.
-    pub fn get_is_active_application_logs(&self) -> bool {
-        self.application_logs
-    }
-
-    /// Returns the value of the default-upscale-type key in the `ConfigData`.
-    pub fn get_default_upscale_type(&self) -> String {
-        self.default_upscale_type.clone()
-    }
-}
 
 pub struct Config {
     path: PathBuf,
@@ -58,10 +13,7 @@ impl Config {
     /// Create a new config with the content as None or the content of `ConfigData` passed as argument.
     pub fn new(config: Option<ConfigData>) -> Self {
         Self::create_config_folder(CONFIG_FOLDER).expect("Failed to create config folder");
-        let path = dirs::config_dir()
-            .expect("Could not find config directory")
-            .join(CONFIG_FOLDER)
-            .join(CONFIG_FILE);
+        let path = ConfigData::path().expect("Could not find config directory");
 
         Self {
             path,
@@ -77,15 +29,18 @@ impl Config {
             .content
             .as_ref()
             .ok_or("Failed to load config file")?
-            .validate_config()
+            .validate()
         {
             Ok(_) => Ok(self.content.clone().ok_or("Failed to load config file")?),
-            Err(err) => Err(err),
+            Err(err) => Err(err.into()),
         }
     }
 
-    /// Write the config to the config file.
+    /// Validates the config and writes it to the config file.
     pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(config) = &self.content {
+            config.validate()?;
+        }
         let content = serde_json::to_string_pretty(&self.content)?;
         std::fs::write(&self.path, content)?;
         Ok(())
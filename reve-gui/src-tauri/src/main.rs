@@ -5,10 +5,14 @@
 
 mod commands;
 mod configuration;
+mod queue;
 mod utils;
 
+use std::sync::Mutex;
+
 fn main() {
     tauri::Builder::default()
+        .manage(Mutex::new(queue::Queue::load()))
         .invoke_handler(tauri::generate_handler![
             utils::get_version,
             utils::replace_file_suffix,
@@ -16,6 +20,12 @@ fn main() {
             utils::write_configuration,
             utils::write_log,
             commands::upscale_single_video,
+            commands::preview_frame,
+            commands::queue_list,
+            commands::queue_add,
+            commands::queue_move,
+            commands::queue_remove,
+            commands::queue_run,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
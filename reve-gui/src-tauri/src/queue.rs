@@ -0,0 +1,191 @@
+use std::{error::Error, path::PathBuf, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::CONFIG_FOLDER;
+
+const QUEUE_FILE: &str = "reve-gui-queue.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "state", content = "error", rename_all = "kebab-case")]
+pub enum QueueStatus {
+    Pending,
+    Running,
+    Done,
+    Failed(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueueItem {
+    id: u64,
+    pub path: String,
+    pub save_path: String,
+    pub upscale_factor: String,
+    pub upscale_type: String,
+    pub status: QueueStatus,
+}
+
+/// Videos to upscale one after the other, saved after every change so a crashed session can
+/// pick up where it stopped.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Queue {
+    next_id: u64,
+    items: Vec<QueueItem>,
+}
+
+/// Queue shared between the commands.
+pub type QueueState = Mutex<Queue>;
+
+impl Queue {
+    fn path() -> PathBuf {
+        dirs::config_dir()
+            .expect("Could not find config directory")
+            .join(CONFIG_FOLDER)
+            .join(QUEUE_FILE)
+    }
+
+    /// Loads the saved queue, or an empty one if there is none.
+    ///
+    /// An item left running was interrupted by a crash, so it is pending again.
+    pub fn load() -> Queue {
+        let mut queue: Queue = std::fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        queue.restore();
+        queue
+    }
+
+    fn restore(&mut self) {
+        for item in &mut self.items {
+            if item.status == QueueStatus::Running {
+                item.status = QueueStatus::Pending;
+            }
+        }
+    }
+
+    /// Write the queue to the queue file.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        std::fs::create_dir_all(Self::path().parent().ok_or("Invalid queue path")?)?;
+        std::fs::write(Self::path(), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn items(&self) -> &[QueueItem] {
+        &self.items
+    }
+
+    /// Appends a pending video to the queue.
+    pub fn push(
+        &mut self,
+        path: String,
+        save_path: String,
+        upscale_factor: String,
+        upscale_type: String,
+    ) {
+        self.items.push(QueueItem {
+            id: self.next_id,
+            path,
+            save_path,
+            upscale_factor,
+            upscale_type,
+            status: QueueStatus::Pending,
+        });
+        self.next_id += 1;
+    }
+
+    /// Moves the item at `from` to `to`, shifting the ones in between.
+    pub fn move_item(&mut self, from: usize, to: usize) -> Result<(), String> {
+        if from >= self.items.len() || to >= self.items.len() {
+            return Err(String::from("Invalid queue position"));
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+        Ok(())
+    }
+
+    /// Removes the item at `index` unless it is being upscaled.
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        match self.items.get(index) {
+            None => Err(String::from("Invalid queue position")),
+            Some(item) if item.status == QueueStatus::Running => {
+                Err(String::from("Can not remove the video being upscaled"))
+            }
+            Some(_) => {
+                self.items.remove(index);
+                Ok(())
+            }
+        }
+    }
+
+    /// Marks the first pending item as running and returns it.
+    pub fn start_next(&mut self) -> Option<QueueItem> {
+        let item = self
+            .items
+            .iter_mut()
+            .find(|item| item.status == QueueStatus::Pending)?;
+        item.status = QueueStatus::Running;
+        Some(item.clone())
+    }
+
+    /// Sets the status of the item `id`, wherever it was moved to.
+    pub fn set_status(&mut self, id: u64, status: QueueStatus) {
+        if let Some(item) = self.items.iter_mut().find(|item| item.id == id) {
+            item.status = status;
+        }
+    }
+}
+
+impl QueueItem {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(paths: &[&str]) -> Queue {
+        let mut queue = Queue::default();
+        for path in paths {
+            queue.push(
+                path.to_string(),
+                format!("{}.mkv", path),
+                String::from("2"),
+                String::from("realesr-animevideov3"),
+            );
+        }
+        queue
+    }
+
+    #[test]
+    fn test_queue_order() {
+        let mut queue = queue(&["a", "b", "c"]);
+        queue.move_item(2, 0).unwrap();
+        let paths: Vec<&str> = queue
+            .items()
+            .iter()
+            .map(|item| item.path.as_str())
+            .collect();
+        assert_eq!(paths, ["c", "a", "b"]);
+        assert!(queue.move_item(3, 0).is_err());
+
+        let running = queue.start_next().unwrap();
+        assert_eq!(running.path, "c");
+        assert!(queue.remove(0).is_err());
+        queue.move_item(0, 2).unwrap();
+        queue.set_status(running.id(), QueueStatus::Done);
+        assert_eq!(queue.items()[2].status, QueueStatus::Done);
+        assert_eq!(queue.start_next().unwrap().path, "a");
+    }
+
+    #[test]
+    fn test_queue_restore() {
+        let mut queue = queue(&["a", "b"]);
+        queue.start_next();
+        queue.restore();
+        assert_eq!(queue.start_next().unwrap().path, "a");
+    }
+}
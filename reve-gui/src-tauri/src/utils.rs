@@ -30,7 +30,7 @@ impl Logger {
             Ok(config) => config,
             Err(_) => ConfigData::default(),
         };
-        if !config.get_is_active_application_logs() {
+        if !config.application_logs {
             return;
         }
         let mut file = OpenOptions::new()
@@ -0,0 +1,180 @@
+//! Animated gif, webp and avif outputs for short clips.
+//!
+//! The segments are still encoded as video parts, which keeps resuming and `--share` working,
+//! and are only turned into the animation when they are concatenated. Animations hold no
+//! audio or subtitles, so only the video of the parts is used.
+
+use crate::binaries::Binaries;
+use crate::encoder::require_encoder;
+use crate::process::{command_failure, run};
+use crate::{paths, ReveError, Video};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Longest clip written as an animation, every frame of which is kept in full.
+pub const MAX_SECONDS: f32 = 30.0;
+
+/// Highest frame rate of gifs, whose frame delays are in hundredths of a second and which
+/// browsers slow down below 2/100s.
+const GIF_MAX_FPS: f32 = 50.0;
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Animation {
+    /// 256 colors from a palette computed over the whole clip
+    Gif,
+    /// lossy animated webp, needs an ffmpeg built with libwebp
+    Webp,
+    /// av1 image sequence, needs an ffmpeg built with libsvtav1
+    Avif,
+}
+
+impl Animation {
+    /// Returns the animation written to `path`, `None` for video outputs.
+    pub fn from_path(path: &str) -> Option<Animation> {
+        match Path::new(path).extension()?.to_str()? {
+            "gif" => Some(Animation::Gif),
+            "webp" => Some(Animation::Webp),
+            "avif" => Some(Animation::Avif),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Animation::Gif => "gif",
+            Animation::Webp => "webp",
+            Animation::Avif => "avif",
+        }
+    }
+
+    fn encoder(self) -> &'static str {
+        match self {
+            Animation::Gif => "gif",
+            Animation::Webp => "libwebp_anim",
+            Animation::Avif => "libsvtav1",
+        }
+    }
+
+    /// Checks that ffmpeg can encode the animation.
+    pub fn check_encoder(self, binaries: &Binaries) -> Result<(), ReveError> {
+        require_encoder(binaries, self.encoder(), self.extension())
+    }
+
+    /// Returns the arguments encoding `frame_rate` fps video as the animation, looping forever.
+    fn encoder_args(self, frame_rate: f32) -> Vec<String> {
+        let args: Vec<&str> = match self {
+            // Dithering only the changed rectangle keeps the static parts from flickering
+            Animation::Gif if frame_rate > GIF_MAX_FPS => vec![
+                "-filter_complex",
+                "[0:v]fps=50,split[a][b];[a]palettegen=stats_mode=diff[p];\
+                 [b][p]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle",
+            ],
+            Animation::Gif => vec![
+                "-filter_complex",
+                "[0:v]split[a][b];[a]palettegen=stats_mode=diff[p];\
+                 [b][p]paletteuse=dither=bayer:bayer_scale=5:diff_mode=rectangle",
+            ],
+            Animation::Webp => vec![
+                "-map",
+                "0:v",
+                "-c:v",
+                "libwebp_anim",
+                "-quality",
+                "80",
+                "-compression_level",
+                "4",
+                "-pix_fmt",
+                "yuv420p",
+            ],
+            Animation::Avif => vec![
+                "-map",
+                "0:v",
+                "-c:v",
+                "libsvtav1",
+                "-crf",
+                "32",
+                "-preset",
+                "8",
+                "-pix_fmt",
+                "yuv420p",
+            ],
+        };
+        args.into_iter()
+            .map(String::from)
+            .chain(["-loop", "0"].map(String::from))
+            .collect()
+    }
+}
+
+/// Checks that a clip of `frame_count` frames at `frame_rate` fps is short enough to be
+/// written as an animation.
+pub fn check_duration(frame_count: u32, frame_rate: f32) -> Result<(), ReveError> {
+    let seconds = frame_count as f32 / frame_rate;
+    if seconds > MAX_SECONDS {
+        return Err(ReveError::InvalidArgument(format!(
+            "animated outputs are limited to {}s, the clip lasts {:.1}s: \
+             select a part with --start and --duration",
+            MAX_SECONDS, seconds
+        )));
+    }
+    Ok(())
+}
+
+impl Video {
+    /// Concatenates the segment parts listed in temp into the animation.
+    pub(crate) fn encode_animation(
+        &self,
+        binaries: &Binaries,
+        animation: Animation,
+    ) -> Result<(), ReveError> {
+        let partial = self.start_partial_output()?;
+        let mut command = Command::new(&binaries.ffmpeg);
+        command
+            .args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(paths::parts_list())
+            .args(animation.encoder_args(self.frame_rate))
+            .args(["-f", self.output_muxer()])
+            .arg(&partial);
+        let output = run(&mut command)?;
+        let failure = || {
+            ReveError::Ffmpeg(command_failure(
+                &format!("could not encode the segments as {}", animation.extension()),
+                &command,
+                &output,
+            ))
+        };
+        if !output.status.success() {
+            return Err(failure());
+        }
+        self.complete_output(binaries, &partial, failure)?;
+        fs::remove_file(paths::parts_list())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_animation() {
+        assert_eq!(
+            Animation::from_path("/clips/meme.gif"),
+            Some(Animation::Gif)
+        );
+        assert_eq!(Animation::from_path("meme.avif"), Some(Animation::Avif));
+        assert_eq!(Animation::from_path("meme.mkv"), None);
+
+        let gif = Animation::Gif.encoder_args(60.0);
+        assert!(gif[1].starts_with("[0:v]fps=50,"));
+        assert_eq!(&gif[2..], ["-loop", "0"]);
+        assert!(!Animation::Gif.encoder_args(24.0)[1].contains("fps="));
+
+        assert!(check_duration(720, 24.0).is_ok());
+        assert!(check_duration(900, 30.0).is_ok());
+        assert!(check_duration(901, 30.0).is_err());
+    }
+}
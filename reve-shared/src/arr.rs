@@ -0,0 +1,118 @@
+//! Events Sonarr and Radarr give to a custom script through its environment, read by
+//! `reve arr` to upscale the files they import.
+
+use crate::ReveError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrApp {
+    Sonarr,
+    Radarr,
+}
+
+impl ArrApp {
+    fn prefix(self) -> &'static str {
+        match self {
+            ArrApp::Sonarr => "sonarr",
+            ArrApp::Radarr => "radarr",
+        }
+    }
+
+    /// Returns the variable holding the path of an imported file.
+    fn file_path_key(self) -> &'static str {
+        match self {
+            ArrApp::Sonarr => "sonarr_episodefile_path",
+            ArrApp::Radarr => "radarr_moviefile_path",
+        }
+    }
+}
+
+/// Event of a custom script run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArrEvent {
+    /// The test of the connection settings.
+    Test(ArrApp),
+    /// A file was imported to `path`, named by the app.
+    Import { app: ArrApp, path: String },
+    /// Any other event, like `Grab` or `Rename`, whose type is given.
+    Other(ArrApp, String),
+}
+
+impl ArrEvent {
+    /// Reads the event of the environment `vars`, `None` when reve was not run by Sonarr or
+    /// Radarr. The names are compared ignoring case, as on Windows.
+    pub fn from_vars(
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Option<ArrEvent>, ReveError> {
+        let vars: Vec<(String, String)> = vars
+            .into_iter()
+            .map(|(key, value)| (key.to_ascii_lowercase(), value))
+            .collect();
+        let get = |key: &str| {
+            vars.iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.clone())
+        };
+        for app in [ArrApp::Sonarr, ArrApp::Radarr] {
+            let Some(event) = get(&format!("{}_eventtype", app.prefix())) else {
+                continue;
+            };
+            return Ok(Some(match event.as_str() {
+                "Test" => ArrEvent::Test(app),
+                "Download" => {
+                    let path = get(app.file_path_key()).ok_or_else(|| {
+                        ReveError::InvalidArgument(format!(
+                            "{} gave no {}",
+                            app.prefix(),
+                            app.file_path_key()
+                        ))
+                    })?;
+                    ArrEvent::Import { app, path }
+                }
+                _ => ArrEvent::Other(app, event),
+            }));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_arr_event() {
+        assert_eq!(
+            ArrEvent::from_vars(vars(&[("PATH", "/bin")])).unwrap(),
+            None
+        );
+        assert_eq!(
+            ArrEvent::from_vars(vars(&[("sonarr_eventtype", "Test")])).unwrap(),
+            Some(ArrEvent::Test(ArrApp::Sonarr))
+        );
+        assert_eq!(
+            ArrEvent::from_vars(vars(&[
+                ("RADARR_EVENTTYPE", "Download"),
+                (
+                    "RADARR_MOVIEFILE_PATH",
+                    "/movies/Movie (2001)/Movie (2001).mkv"
+                ),
+            ]))
+            .unwrap(),
+            Some(ArrEvent::Import {
+                app: ArrApp::Radarr,
+                path: String::from("/movies/Movie (2001)/Movie (2001).mkv")
+            })
+        );
+        assert_eq!(
+            ArrEvent::from_vars(vars(&[("sonarr_eventtype", "Grab")])).unwrap(),
+            Some(ArrEvent::Other(ArrApp::Sonarr, String::from("Grab")))
+        );
+        assert!(ArrEvent::from_vars(vars(&[("sonarr_eventtype", "Download")])).is_err());
+    }
+}
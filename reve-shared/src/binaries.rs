@@ -0,0 +1,203 @@
+//! Locates the external tools reve runs.
+
+use crate::process::{command_failure, run};
+use crate::ReveError;
+use std::env;
+use std::env::consts::EXE_SUFFIX;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const FFMPEG: &str = "ffmpeg";
+pub const FFPROBE: &str = "ffprobe";
+pub const REALESRGAN: &str = "realesrgan-ncnn-vulkan";
+
+/// Oldest ffmpeg release reve is tested with, older ones are warned about when a job starts.
+///
+/// The builds of `reve setup` are newer. Features that differ between releases, like the
+/// options of libsvtav1, are chosen from the detected release instead.
+pub const TESTED_FFMPEG: FfmpegVersion = FfmpegVersion { major: 5, minor: 1 };
+
+/// Folders searched after the executable's own, before `PATH`.
+///
+/// Apps started from the Finder do not inherit the shell's `PATH`, so the Homebrew
+/// prefixes are searched explicitly.
+#[cfg(target_os = "macos")]
+const EXTRA_DIRS: &[&str] = &["/opt/homebrew/bin", "/usr/local/bin"];
+#[cfg(not(target_os = "macos"))]
+const EXTRA_DIRS: &[&str] = &[];
+
+/// Returns the folder `reve setup` installs the tools and models in.
+pub fn managed_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("reve").join("bin"))
+}
+
+/// Returns the program to run for the tool `name`.
+///
+/// The tools installed by `reve setup` win, then a binary next to the reve executable, which
+/// is how the portable releases ship realesrgan with its models (and MoltenVK on macOS).
+/// Otherwise the bare name is returned so the command is looked up in `PATH`.
+pub fn find(name: &str) -> PathBuf {
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    managed_dir()
+        .into_iter()
+        .chain(exe_dir)
+        .chain(EXTRA_DIRS.iter().map(PathBuf::from))
+        .find_map(|dir| find_in(&dir, name))
+        .unwrap_or_else(|| PathBuf::from(name))
+}
+
+/// Programs reve runs, each given by the user or else found with [`find`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binaries {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+    pub realesrgan: PathBuf,
+}
+
+impl Binaries {
+    /// Uses the given paths, looking up the tools that have none.
+    pub fn new(ffmpeg: Option<&str>, ffprobe: Option<&str>, realesrgan: Option<&str>) -> Binaries {
+        let resolve =
+            |path: Option<&str>, name: &str| path.map_or_else(|| find(name), PathBuf::from);
+        Binaries {
+            ffmpeg: resolve(ffmpeg, FFMPEG),
+            ffprobe: resolve(ffprobe, FFPROBE),
+            realesrgan: resolve(realesrgan, REALESRGAN),
+        }
+    }
+}
+
+impl Binaries {
+    /// Checks that every program runs, returning the first line of `ffmpeg -version`.
+    pub fn check(&self) -> Result<String, ReveError> {
+        let version = version_line(&self.ffmpeg)?;
+        version_line(&self.ffprobe)?;
+        // realesrgan has no version option and exits with an error after printing its usage
+        run(Command::new(&self.realesrgan).arg("-h"))?;
+        Ok(version)
+    }
+
+    /// Returns the release of ffmpeg, `None` for a git build, which has no release number.
+    pub fn ffmpeg_version(&self) -> Result<Option<FfmpegVersion>, ReveError> {
+        Ok(FfmpegVersion::parse(&version_line(&self.ffmpeg)?))
+    }
+}
+
+/// Release of ffmpeg, read from the first line of `ffmpeg -version`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FfmpegVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl FfmpegVersion {
+    /// Reads the release from lines like `ffmpeg version 6.1.1-3ubuntu5 Copyright ...` or
+    /// `ffmpeg version n7.0-full_build-www.gyan.dev`.
+    ///
+    /// Git builds are named after a commit (`N-112345-g...`) or a date (`2024-03-11-git-...`)
+    /// and return `None`.
+    pub fn parse(line: &str) -> Option<FfmpegVersion> {
+        let release = line.strip_prefix("ffmpeg version ")?;
+        let release = release.strip_prefix('n').unwrap_or(release);
+        let end = release
+            .find(|char: char| !char.is_ascii_digit() && char != '.')
+            .unwrap_or(release.len());
+        let mut numbers = release[..end].split('.');
+        let major: u32 = numbers.next()?.parse().ok()?;
+        // A date, not a release
+        if major > 100 {
+            return None;
+        }
+        let minor = numbers.next().and_then(|minor| minor.parse().ok());
+        Some(FfmpegVersion {
+            major,
+            minor: minor.unwrap_or(0),
+        })
+    }
+}
+
+impl fmt::Display for FfmpegVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl Default for Binaries {
+    fn default() -> Self {
+        Binaries::new(None, None, None)
+    }
+}
+
+/// Returns the first line of `program -version`.
+fn version_line(program: &Path) -> Result<String, ReveError> {
+    let mut command = Command::new(program);
+    command.arg("-version");
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Ffmpeg(command_failure(
+            "could not get the version",
+            &command,
+            &output,
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+fn find_in(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(format!("{}{}", name, EXE_SUFFIX));
+    path.is_file().then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let exe = env::current_exe().unwrap();
+        let dir = exe.parent().unwrap();
+        let name = exe.file_stem().unwrap().to_str().unwrap();
+        assert_eq!(find_in(dir, name), Some(exe.clone()));
+        assert_eq!(find(name), exe);
+        assert_eq!(
+            find("reve-missing-tool"),
+            PathBuf::from("reve-missing-tool")
+        );
+
+        let binaries = Binaries::new(Some("/opt/ffmpeg/bin/ffmpeg"), None, None);
+        assert_eq!(binaries.ffmpeg, Path::new("/opt/ffmpeg/bin/ffmpeg"));
+        assert_eq!(binaries.realesrgan, find(REALESRGAN));
+    }
+
+    #[test]
+    fn test_ffmpeg_version() {
+        let parse = |line: &str| FfmpegVersion::parse(line).map(|version| version.to_string());
+        assert_eq!(
+            parse("ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers"),
+            Some(String::from("6.1"))
+        );
+        assert_eq!(
+            parse("ffmpeg version n7.0-full_build-www.gyan.dev"),
+            Some(String::from("7.0"))
+        );
+        assert_eq!(
+            parse("ffmpeg version 4.4.2-0ubuntu0.22.04.1"),
+            Some(String::from("4.4"))
+        );
+        assert_eq!(
+            parse("ffmpeg version 7 Copyright"),
+            Some(String::from("7.0"))
+        );
+        assert_eq!(parse("ffmpeg version N-112345-g0123abcd"), None);
+        assert_eq!(parse("ffmpeg version 2024-03-11-git-3d1860ec8d"), None);
+        assert_eq!(parse("ffprobe version 6.1"), None);
+        assert!(FfmpegVersion::parse("ffmpeg version 5.0.1").unwrap() < TESTED_FFMPEG);
+    }
+}
@@ -0,0 +1,1154 @@
+//! Command line arguments of a job, shared by the cli and the gui.
+
+use crate::animation::Animation;
+use crate::binaries::Binaries;
+use crate::encoder::{Codec, PixFmt, Profile};
+use crate::ffprobe;
+use crate::image_input::{self, ImageInput, SequencePattern};
+use crate::metrics::Metric;
+use crate::mux::{self, Mp4Fit, MuxSelection};
+use crate::schedule::Schedule;
+use crate::{
+    default_export_threads, default_frame_quality, default_upscaler_instances, is_video_path,
+    probe, Crop, Denoise, FrameFormat, InputKind, ReveError, TimeRange, TrackSelection,
+    UpscalerThreads, UPSCALED_TAG, VIDEO_EXTENSIONS,
+};
+use crate::{edl, models};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    /// also logs every spawned command line and its stderr
+    Debug,
+    Trace,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    /// interactive progress bars
+    #[default]
+    Bar,
+    /// newline-delimited json events on stdout
+    Json,
+    /// a line of text per finished segment, for logs and pipes
+    Plain,
+    /// no progress, only errors
+    Quiet,
+}
+
+#[derive(Parser, Serialize, Deserialize, Clone, Debug)]
+#[clap(name = "Real-ESRGAN Video Enhance",
+author = "ONdraid <ondraid.png@gmail.com>",
+about = "Real-ESRGAN video upscaler with resumability",
+long_about = None)]
+pub struct Args {
+    /// input video path (mp4/mkv/avi/ts/m2ts/wmv/flv/webm), vapoursynth/avisynth script
+    /// (vpy/avs), animated gif/apng or image sequence pattern (frame%04d.png)
+    #[clap(short = 'i', long, value_parser = input_validation)]
+    pub inputpath: String,
+
+    /// output path (mp4/mkv, or gif/webp/avif for clips up to 30s), generated from
+    /// --output-template when omitted
+    #[clap(value_parser = output_validation)]
+    pub outputpath: Option<String>,
+
+    /// output file name used when no output path is given
+    /// ({stem}, {scale}, {model}, {codec}, {height}, {ext})
+    #[clap(long, default_value = DEFAULT_OUTPUT_TEMPLATE)]
+    #[serde(default = "default_output_template")]
+    pub output_template: String,
+
+    /// directory of the generated output file (defaults to the input directory)
+    #[clap(long)]
+    pub output_dir: Option<String>,
+
+    /// write an animated image instead of a video, for clips up to 30s (no audio)
+    #[clap(long, value_enum)]
+    #[serde(default)]
+    pub format: Option<Animation>,
+
+    /// realesrgan model family, upscaling with its weights for the selected scale
+    #[clap(long, value_parser = model_validation, default_value = models::DEFAULT_FAMILY)]
+    #[serde(default = "default_model")]
+    pub model: String,
+
+    /// upscale ratio (2, 3, 4)
+    #[clap(short = 's', long, value_parser = clap::value_parser!(u8).range(2..5), default_value_t = 2)]
+    pub scale: u8,
+
+    /// output width, reached by upscaling with the smallest sufficient scale and resizing with
+    /// lanczos (keeps the aspect ratio without --target-height)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(2..), conflicts_with = "scale")]
+    #[serde(default)]
+    pub target_width: Option<u32>,
+
+    /// output height, reached by upscaling with the smallest sufficient scale and resizing with
+    /// lanczos (keeps the aspect ratio without --target-width)
+    #[clap(long, value_parser = clap::value_parser!(u32).range(2..), conflicts_with = "scale")]
+    #[serde(default)]
+    pub target_height: Option<u32>,
+
+    /// segment size (in frames)
+    #[clap(short = 'S', long, value_parser, default_value_t = 1000)]
+    pub segmentsize: u32,
+
+    /// video constant rate factor (crf: 51-0), x265 only
+    #[clap(short = 'c', long, value_parser = clap::value_parser!(u8).range(0..52), default_value_t = 15)]
+    pub crf: u8,
+
+    /// video codec
+    #[clap(long, value_enum, default_value_t = Codec::X265)]
+    #[serde(default)]
+    pub codec: Codec,
+
+    /// output pixel format and bit depth (defaults to the codec's own)
+    #[clap(long, value_enum)]
+    pub pix_fmt: Option<PixFmt>,
+
+    /// device target setting the codec, pixel format and encoder profile, or a profile of
+    /// reve.toml
+    #[clap(long, value_enum, conflicts_with_all = ["codec", "pix_fmt"])]
+    #[serde(default)]
+    pub profile: Option<Profile>,
+
+    /// keep the index of mp4 outputs at their end instead of moving it to the start
+    #[clap(long)]
+    #[serde(default)]
+    pub no_faststart: bool,
+
+    /// mux mp4 outputs as fragments, playable while they are still being written
+    #[clap(long)]
+    #[serde(default)]
+    pub fragmented: bool,
+
+    /// video encoding preset
+    #[clap(short = 'p', long, value_parser = preset_validation, default_value = "slow")]
+    pub preset: String,
+
+    /// x265 encoding parameters
+    #[clap(
+        short = 'x',
+        long,
+        value_parser,
+        default_value = "psy-rd=2:aq-strength=1:deblock=0,0:bframes=8"
+    )]
+    pub x265params: String,
+
+    /// start of the range to upscale (seconds or hh:mm:ss)
+    #[clap(long, value_parser = time_validation)]
+    pub start: Option<f64>,
+
+    /// end of the range to upscale (seconds or hh:mm:ss)
+    #[clap(long, value_parser = time_validation, conflicts_with = "duration")]
+    pub end: Option<f64>,
+
+    /// duration of the range to upscale (seconds or hh:mm:ss)
+    #[clap(long, value_parser = time_validation)]
+    pub duration: Option<f64>,
+
+    /// file listing the ranges to keep, one "start end" per line (seconds or hh:mm:ss), the
+    /// rest of the input is cut
+    #[clap(long, value_parser = edl_validation, conflicts_with_all = ["start", "end", "duration", "segment_on_scenecut", "metrics"])]
+    #[serde(default)]
+    pub edl: Option<String>,
+
+    /// frame rate of image sequences and other inputs without timing of their own
+    #[clap(long, value_parser = fps_validation)]
+    pub fps: Option<f32>,
+
+    /// realesrgan tile size (>= 32, 0 for realesrgan's default) or auto to tune it for the gpu
+    #[clap(long, value_parser = tile_validation)]
+    pub tile: Option<TileSize>,
+
+    /// crop the input before upscaling (w:h:x:y)
+    #[clap(long, conflicts_with = "autocrop")]
+    pub crop: Option<Crop>,
+
+    /// detect and crop black bars before upscaling
+    #[clap(long)]
+    #[serde(default)]
+    pub autocrop: bool,
+
+    /// pad a cropped video back to its original aspect ratio after upscaling
+    #[clap(long)]
+    #[serde(default)]
+    pub repad: bool,
+
+    /// maximum number of segments being exported, upscaled or merged at once
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..), default_value_t = 3)]
+    #[serde(default = "default_max_inflight_segments")]
+    pub max_inflight_segments: u16,
+
+    /// progress output format, bars switch to plain when stdout is not a terminal
+    #[clap(long, value_enum, default_value_t = ProgressFormat::Bar)]
+    #[serde(default)]
+    pub progress: ProgressFormat,
+
+    /// print only errors, same as --progress quiet
+    #[clap(short = 'q', long, conflicts_with = "progress")]
+    #[serde(default)]
+    pub quiet: bool,
+
+    /// verbosity of reve.log, written next to the temp folder
+    #[clap(long, value_enum, default_value_t = LogLevel::Info)]
+    #[serde(default)]
+    pub log_level: LogLevel,
+
+    /// number of times a failed export, upscale or merge of a segment is retried, waiting
+    /// 1s, 2s, 4s and so on before each retry
+    #[clap(long, default_value_t = 2)]
+    #[serde(default = "default_retries")]
+    pub retries: u8,
+
+    /// upscale runs of identical frames (static credits, black screens) once and copy the
+    /// result to the others
+    #[clap(long)]
+    #[serde(default)]
+    pub dedup_frames: bool,
+
+    /// keep the upscaled frames in the frame_cache folder of the work dir and reuse them
+    /// when the same frames are upscaled again with the same model and scale
+    #[clap(long)]
+    #[serde(default)]
+    pub frame_cache: bool,
+
+    /// end segments on the scene cut closest to --segmentsize (adds a scene detection pass)
+    #[clap(long)]
+    #[serde(default)]
+    pub segment_on_scenecut: bool,
+
+    /// shared folder the segments are published to, for `reve worker` to upscale them
+    #[clap(long, value_parser = share_validation)]
+    #[serde(default)]
+    pub share: Option<String>,
+
+    /// url to post to or shell command to run once the job is done
+    #[clap(long)]
+    #[serde(default)]
+    pub on_complete: Option<String>,
+
+    /// url to post to or shell command to run when the job fails or is stopped
+    #[clap(long)]
+    #[serde(default)]
+    pub on_error: Option<String>,
+
+    /// url to post to or shell command to run once an output file is written
+    #[clap(long)]
+    #[serde(default)]
+    pub on_file_done: Option<String>,
+
+    /// notify when the job ends: desktop, or email:<address> sent with the [smtp] table of
+    /// reve.toml (repeatable)
+    #[clap(long, value_parser = notify_validation)]
+    #[serde(default)]
+    pub notify: Vec<Notify>,
+
+    /// quality metrics of the output against the source, written next to it as json and csv
+    #[clap(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    pub metrics: Vec<Metric>,
+
+    /// render a subtitle stream of the input onto the frames before upscaling, the first one
+    /// when no index is given
+    #[clap(long, num_args = 0..=1, default_missing_value = "0")]
+    #[serde(default)]
+    pub burn_subs: Option<u32>,
+
+    /// remove noise from the frames before upscaling
+    #[clap(long, value_enum)]
+    #[serde(default)]
+    pub denoise: Option<Denoise>,
+
+    /// image format of the frames kept in temp, jpg and webp write 3-5x less at some quality
+    /// cost
+    #[clap(long, value_enum, default_value_t = FrameFormat::Png)]
+    #[serde(default)]
+    pub frame_format: FrameFormat,
+
+    /// quality of jpg and webp frames (1-100)
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..=100), default_value_t = 95)]
+    #[serde(default = "default_frame_quality")]
+    pub frame_quality: u8,
+
+    /// number of ffmpeg processes exporting the frames of a segment in parallel
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 1)]
+    #[serde(default = "default_export_threads")]
+    pub export_threads: u32,
+
+    /// audio streams of the input kept in the output: all, first, none or indices like 0,2
+    #[clap(long, default_value = "all")]
+    #[serde(default)]
+    pub audio_tracks: TrackSelection,
+
+    /// subtitle streams of the input kept in the output: all, first, none or indices like 0,2
+    #[clap(long, default_value = "all")]
+    #[serde(default)]
+    pub subtitle_tracks: TrackSelection,
+
+    /// container tag written to the output (key=value, can be repeated)
+    #[clap(long, value_parser = meta_validation)]
+    #[serde(default)]
+    pub set_meta: Vec<(String, String)>,
+
+    /// skip inputs tagged as upscaled by reve instead of upscaling them again
+    #[clap(long)]
+    #[serde(default)]
+    pub skip_upscaled: bool,
+
+    /// folder of a ram disk, like R: or /dev/shm, the frames are exported and upscaled in
+    /// instead of the temp folder
+    #[clap(long, value_parser = ramdisk_validation)]
+    #[serde(default)]
+    pub ramdisk: Option<String>,
+
+    /// vulkan device used by realesrgan, see `reve gpus` (auto when not given)
+    #[clap(long, visible_alias = "gpu", value_name = "ID")]
+    #[serde(default)]
+    pub gpu_id: Option<u32>,
+
+    /// wait before each segment while the gpu is at this temperature in °C or above, until it
+    /// cooled down 10°C (80 with --throttle)
+    #[clap(
+        long,
+        visible_alias = "throttle",
+        value_name = "CELSIUS",
+        num_args = 0..=1,
+        default_missing_value = "80",
+        value_parser = clap::value_parser!(u8).range(40..=110)
+    )]
+    #[serde(default)]
+    pub gpu_max_temp: Option<u8>,
+
+    /// run ffmpeg and realesrgan below the normal priority (and idle io priority on linux),
+    /// so the computer stays usable
+    #[clap(long)]
+    #[serde(default)]
+    pub nice: bool,
+
+    /// daily window of local time segments are upscaled in, like 01:00-07:00, waiting for it
+    /// to open outside of it
+    #[clap(long, value_parser = schedule_validation)]
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+
+    /// realesrgan threads as load:proc:save (scaled with the cpu count by default)
+    #[clap(long)]
+    #[serde(default)]
+    pub upscaler_threads: Option<UpscalerThreads>,
+
+    /// number of realesrgan processes upscaling the frames of a segment in parallel, for a
+    /// gpu a single one can not keep busy
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 1)]
+    #[serde(default = "default_upscaler_instances")]
+    pub upscaler_instances: u32,
+
+    /// discard the temporary files of a previous run of the same input and output instead of
+    /// resuming it
+    #[clap(long, visible_alias = "force")]
+    #[serde(default)]
+    pub no_resume: bool,
+
+    /// keep the temp folder of the job once the output is written, to inspect its segments
+    #[clap(long)]
+    #[serde(default)]
+    pub keep_temp: bool,
+
+    /// copy the exported frames, upscaled frames and encoded part of this segment to a
+    /// folder next to the output, to inspect them
+    #[clap(long, value_name = "INDEX")]
+    #[serde(default)]
+    pub inspect_segment: Option<u32>,
+
+    /// ffmpeg executable (looked up next to reve and in PATH by default)
+    #[clap(long, value_parser = binary_validation)]
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
+
+    /// ffprobe executable (looked up next to reve and in PATH by default)
+    #[clap(long, value_parser = binary_validation)]
+    #[serde(default)]
+    pub ffprobe_path: Option<String>,
+
+    /// realesrgan-ncnn-vulkan executable, with its models folder next to it (looked up next
+    /// to reve and in PATH by default)
+    #[clap(long, value_parser = binary_validation)]
+    #[serde(default)]
+    pub realesrgan_path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileSize {
+    Auto,
+    Fixed(u32),
+}
+
+impl fmt::Display for TileSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileSize::Auto => f.write_str("auto"),
+            TileSize::Fixed(size) => write!(f, "{}", size),
+        }
+    }
+}
+
+/// Notification sent when the job completes or fails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Notify {
+    /// Native notification of the desktop.
+    Desktop,
+    /// Mail to the address.
+    Email(String),
+}
+
+/// Options that only change how a job runs, not its output, so a job can be resumed with
+/// other values.
+const RUN_OPTIONS: &[&str] = &[
+    "max_inflight_segments",
+    "progress",
+    "quiet",
+    "log_level",
+    "retries",
+    "on_complete",
+    "on_error",
+    "on_file_done",
+    "notify",
+    "metrics",
+    "skip_upscaled",
+    "ramdisk",
+    "gpu_id",
+    "gpu_max_temp",
+    "nice",
+    "schedule",
+    "upscaler_threads",
+    "upscaler_instances",
+    "no_resume",
+    "keep_temp",
+    "inspect_segment",
+    "ffmpeg_path",
+    "ffprobe_path",
+    "realesrgan_path",
+];
+
+impl Args {
+    /// Returns the options changing the output whose value in `given` differs from the one
+    /// the job was started with, as `(option, saved value, given value)` sorted by option.
+    pub fn changed_options(&self, given_args: &Args) -> Vec<(String, String, String)> {
+        let (Ok(serde_json::Value::Object(saved)), Ok(serde_json::Value::Object(given))) =
+            (serde_json::to_value(self), serde_json::to_value(given_args))
+        else {
+            return Vec::new();
+        };
+        saved
+            .iter()
+            .filter(|(key, _)| !RUN_OPTIONS.contains(&key.as_str()))
+            .filter_map(|(key, value)| {
+                let given_value = given.get(key).unwrap_or(&serde_json::Value::Null);
+                (value != given_value).then(|| {
+                    (
+                        format!("--{}", key.replace('_', "-")),
+                        self.option_value(key, value),
+                        given_args.option_value(key, given_value),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the option `key` the way the command line takes it, from its field or, for
+    /// the plain ones, from its json `value`.
+    fn option_value(&self, key: &str, value: &serde_json::Value) -> String {
+        fn name(value: impl ValueEnum) -> String {
+            value
+                .to_possible_value()
+                .map_or_else(String::new, |value| value.get_name().to_string())
+        }
+        let value = match key {
+            "codec" => Some(name(self.codec)),
+            "frame_format" => Some(name(self.frame_format)),
+            "format" => self.format.map(name),
+            "denoise" => self.denoise.map(name),
+            "pix_fmt" => self.pix_fmt.map(name),
+            "profile" => self.profile.map(name),
+            "tile" => self.tile.map(|tile| tile.to_string()),
+            "crop" => self.crop.map(|crop| crop.to_string()),
+            "audio_tracks" => Some(self.audio_tracks.to_string()),
+            "subtitle_tracks" => Some(self.subtitle_tracks.to_string()),
+            "set_meta" => Some(
+                self.set_meta
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            _ => match value {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(value) => Some(value.clone()),
+                value => Some(value.to_string()),
+            },
+        };
+        value.unwrap_or_else(|| String::from("unset"))
+    }
+
+    /// Applies `--quiet` and replaces the progress bars by plain lines when stdout is not
+    /// a terminal, like under cron or in ci logs.
+    pub fn resolve_progress(&mut self, is_terminal: bool) {
+        if self.quiet {
+            self.progress = ProgressFormat::Quiet;
+        } else if self.progress == ProgressFormat::Bar && !is_terminal {
+            self.progress = ProgressFormat::Plain;
+        }
+    }
+
+    /// Sets the codec and pixel format of `--profile`.
+    pub fn apply_profile(&mut self) {
+        if let Some(profile) = self.profile {
+            self.codec = profile.codec();
+            self.pix_fmt = Some(profile.pix_fmt());
+        }
+    }
+
+    /// Checks the options that depend on each other.
+    pub fn validate(&self) -> Result<(), ReveError> {
+        if self.target_width.is_none() && self.target_height.is_none() {
+            models::model_name(&self.model, self.scale)?;
+        }
+        if self.burn_subs.is_some() && InputKind::from_path(&self.inputpath) != InputKind::Video {
+            return Err(ReveError::InvalidArgument(String::from(
+                "--burn-subs needs a video input with subtitles",
+            )));
+        }
+        if let (Some(format), Some(output_path)) = (self.format, &self.outputpath) {
+            if Animation::from_path(output_path) != Some(format) {
+                return Err(ReveError::InvalidArgument(format!(
+                    "--format {} needs a .{} output path",
+                    format.extension(),
+                    format.extension()
+                )));
+            }
+        }
+        if let Some(pix_fmt) = self.pix_fmt {
+            if !self.codec.supports(pix_fmt) {
+                return Err(ReveError::InvalidArgument(format!(
+                    "{} does not support the {} pixel format",
+                    self.codec.name(),
+                    pix_fmt.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the [`UPSCALED_TAG`] of the input if it was upscaled by reve and
+    /// `--skip-upscaled` is set.
+    pub fn upscaled_by_reve(&self) -> Result<Option<String>, ReveError> {
+        if !self.skip_upscaled || InputKind::from_path(&self.inputpath) != InputKind::Video {
+            return Ok(None);
+        }
+        ffprobe::format_tag(&self.binaries(), &self.inputpath, UPSCALED_TAG)
+    }
+
+    /// Returns the output path, rendering `--output-template` when none was given.
+    pub fn resolve_output_path(&self) -> Result<String, ReveError> {
+        let output_path = match &self.outputpath {
+            Some(output_path) => output_path.clone(),
+            None => {
+                let info = probe(&self.binaries(), &self.inputpath, self.fps)?;
+                let scale = self.upscale_ratio(info.width, info.height)?;
+                let streams_fit_mp4 = Path::new(&self.inputpath)
+                    .extension()
+                    .is_some_and(|extension| extension == "mp4")
+                    && self.codec.fits_mp4()
+                    && self.mp4_fit()?.is_some_and(|fit| fit.is_lossless());
+                let height = self
+                    .target_size(info.width, info.height)
+                    .map_or(info.height * scale as u32, |(_, height)| height);
+                let file_name = render_output_template(
+                    &self.output_template,
+                    &self.inputpath,
+                    scale,
+                    &self.model,
+                    height,
+                    self.codec,
+                    streams_fit_mp4,
+                );
+                let file_name = match self.format {
+                    Some(format) => Path::new(&file_name)
+                        .with_extension(format.extension())
+                        .to_string_lossy()
+                        .into_owned(),
+                    None => file_name,
+                };
+                let output_dir = match &self.output_dir {
+                    Some(output_dir) => PathBuf::from(output_dir),
+                    None => Path::new(&self.inputpath)
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .unwrap_or_default(),
+                };
+                let output_path = output_dir.join(file_name).to_string_lossy().to_string();
+                output_validation(&output_path).map_err(|err| {
+                    ReveError::InvalidArgument(format!(
+                        "invalid output path {:?}: {}",
+                        output_path, err
+                    ))
+                })?
+            }
+        };
+
+        let is_mp4 = Path::new(&output_path)
+            .extension()
+            .is_some_and(|extension| extension == "mp4");
+        if is_mp4 && !self.codec.fits_mp4() {
+            return Err(ReveError::InvalidArgument(format!(
+                "{} can only be exported as mkv file",
+                self.codec.name()
+            )));
+        }
+        // Only a forced mp4 output gets here with streams mp4 can not hold, the template
+        // picks mkv for them
+        let fit = if is_mp4 { self.mp4_fit()? } else { None };
+        if let Some(fit) = fit.filter(|fit| !fit.dropped.is_empty()) {
+            return Err(ReveError::InvalidArgument(format!(
+                "mp4 can not hold the {} of the input, export it as mkv or leave the \
+                 subtitles out with --subtitle-tracks",
+                fit.dropped.join(", ")
+            )));
+        }
+        Ok(output_path)
+    }
+
+    /// Returns how the streams of the input kept by `--audio-tracks` and `--subtitle-tracks`
+    /// fit an mp4 output, `None` for inputs without streams to mux.
+    fn mp4_fit(&self) -> Result<Option<Mp4Fit>, ReveError> {
+        if InputKind::from_path(&self.inputpath) != InputKind::Video {
+            return Ok(None);
+        }
+        let selection = MuxSelection {
+            audio_tracks: &self.audio_tracks,
+            subtitle_tracks: &self.subtitle_tracks,
+            burn_subs: self.burn_subs,
+            mp4: true,
+        };
+        let streams = mux::source_streams(&self.binaries(), Path::new(&self.inputpath))?;
+        Ok(Some(selection.mp4_fit(&streams)))
+    }
+
+    /// Returns the output size of a `width`x`height` input given `--target-width` or
+    /// `--target-height`, computing the other one from the aspect ratio if it is not given.
+    pub fn target_size(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        // Encoders need even dimensions
+        let even = |size: f64| ((size / 2.0).round() as u32 * 2).max(2);
+        match (self.target_width, self.target_height) {
+            (Some(target_width), Some(target_height)) => Some((target_width, target_height)),
+            (Some(target_width), None) => Some((
+                target_width,
+                even(height as f64 * target_width as f64 / width as f64),
+            )),
+            (None, Some(target_height)) => Some((
+                even(width as f64 * target_height as f64 / height as f64),
+                target_height,
+            )),
+            (None, None) => None,
+        }
+    }
+
+    /// Returns the upscale ratio of a `width`x`height` input: `--scale`, or the smallest scale
+    /// of the model reaching the target size.
+    pub fn upscale_ratio(&self, width: u32, height: u32) -> Result<u8, ReveError> {
+        let Some((target_width, target_height)) = self.target_size(width, height) else {
+            return Ok(self.scale);
+        };
+        models::scales(&self.model)
+            .into_iter()
+            .filter(|&scale| {
+                width * scale as u32 >= target_width && height * scale as u32 >= target_height
+            })
+            .min()
+            .ok_or_else(|| {
+                ReveError::InvalidArgument(format!(
+                    "{} can not upscale {}x{} to {}x{}",
+                    self.model, width, height, target_width, target_height
+                ))
+            })
+    }
+
+    /// Returns the programs to run, from `--ffmpeg-path` and the other tool paths.
+    pub fn binaries(&self) -> Binaries {
+        Binaries::new(
+            self.ffmpeg_path.as_deref(),
+            self.ffprobe_path.as_deref(),
+            self.realesrgan_path.as_deref(),
+        )
+    }
+
+    /// Returns the part of the input selected with `--start`, `--end` and `--duration`.
+    pub fn time_range(&self) -> TimeRange {
+        let start = self.start.unwrap_or(0.0);
+        TimeRange {
+            start,
+            end: self.end.or(self.duration.map(|duration| start + duration)),
+        }
+    }
+}
+
+const DEFAULT_OUTPUT_TEMPLATE: &str = "{stem}.{codec}.{ext}";
+
+fn default_output_template() -> String {
+    String::from(DEFAULT_OUTPUT_TEMPLATE)
+}
+
+/// Builds an output file name by replacing the placeholders of `template`.
+///
+/// `height` is the height of the output and `{ext}` keeps the input container, using mkv for
+/// script inputs, codecs mp4 cannot hold and inputs whose streams would not fit it as they
+/// are (`streams_fit_mp4` unset).
+pub fn render_output_template(
+    template: &str,
+    input_path: &str,
+    scale: u8,
+    model: &str,
+    height: u32,
+    codec: Codec,
+    streams_fit_mp4: bool,
+) -> String {
+    let input_path = Path::new(input_path);
+    let mut stem = input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Name image sequences after their prefix (frame%04d.png) or else their directory
+    if let Some(pattern) = SequencePattern::parse(&stem) {
+        stem = pattern
+            .prefix
+            .trim_end_matches(['_', '-', '.', ' '])
+            .to_string();
+        if stem.is_empty() {
+            stem = input_path
+                .parent()
+                .and_then(|dir| dir.file_name())
+                .map(|dir| dir.to_string_lossy().to_string())
+                .unwrap_or_else(|| String::from("sequence"));
+        }
+    }
+    let ext = match input_path.extension().and_then(|e| e.to_str()) {
+        Some("mp4") if codec.fits_mp4() && streams_fit_mp4 => "mp4",
+        _ => "mkv",
+    };
+    template
+        .replace("{stem}", &stem)
+        .replace("{scale}", &scale.to_string())
+        .replace("{model}", model)
+        .replace("{codec}", codec.name())
+        .replace("{height}", &height.to_string())
+        .replace("{ext}", ext)
+}
+
+fn default_model() -> String {
+    String::from(models::DEFAULT_FAMILY)
+}
+
+fn default_max_inflight_segments() -> u16 {
+    3
+}
+
+fn default_retries() -> u8 {
+    2
+}
+
+fn input_validation(s: &str) -> Result<String, String> {
+    let p = Path::new(s);
+    if ImageInput::from_path(s) == Some(ImageInput::Sequence) {
+        return image_input::sequence_frames(s)
+            .map(|_| s.to_string())
+            .map_err(|err| err.to_string());
+    }
+    if !p.exists() {
+        return Err(String::from("input path not found"));
+    }
+    if is_video_path(p) {
+        return Ok(s.to_string());
+    }
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("vpy" | "avs" | "gif" | "apng") => Ok(s.to_string()),
+        Some("png") if ImageInput::from_path(s).is_some() => Ok(s.to_string()),
+        _ => Err(format!(
+            "valid input formats: {}/vpy/avs/gif/apng, an animated png or an image sequence \
+             (frame%04d.png)",
+            VIDEO_EXTENSIONS.join("/")
+        )),
+    }
+}
+
+fn binary_validation(s: &str) -> Result<String, String> {
+    if Path::new(s).is_file() {
+        Ok(s.to_string())
+    } else {
+        Err(String::from("executable not found"))
+    }
+}
+
+fn meta_validation(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(String::from("valid format: key=value")),
+    }
+}
+
+fn share_validation(s: &str) -> Result<String, String> {
+    if Path::new(s).is_dir() {
+        Ok(s.to_string())
+    } else {
+        Err(String::from("folder not found"))
+    }
+}
+
+/// Accepts a bare drive letter like `R:` as the root of the drive, which would otherwise be
+/// its current folder.
+fn ramdisk_validation(s: &str) -> Result<String, String> {
+    let path = match s.as_bytes() {
+        [letter, b':'] if letter.is_ascii_alphabetic() => format!("{}\\", s),
+        _ => s.to_string(),
+    };
+    if Path::new(&path).is_dir() {
+        Ok(path)
+    } else {
+        Err(String::from("folder not found, create the ram disk first"))
+    }
+}
+
+pub fn model_validation(s: &str) -> Result<String, String> {
+    if models::families().contains(&s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("valid: {}", models::families().join("/")))
+    }
+}
+
+fn fps_validation(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err(String::from("valid: a frame rate above 0")),
+    }
+}
+
+fn output_validation(s: &str) -> Result<String, String> {
+    let p = Path::new(s);
+    if p.exists() {
+        return Err(String::from("output path already exists"));
+    }
+    match p.extension().and_then(|e| e.to_str()) {
+        Some("mp4" | "mkv" | "gif" | "webp" | "avif") => Ok(s.to_string()),
+        _ => Err(String::from("valid output formats: mp4/mkv/gif/webp/avif")),
+    }
+}
+
+fn edl_validation(s: &str) -> Result<String, String> {
+    edl::read(s)
+        .map(|_| s.to_string())
+        .map_err(|err| err.to_string())
+}
+
+/// Parses a timestamp given either in seconds (`90.5`) or as `[hh:]mm:ss[.ms]`.
+pub(crate) fn time_validation(s: &str) -> Result<f64, String> {
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return Err(String::from("valid time formats: seconds or hh:mm:ss"));
+    }
+    let mut seconds = 0.0;
+    for part in parts {
+        // f64 parses nan and inf, which ffmpeg can not seek to
+        let value = part
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| String::from("valid time formats: seconds or hh:mm:ss"))?;
+        if value < 0.0 {
+            return Err(String::from("time can not be negative"));
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Ok(seconds)
+}
+
+fn notify_validation(s: &str) -> Result<Notify, String> {
+    if s == "desktop" {
+        return Ok(Notify::Desktop);
+    }
+    match s.strip_prefix("email:") {
+        Some(address) if address.contains('@') => Ok(Notify::Email(address.to_string())),
+        _ => Err(String::from("valid: desktop or email:<address>")),
+    }
+}
+
+fn schedule_validation(s: &str) -> Result<Schedule, String> {
+    Schedule::parse(s)
+}
+
+fn tile_validation(s: &str) -> Result<TileSize, String> {
+    if s == "auto" {
+        return Ok(TileSize::Auto);
+    }
+    match s.parse::<u32>() {
+        Ok(tile_size) if tile_size == 0 || tile_size >= 32 => Ok(TileSize::Fixed(tile_size)),
+        _ => Err(String::from("valid: auto, 0 or a tile size of at least 32")),
+    }
+}
+
+pub(crate) fn preset_validation(s: &str) -> Result<String, String> {
+    match s {
+        "ultrafast" | "superfast" | "veryfast" | "faster" | "fast" | "medium" | "slow"
+        | "slower" | "veryslow" => Ok(s.to_string()),
+        _ => Err(String::from(
+            "valid: ultrafast/superfast/veryfast/faster/fast/medium/slow/slower/veryslow",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_validation() {
+        assert_eq!(time_validation("90.5"), Ok(90.5));
+        assert_eq!(time_validation("07:30"), Ok(450.0));
+        assert_eq!(time_validation("00:05:00"), Ok(300.0));
+        assert_eq!(time_validation("1:00:00.25"), Ok(3600.25));
+        assert!(time_validation("1:2:3:4").is_err());
+        assert!(time_validation("-5").is_err());
+        assert!(time_validation("five").is_err());
+        assert!(time_validation("nan").is_err());
+        assert!(time_validation("inf").is_err());
+        assert!(time_validation("-infinity").is_err());
+        assert!(time_validation("00:NaN").is_err());
+    }
+
+    #[test]
+    fn test_meta_validation() {
+        assert_eq!(
+            meta_validation("title=One Piece = 1"),
+            Ok((String::from("title"), String::from("One Piece = 1")))
+        );
+        assert_eq!(
+            meta_validation("comment="),
+            Ok((String::from("comment"), String::new()))
+        );
+        assert!(meta_validation("=value").is_err());
+        assert!(meta_validation("title").is_err());
+    }
+
+    #[test]
+    fn test_render_output_template() {
+        assert_eq!(
+            render_output_template(
+                DEFAULT_OUTPUT_TEMPLATE,
+                "/videos/episode 01.mkv",
+                2,
+                models::DEFAULT_FAMILY,
+                1080,
+                Codec::X265,
+                true
+            ),
+            "episode 01.x265.mkv"
+        );
+        assert_eq!(
+            render_output_template(
+                "{stem}_{height}p_{scale}x.{ext}",
+                "/anime/op.mp4",
+                4,
+                models::DEFAULT_FAMILY,
+                1920,
+                Codec::X265,
+                true
+            ),
+            "op_1920p_4x.mp4"
+        );
+        assert_eq!(
+            render_output_template(
+                DEFAULT_OUTPUT_TEMPLATE,
+                "/anime/op.mp4",
+                2,
+                models::DEFAULT_FAMILY,
+                1440,
+                Codec::X265,
+                false
+            ),
+            "op.x265.mkv"
+        );
+        assert_eq!(
+            render_output_template(
+                "{stem}-{model}.{ext}",
+                "filtered.vpy",
+                4,
+                "realesrgan-x4plus",
+                2880,
+                Codec::X265,
+                true
+            ),
+            "filtered-realesrgan-x4plus.mkv"
+        );
+        assert_eq!(
+            render_output_template(
+                DEFAULT_OUTPUT_TEMPLATE,
+                "/sprites/walk_%04d.png",
+                4,
+                models::DEFAULT_FAMILY,
+                256,
+                Codec::X265,
+                true
+            ),
+            "walk.x265.mkv"
+        );
+        assert_eq!(
+            render_output_template(
+                DEFAULT_OUTPUT_TEMPLATE,
+                "/sprites/run/%d.png",
+                4,
+                models::DEFAULT_FAMILY,
+                256,
+                Codec::X265,
+                true
+            ),
+            "run.x265.mkv"
+        );
+        assert_eq!(
+            render_output_template(
+                DEFAULT_OUTPUT_TEMPLATE,
+                "/anime/op.mp4",
+                2,
+                models::DEFAULT_FAMILY,
+                2160,
+                Codec::Prores,
+                true
+            ),
+            "op.prores.mkv"
+        );
+    }
+
+    #[test]
+    fn test_target_size() {
+        let mut args = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "--target-width",
+            "1920",
+            "--target-height",
+            "1080",
+        ]);
+        assert_eq!(args.target_size(640, 480), Some((1920, 1080)));
+        assert_eq!(args.upscale_ratio(640, 480).unwrap(), 3);
+
+        args.target_width = None;
+        assert_eq!(args.target_size(640, 480), Some((1440, 1080)));
+        assert_eq!(args.upscale_ratio(640, 480).unwrap(), 3);
+        assert!(args.upscale_ratio(200, 150).is_err());
+
+        args.target_height = None;
+        assert_eq!(args.upscale_ratio(640, 480).unwrap(), args.scale);
+    }
+
+    #[test]
+    fn test_resolve_progress() {
+        let mut args = Args::parse_from(["reve", "-i", "../reve-cli/assets/test.mp4"]);
+        args.resolve_progress(true);
+        assert_eq!(args.progress, ProgressFormat::Bar);
+        args.resolve_progress(false);
+        assert_eq!(args.progress, ProgressFormat::Plain);
+
+        let mut args = Args::parse_from(["reve", "-i", "../reve-cli/assets/test.mp4", "-q"]);
+        args.resolve_progress(true);
+        assert_eq!(args.progress, ProgressFormat::Quiet);
+
+        let mut args = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "--progress",
+            "json",
+        ]);
+        args.resolve_progress(false);
+        assert_eq!(args.progress, ProgressFormat::Json);
+    }
+
+    #[test]
+    fn test_changed_options() {
+        let saved = Args::parse_from(["reve", "-i", "../reve-cli/assets/test.mp4", "-s", "4"]);
+        let given = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "-s",
+            "4",
+            "--nice",
+            "--progress",
+            "json",
+        ]);
+        assert!(saved.changed_options(&given).is_empty());
+
+        let given = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "--crf",
+            "20",
+            "--tile",
+            "256",
+            "--codec",
+            "av1",
+            "--audio-tracks",
+            "0,2",
+        ]);
+        assert_eq!(
+            saved.changed_options(&given),
+            vec![
+                (
+                    String::from("--audio-tracks"),
+                    String::from("all"),
+                    String::from("0,2")
+                ),
+                (
+                    String::from("--codec"),
+                    String::from("x265"),
+                    String::from("av1")
+                ),
+                (
+                    String::from("--crf"),
+                    String::from("15"),
+                    String::from("20")
+                ),
+                (
+                    String::from("--scale"),
+                    String::from("4"),
+                    String::from("2")
+                ),
+                (
+                    String::from("--tile"),
+                    String::from("unset"),
+                    String::from("256")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_notify_validation() {
+        assert_eq!(notify_validation("desktop"), Ok(Notify::Desktop));
+        assert_eq!(
+            notify_validation("email:me@example.com"),
+            Ok(Notify::Email(String::from("me@example.com")))
+        );
+        assert!(notify_validation("email:").is_err());
+        assert!(notify_validation("sms").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::binaries::Binaries;
+use crate::cli::preset_validation;
+use crate::encoder::Codec;
+use crate::models;
+use crate::toml_config::TomlConfig;
+use crate::{paths, Args, ReveError};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FOLDER: &str = "reve-gui";
+const CONFIG_FILE: &str = "reve-gui-config.json";
+
+/// Settings shared by the GUI and the CLI, stored in the GUI's configuration file.
+///
+/// The CLI uses them as the defaults of the options not given on the command line.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct ConfigData {
+    pub application_logs: bool,
+    #[serde(rename = "default-upscale-type")]
+    pub model: String,
+    #[serde(rename = "default-upscale-scale")]
+    pub scale: u8,
+    pub crf: u8,
+    pub preset: String,
+    pub codec: Codec,
+    pub segment_size: u32,
+    /// Folder holding the `temp` folder, see [`ConfigData::work_dir`] when unset.
+    pub temp_dir: Option<String>,
+    /// Vulkan device used by realesrgan, its default when unset.
+    pub gpu_id: Option<u32>,
+    /// Executables of the tools, looked up like [`crate::binaries::find`] when unset.
+    pub ffmpeg_path: Option<String>,
+    pub ffprobe_path: Option<String>,
+    pub realesrgan_path: Option<String>,
+}
+
+impl Default for ConfigData {
+    fn default() -> Self {
+        Self {
+            application_logs: false,
+            model: String::from(models::DEFAULT_FAMILY),
+            scale: 2,
+            crf: 15,
+            preset: String::from("slow"),
+            codec: Codec::default(),
+            segment_size: 1000,
+            temp_dir: None,
+            gpu_id: None,
+            ffmpeg_path: None,
+            ffprobe_path: None,
+            realesrgan_path: None,
+        }
+    }
+}
+
+impl ConfigData {
+    /// Returns the path of the configuration file.
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_FOLDER).join(CONFIG_FILE))
+    }
+
+    /// Loads the configuration file, returning the defaults if there is none.
+    pub fn load() -> Result<ConfigData, ReveError> {
+        let content = match Self::path().map(fs::read_to_string) {
+            Some(Ok(content)) => content,
+            _ => return Ok(ConfigData::default()),
+        };
+        let config: ConfigData = serde_json::from_str(&content).map_err(|err| {
+            ReveError::InvalidArgument(format!("invalid configuration file: {}", err))
+        })?;
+        config.validate().map_err(|err| {
+            ReveError::InvalidArgument(format!("invalid configuration file: {}", err))
+        })?;
+        Ok(config)
+    }
+
+    /// Loads the configuration file and the `reve.toml` of the CLI, whose settings take
+    /// precedence.
+    pub fn load_with_file(exe_dir: &Path) -> Result<(ConfigData, Option<TomlConfig>), ReveError> {
+        let mut config = Self::load()?;
+        let file = TomlConfig::load(exe_dir)?;
+        if let Some(file) = &file {
+            config.apply_file(file);
+        }
+        Ok((config, file))
+    }
+
+    /// Checks every setting against the values the command line accepts.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(2..=4).contains(&self.scale) {
+            return Err(String::from("scale must be 2, 3 or 4"));
+        }
+        models::model_name(&self.model, self.scale).map_err(|err| err.to_string())?;
+        if self.crf > 51 {
+            return Err(String::from("crf must be between 0 and 51"));
+        }
+        preset_validation(&self.preset)?;
+        if self.segment_size == 0 {
+            return Err(String::from("segment size must be at least 1"));
+        }
+        if let Some(temp_dir) = &self.temp_dir {
+            if !Path::new(temp_dir).is_dir() {
+                return Err(format!("temp dir {:?} is not a folder", temp_dir));
+            }
+        }
+        for path in [&self.ffmpeg_path, &self.ffprobe_path, &self.realesrgan_path]
+            .into_iter()
+            .flatten()
+        {
+            if !Path::new(path).is_file() {
+                return Err(format!("executable {:?} not found", path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the folder reve should create its `temp` folder in.
+    ///
+    /// Without a configured `temp_dir` this is the executable's folder, unless reve cannot
+    /// write there (an installed package or a macOS app bundle) or it is on a network share,
+    /// in which case a `reve` folder of the user's cache directory is created and used
+    /// instead.
+    pub fn work_dir(&self, exe_dir: &Path) -> Result<PathBuf, ReveError> {
+        if let Some(temp_dir) = &self.temp_dir {
+            return Ok(PathBuf::from(temp_dir));
+        }
+        if is_writable(exe_dir)
+            && !in_app_bundle(exe_dir)
+            && !paths::is_network(&exe_dir.to_string_lossy())
+        {
+            return Ok(exe_dir.to_path_buf());
+        }
+        let Some(cache_dir) = dirs::cache_dir() else {
+            return Ok(exe_dir.to_path_buf());
+        };
+        let work_dir = cache_dir.join("reve");
+        fs::create_dir_all(&work_dir)?;
+        Ok(work_dir)
+    }
+
+    /// Replaces the settings shared with `reve.toml` by the ones of the file, so workers and
+    /// `reve image` use its folder and executables too.
+    pub fn apply_file(&mut self, file: &TomlConfig) {
+        let get = |key| file.get_str(key).map(String::from);
+        self.temp_dir = get("temp-dir").or(self.temp_dir.take());
+        self.ffmpeg_path = get("ffmpeg-path").or(self.ffmpeg_path.take());
+        self.ffprobe_path = get("ffprobe-path").or(self.ffprobe_path.take());
+        self.realesrgan_path = get("realesrgan-path").or(self.realesrgan_path.take());
+        self.gpu_id = file.get_u32("gpu-id").or(self.gpu_id);
+    }
+
+    /// Replaces the options of `args` left to their default in `matches` by the configured ones.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) {
+        self.apply_unset(args, matches, &[]);
+    }
+
+    /// Same as [`ConfigData::apply`], leaving the options of `file_ids` to the defaults
+    /// `reve.toml` gave them.
+    fn apply_unset(&self, args: &mut Args, matches: &ArgMatches, file_ids: &[String]) {
+        let is_default = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            ) && !file_ids.iter().any(|file_id| file_id == id)
+        };
+        if is_default("model") {
+            args.model = self.model.clone();
+        }
+        if is_default("scale") {
+            args.scale = self.scale;
+        }
+        if is_default("crf") {
+            args.crf = self.crf;
+        }
+        if is_default("preset") {
+            args.preset = self.preset.clone();
+        }
+        if is_default("codec") {
+            args.codec = self.codec;
+        }
+        if is_default("segmentsize") {
+            args.segmentsize = self.segment_size;
+        }
+        if is_default("gpu_id") {
+            args.gpu_id = self.gpu_id;
+        }
+        if is_default("ffmpeg_path") {
+            args.ffmpeg_path = self.ffmpeg_path.clone();
+        }
+        if is_default("ffprobe_path") {
+            args.ffprobe_path = self.ffprobe_path.clone();
+        }
+        if is_default("realesrgan_path") {
+            args.realesrgan_path = self.realesrgan_path.clone();
+        }
+    }
+
+    /// Returns the programs to run outside of a job, like the GUI preview or a worker.
+    pub fn binaries(&self) -> Binaries {
+        Binaries::new(
+            self.ffmpeg_path.as_deref(),
+            self.ffprobe_path.as_deref(),
+            self.realesrgan_path.as_deref(),
+        )
+    }
+
+    /// Gives the options of the upscale `command` the values of `reve.toml`, and of the named
+    /// profile `argv` selects in it, as defaults, removing the selection from `argv`. Returns
+    /// the command with the ids of the options the file gives, for [`ConfigData::complete_args`].
+    pub fn upscale_command(
+        command: Command,
+        argv: &mut Vec<OsString>,
+        file: Option<&TomlConfig>,
+    ) -> Result<(Command, Vec<String>), ReveError> {
+        match file {
+            Some(file) => file.select_profile(argv)?.apply_defaults(command),
+            None => Ok((command, Vec::new())),
+        }
+    }
+
+    /// Sets the options of `args`, parsed into `matches` with a command of
+    /// [`ConfigData::upscale_command`], that neither the command line nor `reve.toml` give to
+    /// the configuration, then applies the device profile.
+    pub fn complete_args(&self, args: &mut Args, matches: &ArgMatches, file_ids: &[String]) {
+        self.apply_unset(args, matches, file_ids);
+        args.apply_profile();
+    }
+}
+
+/// Returns whether a file can be created in `dir`.
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(".reve-write-test");
+    let writable = fs::File::create(&probe).is_ok();
+    let _ = fs::remove_file(probe);
+    writable
+}
+
+/// Returns whether `dir` is inside a macOS `.app` bundle, which must not be modified once
+/// signed.
+fn in_app_bundle(dir: &Path) -> bool {
+    dir.ancestors()
+        .any(|dir| dir.extension().is_some_and(|ext| ext == "app"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn test_config_apply() {
+        let config = ConfigData {
+            scale: 4,
+            crf: 20,
+            codec: Codec::Ffv1,
+            gpu_id: Some(1),
+            ..ConfigData::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let matches = Args::command()
+            .try_get_matches_from(["reve", "-i", "../reve-cli/assets/test.mp4", "-s", "3"])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches);
+        assert_eq!(args.scale, 3);
+        assert_eq!(args.crf, 20);
+        assert_eq!(args.codec, Codec::Ffv1);
+        assert_eq!(args.gpu_id, Some(1));
+
+        let invalid = ConfigData {
+            preset: String::from("fastest"),
+            ..ConfigData::default()
+        };
+        assert!(invalid.validate().is_err());
+
+        let invalid = ConfigData {
+            ffmpeg_path: Some(String::from("/missing/ffmpeg")),
+            ..ConfigData::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_work_dir() {
+        let exe_dir = std::env::current_exe().unwrap();
+        let exe_dir = exe_dir.parent().unwrap();
+        let config = ConfigData::default();
+        assert_eq!(config.work_dir(exe_dir).unwrap(), exe_dir);
+
+        let config = ConfigData {
+            temp_dir: Some(String::from("scratch")),
+            ..ConfigData::default()
+        };
+        assert_eq!(config.work_dir(exe_dir).unwrap(), Path::new("scratch"));
+
+        assert!(in_app_bundle(Path::new(
+            "/Applications/reve.app/Contents/MacOS"
+        )));
+        assert!(!in_app_bundle(exe_dir));
+    }
+}
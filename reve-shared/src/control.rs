@@ -0,0 +1,122 @@
+use std::process::Child;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+#[derive(Default)]
+struct ControlState {
+    paused: bool,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<ControlState>,
+    condvar: Condvar,
+    children: Mutex<Vec<Weak<Mutex<Child>>>>,
+}
+
+/// Pause and stop switches shared between the control inputs and the stages of a job.
+///
+/// Pausing lets the segment being upscaled finish and holds the next one back until resumed,
+/// freeing the gpu while keeping every finished segment. Stopping kills the running commands
+/// so the job ends with only fully merged segments counted as done.
+#[derive(Clone, Default)]
+pub struct JobControl {
+    shared: Arc<Shared>,
+}
+
+impl JobControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.shared.state.lock().unwrap().paused = paused;
+        self.shared.condvar.notify_all();
+    }
+
+    pub fn toggle(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.paused = !state.paused;
+        self.shared.condvar.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+
+    /// Stops the job: kills the tracked commands and wakes a paused stage.
+    pub fn stop(&self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.condvar.notify_all();
+        for child in self.shared.children.lock().unwrap().drain(..) {
+            if let Some(child) = child.upgrade() {
+                let _ = child.lock().unwrap().kill();
+            }
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.shared.state.lock().unwrap().stopped
+    }
+
+    /// Blocks while paused, returning early when stopped.
+    pub fn wait(&self) {
+        let _unpaused = self
+            .shared
+            .condvar
+            .wait_while(self.shared.state.lock().unwrap(), |state| {
+                state.paused && !state.stopped
+            })
+            .unwrap();
+    }
+
+    /// Kills `child` when the job is stopped, right away if it already is.
+    pub(crate) fn track(&self, child: &Arc<Mutex<Child>>) {
+        let mut children = self.shared.children.lock().unwrap();
+        if self.is_stopped() {
+            let _ = child.lock().unwrap().kill();
+            return;
+        }
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(child));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn wait_returns_once_resumed() {
+        let control = JobControl::new();
+        control.wait();
+
+        control.toggle();
+        assert!(control.is_paused());
+        let resumer = control.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            resumer.set_paused(false);
+        });
+        control.wait();
+        assert!(!control.is_paused());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn wait_returns_once_stopped() {
+        let control = JobControl::new();
+        control.set_paused(true);
+        let stopper = control.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopper.stop();
+        });
+        control.wait();
+        assert!(control.is_paused());
+        assert!(control.is_stopped());
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Passing runs of identical frames through the upscale with `--dedup-frames`.
+//!
+//! Static credits, title cards and black screens export to byte-identical images, since the
+//! image encoders are deterministic. Only the first frame of each run is left for realesrgan;
+//! the upscaled frame is then copied under the names of the others, so the merge still gets
+//! every frame of the segment.
+
+use crate::ReveError;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+/// A frame removed before upscaling and the frame of its run that is upscaled instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Duplicate {
+    pub frame: OsString,
+    pub source: OsString,
+}
+
+/// Removes the frames of `dir` identical to the frame before them, returning them with the
+/// first frame of their run.
+pub(crate) fn remove_duplicates(dir: &Path) -> Result<Vec<Duplicate>, ReveError> {
+    let mut names: Vec<OsString> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort();
+
+    let mut duplicates = Vec::new();
+    // First frame of the current run and its content
+    let mut run: Option<(OsString, Vec<u8>)> = None;
+    for name in names {
+        let content = fs::read(dir.join(&name))?;
+        match &run {
+            Some((source, run_content)) if *run_content == content => {
+                fs::remove_file(dir.join(&name))?;
+                duplicates.push(Duplicate {
+                    frame: name,
+                    source: source.clone(),
+                });
+            }
+            _ => run = Some((name, content)),
+        }
+    }
+    Ok(duplicates)
+}
+
+/// Copies the upscaled frames of `dir` to the names of their removed duplicates.
+pub(crate) fn restore_duplicates(dir: &Path, duplicates: &[Duplicate]) -> Result<(), ReveError> {
+    for duplicate in duplicates {
+        let frame = dir.join(&duplicate.frame);
+        // A hard link takes no space, the merge only reads the frames
+        if fs::hard_link(dir.join(&duplicate.source), &frame).is_err() {
+            fs::copy(dir.join(&duplicate.source), &frame)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicates() {
+        let dir = std::env::temp_dir().join(format!("reve-dedup-{}", std::process::id()));
+        let (exported, upscaled) = (dir.join("exported"), dir.join("upscaled"));
+        fs::create_dir_all(&exported).unwrap();
+        fs::create_dir_all(&upscaled).unwrap();
+        for (frame, content) in ["a", "b", "b", "b", "a", "c", "c"].iter().enumerate() {
+            fs::write(exported.join(format!("frame{:08}.png", frame + 1)), content).unwrap();
+        }
+
+        let duplicates = remove_duplicates(&exported).unwrap();
+        let pairs: Vec<(&str, &str)> = duplicates
+            .iter()
+            .map(|d| (d.frame.to_str().unwrap(), d.source.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("frame00000003.png", "frame00000002.png"),
+                ("frame00000004.png", "frame00000002.png"),
+                ("frame00000007.png", "frame00000006.png"),
+            ]
+        );
+        assert_eq!(fs::read_dir(&exported).unwrap().count(), 4);
+
+        for frame in [1, 2, 5, 6] {
+            fs::write(
+                upscaled.join(format!("frame{:08}.png", frame)),
+                frame.to_string(),
+            )
+            .unwrap();
+        }
+        restore_duplicates(&upscaled, &duplicates).unwrap();
+        assert_eq!(fs::read_dir(&upscaled).unwrap().count(), 7);
+        assert_eq!(
+            fs::read_to_string(upscaled.join("frame00000004.png")).unwrap(),
+            "2"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{FrameFormat, ReveError, Video};
+use std::fs;
+use std::path::Path;
+
+/// Returns the space available to the current user on the volume holding `path`.
+pub fn free_space(path: impl AsRef<Path>) -> Result<u64, ReveError> {
+    Ok(fs2::available_space(path)?)
+}
+
+/// Returns the size of the files under `path`, skipping the ones that cannot be read, which
+/// a running job may be removing.
+pub fn dir_size(path: impl AsRef<Path>) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .map_while(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => dir_size(entry.path()),
+            _ => entry.metadata().map_or(0, |metadata| metadata.len()),
+        })
+        .sum()
+}
+
+/// Estimates the size of a `width`x`height` frame in `format`.
+fn frame_bytes(width: u32, height: u32, format: FrameFormat) -> u64 {
+    width as u64 * height as u64 * 3 / format.compression_ratio()
+}
+
+impl Video {
+    /// Estimates the temp space used by one segment, from its exported frames to its
+    /// upscaled frames, before the merge removes them.
+    pub fn segment_temp_usage(&self) -> u64 {
+        let (width, height) = match self.crop {
+            Some(crop) => (crop.width, crop.height),
+            None => (self.width, self.height),
+        };
+        let ratio = self.upscale_ratio as u32;
+        let frame = frame_bytes(width, height, self.frame_format)
+            + frame_bytes(width * ratio, height * ratio, self.frame_format);
+        let frames = self
+            .segments
+            .iter()
+            .map(|segment| segment.size)
+            .max()
+            .unwrap_or(self.segment_size);
+        frame * frames.min(self.frame_count) as u64
+    }
+
+    /// Returns the largest number of in-flight segments, up to `max_inflight`, whose frames
+    /// fit in `available` bytes.
+    ///
+    /// Fails when not even a single segment fits.
+    pub fn fit_inflight_segments(
+        &self,
+        max_inflight: usize,
+        available: u64,
+    ) -> Result<usize, ReveError> {
+        let required = self.segment_temp_usage();
+        let fitting = (available / required.max(1)) as usize;
+        if fitting == 0 {
+            return Err(ReveError::InsufficientSpace {
+                required,
+                available,
+            });
+        }
+        Ok(fitting.min(max_inflight.max(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::{Segment, TimeRange, TrackSelection};
+
+    fn video(width: u32, height: u32, segment_size: u32) -> Video {
+        Video {
+            path: String::from("input.mkv"),
+            output_path: String::from("output.mkv"),
+            segments: vec![Segment {
+                index: 0,
+                size: segment_size,
+                start: None,
+            }],
+            frame_rate: 23.976,
+            frame_count: 10_000,
+            segment_size,
+            segment_count: 10,
+            upscale_ratio: 2,
+            start_frame: 0,
+            range: TimeRange::default(),
+            tile_size: None,
+            width,
+            height,
+            crop: None,
+            codec: Codec::default(),
+            variable_frame_rate: false,
+            start_offset: 0.0,
+            gpu_id: None,
+            upscaler_threads: None,
+            edl: Vec::new(),
+            target_size: None,
+            model: String::from("realesr-animevideov3-x2"),
+            burn_subs: None,
+            audio_tracks: TrackSelection::All,
+            subtitle_tracks: TrackSelection::All,
+            metadata: Vec::new(),
+            faststart: false,
+            fragmented: false,
+            denoise: None,
+            frame_format: FrameFormat::Png,
+            frame_quality: 95,
+        }
+    }
+
+    #[test]
+    fn test_fit_inflight_segments() {
+        let video = video(1920, 1080, 1000);
+        let segment = video.segment_temp_usage();
+        assert_eq!(segment, (3_110_400 + 12_441_600) * 1000);
+
+        assert_eq!(video.fit_inflight_segments(3, segment * 10).unwrap(), 3);
+        assert_eq!(video.fit_inflight_segments(3, segment * 2 + 1).unwrap(), 2);
+        assert!(matches!(
+            video.fit_inflight_segments(3, segment - 1),
+            Err(ReveError::InsufficientSpace { .. })
+        ));
+    }
+}
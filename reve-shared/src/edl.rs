@@ -0,0 +1,173 @@
+//! Edit lists given with `--edl`, keeping only some ranges of the input.
+//!
+//! Each line of the file holds the start and end of a range to keep, in seconds or as
+//! `hh:mm:ss`, separated by spaces, a comma or `-`. Empty lines and lines starting with `#`
+//! are ignored:
+//!
+//! ```text
+//! # first act, then the second after the break
+//! 0:00 12:31.5
+//! 15:02 - 27:40
+//! ```
+//!
+//! Segments are planned inside the kept ranges, so the cut frames are never exported. The
+//! audio and subtitles of the kept ranges are cut from the input by ffmpeg's concat demuxer,
+//! which keeps them stream copied.
+
+use crate::cli::time_validation;
+use crate::{paths, ReveError, Segment, TimeRange, Video};
+use std::fs;
+use std::path::Path;
+
+/// Reads the ranges listed in the edit list at `path`.
+pub fn read(path: &str) -> Result<Vec<TimeRange>, ReveError> {
+    parse(&fs::read_to_string(path)?)
+        .map_err(|err| ReveError::InvalidArgument(format!("invalid edit list {:?}: {}", path, err)))
+}
+
+/// Parses the ranges of an edit list, which must be in order and not overlap.
+fn parse(content: &str) -> Result<Vec<TimeRange>, String> {
+    let mut ranges: Vec<TimeRange> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let times: Vec<&str> = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '-')
+            .filter(|time| !time.is_empty())
+            .collect();
+        let [start, end] = times[..] else {
+            return Err(format!("line {}: expected a start and an end", number + 1));
+        };
+        let start =
+            time_validation(start).map_err(|err| format!("line {}: {}", number + 1, err))?;
+        let end = time_validation(end).map_err(|err| format!("line {}: {}", number + 1, err))?;
+        if end <= start {
+            return Err(format!(
+                "line {}: the range ends before it starts",
+                number + 1
+            ));
+        }
+        if ranges.last().is_some_and(|last| last.end.unwrap() > start) {
+            return Err(format!(
+                "line {}: ranges must be in order and not overlap",
+                number + 1
+            ));
+        }
+        ranges.push(TimeRange {
+            start,
+            end: Some(end),
+        });
+    }
+    if ranges.is_empty() {
+        return Err(String::from("no ranges to keep"));
+    }
+    Ok(ranges)
+}
+
+/// Splits the kept frame ranges into segments of at most `segment_size` frames, returning
+/// the first frame (from the start of the first range) and size of each.
+fn split_ranges(ranges: &[(u32, u32)], segment_size: u32) -> Vec<(u32, u32)> {
+    let first_frame = ranges.first().map_or(0, |(start, _)| *start);
+    ranges
+        .iter()
+        .flat_map(|&(start, end)| {
+            (start..end)
+                .step_by(segment_size as usize)
+                .map(move |segment_start| {
+                    (
+                        segment_start - first_frame,
+                        (end - segment_start).min(segment_size),
+                    )
+                })
+        })
+        .collect()
+}
+
+impl Video {
+    /// Plans the segments inside the kept `ranges` of the whole input.
+    pub fn apply_edl(&mut self, ranges: &[TimeRange]) -> Result<(), ReveError> {
+        let total_frames = self.start_frame + self.frame_count;
+        let frame = |time: f64| ((time * self.frame_rate as f64).round() as u32).min(total_frames);
+        let kept: Vec<(u32, u32)> = ranges
+            .iter()
+            .map(|range| (frame(range.start), frame(range.end.unwrap_or(f64::MAX))))
+            .filter(|(start, end)| start < end)
+            .collect();
+        if kept.is_empty() {
+            return Err(ReveError::InvalidArgument(format!(
+                "the edit list keeps no frames (video has {} frames at {} fps)",
+                total_frames, self.frame_rate
+            )));
+        }
+
+        self.start_frame = kept[0].0;
+        self.frame_count = kept.iter().map(|(start, end)| end - start).sum();
+        self.segments = split_ranges(&kept, self.segment_size)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (start, size))| Segment {
+                index: index as u32,
+                size,
+                start: Some(start),
+            })
+            .collect();
+        self.segment_count = self.segments.len() as u32;
+        self.edl = kept;
+        Ok(())
+    }
+
+    /// Writes the concat list reading the kept ranges of the input, for the streams muxed
+    /// next to the upscaled video.
+    pub(crate) fn write_edl_list(&self) -> Result<(), ReveError> {
+        let entry = paths::concat_entry(Path::new(&self.path));
+        let mut list = String::new();
+        for &(start, end) in &self.edl {
+            list.push_str(&format!(
+                "{}\ninpoint {}\noutpoint {}\n",
+                entry,
+                self.frame_time(start)?,
+                self.frame_time(end)?
+            ));
+        }
+        fs::write(paths::edl_list(), list)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_edl() {
+        let ranges = parse("# intro\n\n0 12:31.5\n15:02 - 27:40\n1:00:00,1:00:30\n").unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                TimeRange {
+                    start: 0.0,
+                    end: Some(751.5)
+                },
+                TimeRange {
+                    start: 902.0,
+                    end: Some(1660.0)
+                },
+                TimeRange {
+                    start: 3600.0,
+                    end: Some(3630.0)
+                },
+            ]
+        );
+        assert!(parse("10 5").is_err());
+        assert!(parse("0 10\n5 20").is_err());
+        assert!(parse("0 10 20").is_err());
+        assert!(parse("# nothing\n").is_err());
+
+        assert_eq!(
+            split_ranges(&[(100, 350), (500, 600)], 100),
+            vec![(0, 100), (100, 100), (200, 50), (400, 100)]
+        );
+    }
+}
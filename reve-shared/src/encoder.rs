@@ -0,0 +1,360 @@
+use crate::binaries::{Binaries, FfmpegVersion};
+use crate::process::{command_failure, run};
+use crate::{Args, ReveError};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Video encoder used to merge upscaled frames.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// hevc with --crf, --preset and --x265params
+    #[default]
+    #[value(name = "x265")]
+    X265,
+    /// lossless h264 (-qp 0) with --preset
+    #[value(name = "x264-lossless")]
+    X264Lossless,
+    /// prores 422 hq intermediate, mkv only
+    #[value(name = "prores")]
+    Prores,
+    /// lossless ffv1 archive, mkv only
+    #[value(name = "ffv1")]
+    Ffv1,
+    /// hardware hevc on macOS, quality from --crf
+    #[value(name = "hevc-videotoolbox")]
+    HevcVideotoolbox,
+    /// h264 with --crf and --preset, plays on nearly every device
+    #[value(name = "x264")]
+    X264,
+    /// av1 (svt-av1) with --crf, --preset is mapped to its speed
+    #[value(name = "av1")]
+    Av1,
+}
+
+impl Codec {
+    /// Returns the name used for the `{codec}` placeholder of `--output-template`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::X265 => "x265",
+            Codec::X264Lossless => "x264",
+            Codec::Prores => "prores",
+            Codec::Ffv1 => "ffv1",
+            Codec::HevcVideotoolbox => "videotoolbox",
+            Codec::X264 => "h264",
+            Codec::Av1 => "av1",
+        }
+    }
+
+    /// Returns the ffmpeg encoder of the codec.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            Codec::X265 => "libx265",
+            Codec::X264Lossless | Codec::X264 => "libx264",
+            Codec::Prores => "prores_ks",
+            Codec::Ffv1 => "ffv1",
+            Codec::HevcVideotoolbox => "hevc_videotoolbox",
+            Codec::Av1 => "libsvtav1",
+        }
+    }
+
+    /// Returns whether the codec can be stored in an mp4 container.
+    pub fn fits_mp4(&self) -> bool {
+        matches!(
+            self,
+            Codec::X265 | Codec::X264Lossless | Codec::HevcVideotoolbox | Codec::X264 | Codec::Av1
+        )
+    }
+
+    /// Returns the container extension of the segment parts.
+    pub fn part_extension(&self) -> &'static str {
+        if self.fits_mp4() {
+            "mp4"
+        } else {
+            "mkv"
+        }
+    }
+
+    /// Returns the pixel format used when `--pix-fmt` is not given.
+    pub fn default_pix_fmt(&self) -> PixFmt {
+        match self {
+            Codec::X265 => PixFmt::Yuv420p10le,
+            Codec::X264Lossless => PixFmt::Yuv444p,
+            Codec::Prores => PixFmt::Yuv422p10le,
+            Codec::Ffv1 => PixFmt::Yuv444p10le,
+            Codec::HevcVideotoolbox => PixFmt::Yuv420p10le,
+            Codec::X264 => PixFmt::Yuv420p,
+            Codec::Av1 => PixFmt::Yuv420p10le,
+        }
+    }
+
+    /// Returns whether the encoder accepts frames in `pix_fmt`.
+    pub fn supports(&self, pix_fmt: PixFmt) -> bool {
+        match self {
+            Codec::Prores => matches!(pix_fmt, PixFmt::Yuv422p10le | PixFmt::Yuv444p10le),
+            Codec::HevcVideotoolbox | Codec::Av1 => {
+                matches!(pix_fmt, PixFmt::Yuv420p | PixFmt::Yuv420p10le)
+            }
+            Codec::X265 | Codec::X264Lossless | Codec::Ffv1 | Codec::X264 => true,
+        }
+    }
+}
+
+/// Pixel format and bit depth of the encoded video.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixFmt {
+    /// 8 bit 4:2:0, the most compatible
+    #[value(name = "420p")]
+    Yuv420p,
+    #[value(name = "420p10le")]
+    Yuv420p10le,
+    #[value(name = "422p10le")]
+    Yuv422p10le,
+    /// 8 bit 4:4:4
+    #[value(name = "444p")]
+    Yuv444p,
+    #[value(name = "444p10le")]
+    Yuv444p10le,
+}
+
+impl PixFmt {
+    /// Returns the ffmpeg name of the pixel format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixFmt::Yuv420p => "yuv420p",
+            PixFmt::Yuv420p10le => "yuv420p10le",
+            PixFmt::Yuv422p10le => "yuv422p10le",
+            PixFmt::Yuv444p => "yuv444p",
+            PixFmt::Yuv444p10le => "yuv444p10le",
+        }
+    }
+}
+
+/// Device targets bundling a codec, pixel format, encoder profile and mux options.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// 8 bit h264 high profile, level 5.1, for tvs and older players
+    #[value(name = "tv-h264")]
+    TvH264,
+    /// 8 bit av1 for browsers
+    #[value(name = "web-av1")]
+    WebAv1,
+    /// 10 bit hevc main10 to keep
+    #[value(name = "archive-x265")]
+    ArchiveX265,
+}
+
+impl Profile {
+    pub fn codec(&self) -> Codec {
+        match self {
+            Profile::TvH264 => Codec::X264,
+            Profile::WebAv1 => Codec::Av1,
+            Profile::ArchiveX265 => Codec::X265,
+        }
+    }
+
+    pub fn pix_fmt(&self) -> PixFmt {
+        match self {
+            Profile::TvH264 | Profile::WebAv1 => PixFmt::Yuv420p,
+            Profile::ArchiveX265 => PixFmt::Yuv420p10le,
+        }
+    }
+
+    /// Returns the encoder arguments added to the ones of the codec.
+    fn encoder_args(&self) -> &'static [&'static str] {
+        match self {
+            Profile::TvH264 => &["-profile:v", "high", "-level:v", "5.1"],
+            Profile::WebAv1 => &[],
+            Profile::ArchiveX265 => &["-profile:v", "main10"],
+        }
+    }
+}
+
+/// Checks that the ffmpeg build has the encoder of `codec`.
+pub fn check_encoder(binaries: &Binaries, codec: Codec) -> Result<(), ReveError> {
+    require_encoder(binaries, codec.encoder(), codec.name())
+}
+
+/// Checks that the ffmpeg build has `encoder`, which `purpose` is encoded with.
+pub(crate) fn require_encoder(
+    binaries: &Binaries,
+    encoder: &str,
+    purpose: &str,
+) -> Result<(), ReveError> {
+    let mut command = Command::new(&binaries.ffmpeg);
+    command.args(["-hide_banner", "-encoders"]);
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Ffmpeg(command_failure(
+            "could not list the encoders",
+            &command,
+            &output,
+        )));
+    }
+    if !has_encoder(&String::from_utf8_lossy(&output.stdout), encoder) {
+        return Err(ReveError::InvalidArgument(format!(
+            "this ffmpeg build has no {} encoder for {}",
+            encoder, purpose
+        )));
+    }
+    Ok(())
+}
+
+/// Returns whether the `ffmpeg -encoders` listing has `encoder`.
+fn has_encoder(encoders: &str, encoder: &str) -> bool {
+    encoders
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(encoder))
+}
+
+/// First ffmpeg release whose libsvtav1 wrapper takes `-crf`.
+const SVTAV1_CRF: FfmpegVersion = FfmpegVersion { major: 5, minor: 1 };
+
+/// Maps an x265 preset to an svt-av1 preset (13-0, lower is slower).
+fn svtav1_preset(preset: &str) -> &'static str {
+    match preset {
+        "ultrafast" => "12",
+        "superfast" => "11",
+        "veryfast" => "10",
+        "faster" => "9",
+        "fast" => "8",
+        "medium" => "6",
+        "slow" => "5",
+        "slower" => "4",
+        _ => "2",
+    }
+}
+
+/// Returns the ffmpeg output arguments encoding the merged frames with `args.codec`, for the
+/// ffmpeg release `ffmpeg` (`None` for a git build, taken as recent).
+pub fn encoder_args(args: &Args, ffmpeg: Option<FfmpegVersion>) -> Vec<String> {
+    let codec = args.codec;
+    let pix_fmt = args.pix_fmt.unwrap_or(codec.default_pix_fmt());
+    let crf = args.crf.to_string();
+    let quality = videotoolbox_quality(args.crf).to_string();
+    let mut encoder: Vec<String> = match codec {
+        Codec::X265 => vec![
+            "-c:v",
+            "libx265",
+            "-crf",
+            &crf,
+            "-preset",
+            &args.preset,
+            "-x265-params",
+            &args.x265params,
+        ],
+        Codec::X264Lossless => vec!["-c:v", "libx264", "-qp", "0", "-preset", &args.preset],
+        // Profile 3 is ProRes 422 HQ, 4 is ProRes 4444
+        Codec::Prores if pix_fmt == PixFmt::Yuv444p10le => {
+            vec!["-c:v", "prores_ks", "-profile:v", "4"]
+        }
+        Codec::Prores => vec!["-c:v", "prores_ks", "-profile:v", "3"],
+        Codec::Ffv1 => vec!["-c:v", "ffv1", "-level", "3", "-g", "1"],
+        // hvc1 lets QuickTime play the output
+        Codec::HevcVideotoolbox if pix_fmt == PixFmt::Yuv420p10le => vec![
+            "-c:v",
+            "hevc_videotoolbox",
+            "-q:v",
+            &quality,
+            "-profile:v",
+            "main10",
+            "-tag:v",
+            "hvc1",
+        ],
+        Codec::HevcVideotoolbox => vec![
+            "-c:v",
+            "hevc_videotoolbox",
+            "-q:v",
+            &quality,
+            "-tag:v",
+            "hvc1",
+        ],
+        Codec::X264 => vec!["-c:v", "libx264", "-crf", &crf, "-preset", &args.preset],
+        // Older libsvtav1 wrappers take a qp, and presets up to 8
+        Codec::Av1 if ffmpeg.is_some_and(|version| version < SVTAV1_CRF) => vec![
+            "-c:v",
+            "libsvtav1",
+            "-rc",
+            "0",
+            "-qp",
+            &crf,
+            "-preset",
+            match svtav1_preset(&args.preset) {
+                "12" | "11" | "10" | "9" => "8",
+                preset => preset,
+            },
+        ],
+        Codec::Av1 => vec![
+            "-c:v",
+            "libsvtav1",
+            "-crf",
+            &crf,
+            "-preset",
+            svtav1_preset(&args.preset),
+        ],
+    }
+    .into_iter()
+    .map(String::from)
+    .collect();
+    // videotoolbox only takes 10 bit frames in the semi-planar layout
+    let pix_fmt_name = match (codec, pix_fmt) {
+        (Codec::HevcVideotoolbox, PixFmt::Yuv420p10le) => "p010le",
+        _ => pix_fmt.name(),
+    };
+    encoder.extend([String::from("-pix_fmt"), pix_fmt_name.to_string()]);
+    if let Some(profile) = args.profile {
+        encoder.extend(profile.encoder_args().iter().map(|arg| arg.to_string()));
+    }
+    encoder
+}
+
+/// Maps a crf (51-0, lower is better) to a videotoolbox quality (1-100, higher is better).
+fn videotoolbox_quality(crf: u8) -> u32 {
+    (100 - u32::from(crf.min(51)) * 100 / 51).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_codec_pix_fmts() {
+        for codec in Codec::value_variants() {
+            assert!(codec.supports(codec.default_pix_fmt()));
+        }
+        assert!(Codec::X265.supports(PixFmt::Yuv420p));
+        assert!(!Codec::Prores.supports(PixFmt::Yuv420p10le));
+        assert!(!Codec::Prores.supports(PixFmt::Yuv444p));
+        assert!(!Codec::HevcVideotoolbox.supports(PixFmt::Yuv444p10le));
+        assert_eq!(videotoolbox_quality(0), 100);
+        assert_eq!(videotoolbox_quality(15), 71);
+        assert_eq!(videotoolbox_quality(51), 1);
+
+        for profile in Profile::value_variants() {
+            assert!(profile.codec().supports(profile.pix_fmt()));
+        }
+        let encoders = "\
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10 (codec h264)
+ V....D libx265              libx265 H.265 / HEVC (codec hevc)";
+        assert!(has_encoder(encoders, "libx265"));
+        assert!(!has_encoder(encoders, "libsvtav1"));
+        assert!(!has_encoder(encoders, "H.265"));
+
+        let args = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "--codec",
+            "av1",
+            "-p",
+            "veryfast",
+        ]);
+        let old = FfmpegVersion { major: 4, minor: 4 };
+        assert!(encoder_args(&args, None).contains(&String::from("-crf")));
+        assert!(encoder_args(&args, Some(SVTAV1_CRF)).contains(&String::from("-crf")));
+        let old_args = encoder_args(&args, Some(old));
+        assert!(old_args.contains(&String::from("-qp")));
+        assert!(old_args.contains(&String::from("8")));
+    }
+}
@@ -0,0 +1,416 @@
+//! Reads the properties of video files with ffprobe.
+
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{ReveError, VideoInfo};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Properties of the first video stream of a file.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamInfo {
+    pub info: VideoInfo,
+    /// Whether frame durations vary, reported as a real frame rate above the average one.
+    pub variable_frame_rate: bool,
+    /// Seconds between the start of the container and the first frame, which audio can
+    /// start before.
+    pub start_offset: f64,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    #[serde(default)]
+    streams: Vec<Stream>,
+    format: Option<Format>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    width: Option<u32>,
+    height: Option<u32>,
+    nb_frames: Option<String>,
+    r_frame_rate: Option<String>,
+    avg_frame_rate: Option<String>,
+    duration: Option<String>,
+    start_time: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct Format {
+    duration: Option<String>,
+    start_time: Option<String>,
+}
+
+/// Runs ffprobe on the first video stream of `path`.
+///
+/// A frame count computed from a duration, the only one transport streams, flv and most
+/// webm files give, is replaced by the packets [`count_frames`] counts, since a segment
+/// planned past the last frame would never be exported.
+pub fn probe_video(binaries: &Binaries, path: impl AsRef<Path>) -> Result<StreamInfo, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-show_entries",
+        "stream=width,height,nb_frames,r_frame_rate,avg_frame_rate,duration,start_time:\
+         stream_tags:format=duration,start_time",
+        "-of",
+        "json",
+    ]);
+    command.arg(path.as_ref());
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not read the input",
+            &command,
+            &output,
+        )));
+    }
+    let (mut stream, estimated) = parse(&String::from_utf8_lossy(&output.stdout))?;
+    if estimated {
+        match count_frames(binaries, path.as_ref()) {
+            Ok(frames) if frames > 0 => stream.info.frame_count = frames,
+            _ => {}
+        }
+    }
+    Ok(stream)
+}
+
+/// Returns the number of streams of `path` matching the stream specifier `streams` (`t` for
+/// attachments, `s` for subtitles...).
+pub fn count_streams(
+    binaries: &Binaries,
+    path: impl AsRef<Path>,
+    streams: &str,
+) -> Result<usize, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        streams,
+        "-show_entries",
+        "stream=index",
+        "-of",
+        "csv=p=0",
+    ]);
+    command.arg(path.as_ref());
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not read the input",
+            &command,
+            &output,
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count())
+}
+
+/// Runs ffprobe on the streams and the container of `path`, returning its json output for
+/// [`video_stream_count`] and to be cached.
+pub fn probe_json(binaries: &Binaries, path: impl AsRef<Path>) -> Result<String, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-show_entries",
+        "stream=index,codec_type,codec_name,width,height:stream_disposition=attached_pic:\
+         format=format_name,duration,size",
+        "-of",
+        "json",
+    ]);
+    command.arg(path.as_ref());
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not read the input",
+            &command,
+            &output,
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Codec and size of the first video stream of a file and its duration, from a
+/// [`probe_json`] output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProbeSummary {
+    pub codec: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    /// Duration of the container in seconds, 0 when unknown.
+    pub duration: f64,
+}
+
+/// Returns the number of video streams of a [`probe_json`] output, leaving out the pictures
+/// attached to audio files.
+pub fn video_stream_count(json: &str) -> Result<usize, ReveError> {
+    Ok(video_streams(&parse_json(json)?).count())
+}
+
+/// Reads the [`ProbeSummary`] of a [`probe_json`] output, whose values are unset or 0 when
+/// the file has no video stream.
+pub fn summary(json: &str) -> Result<ProbeSummary, ReveError> {
+    let output = parse_json(json)?;
+    let stream = video_streams(&output).next();
+    let size = |key: &str| {
+        stream
+            .and_then(|stream| stream[key].as_u64())
+            .map_or(0, |size| size as u32)
+    };
+    Ok(ProbeSummary {
+        codec: stream.and_then(|stream| stream["codec_name"].as_str().map(String::from)),
+        width: size("width"),
+        height: size("height"),
+        duration: output["format"]["duration"]
+            .as_str()
+            .and_then(parse_duration)
+            .unwrap_or_default(),
+    })
+}
+
+fn parse_json(json: &str) -> Result<serde_json::Value, ReveError> {
+    serde_json::from_str(json)
+        .map_err(|err| ReveError::Probe(format!("invalid ffprobe output: {}", err)))
+}
+
+/// Returns the video streams of a [`probe_json`] output, without attached pictures.
+fn video_streams(output: &serde_json::Value) -> impl Iterator<Item = &serde_json::Value> {
+    output["streams"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|stream| {
+            stream["codec_type"] == "video" && stream["disposition"]["attached_pic"] != 1
+        })
+}
+
+/// Returns the exact number of frames of the first video stream of `path`, counted from its
+/// packets without decoding them.
+pub fn count_frames(binaries: &Binaries, path: impl AsRef<Path>) -> Result<u32, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-select_streams",
+        "v:0",
+        "-count_packets",
+        "-show_entries",
+        "stream=nb_read_packets",
+        "-of",
+        "csv=p=0",
+    ]);
+    command.arg(path.as_ref());
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not count the frames",
+            &command,
+            &output,
+        )));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| ReveError::Probe(format!("{} has no video stream", path.as_ref().display())))
+}
+
+/// Returns the value of the container tag `key` of `path`, `None` if it is not set.
+pub fn format_tag(
+    binaries: &Binaries,
+    path: impl AsRef<Path>,
+    key: &str,
+) -> Result<Option<String>, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-show_entries",
+        &format!("format_tags={}", key),
+        "-of",
+        "default=noprint_wrappers=1:nokey=1",
+    ]);
+    command.arg(path.as_ref());
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not read the input",
+            &command,
+            &output,
+        )));
+    }
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!value.is_empty()).then_some(value))
+}
+
+/// Reads the stream properties from ffprobe's json output, and whether the frame count was
+/// estimated from a duration.
+///
+/// Containers like mkv do not store a frame count in the stream, so it is taken from the
+/// `NUMBER_OF_FRAMES` statistics tag, or else computed from the stream, tag or container
+/// duration.
+fn parse(json: &str) -> Result<(StreamInfo, bool), ReveError> {
+    let output: Output = serde_json::from_str(json)
+        .map_err(|err| ReveError::Probe(format!("invalid ffprobe output: {}", err)))?;
+    let stream = output
+        .streams
+        .into_iter()
+        .next()
+        .ok_or_else(|| ReveError::Probe(String::from("the input has no video stream")))?;
+
+    let real_rate = stream.r_frame_rate.as_deref().and_then(parse_rate);
+    let average_rate = stream.avg_frame_rate.as_deref().and_then(parse_rate);
+    let frame_rate = average_rate
+        .or(real_rate)
+        .ok_or_else(|| ReveError::Probe(String::from("missing frame rate")))?;
+    // Interlaced transport streams give the field rate, exactly twice the frame rate, as
+    // real rate
+    let field_rate = |real: f32, average: f32| (real - 2.0 * average).abs() / real < 0.0001;
+    let variable_frame_rate = match (real_rate, average_rate) {
+        (Some(real), Some(average)) => {
+            (real - average).abs() / real > 0.01 && !field_rate(real, average)
+        }
+        _ => false,
+    };
+
+    let tag = |name: &str| {
+        stream
+            .tags
+            .iter()
+            .find(|(key, _)| key.to_uppercase().starts_with(name))
+            .map(|(_, value)| value.as_str())
+    };
+    let counted = stream
+        .nb_frames
+        .as_deref()
+        .and_then(|frames| frames.parse().ok())
+        .or_else(|| tag("NUMBER_OF_FRAMES").and_then(|frames| frames.parse().ok()))
+        .filter(|&frames: &u32| frames > 0);
+    let frame_count = counted
+        .or_else(|| {
+            stream
+                .duration
+                .as_deref()
+                .or_else(|| tag("DURATION"))
+                .or(output.format.as_ref().and_then(|f| f.duration.as_deref()))
+                .and_then(parse_duration)
+                .map(|duration| (duration * frame_rate as f64).round() as u32)
+        })
+        .ok_or_else(|| ReveError::Probe(String::from("missing frame count and duration")))?;
+    let start_time = |start_time: Option<&str>| {
+        start_time
+            .and_then(|start_time| start_time.parse::<f64>().ok())
+            .unwrap_or_default()
+    };
+    let start_offset = (start_time(stream.start_time.as_deref())
+        - start_time(output.format.as_ref().and_then(|f| f.start_time.as_deref())))
+    .max(0.0);
+
+    let stream = StreamInfo {
+        info: VideoInfo {
+            frame_count,
+            frame_rate,
+            width: stream
+                .width
+                .ok_or_else(|| ReveError::Probe(String::from("missing width")))?,
+            height: stream
+                .height
+                .ok_or_else(|| ReveError::Probe(String::from("missing height")))?,
+        },
+        variable_frame_rate,
+        start_offset,
+    };
+    Ok((stream, counted.is_none()))
+}
+
+/// Parses a frame rate written as a fraction (`24000/1001`), `0/0` being unknown.
+fn parse_rate(rate: &str) -> Option<f32> {
+    let (numerator, denominator) = rate.split_once('/').unwrap_or((rate, "1"));
+    let rate = numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(rate as f32)
+}
+
+/// Parses a duration in seconds (`62.5`) or as `hh:mm:ss.fraction` like mkv tags.
+fn parse_duration(duration: &str) -> Option<f64> {
+    duration.split(':').try_fold(0.0, |seconds, part| {
+        Some(seconds * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mp4 = r#"{"streams": [{"width": 640, "height": 360, "r_frame_rate": "24000/1001",
+            "avg_frame_rate": "24000/1001", "duration": "10.010000", "nb_frames": "240"}]}"#;
+        let (stream, estimated) = parse(mp4).unwrap();
+        assert!(!estimated);
+        assert_eq!(stream.start_offset, 0.0);
+        assert_eq!(stream.info.frame_count, 240);
+        assert!((stream.info.frame_rate - 23.976).abs() < 0.001);
+        assert_eq!((stream.info.width, stream.info.height), (640, 360));
+        assert!(!stream.variable_frame_rate);
+
+        let mkv = r#"{"streams": [{"width": 1920, "height": 1080, "r_frame_rate": "24/1",
+            "avg_frame_rate": "24/1", "tags": {"NUMBER_OF_FRAMES-eng": "34046",
+            "DURATION-eng": "00:23:40.125000000"}}], "format": {"duration": "1420.125000"}}"#;
+        assert_eq!(parse(mkv).unwrap().0.info.frame_count, 34046);
+
+        let no_count = r#"{"streams": [{"width": 1920, "height": 1080, "r_frame_rate": "60/1",
+            "avg_frame_rate": "2997/100", "tags": {"DURATION": "00:00:10.000000000"}}]}"#;
+        let (stream, estimated) = parse(no_count).unwrap();
+        assert_eq!(stream.info.frame_count, 300);
+        assert!(stream.variable_frame_rate && estimated);
+
+        // Interlaced transport stream, without a container duration
+        let ts = r#"{"streams": [{"width": 1920, "height": 1080, "r_frame_rate": "60000/1001",
+            "avg_frame_rate": "30000/1001", "duration": "10.010000"}],
+            "format": {"duration": "N/A"}}"#;
+        let (stream, estimated) = parse(ts).unwrap();
+        assert_eq!(stream.info.frame_count, 300);
+        assert!(!stream.variable_frame_rate && estimated);
+
+        // Audio starting 0.1s before the first frame
+        let ts = r#"{"streams": [{"width": 1920, "height": 1080, "r_frame_rate": "25/1",
+            "avg_frame_rate": "25/1", "duration": "10.0", "start_time": "1.500000"}],
+            "format": {"duration": "10.1", "start_time": "1.400000"}}"#;
+        assert!((parse(ts).unwrap().0.start_offset - 0.1).abs() < 0.000001);
+
+        assert!(parse(r#"{"streams": []}"#).is_err());
+
+        let song = r#"{"streams": [{"index": 0, "codec_type": "audio", "codec_name": "flac"},
+            {"index": 1, "codec_type": "video", "codec_name": "mjpeg",
+            "disposition": {"attached_pic": 1}}], "format": {"format_name": "matroska,webm"}}"#;
+        assert_eq!(video_stream_count(song).unwrap(), 0);
+        let movie = r#"{"streams": [{"index": 0, "codec_type": "video", "codec_name": "hevc",
+            "disposition": {"attached_pic": 0}}]}"#;
+        assert_eq!(video_stream_count(movie).unwrap(), 1);
+        assert!(video_stream_count("").is_err());
+        assert_eq!(summary(song).unwrap(), ProbeSummary::default());
+        let movie = r#"{"streams": [{"index": 0, "codec_type": "audio", "codec_name": "aac"},
+            {"index": 1, "codec_type": "video", "codec_name": "hevc", "width": 3840,
+            "height": 2160}], "format": {"duration": "5400.040000", "size": "9000000000"}}"#;
+        assert_eq!(
+            summary(movie).unwrap(),
+            ProbeSummary {
+                codec: Some(String::from("hevc")),
+                width: 3840,
+                height: 2160,
+                duration: 5400.04,
+            }
+        );
+        assert_eq!(parse_rate("0/0"), None);
+        assert_eq!(parse_duration("01:02:03.5"), Some(3723.5));
+    }
+}
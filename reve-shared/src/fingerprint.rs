@@ -0,0 +1,74 @@
+//! Fingerprint of the content of a video, read by `reve db duplicates` to find the same
+//! movie under two paths.
+//!
+//! Reading whole movies would take minutes on a network share, so only the first, middle and
+//! last chunks are hashed, with the size. Two remuxes of a movie differ in all of them, so
+//! only copies of the same file match.
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes hashed at each of the three places of a file.
+const CHUNK_SIZE: u64 = 1 << 20;
+
+/// Returns the hex sha256 of the size of the file at `path` and of its first, middle and last
+/// chunks, the whole file when it is smaller than three of them.
+pub fn fingerprint(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+    let (starts, length) = if size <= 3 * CHUNK_SIZE {
+        (vec![0], size)
+    } else {
+        (
+            vec![0, size / 2 - CHUNK_SIZE / 2, size - CHUNK_SIZE],
+            CHUNK_SIZE,
+        )
+    };
+    let mut chunk = Vec::new();
+    for start in starts {
+        file.seek(SeekFrom::Start(start))?;
+        chunk.clear();
+        (&mut file).take(length).read_to_end(&mut chunk)?;
+        hasher.update(&chunk);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = std::env::temp_dir().join(format!("reve-fingerprint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let movie: Vec<u8> = (0..4 * CHUNK_SIZE).map(|byte| (byte % 251) as u8).collect();
+        fs::write(dir.join("a.mkv"), &movie).unwrap();
+        fs::write(dir.join("b.mkv"), &movie).unwrap();
+        let mut edited = movie.clone();
+        edited[(2 * CHUNK_SIZE) as usize] ^= 1;
+        fs::write(dir.join("middle.mkv"), &edited).unwrap();
+        // Outside the chunks hashed
+        let mut edited = movie.clone();
+        edited[(CHUNK_SIZE + 1) as usize] ^= 1;
+        fs::write(dir.join("unread.mkv"), &edited).unwrap();
+        fs::write(dir.join("short.mkv"), &movie[..100]).unwrap();
+
+        let print = |name: &str| fingerprint(dir.join(name)).unwrap();
+        assert_eq!(print("a.mkv"), print("b.mkv"));
+        assert_ne!(print("a.mkv"), print("middle.mkv"));
+        assert_eq!(print("a.mkv"), print("unread.mkv"));
+        assert_ne!(print("a.mkv"), print("short.mkv"));
+        assert_eq!(print("short.mkv").len(), 64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
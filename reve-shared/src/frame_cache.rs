@@ -0,0 +1,111 @@
+//! Cache of upscaled frames with `--frame-cache`, so a job run again with other encoder
+//! settings only encodes again.
+//!
+//! Upscaled frames are stored in `frame_cache` in the work dir, outside the temp folder of
+//! the job, named after the sha256 of the exported frame and of the upscale settings. The
+//! cache is never trimmed, deleting the folder frees its space.
+
+use crate::{ReveError, Video};
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Folder of the cache, relative to the work dir.
+pub fn cache_dir() -> PathBuf {
+    PathBuf::from("frame_cache")
+}
+
+/// Exported frames of a segment looked up in the cache.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CachedFrames {
+    /// Frames removed from the export, with their cached upscale.
+    pub hits: Vec<(OsString, PathBuf)>,
+    /// Frames left to upscale, with the path their upscale is cached at.
+    pub misses: Vec<(OsString, PathBuf)>,
+}
+
+/// Returns the cache path of the upscale of `frame` with `settings`.
+fn cache_path(settings: &str, frame: &[u8], extension: &str) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(settings.as_bytes());
+    hasher.update([0]);
+    hasher.update(frame);
+    let key: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    cache_dir()
+        .join(&key[..2])
+        .join(format!("{}.{}", key, extension))
+}
+
+impl Video {
+    /// Returns what decides the upscale of an exported frame besides its content.
+    fn cache_settings(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.model,
+            self.upscale_ratio,
+            self.frame_format.extension()
+        )
+    }
+}
+
+/// Removes the frames of `dir` whose upscale is cached.
+pub(crate) fn take_cached(video: &Video, dir: &Path) -> Result<CachedFrames, ReveError> {
+    let settings = video.cache_settings();
+    let mut cached = CachedFrames::default();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let path = cache_path(
+            &settings,
+            &fs::read(dir.join(&name))?,
+            video.frame_format.extension(),
+        );
+        if path.is_file() {
+            fs::remove_file(dir.join(&name))?;
+            cached.hits.push((name, path));
+        } else {
+            cached.misses.push((name, path));
+        }
+    }
+    Ok(cached)
+}
+
+/// Stores the upscaled misses of `dir` in the cache and adds the cached hits to it.
+pub(crate) fn store_and_restore(dir: &Path, cached: &CachedFrames) -> Result<(), ReveError> {
+    for (name, path) in &cached.misses {
+        fs::create_dir_all(path.parent().unwrap())?;
+        // Other jobs may store the same frame, the rename keeps the cache from holding a
+        // partial copy
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        fs::copy(dir.join(name), &partial)?;
+        fs::rename(&partial, path)?;
+    }
+    for (name, path) in &cached.hits {
+        fs::copy(path, dir.join(name))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_path() {
+        let path = cache_path("realesr-animevideov3-x2:2:png", b"frame", "png");
+        let other_model = cache_path("realesr-animevideov3-x3:3:png", b"frame", "png");
+        assert_ne!(path, other_model);
+        assert_eq!(
+            path,
+            cache_path("realesr-animevideov3-x2:2:png", b"frame", "png")
+        );
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), 64 + ".png".len());
+        assert_eq!(path.parent().unwrap(), cache_dir().join(&name[..2]));
+    }
+}
@@ -0,0 +1,108 @@
+//! Vulkan devices realesrgan can upscale on, selected with `--gpu-id`.
+//!
+//! realesrgan has no option listing them, but ncnn prints a
+//! `[0 NVIDIA GeForce RTX 3060]  queueC=2[8] ...` line for every device when it starts, so a
+//! tiny frame is upscaled to read them.
+
+use crate::binaries::Binaries;
+use crate::models::{self, DEFAULT_FAMILY};
+use crate::process::{command_failure, run};
+use crate::ReveError;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// A device realesrgan found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gpu {
+    /// Value of `--gpu-id` selecting it.
+    pub id: u32,
+    pub name: String,
+}
+
+/// Returns the id and name of the device of a `[0 NVIDIA GeForce RTX 3060]  queueC=2[8] ...`
+/// line printed by realesrgan.
+pub(crate) fn parse_device(line: &str) -> Option<(u32, &str)> {
+    let (device, queues) = line.strip_prefix('[')?.split_once(']')?;
+    let (id, name) = device.split_once(' ')?;
+    if !queues.contains("queueC") {
+        return None;
+    }
+    Some((id.parse().ok()?, name))
+}
+
+/// Lists the devices realesrgan can run on, in the order of their ids.
+pub fn list(binaries: &Binaries) -> Result<Vec<Gpu>, ReveError> {
+    let dir = std::env::temp_dir().join(format!("reve-gpus-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let result = list_in(binaries, &dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn list_in(binaries: &Binaries, dir: &Path) -> Result<Vec<Gpu>, ReveError> {
+    let input_path = dir.join("input.png");
+    let mut command = Command::new(&binaries.ffmpeg);
+    command
+        .args(["-v", "error", "-f", "lavfi", "-i", "color=black:s=16x16"])
+        .args(["-frames:v", "1"])
+        .arg(&input_path);
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Ffmpeg(command_failure(
+            "could not write a sample frame",
+            &command,
+            &output,
+        )));
+    }
+
+    let mut command = Command::new(&binaries.realesrgan);
+    command
+        .arg("-i")
+        .arg(&input_path)
+        .arg("-o")
+        .arg(dir.join("output.png"))
+        .args(["-n", models::model_name(DEFAULT_FAMILY, 2)?, "-s", "2"]);
+    let output = run(&mut command)?;
+    let mut gpus: Vec<Gpu> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(parse_device)
+        .map(|(id, name)| Gpu {
+            id,
+            name: name.to_string(),
+        })
+        .collect();
+    gpus.sort_by_key(|gpu| gpu.id);
+    gpus.dedup_by_key(|gpu| gpu.id);
+    if gpus.is_empty() {
+        return Err(ReveError::Upscaler(command_failure(
+            "realesrgan found no vulkan device",
+            &command,
+            &output,
+        )));
+    }
+    Ok(gpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_device() {
+        assert_eq!(
+            parse_device("[0 NVIDIA GeForce RTX 3060]  queueC=2[8]  queueG=0[16]  queueT=1[2]"),
+            Some((0, "NVIDIA GeForce RTX 3060"))
+        );
+        assert_eq!(
+            parse_device("[1 Intel(R) UHD Graphics 630]  queueC=0[1]  queueG=0[1]  queueT=0[1]"),
+            Some((1, "Intel(R) UHD Graphics 630"))
+        );
+        assert_eq!(
+            parse_device("[0 AMD Radeon(TM) Graphics]  bugsbn1=0  bugbilz=0"),
+            None
+        );
+        assert_eq!(parse_device("[output.png] done"), None);
+        assert_eq!(parse_device("0.00%"), None);
+    }
+}
@@ -0,0 +1,267 @@
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{ReveError, VideoInfo};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+/// Image based input decoded by ffmpeg without a video container.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageInput {
+    /// Animated GIF or APNG.
+    Animation,
+    /// Numbered frames matched by a printf pattern like `frame%04d.png`.
+    Sequence,
+}
+
+impl ImageInput {
+    pub fn from_path(path: &str) -> Option<ImageInput> {
+        let path = Path::new(path);
+        let file_name = path.file_name()?.to_str()?;
+        // A file named like a pattern (50%done.mp4) is not a sequence
+        if !path.exists() && SequencePattern::parse(file_name).is_some() {
+            return Some(ImageInput::Sequence);
+        }
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gif" | "apng") => Some(ImageInput::Animation),
+            Some("png") if is_animated_png(path) => Some(ImageInput::Animation),
+            _ => None,
+        }
+    }
+
+    /// Returns the ffmpeg input arguments reading the input from `first_frame` on.
+    pub fn input_args(
+        &self,
+        path: &str,
+        first_frame: u32,
+        frame_rate: f32,
+    ) -> Result<Vec<String>, ReveError> {
+        Ok(match self {
+            ImageInput::Animation => vec![
+                String::from("-ss"),
+                (first_frame as f32 / frame_rate).to_string(),
+                String::from("-i"),
+                path.to_string(),
+            ],
+            ImageInput::Sequence => vec![
+                String::from("-f"),
+                String::from("image2"),
+                String::from("-framerate"),
+                frame_rate.to_string(),
+                String::from("-start_number"),
+                (sequence_frames(path)?.0 + first_frame).to_string(),
+                String::from("-i"),
+                path.to_string(),
+            ],
+        })
+    }
+
+    /// Returns the frame count, frame rate and dimensions of the input.
+    ///
+    /// Image sequences have no timing, so their frame rate is taken from `fps`, which also
+    /// covers animations ffmpeg reports no frame rate for.
+    pub fn probe(
+        &self,
+        binaries: &Binaries,
+        path: &str,
+        fps: Option<f32>,
+    ) -> Result<VideoInfo, ReveError> {
+        let frame_rate = match (self, fps) {
+            (ImageInput::Sequence, Some(fps)) => fps,
+            (ImageInput::Sequence, None) => {
+                return Err(ReveError::InvalidArgument(String::from(
+                    "image sequences have no frame rate, set one with --fps",
+                )))
+            }
+            // Replaced by the rate ffmpeg reports below
+            (ImageInput::Animation, _) => 0.0,
+        };
+
+        let mut command = Command::new(&binaries.ffmpeg);
+        command
+            .arg("-hide_banner")
+            .args(self.input_args(path, 0, frame_rate.max(1.0))?)
+            .args(["-map", "0:v:0", "-f", "null", "-"]);
+        let output = run(&mut command)?;
+        if !output.status.success() {
+            return Err(ReveError::Probe(command_failure(
+                "ffmpeg could not decode the input",
+                &command,
+                &output,
+            )));
+        }
+
+        let mut info =
+            parse_ffmpeg_info(&String::from_utf8_lossy(&output.stderr)).ok_or_else(|| {
+                ReveError::Probe(String::from(
+                    "could not read the video properties from ffmpeg",
+                ))
+            })?;
+        if frame_rate > 0.0 {
+            info.frame_rate = frame_rate;
+        } else if info.frame_rate <= 0.0 {
+            info.frame_rate = fps.ok_or_else(|| {
+                ReveError::InvalidArgument(String::from(
+                    "the input has no frame rate, set one with --fps",
+                ))
+            })?;
+        }
+        Ok(info)
+    }
+}
+
+/// Returns whether the png at `path` has an animation control chunk before its image data.
+fn is_animated_png(path: &Path) -> bool {
+    let mut header = Vec::new();
+    let read = fs::File::open(path).and_then(|file| file.take(4096).read_to_end(&mut header));
+    if read.is_err() {
+        return false;
+    }
+    let position = |chunk: &[u8]| header.windows(4).position(|window| window == chunk);
+    match (position(b"acTL"), position(b"IDAT")) {
+        (Some(actl), Some(idat)) => actl < idat,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+/// File name pattern of an image sequence, split around its `%d` or `%0Nd` placeholder.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SequencePattern<'a> {
+    pub prefix: &'a str,
+    /// Zero padded width of the frame number, if any.
+    pub width: Option<usize>,
+    pub suffix: &'a str,
+}
+
+impl<'a> SequencePattern<'a> {
+    pub fn parse(file_name: &'a str) -> Option<SequencePattern<'a>> {
+        let (prefix, rest) = file_name.split_once('%')?;
+        let (width, suffix) = rest.split_once('d')?;
+        let width = match width {
+            "" => None,
+            width if width.starts_with('0') => Some(width.parse::<usize>().ok()?),
+            _ => return None,
+        };
+        Some(SequencePattern {
+            prefix,
+            width,
+            suffix,
+        })
+    }
+
+    /// Returns the frame number of `file_name` if it belongs to the sequence.
+    fn frame_number(&self, file_name: &str) -> Option<u32> {
+        let digits = file_name
+            .strip_prefix(self.prefix)?
+            .strip_suffix(self.suffix)?;
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let number = digits.parse::<u32>().ok()?;
+        // ffmpeg only reads the exact name the pattern renders for each number
+        (format!("{:0width$}", number, width = self.width.unwrap_or(0)) == digits).then_some(number)
+    }
+}
+
+/// Returns the first frame number of the image sequence at `path` and the number of
+/// consecutive frames from it, like ffmpeg's image2 demuxer reads them.
+pub fn sequence_frames(path: &str) -> Result<(u32, u32), ReveError> {
+    let path = Path::new(path);
+    let pattern = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(SequencePattern::parse)
+        .ok_or_else(|| ReveError::InvalidArgument(format!("{:?} is not a sequence", path)))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut numbers: Vec<u32> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| pattern.frame_number(&entry.file_name().to_string_lossy()))
+        .collect();
+    numbers.sort_unstable();
+    let first = *numbers.first().ok_or_else(|| {
+        ReveError::InvalidArgument(format!("no frames match the sequence {:?}", path))
+    })?;
+    let count = numbers
+        .iter()
+        .zip(first..)
+        .take_while(|(number, expected)| **number == *expected)
+        .count();
+    Ok((first, count as u32))
+}
+
+/// Parses the stream line (`Video: gif, bgra, 320x240, 10 fps, ...`) and the last
+/// `frame=` progress of an ffmpeg null encode.
+fn parse_ffmpeg_info(stderr: &str) -> Option<VideoInfo> {
+    let stream = stderr.lines().find(|line| line.contains("Video:"))?;
+    let mut frame_rate = None;
+    let mut dimensions = None;
+    for part in stream.split(", ") {
+        let part = part.trim();
+        if let Some(value) = part.strip_suffix(" fps") {
+            frame_rate = value.parse::<f32>().ok();
+        } else if let Some((width, height)) = part.split(' ').next()?.split_once('x') {
+            dimensions = dimensions.or(width.parse::<u32>().ok().zip(height.parse::<u32>().ok()));
+        }
+    }
+    let frame_count = stderr
+        .rsplit("frame=")
+        .next()?
+        .split_whitespace()
+        .next()?
+        .parse::<u32>()
+        .ok()?;
+    let (width, height) = dimensions?;
+    Some(VideoInfo {
+        frame_count,
+        frame_rate: frame_rate.unwrap_or(0.0),
+        width,
+        height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_pattern() {
+        let pattern = SequencePattern::parse("frame%04d.png").unwrap();
+        assert_eq!(
+            pattern,
+            SequencePattern {
+                prefix: "frame",
+                width: Some(4),
+                suffix: ".png"
+            }
+        );
+        assert_eq!(pattern.frame_number("frame0012.png"), Some(12));
+        assert_eq!(pattern.frame_number("frame12345.png"), Some(12345));
+        assert_eq!(pattern.frame_number("frame012.png"), None);
+        assert_eq!(pattern.frame_number("frame00012.png"), None);
+        assert_eq!(pattern.frame_number("frame0012.jpg"), None);
+        assert_eq!(
+            SequencePattern::parse("%d.jpg")
+                .unwrap()
+                .frame_number("7.jpg"),
+            Some(7)
+        );
+        assert!(SequencePattern::parse("frame.png").is_none());
+        assert!(SequencePattern::parse("frame%4d.png").is_none());
+    }
+
+    #[test]
+    fn test_parse_ffmpeg_info() {
+        let stderr = "Input #0, gif, from 'walk.gif':\n  Duration: 00:00:01.20, start: 0.000000, bitrate: 353 kb/s\n  Stream #0:0: Video: gif, bgra, 320x240, 10 fps, 10 tbr, 100 tbn\nStream mapping:\n  Stream #0:0 -> #0:0 (gif (native) -> wrapped_avframe (native))\n  Stream #0:0: Video: wrapped_avframe, bgra(pc, gbr/unknown/unknown), 320x240, q=2-31, 200 kb/s, 10 fps, 10 tbn\nframe=    5 fps=0.0 q=-0.0 size=N/A time=00:00:00.50\rframe=   12 fps=0.0 q=-0.0 Lsize=N/A time=00:00:01.20\n";
+        let info = parse_ffmpeg_info(stderr).unwrap();
+        assert_eq!(info.frame_count, 12);
+        assert_eq!((info.width, info.height), (320, 240));
+        assert_eq!(info.frame_rate, 10.0);
+        assert!(parse_ffmpeg_info("Stream #0:0: Video: gif, bgra, 320x240\n").is_none());
+    }
+}
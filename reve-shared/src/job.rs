@@ -0,0 +1,1101 @@
+use crate::animation::{self, Animation};
+use crate::binaries::{Binaries, TESTED_FFMPEG};
+use crate::control::JobControl;
+use crate::dedup::{self, Duplicate};
+use crate::disk::free_space;
+use crate::edl;
+use crate::encoder::{self, encoder_args};
+use crate::ffprobe;
+use crate::frame_cache::{self, CachedFrames};
+use crate::gpu;
+use crate::models;
+use crate::process::lower_priority;
+use crate::remote::{self, RemoteJob};
+use crate::schedule;
+use crate::scheduler::{Merged, Scheduler};
+use crate::state;
+use crate::thermal;
+use crate::{
+    paths, rebuild_temp, remove_upscale_parts, vfr, Args, ReveError, Segment, Stage, TileSize,
+    UpscalerThreads, Video, UPSCALED_TAG,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Receives the progress of an [`UpscaleJob`]. Every method defaults to doing nothing.
+///
+/// Stage callbacks are made from the thread running the stage, hence the `Sync` bound.
+pub trait JobProgress: Sync {
+    fn stage_started(&self, _stage: Stage, _segment: &Segment) {}
+    fn stage_frames(&self, _stage: Stage, _segment: &Segment, _frames_done: u64) {}
+    fn stage_finished(&self, _stage: Stage, _segment: &Segment) {}
+    /// A segment was merged, `eta` is in seconds once it can be estimated.
+    fn segment_done(&self, _segments_done: u32, _segments_total: u32, _eta: Option<f64>) {}
+    /// Upscaling is paused before `segment` until the job's pause control resumes it.
+    fn paused(&self, _segment: &Segment) {}
+    fn resumed(&self, _segment: &Segment) {}
+    /// Upscaling waits `opens_in` seconds before `segment` for the `--schedule` window, then
+    /// calls `resumed`.
+    fn outside_schedule(&self, _segment: &Segment, _opens_in: u64) {}
+    /// Export is waiting for `required` bytes to be free on the temp volume.
+    fn low_disk_space(&self, _required: u64, _available: u64) {}
+    /// Upscaling is waiting for the gpu, at `temperature` °C, to cool down below
+    /// `--gpu-max-temp`.
+    fn gpu_hot(&self, _temperature: u8, _max_temperature: u8) {}
+    /// A segment was upscaled, upscaling at `fps` frames per second leaves `eta` seconds.
+    fn eta(&self, _fps: f64, _eta: f64) {}
+    /// realesrgan runs on the vulkan device `name`.
+    fn gpu(&self, _name: &str) {}
+    /// `stage` failed on its `attempt`th try and is about to be retried from scratch.
+    fn stage_failed(&self, _stage: Stage, _segment: &Segment, _attempt: u8, _err: &ReveError) {}
+}
+
+impl JobProgress for () {}
+
+/// Upscale of one input, from export to the concatenated output, resumable from temp.
+///
+/// The working directory must contain the temp folder prepared by [`rebuild_temp`].
+pub struct UpscaleJob {
+    args: Args,
+    binaries: Binaries,
+    video: Video,
+    control: JobControl,
+}
+
+impl UpscaleJob {
+    /// Plans the upscale of `args.inputpath`, including its crop, and saves it with `args` to
+    /// the resume state.
+    pub fn new(args: Args) -> Result<UpscaleJob, ReveError> {
+        let binaries = args.binaries();
+        encoder::check_encoder(&binaries, args.codec)?;
+        // Like the encoder options, an unreadable version is taken for a recent release
+        match binaries.ffmpeg_version() {
+            Ok(Some(version)) if version < TESTED_FFMPEG => warn!(
+                "ffmpeg {} is older than {}, the oldest release reve is tested with, \
+                 run reve setup to install a newer build",
+                version, TESTED_FFMPEG
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("could not read the ffmpeg version: {}", err),
+        }
+        let mut video = Video::new(
+            &binaries,
+            &args.inputpath,
+            &args.resolve_output_path()?,
+            args.segmentsize,
+            args.scale,
+            args.time_range(),
+            args.fps,
+        )?;
+        if let Some(animation) = Animation::from_path(&video.output_path) {
+            animation.check_encoder(&binaries)?;
+            animation::check_duration(video.frame_count, video.frame_rate)?;
+        }
+        video.upscale_ratio = args.upscale_ratio(video.width, video.height)?;
+        video.target_size = args.target_size(video.width, video.height);
+        video.codec = args.codec;
+        video.faststart = !args.no_faststart;
+        video.fragmented = args.fragmented;
+        apply_run_options(&mut video, &args);
+        video.burn_subs = args.burn_subs;
+        video.denoise = args.denoise;
+        video.frame_format = args.frame_format;
+        video.frame_quality = args.frame_quality;
+        video.audio_tracks = args.audio_tracks.clone();
+        video.subtitle_tracks = args.subtitle_tracks.clone();
+        video.model = models::model_name(&args.model, video.upscale_ratio)?.to_string();
+        video.metadata = args.set_meta.clone();
+        video.metadata.push((
+            String::from(UPSCALED_TAG),
+            format!(
+                "{}/{}/{}",
+                video.model,
+                video.upscale_ratio,
+                env!("CARGO_PKG_VERSION")
+            ),
+        ));
+
+        if video.variable_frame_rate {
+            info!("variable frame rate input, extracting timestamps");
+            vfr::extract_timestamps(&binaries, &video.path)?;
+        }
+
+        video.crop = if args.autocrop {
+            info!("detecting black bars");
+            video.detect_crop(&binaries)?
+        } else {
+            args.crop
+        };
+
+        if let Some(edl) = &args.edl {
+            video.apply_edl(&edl::read(edl)?)?;
+        }
+
+        if args.segment_on_scenecut {
+            info!("detecting scene cuts");
+            video.split_on_scenecuts(&binaries)?;
+        }
+
+        state::start(&args, &video)?;
+        Ok(UpscaleJob {
+            args,
+            binaries,
+            video,
+            control: JobControl::new(),
+        })
+    }
+
+    /// Loads the job saved by a previous run started with `args`, with the options of `args`
+    /// that may change on resume.
+    pub fn resume(args: Args) -> Result<UpscaleJob, ReveError> {
+        let video = state::load_video()?.ok_or_else(|| {
+            ReveError::InvalidArgument(String::from("no interrupted upscale to resume"))
+        })?;
+        Ok(UpscaleJob::resumed(args, video))
+    }
+
+    fn resumed(args: Args, mut video: Video) -> UpscaleJob {
+        apply_run_options(&mut video, &args);
+        UpscaleJob {
+            binaries: args.binaries(),
+            args,
+            video,
+            control: JobControl::new(),
+        }
+    }
+
+    pub fn args(&self) -> &Args {
+        &self.args
+    }
+
+    /// Returns the planned video, whose segments are the ones left to upscale.
+    pub fn video(&self) -> &Video {
+        &self.video
+    }
+
+    /// Returns the switches pausing the job before its next upscale or stopping it.
+    pub fn control(&self) -> JobControl {
+        self.control.clone()
+    }
+
+    /// Upscales the remaining segments, concatenates them into the output and clears temp.
+    ///
+    /// With `--share` the segments are upscaled by the workers watching the shared folder.
+    pub fn run<P: JobProgress + ?Sized>(&mut self, progress: &P) -> Result<(), ReveError> {
+        if self.args.nice {
+            lower_priority();
+        }
+        if let Some(share) = self.args.share.clone() {
+            return self.run_remote(Path::new(&share), progress);
+        }
+
+        if self.video.tile_size.is_none() {
+            self.video.tile_size = resolve_tile_size(&self.video, &self.args, &self.binaries)?;
+            state::update_video(&self.video)?;
+        }
+
+        // Preflight: keep the frames of every in-flight segment within the temp volume, or the
+        // ram disk of --ramdisk
+        let max_inflight = self.video.fit_inflight_segments(
+            self.args.max_inflight_segments as usize,
+            free_space(paths::frames_dir())?,
+        )?;
+        if max_inflight < self.args.max_inflight_segments as usize {
+            warn!(
+                "not enough disk space for {} in-flight segments, using {}",
+                self.args.max_inflight_segments, max_inflight
+            );
+        }
+
+        if let Some(index) = self.args.inspect_segment {
+            if !self
+                .video
+                .segments
+                .iter()
+                .any(|segment| segment.index == index)
+            {
+                warn!(
+                    "segment {} is not left to upscale, --inspect-segment copies nothing",
+                    index
+                );
+            }
+        }
+        if self.args.gpu_max_temp.is_some() && thermal::read_temperature().is_none() {
+            warn!(
+                "no gpu temperature sensor found (nvidia-smi or hwmon), --gpu-max-temp is ignored"
+            );
+        }
+
+        // Segments are marked merged once their part is complete, any part left for a pending
+        // one was cut short by a crash or an interrupt
+        for segment in &self.video.segments {
+            let _ = fs::remove_file(paths::temp().join(self.video.part_path(segment.index)));
+        }
+
+        if let Err(err) = self.run_segments(max_inflight, progress) {
+            if matches!(err, ReveError::Interrupted) {
+                info!("stopped, every merged segment is kept");
+            }
+            return Err(err);
+        }
+        self.finish()
+    }
+
+    /// Concatenates the encoded segments into the output and clears temp.
+    fn finish(&self) -> Result<(), ReveError> {
+        info!("merging video segments");
+        self.video.concatenate_segments(&self.binaries)?;
+        // The output is verified, the parts are not needed during the quality measure
+        if !self.args.keep_temp {
+            fs::remove_dir_all(paths::video_parts_root())?;
+        }
+        if !self.args.metrics.is_empty() {
+            info!("measuring the quality of the output");
+            // The output is already written, a failed measure only loses the report
+            match self
+                .video
+                .measure_quality(&self.binaries, &self.args.metrics, self.args.repad)
+            {
+                Ok(report) => info!(
+                    "quality report written next to the output (vmaf {:?}, ssim {:?}, psnr {:?})",
+                    report.average.vmaf, report.average.ssim, report.average.psnr
+                ),
+                Err(err) => warn!("could not measure the quality of the output: {}", err),
+            }
+        }
+        if self.args.keep_temp {
+            // Without its state a run with the same paths starts over rather than resuming
+            state::remove()?;
+            info!("temp files kept in {}", paths::temp().display());
+        } else {
+            rebuild_temp(false)?;
+        }
+        info!("done!");
+        Ok(())
+    }
+
+    /// Publishes the remaining segments to the workers of `share` and collects their parts.
+    fn run_remote<P: JobProgress + ?Sized>(
+        &mut self,
+        share: &Path,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        let remote = RemoteJob::publish(share, &self.args, &self.video)?;
+        info!(
+            "published {} to {}",
+            self.video.path,
+            remote.dir().display()
+        );
+        let started = Instant::now();
+        let segments_before = self.video.segments.len() as u32;
+
+        while !self.video.segments.is_empty() {
+            if self.control.is_stopped() {
+                info!("stopped, the workers keep upscaling the published segments");
+                return Err(ReveError::Interrupted);
+            }
+            if let Some((index, err)) = remote.failure()? {
+                remote.close()?;
+                return Err(ReveError::Worker(format!("segment {}: {}", index, err)));
+            }
+
+            for index in remote.done()? {
+                let Some(segment) = self.video.segments.iter().find(|s| s.index == index) else {
+                    continue;
+                };
+                let segment = segment.clone();
+                remote.collect(&self.video, index)?;
+                progress.stage_finished(Stage::Merge, &segment);
+                self.video.segments.retain(|s| s.index != index);
+                state::mark_merged(index)?;
+
+                let left = self.video.segments.len() as u32;
+                let processed = segments_before - left;
+                progress.segment_done(
+                    self.video.segment_count - left,
+                    self.video.segment_count,
+                    Some(started.elapsed().as_secs_f64() / processed as f64 * left as f64),
+                );
+            }
+            thread::sleep(remote::POLL_INTERVAL);
+        }
+
+        remote.close()?;
+        self.finish()
+    }
+
+    fn run_segments<P: JobProgress + ?Sized>(
+        &self,
+        max_inflight: usize,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        let video = &self.video;
+        let stages = Stages::new(video, &self.args, &self.binaries, &self.control);
+
+        let remaining = Mutex::new(video.clone());
+        let merge = |segment: &Segment| {
+            stages.merge(segment, progress)?;
+            if stages.reconcile(segment)? == Merged::Again {
+                return Ok(Merged::Again);
+            }
+
+            let mut remaining = remaining.lock().unwrap();
+            remaining.segments.retain(|s| s.index != segment.index);
+            state::mark_merged(segment.index)?;
+
+            let segments_done = video.segment_count - remaining.segments.len() as u32;
+            progress.segment_done(
+                segments_done,
+                video.segment_count,
+                stages.rate.lock().unwrap().eta(),
+            );
+            Ok(Merged::Done)
+        };
+
+        Scheduler::new(max_inflight).run(
+            &video.segments,
+            |segment| stages.export(segment, progress),
+            |segment| stages.upscale(segment, progress),
+            merge,
+        )
+    }
+}
+
+/// Returns the tile size to upscale `video` with, tuning it on this machine's gpu for
+/// `--tile auto`.
+pub(crate) fn resolve_tile_size(
+    video: &Video,
+    args: &Args,
+    binaries: &Binaries,
+) -> Result<Option<u32>, ReveError> {
+    Ok(match args.tile {
+        Some(TileSize::Auto) => {
+            info!("tuning realesrgan tile size");
+            Some(video.tune_tile_size(binaries)?)
+        }
+        Some(TileSize::Fixed(tile_size)) => Some(tile_size),
+        None => None,
+    })
+}
+
+/// The export, upscale and merge of single segments, run in temp.
+///
+/// Local jobs run them through the [`Scheduler`], remote workers one segment at a time.
+pub(crate) struct Stages<'a> {
+    video: &'a Video,
+    args: &'a Args,
+    binaries: &'a Binaries,
+    control: &'a JobControl,
+    encoder_args: Vec<String>,
+    rate: Mutex<UpscaleRate>,
+    /// Frames removed from the exported segments by `--dedup-frames`, by segment.
+    duplicates: Mutex<HashMap<u32, Vec<Duplicate>>>,
+    /// Exported frames looked up in the cache by `--frame-cache`, by segment.
+    cached: Mutex<HashMap<u32, CachedFrames>>,
+    /// Segments processed again by [`Stages::reconcile`].
+    reprocessed: Mutex<HashSet<u32>>,
+}
+
+impl<'a> Stages<'a> {
+    pub(crate) fn new(
+        video: &'a Video,
+        args: &'a Args,
+        binaries: &'a Binaries,
+        control: &'a JobControl,
+    ) -> Stages<'a> {
+        Stages {
+            video,
+            args,
+            binaries,
+            control,
+            // A git build or a failure to run ffmpeg leaves the options of recent releases
+            encoder_args: encoder_args(args, binaries.ffmpeg_version().ok().flatten()),
+            rate: Mutex::new(UpscaleRate::new(
+                video
+                    .segments
+                    .iter()
+                    .map(|segment| video.segment_frames(segment.index) as u64)
+                    .sum(),
+            )),
+            duplicates: Mutex::new(HashMap::new()),
+            cached: Mutex::new(HashMap::new()),
+            reprocessed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Waits for the `--schedule` window to open before upscaling `segment`.
+    fn wait_for_schedule<P: JobProgress + ?Sized>(
+        &self,
+        segment: &Segment,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        let Some(window) = self.args.schedule else {
+            return Ok(());
+        };
+        let minutes = window.minutes_until_open(schedule::local_minute());
+        if minutes == 0 {
+            return Ok(());
+        }
+        info!(
+            "outside the schedule, segment {} waits until {} ({}h{:02})",
+            segment.index,
+            window.opening(),
+            minutes / 60,
+            minutes % 60
+        );
+        progress.outside_schedule(segment, minutes as u64 * 60);
+        // The clock is read again as the computer may sleep or change time zones meanwhile
+        while window.minutes_until_open(schedule::local_minute()) > 0 {
+            if self.control.is_stopped() {
+                return Err(ReveError::Interrupted);
+            }
+            thread::sleep(Duration::from_secs(10));
+        }
+        info!("schedule window open, upscaling resumed");
+        progress.resumed(segment);
+        Ok(())
+    }
+
+    /// Waits while the gpu is at `--gpu-max-temp` or above, until it is `COOL_DOWN` degrees
+    /// below.
+    fn cool_down<P: JobProgress + ?Sized>(&self, progress: &P) -> Result<(), ReveError> {
+        let Some(max_temperature) = self.args.gpu_max_temp else {
+            return Ok(());
+        };
+        let mut limit = max_temperature;
+        loop {
+            if self.control.is_stopped() {
+                return Err(ReveError::Interrupted);
+            }
+            match thermal::read_temperature() {
+                Some(temperature) if temperature >= limit => {
+                    if limit == max_temperature {
+                        limit = max_temperature.saturating_sub(thermal::COOL_DOWN);
+                        warn!(
+                            "gpu at {}°C, upscaling paused until it cools down to {}°C",
+                            temperature, limit
+                        );
+                    }
+                    progress.gpu_hot(temperature, max_temperature);
+                }
+                _ => break,
+            }
+            thread::sleep(Duration::from_secs(15));
+        }
+        if limit != max_temperature {
+            info!("gpu cooled down, upscaling resumed");
+        }
+        Ok(())
+    }
+
+    /// Extracts the frames of `segment`, once the temp volume has room for them.
+    pub(crate) fn export<P: JobProgress + ?Sized>(
+        &self,
+        segment: &Segment,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        // Wait for the other stages to free space before extracting more frames
+        let required = self.video.segment_temp_usage();
+        let mut waiting = false;
+        loop {
+            if self.control.is_stopped() {
+                return Err(ReveError::Interrupted);
+            }
+            let available = free_space(paths::frames_dir())?;
+            if available >= required {
+                break;
+            }
+            if !waiting {
+                waiting = true;
+                warn!("low disk space, export paused until space is freed");
+                progress.low_disk_space(required, available);
+            }
+            thread::sleep(Duration::from_secs(10));
+        }
+
+        let frames_dir = paths::tmp_frames(segment.index);
+        with_retries(
+            self.args.retries,
+            self.control,
+            Stage::Export,
+            segment,
+            progress,
+            &frames_dir,
+            || {
+                progress.stage_started(Stage::Export, segment);
+                let processes = self.video.export_segment_parts(
+                    self.binaries,
+                    segment.index,
+                    self.args.export_threads,
+                )?;
+                // ffmpeg logs the closing of every written frame and of its input
+                let count = AtomicI64::new(-(processes.len() as i64));
+                thread::scope(|scope| {
+                    let handles: Vec<_> = processes
+                        .into_iter()
+                        .map(|mut process| {
+                            process.track(self.control);
+                            let count = &count;
+                            scope.spawn(move || {
+                                process
+                                    .lines()
+                                    .filter(|line| line.contains("AVIOContext"))
+                                    .for_each(|_| {
+                                        let done = count.fetch_add(1, Ordering::Relaxed) + 1;
+                                        progress.stage_frames(
+                                            Stage::Export,
+                                            segment,
+                                            done.max(0) as u64,
+                                        );
+                                    });
+                                process.wait()
+                            })
+                        })
+                        .collect();
+                    handles
+                        .into_iter()
+                        .try_for_each(|handle| handle.join().unwrap())
+                })?;
+                if self.args.dedup_frames {
+                    let duplicates = dedup::remove_duplicates(&frames_dir)?;
+                    if !duplicates.is_empty() {
+                        info!(
+                            "segment {}: passing {} identical frames through",
+                            segment.index,
+                            duplicates.len()
+                        );
+                    }
+                    self.duplicates
+                        .lock()
+                        .unwrap()
+                        .insert(segment.index, duplicates);
+                }
+                if self.args.frame_cache {
+                    let cached = frame_cache::take_cached(self.video, &frames_dir)?;
+                    if !cached.hits.is_empty() {
+                        info!(
+                            "segment {}: reusing {} cached upscaled frames",
+                            segment.index,
+                            cached.hits.len()
+                        );
+                    }
+                    self.cached.lock().unwrap().insert(segment.index, cached);
+                }
+                progress.stage_finished(Stage::Export, segment);
+                Ok(())
+            },
+        )
+    }
+
+    /// Upscales the exported frames of `segment`, waiting first while the job is paused.
+    pub(crate) fn upscale<P: JobProgress + ?Sized>(
+        &self,
+        segment: &Segment,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        if self.control.is_paused() && !self.control.is_stopped() {
+            info!("paused before segment {}", segment.index);
+            progress.paused(segment);
+            self.control.wait();
+            progress.resumed(segment);
+        }
+        self.wait_for_schedule(segment, progress)?;
+        self.cool_down(progress)?;
+
+        let frames_dir = paths::tmp_frames(segment.index);
+        let upscaled_dir = paths::out_frames(segment.index);
+        with_retries(
+            self.args.retries,
+            self.control,
+            Stage::Upscale,
+            segment,
+            progress,
+            &upscaled_dir,
+            || {
+                let started = Instant::now();
+                progress.stage_started(Stage::Upscale, segment);
+                // Every frame of the segment may be cached
+                if file_count(&frames_dir)? == 0 {
+                    fs::create_dir(&upscaled_dir)?;
+                } else {
+                    let processes = self.video.upscale_segment_parts(
+                        self.binaries,
+                        segment.index,
+                        self.args.upscaler_instances,
+                    )?;
+                    let count = AtomicU64::new(0);
+                    let result = thread::scope(|scope| {
+                        let handles: Vec<_> = processes
+                            .into_iter()
+                            .map(|mut process| {
+                                process.track(self.control);
+                                let count = &count;
+                                scope.spawn(move || {
+                                    process
+                                        .lines()
+                                        .inspect(|line| {
+                                            if let Some((_, name)) = gpu::parse_device(line) {
+                                                progress.gpu(name);
+                                            }
+                                        })
+                                        .filter(|line| line.contains("done"))
+                                        .for_each(|_| {
+                                            let done = count.fetch_add(1, Ordering::Relaxed) + 1;
+                                            progress.stage_frames(Stage::Upscale, segment, done);
+                                        });
+                                    process.wait()
+                                })
+                            })
+                            .collect();
+                        handles
+                            .into_iter()
+                            .try_for_each(|handle| handle.join().unwrap())
+                    });
+                    remove_upscale_parts(segment.index)?;
+                    result?;
+                }
+
+                // realesrgan can exit cleanly after losing the gpu mid-segment
+                let (exported, upscaled) = (file_count(&frames_dir)?, file_count(&upscaled_dir)?);
+                if upscaled < exported {
+                    return Err(ReveError::Upscaler(format!(
+                        "only {} of {} frames of segment {} were upscaled",
+                        upscaled, exported, segment.index
+                    )));
+                }
+                let cached = self
+                    .cached
+                    .lock()
+                    .unwrap()
+                    .get(&segment.index)
+                    .cloned()
+                    .unwrap_or_default();
+                frame_cache::store_and_restore(&upscaled_dir, &cached)?;
+                let duplicates = self
+                    .duplicates
+                    .lock()
+                    .unwrap()
+                    .get(&segment.index)
+                    .cloned()
+                    .unwrap_or_default();
+                dedup::restore_duplicates(&upscaled_dir, &duplicates)?;
+                let frames = (exported + cached.hits.len() + duplicates.len()) as u64;
+                progress.stage_frames(Stage::Upscale, segment, frames);
+                progress.stage_finished(Stage::Upscale, segment);
+
+                let mut rate = self.rate.lock().unwrap();
+                rate.record(frames, started.elapsed().as_secs_f64());
+                if let (Some(fps), Some(eta)) = (rate.fps(), rate.eta()) {
+                    progress.eta(fps, eta);
+                }
+                Ok(())
+            },
+        )?;
+        self.duplicates.lock().unwrap().remove(&segment.index);
+        self.cached.lock().unwrap().remove(&segment.index);
+        self.inspect(segment, &frames_dir, "exported")?;
+        fs::remove_dir_all(frames_dir)?;
+        Ok(())
+    }
+
+    /// Checks that the part of `segment` holds its planned frames, since the concatenation
+    /// would drop or repeat frames at its seams. The first time it does not, the part is
+    /// removed and [`Merged::Again`] has the segment exported, upscaled and merged again.
+    pub(crate) fn reconcile(&self, segment: &Segment) -> Result<Merged, ReveError> {
+        let Err(err) = self.check_part(segment) else {
+            return Ok(Merged::Done);
+        };
+        if !self.reprocessed.lock().unwrap().insert(segment.index) {
+            return Err(err);
+        }
+        warn!("{}, processing it again", err);
+        fs::remove_file(paths::temp().join(self.video.part_path(segment.index)))?;
+        Ok(Merged::Again)
+    }
+
+    fn check_part(&self, segment: &Segment) -> Result<(), ReveError> {
+        let part = paths::temp().join(self.video.part_path(segment.index));
+        let frames = ffprobe::count_frames(self.binaries, &part)?;
+        if frames != segment.size {
+            return Err(ReveError::Ffmpeg(format!(
+                "the part of segment {} has {} frames instead of {}",
+                segment.index, frames, segment.size
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the ffmpeg arguments encoding the upscaled frames of the segment `index` into
+    /// `output`, its part.
+    fn merge_args(&self, index: u32, output: &Path) -> Result<Vec<OsString>, ReveError> {
+        let video = self.video;
+        let mut args: Vec<OsString> = vec![OsString::from("-v"), OsString::from("verbose")];
+        args.extend(
+            video
+                .merge_input_args(index)?
+                .into_iter()
+                .map(OsString::from),
+        );
+        let filters: Vec<String> = video
+            .pad_filter()
+            .filter(|_| self.args.repad)
+            .into_iter()
+            .chain(video.scale_filter())
+            .collect();
+        if !filters.is_empty() {
+            args.extend([OsString::from("-vf"), OsString::from(filters.join(","))]);
+        }
+        args.extend(self.encoder_args.iter().map(OsString::from));
+        args.push(output.as_os_str().to_owned());
+        Ok(args)
+    }
+
+    /// Encodes the upscaled frames of `segment` into its part.
+    pub(crate) fn merge<P: JobProgress + ?Sized>(
+        &self,
+        segment: &Segment,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        let video = self.video;
+        let output = paths::temp().join(video.part_path(segment.index));
+        let merge_args = self.merge_args(segment.index, &output)?;
+
+        with_retries(
+            self.args.retries,
+            self.control,
+            Stage::Merge,
+            segment,
+            progress,
+            &output,
+            || {
+                progress.stage_started(Stage::Merge, segment);
+                let mut process = video.merge_segment(
+                    self.binaries,
+                    merge_args.iter().map(OsString::as_os_str).collect(),
+                )?;
+                process.track(self.control);
+                let mut count = 0;
+                process
+                    .lines()
+                    .filter(|line| line.contains("AVIOContext"))
+                    .for_each(|_| {
+                        count += 1;
+                        progress.stage_frames(Stage::Merge, segment, count);
+                    });
+                process.wait()?;
+                progress.stage_finished(Stage::Merge, segment);
+                Ok(())
+            },
+        )?;
+        let upscaled_dir = paths::out_frames(segment.index);
+        self.inspect(segment, &upscaled_dir, "upscaled")?;
+        self.inspect(segment, &output, "")?;
+        let _ = fs::remove_dir_all(upscaled_dir);
+        Ok(())
+    }
+
+    /// Copies `path`, a folder of frames or the part, of the segment of `--inspect-segment`
+    /// to the inspect folder, under `name` or its own name when empty.
+    fn inspect(&self, segment: &Segment, path: &Path, name: &str) -> Result<(), ReveError> {
+        if self.args.inspect_segment != Some(segment.index) {
+            return Ok(());
+        }
+        let dir = paths::inspect_dir(&self.video.output_path, segment.index);
+        fs::create_dir_all(&dir)?;
+        let target = match name {
+            "" => dir.join(path.file_name().unwrap_or_default()),
+            name => dir.join(name),
+        };
+        if path.is_dir() {
+            // A resumed run copies the segment again
+            let _ = fs::remove_dir_all(&target);
+            fs::create_dir(&target)?;
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                fs::copy(entry.path(), target.join(entry.file_name()))?;
+            }
+        } else {
+            fs::copy(path, &target)?;
+        }
+        info!(
+            "{} of segment {} copied to {}",
+            path.display(),
+            segment.index,
+            target.display()
+        );
+        Ok(())
+    }
+}
+
+/// Number of upscaled segments the upscale speed is averaged over.
+const RATE_SEGMENTS: usize = 5;
+
+/// Rolling upscale speed of a job, the eta is estimated from since upscaling is the slowest
+/// stage.
+struct UpscaleRate {
+    /// Frames and seconds of the last upscaled segments.
+    samples: VecDeque<(u64, f64)>,
+    frames_left: u64,
+}
+
+impl UpscaleRate {
+    fn new(frames_left: u64) -> UpscaleRate {
+        UpscaleRate {
+            samples: VecDeque::with_capacity(RATE_SEGMENTS),
+            frames_left,
+        }
+    }
+
+    fn record(&mut self, frames: u64, seconds: f64) {
+        if self.samples.len() == RATE_SEGMENTS {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((frames, seconds));
+        self.frames_left = self.frames_left.saturating_sub(frames);
+    }
+
+    /// Returns the upscaled frames per second over the last segments.
+    fn fps(&self) -> Option<f64> {
+        let (frames, seconds) = self
+            .samples
+            .iter()
+            .fold((0, 0.0), |(frames, seconds), sample| {
+                (frames + sample.0, seconds + sample.1)
+            });
+        (frames > 0 && seconds > 0.0).then(|| frames as f64 / seconds)
+    }
+
+    /// Returns the seconds left to upscale the remaining frames.
+    fn eta(&self) -> Option<f64> {
+        self.fps().map(|fps| self.frames_left as f64 / fps)
+    }
+}
+
+/// Wait before the first retry of a stage, doubled before each next one.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Returns the wait before retrying a stage that failed `attempt` times.
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Runs `stage` of `segment`, retrying it up to `retries` times after removing `temp_path`,
+/// the partial output of the failed attempt.
+///
+/// Each retry waits longer than the last, 1s, 2s, 4s and so on, giving a gpu driver that
+/// reset time to come back. A missing binary fails at once since retrying cannot fix it.
+/// Once `control` is stopped, during an attempt or the wait, the partial output is removed
+/// and the stage fails with [`ReveError::Interrupted`].
+fn with_retries<P: JobProgress + ?Sized>(
+    retries: u8,
+    control: &JobControl,
+    stage: Stage,
+    segment: &Segment,
+    progress: &P,
+    temp_path: &Path,
+    mut attempt_stage: impl FnMut() -> Result<(), ReveError>,
+) -> Result<(), ReveError> {
+    let remove_temp = || -> Result<(), ReveError> {
+        if temp_path.is_dir() {
+            fs::remove_dir_all(temp_path)?;
+        } else if temp_path.exists() {
+            fs::remove_file(temp_path)?;
+        }
+        Ok(())
+    };
+
+    let mut attempt = 1;
+    loop {
+        if control.is_stopped() {
+            return Err(ReveError::Interrupted);
+        }
+        match attempt_stage() {
+            Ok(()) => return Ok(()),
+            Err(_) if control.is_stopped() => {
+                remove_temp()?;
+                return Err(ReveError::Interrupted);
+            }
+            Err(err @ ReveError::MissingBinary(_)) => return Err(err),
+            Err(err) if attempt > retries => return Err(err),
+            Err(err) => {
+                let delay = retry_delay(attempt as u32);
+                warn!(
+                    "{} of segment {} failed (attempt {}/{}), retrying in {}s: {}",
+                    stage,
+                    segment.index,
+                    attempt,
+                    retries as u32 + 1,
+                    delay.as_secs(),
+                    err
+                );
+                progress.stage_failed(stage, segment, attempt, &err);
+                remove_temp()?;
+                let waited = Instant::now();
+                while waited.elapsed() < delay && !control.is_stopped() {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn file_count(dir: &Path) -> Result<usize, ReveError> {
+    Ok(fs::read_dir(dir)?.count())
+}
+
+/// Sets the fields of `video` given by options that may change on resume, which a resumed job
+/// takes from the new run instead of the saved plan.
+fn apply_run_options(video: &mut Video, args: &Args) {
+    video.gpu_id = args.gpu_id;
+    video.upscaler_threads = Some(args.upscaler_threads.unwrap_or_else(UpscalerThreads::auto));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_with_retries() {
+        let segment = Segment {
+            index: 0,
+            size: 1,
+            start: None,
+        };
+        let control = JobControl::new();
+        let attempts = Cell::new(0);
+        let result = with_retries(
+            2,
+            &control,
+            Stage::Upscale,
+            &segment,
+            &(),
+            Path::new("missing"),
+            || {
+                attempts.set(attempts.get() + 1);
+                if attempts.get() < 3 {
+                    Err(ReveError::Upscaler(String::from("vkQueueSubmit failed")))
+                } else {
+                    Ok(())
+                }
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+
+        attempts.set(0);
+        let result = with_retries(
+            2,
+            &control,
+            Stage::Upscale,
+            &segment,
+            &(),
+            Path::new("missing"),
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(ReveError::MissingBinary(String::from(
+                    "realesrgan-ncnn-vulkan",
+                )))
+            },
+        );
+        assert!(matches!(result, Err(ReveError::MissingBinary(_))));
+        assert_eq!(attempts.get(), 1);
+
+        // Stopping the job ends the wait before the next attempt
+        attempts.set(0);
+        let started = Instant::now();
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(200));
+                control.stop();
+            });
+            with_retries(
+                2,
+                &control,
+                Stage::Upscale,
+                &segment,
+                &(),
+                Path::new("missing"),
+                || {
+                    attempts.set(attempts.get() + 1);
+                    Err(ReveError::Upscaler(String::from("vkQueueSubmit failed")))
+                },
+            )
+        });
+        assert!(matches!(result, Err(ReveError::Interrupted)));
+        assert_eq!(attempts.get(), 1);
+        assert!(started.elapsed() < RETRY_DELAY);
+
+        let result = with_retries(
+            2,
+            &control,
+            Stage::Upscale,
+            &segment,
+            &(),
+            Path::new("missing"),
+            || panic!("a stopped job must not start a stage"),
+        );
+        assert!(matches!(result, Err(ReveError::Interrupted)));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let delays: Vec<u64> = (1..=8)
+            .map(|attempt| retry_delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(retry_delay(255), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_resumed_run_options() {
+        let video: Video = serde_json::from_str(
+            r#"{"path": "input.mkv", "output_path": "output.mkv", "frame_rate": 24.0,
+                "frame_count": 2000, "segment_size": 1000, "segment_count": 2,
+                "upscale_ratio": 2, "segments": [{"index": 1, "size": 1000}],
+                "gpu_id": 0, "upscaler_threads": {"load": 1, "proc": 2, "save": 2}}"#,
+        )
+        .unwrap();
+        let args = Args::parse_from([
+            "reve",
+            "-i",
+            "../reve-cli/assets/test.mp4",
+            "--gpu",
+            "1",
+            "--upscaler-threads",
+            "2:4:4",
+        ]);
+        let job = UpscaleJob::resumed(args, video);
+        assert_eq!(job.video.gpu_id, Some(1));
+        assert_eq!(job.video.upscaler_threads.unwrap().to_string(), "2:4:4");
+        assert_eq!(job.video.segments.len(), 1);
+    }
+
+    #[test]
+    fn test_upscale_rate() {
+        let mut rate = UpscaleRate::new(6000);
+        assert_eq!(rate.eta(), None);
+        rate.record(1000, 100.0);
+        assert_eq!(rate.fps(), Some(10.0));
+        assert_eq!(rate.eta(), Some(500.0));
+
+        // Only the last segments count once the speed changes
+        for _ in 0..RATE_SEGMENTS {
+            rate.record(200, 10.0);
+        }
+        assert_eq!(rate.fps(), Some(20.0));
+        assert_eq!(rate.eta(), Some(200.0));
+    }
+}
@@ -1,18 +1,233 @@
-use clap::Parser;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
-use std::io::{BufReader, Error, ErrorKind};
-use std::path::Path;
-use std::process::{ChildStderr, Command, Stdio};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::thread;
+use tracing::{debug, info, warn};
 
-#[derive(Serialize, Deserialize)]
+pub mod animation;
+pub mod arr;
+pub mod binaries;
+pub mod cli;
+pub mod config;
+pub mod control;
+pub mod dedup;
+pub mod disk;
+pub mod edl;
+pub mod encoder;
+pub mod ffprobe;
+pub mod fingerprint;
+pub mod frame_cache;
+pub mod gpu;
+pub mod image_input;
+pub mod job;
+pub mod library;
+pub mod metrics;
+pub mod models;
+pub mod mux;
+pub mod paths;
+pub mod preview;
+pub mod process;
+pub mod queue_export;
+pub mod remote;
+pub mod scene;
+pub mod schedule;
+pub mod scheduler;
+pub mod script;
+pub mod selection;
+pub mod state;
+pub mod still;
+pub mod thermal;
+pub mod toml_config;
+pub mod verify;
+pub mod vfr;
+
+pub use cli::{
+    model_validation, render_output_template, Args, LogLevel, Notify, ProgressFormat, TileSize,
+};
+pub use process::StageProcess;
+
+use animation::Animation;
+use binaries::Binaries;
+use encoder::Codec;
+use image_input::ImageInput;
+use mux::MuxSelection;
+use process::{command_failure, run, spawn_stderr};
+use script::ScriptKind;
+
+#[derive(Debug)]
+pub enum ReveError {
+    /// An external tool (ffmpeg, ffprobe, realesrgan) could not be found.
+    MissingBinary(String),
+    /// ffmpeg exited unsuccessfully.
+    Ffmpeg(String),
+    /// realesrgan-ncnn-vulkan exited unsuccessfully.
+    Upscaler(String),
+    /// Media information could not be parsed from the probe output.
+    Probe(String),
+    /// A user supplied option does not make sense for the input.
+    InvalidArgument(String),
+    /// The temp volume cannot hold the frames of a single segment.
+    InsufficientSpace {
+        required: u64,
+        available: u64,
+    },
+    /// The job was stopped, every segment merged so far is kept.
+    Interrupted,
+    /// A dependency could not be downloaded or did not match its checksum.
+    Download(String),
+    /// A worker could not upscale a segment of a job published with `--share`.
+    Worker(String),
+    /// The temp folder holds a job of the same input and output started with other options.
+    StaleJob(String),
+    /// A request to the media server of the `[library]` table failed.
+    Library(String),
+    /// `reve.db` could not be read or written.
+    Db(String),
+    /// Some of the videos of `reve batch` could not be upscaled.
+    Batch(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ReveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReveError::MissingBinary(name) => write!(f, "could not find {}", name),
+            ReveError::Ffmpeg(message) => write!(f, "ffmpeg failed: {}", message),
+            ReveError::Upscaler(message) => write!(f, "realesrgan failed: {}", message),
+            ReveError::Probe(message) => write!(f, "could not probe video: {}", message),
+            ReveError::InvalidArgument(message) => write!(f, "{}", message),
+            ReveError::InsufficientSpace {
+                required,
+                available,
+            } => write!(
+                f,
+                "not enough space for temporary files: {} MB required, {} MB available",
+                required / 1_000_000,
+                available / 1_000_000
+            ),
+            ReveError::Interrupted => write!(f, "stopped before the last segment"),
+            ReveError::Download(message) => write!(f, "setup failed: {}", message),
+            ReveError::Worker(message) => write!(f, "worker failed: {}", message),
+            ReveError::Library(message) => write!(f, "media server request failed: {}", message),
+            ReveError::Db(message) => write!(f, "reve.db failed: {}", message),
+            ReveError::Batch(message) => write!(f, "{}", message),
+            ReveError::StaleJob(message) => write!(
+                f,
+                "temporary files of a previous run do not match this one: {}",
+                message
+            ),
+            ReveError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReveError {
+    fn from(err: io::Error) -> Self {
+        ReveError::Io(err)
+    }
+}
+
+/// Properties of the input's video stream.
+#[derive(Clone, Copy, Debug)]
+pub struct VideoInfo {
+    pub frame_count: u32,
+    pub frame_rate: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Extensions of the video containers upscaled, the same for a file and the videos of a
+/// folder. The ones other than mp4 are exported as mkv.
+pub const VIDEO_EXTENSIONS: [&str; 8] = ["mp4", "mkv", "avi", "ts", "m2ts", "wmv", "flv", "webm"];
+
+/// Returns whether `path` has the extension of a video container reve upscales.
+pub fn is_video_path(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            VIDEO_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+/// How frames are read from the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputKind {
+    /// A video container, the only kind of input with audio, subtitles and chapters.
+    Video,
+    Script(ScriptKind),
+    Image(ImageInput),
+}
+
+impl InputKind {
+    pub fn from_path(path: &str) -> InputKind {
+        if let Some(script) = ScriptKind::from_path(path) {
+            InputKind::Script(script)
+        } else if let Some(image) = ImageInput::from_path(path) {
+            InputKind::Image(image)
+        } else {
+            InputKind::Video
+        }
+    }
+}
+
+/// Reads the video stream properties of a video file, script or image input.
+///
+/// `fps` sets the frame rate of inputs without timing, like image sequences.
+pub fn probe(binaries: &Binaries, path: &str, fps: Option<f32>) -> Result<VideoInfo, ReveError> {
+    match InputKind::from_path(path) {
+        InputKind::Script(script) => script.probe(path),
+        InputKind::Image(image) => image.probe(binaries, path, fps),
+        InputKind::Video => Ok(ffprobe::probe_video(binaries, path)?.info),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Segment {
     pub index: u32,
     pub size: u32,
+    /// First frame of the segment from the start of the range when segments end on scene
+    /// cuts, otherwise segments are `segment_size` frames apart.
+    #[serde(default)]
+    pub start: Option<u32>,
+}
+
+/// Tile sizes tried by `Video::tune_tile_size`, largest first.
+const TILE_SIZES: [u32; 7] = [512, 384, 256, 192, 128, 64, 32];
+
+/// Seconds a video input is seeked to before the first frame read, which the timestamps of
+/// the frames are picked by.
+const SEEK_MARGIN: f64 = 2.0;
+
+/// Part of the source video to process, in seconds.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimeRange {
+    pub start: f64,
+    pub end: Option<f64>,
+}
+
+impl TimeRange {
+    pub fn is_full(&self) -> bool {
+        self.start == 0.0 && self.end.is_none()
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Video {
     pub path: String,
     pub output_path: String,
@@ -22,39 +237,341 @@ pub struct Video {
     pub segment_size: u32,
     pub segment_count: u32,
     pub upscale_ratio: u8,
+    /// First source frame of the processed range.
+    #[serde(default)]
+    pub start_frame: u32,
+    #[serde(default)]
+    pub range: TimeRange,
+    /// Tile size passed to realesrgan, `None` leaves it to realesrgan.
+    #[serde(default)]
+    pub tile_size: Option<u32>,
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    /// Area of the source frames kept for upscaling.
+    #[serde(default)]
+    pub crop: Option<Crop>,
+    /// Encoder of the segment parts, which decides their container.
+    #[serde(default)]
+    pub codec: Codec,
+    /// Whether frame durations vary, in which case the input timestamps are kept in temp.
+    #[serde(default)]
+    pub variable_frame_rate: bool,
+    /// Seconds between the start of the input and its first frame, see
+    /// [`ffprobe::StreamInfo`].
+    #[serde(default)]
+    pub start_offset: f64,
+    /// Vulkan device realesrgan upscales with, its default when unset.
+    #[serde(default)]
+    pub gpu_id: Option<u32>,
+    /// Size the upscaled frames are resized to with lanczos, `None` keeps the upscaled size.
+    #[serde(default)]
+    pub target_size: Option<(u32, u32)>,
+    /// Model file realesrgan upscales with, see [`models::model_name`].
+    #[serde(default = "default_video_model")]
+    pub model: String,
+    /// Subtitle stream of the input rendered onto the exported frames.
+    #[serde(default)]
+    pub burn_subs: Option<u32>,
+    #[serde(default)]
+    pub audio_tracks: TrackSelection,
+    #[serde(default)]
+    pub subtitle_tracks: TrackSelection,
+    /// Container tags written to the output on top of the ones of the input.
+    #[serde(default)]
+    pub metadata: Vec<(String, String)>,
+    /// Whether mp4 outputs are muxed with their index at the start.
+    #[serde(default)]
+    pub faststart: bool,
+    /// Whether mp4 outputs are muxed as fragments, playable while they are written.
+    #[serde(default)]
+    pub fragmented: bool,
+    /// Noise removed from the exported frames before upscaling.
+    #[serde(default)]
+    pub denoise: Option<Denoise>,
+    #[serde(default)]
+    pub frame_format: FrameFormat,
+    #[serde(default = "default_frame_quality")]
+    pub frame_quality: u8,
+    /// Threads realesrgan runs with, its defaults when unset.
+    #[serde(default)]
+    pub upscaler_threads: Option<UpscalerThreads>,
+    /// Input frame ranges kept with `--edl`, as first and end frame, empty without one.
+    #[serde(default)]
+    pub edl: Vec<(u32, u32)>,
+}
+
+/// Crop rectangle in source pixels, written as `w:h:x:y`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(':')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| String::from("valid crop format: w:h:x:y"))?;
+        match values[..] {
+            [width, height, x, y] if width > 0 && height > 0 => Ok(Crop {
+                width,
+                height,
+                x,
+                y,
+            }),
+            _ => Err(String::from("valid crop format: w:h:x:y")),
+        }
+    }
+}
+
+/// Threads realesrgan loads, upscales and saves frames with, written as `load:proc:save`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpscalerThreads {
+    pub load: u32,
+    pub proc: u32,
+    pub save: u32,
+}
+
+impl UpscalerThreads {
+    /// Scales the load and save threads with the cpu count, realesrgan's own `1:2:2` leaves
+    /// the gpu waiting on frames on high-core machines. Saving is the slowest of the two.
+    pub fn auto() -> Self {
+        let cpus = thread::available_parallelism().map_or(4, |cpus| cpus.get() as u32);
+        UpscalerThreads {
+            load: (cpus / 8).clamp(1, 4),
+            proc: 2,
+            save: (cpus / 4).clamp(2, 8),
+        }
+    }
+}
+
+impl fmt::Display for UpscalerThreads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.load, self.proc, self.save)
+    }
+}
+
+impl FromStr for UpscalerThreads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(':')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|_| String::from("valid upscaler threads format: load:proc:save"))?;
+        match values[..] {
+            [load, proc, save] if load > 0 && proc > 0 && save > 0 => {
+                Ok(UpscalerThreads { load, proc, save })
+            }
+            _ => Err(String::from(
+                "valid upscaler threads format: load:proc:save",
+            )),
+        }
+    }
+}
+
+/// Strength of the denoise filter applied before upscaling, since realesrgan amplifies the
+/// noise of old sources.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denoise {
+    /// hqdn3d tuned down, for clean digital sources with light grain
+    Light,
+    /// hqdn3d defaults, for dvd sources
+    Medium,
+    /// nlmeans, for vhs captures (slow)
+    Strong,
+}
+
+impl Denoise {
+    pub fn filter(&self) -> &'static str {
+        match self {
+            Denoise::Light => "hqdn3d=2:1.5:3:2.25",
+            Denoise::Medium => "hqdn3d=4:3:6:4.5",
+            Denoise::Strong => "nlmeans=s=4:p=7:r=15",
+        }
+    }
+}
+
+/// Image format of the frames kept in temp between the stages.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// lossless, largest and slowest to write
+    #[default]
+    Png,
+    /// lossy, quality from --frame-quality
+    Jpg,
+    /// lossy, quality from --frame-quality
+    Webp,
+}
+
+impl FrameFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Jpg => "jpg",
+            FrameFormat::Webp => "webp",
+        }
+    }
+
+    /// Returns the rough ratio between a raw rgb24 frame and the same frame in this format.
+    pub fn compression_ratio(&self) -> u64 {
+        match self {
+            FrameFormat::Png => 2,
+            FrameFormat::Jpg => 10,
+            FrameFormat::Webp => 12,
+        }
+    }
+
+    /// Returns the ffmpeg arguments writing frames at `quality` (1-100).
+    fn quality_args(&self, quality: u8) -> Vec<String> {
+        match self {
+            FrameFormat::Png => Vec::new(),
+            // mjpeg qscale goes from 2 (best) to 31
+            FrameFormat::Jpg => vec![
+                String::from("-qscale:v"),
+                (2 + (100 - quality.min(100) as u32) * 29 / 100).to_string(),
+            ],
+            FrameFormat::Webp => vec![String::from("-quality"), quality.to_string()],
+        }
+    }
+}
+
+fn default_export_threads() -> u32 {
+    1
+}
+
+fn default_upscaler_instances() -> u32 {
+    1
+}
+
+fn default_frame_quality() -> u8 {
+    95
+}
+
+/// Container tag reve writes to its outputs, `model/scale/version`, so they are not upscaled
+/// again.
+pub const UPSCALED_TAG: &str = "REVE_UPSCALED";
+
+/// Streams of one type (audio, subtitles) of the input muxed into the output.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TrackSelection {
+    #[default]
+    All,
+    First,
+    None,
+    /// Indices among the streams of the type, from 0.
+    Indices(Vec<u32>),
+}
+
+impl TrackSelection {
+    /// Returns whether the stream `track` among the ones of its type is selected.
+    pub fn selects(&self, track: u32) -> bool {
+        match self {
+            TrackSelection::All => true,
+            TrackSelection::First => track == 0,
+            TrackSelection::None => false,
+            TrackSelection::Indices(indices) => indices.contains(&track),
+        }
+    }
+}
+
+impl fmt::Display for TrackSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackSelection::All => f.write_str("all"),
+            TrackSelection::First => f.write_str("first"),
+            TrackSelection::None => f.write_str("none"),
+            TrackSelection::Indices(indices) => {
+                let indices: Vec<String> = indices.iter().map(u32::to_string).collect();
+                f.write_str(&indices.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for TrackSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(TrackSelection::All),
+            "first" => Ok(TrackSelection::First),
+            "none" => Ok(TrackSelection::None),
+            indices => indices
+                .split(',')
+                .map(|index| index.trim().parse::<u32>())
+                .collect::<Result<Vec<u32>, _>>()
+                .map(TrackSelection::Indices)
+                .map_err(|_| String::from("valid: all, first, none or indices like 0,2")),
+        }
+    }
+}
+
+/// Returns the crop reported most often in ffmpeg's cropdetect output.
+fn parse_cropdetect(stderr: &str) -> Option<Crop> {
+    let mut counts: Vec<(Crop, u32)> = Vec::new();
+    for crop in stderr
+        .lines()
+        .filter_map(|line| line.rsplit_once("crop=").map(|(_, crop)| crop))
+        .filter_map(|value| value.trim().parse::<Crop>().ok())
+    {
+        match counts.iter_mut().find(|(c, _)| *c == crop) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((crop, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(crop, _)| crop)
 }
 
 impl Video {
-    pub fn new(path: &str, output_path: &str, segment_size: u32, upscale_ratio: u8) -> Video {
-        let frame_count = {
-            let output = Command::new("mediainfo")
-                .arg("--Output=Video;%FrameCount%")
-                .arg(path)
-                .output()
-                .expect("failed to execute process");
-            let r = String::from_utf8(output.stdout)
-                .unwrap()
-                .trim()
-                .parse::<u32>();
-            match r {
-                Err(_e) => 0,
-                _ => r.unwrap(),
+    pub fn new(
+        binaries: &Binaries,
+        path: &str,
+        output_path: &str,
+        segment_size: u32,
+        upscale_ratio: u8,
+        range: TimeRange,
+        fps: Option<f32>,
+    ) -> Result<Video, ReveError> {
+        let (info, variable_frame_rate, start_offset) = match InputKind::from_path(path) {
+            InputKind::Video => {
+                let stream = ffprobe::probe_video(binaries, path)?;
+                (stream.info, stream.variable_frame_rate, stream.start_offset)
             }
+            _ => (probe(binaries, path, fps)?, false, 0.0),
         };
+        let total_frames = info.frame_count;
+        let frame_rate = info.frame_rate;
 
-        let frame_rate = {
-            let output = Command::new("mediainfo")
-                .arg("--Output=Video;%FrameRate%")
-                .arg(path)
-                .output()
-                .expect("failed to execute process");
-            String::from_utf8(output.stdout)
-                .unwrap()
-                .trim()
-                .to_string()
-                .parse::<f32>()
-                .unwrap()
-        };
+        let start_frame = (range.start * frame_rate as f64).round() as u32;
+        let end_frame = range.end.map_or(total_frames, |end| {
+            ((end * frame_rate as f64).round() as u32).min(total_frames)
+        });
+        if start_frame >= end_frame {
+            return Err(ReveError::InvalidArgument(format!(
+                "the selected range is empty (video has {} frames at {} fps)",
+                total_frames, frame_rate
+            )));
+        }
+        let frame_count = end_frame - start_frame;
 
         let parts_num = (frame_count as f32 / segment_size as f32).ceil() as i32;
         let last_segment_size = get_last_segment_size(frame_count, segment_size);
@@ -64,17 +581,19 @@ impl Video {
             let frame_number = segment_size;
             segments.push(Segment {
                 index: i as u32,
-                size: frame_number as u32,
+                size: frame_number,
+                start: None,
             });
         }
         segments.push(Segment {
             index: (parts_num - 1) as u32,
-            size: last_segment_size as u32,
+            size: last_segment_size,
+            start: None,
         });
 
         let segment_count = segments.len() as u32;
 
-        Video {
+        Ok(Video {
             path: path.to_string(),
             output_path: output_path.to_string(),
             segments,
@@ -83,230 +602,841 @@ impl Video {
             segment_size,
             segment_count,
             upscale_ratio,
+            start_frame,
+            range,
+            tile_size: None,
+            width: info.width,
+            height: info.height,
+            crop: None,
+            codec: Codec::default(),
+            variable_frame_rate,
+            start_offset,
+            gpu_id: None,
+            upscaler_threads: None,
+            edl: Vec::new(),
+            target_size: None,
+            model: models::model_name(models::DEFAULT_FAMILY, upscale_ratio)?.to_string(),
+            burn_subs: None,
+            audio_tracks: TrackSelection::All,
+            subtitle_tracks: TrackSelection::All,
+            metadata: Vec::new(),
+            faststart: false,
+            fragmented: false,
+            denoise: None,
+            frame_format: FrameFormat::Png,
+            frame_quality: default_frame_quality(),
+        })
+    }
+
+    /// Returns the number of frames planned for the segment at `index`.
+    pub fn segment_frames(&self, index: u32) -> u32 {
+        if let Some(segment) = self.scene_segment(index) {
+            segment.size
+        } else if index + 1 == self.segment_count {
+            get_last_segment_size(self.frame_count, self.segment_size)
+        } else {
+            self.segment_size
         }
     }
 
-    pub fn export_segment(&self, index: usize) -> Result<BufReader<ChildStderr>, Error> {
-        let index_dir = format!("temp\\tmp_frames\\{}", index);
-        fs::create_dir(&index_dir).unwrap();
+    /// Returns the time in seconds at which `frame` of the input is shown.
+    fn frame_time(&self, frame: u32) -> Result<f64, ReveError> {
+        if self.variable_frame_rate {
+            Ok(vfr::frame_time(
+                &vfr::read_timestamps()?,
+                frame,
+                self.frame_rate,
+            ))
+        } else {
+            Ok(frame as f64 / self.frame_rate as f64)
+        }
+    }
 
-        let output_path = format!("temp\\tmp_frames\\{}\\frame%08d.png", index);
-        let start_time = if index == 0 {
-            String::from("0")
+    /// Returns the timestamp of `frame` when the input is decoded with [`Video::decoder`],
+    /// counted from the start of the container like the timestamps of variable frame rate
+    /// inputs are.
+    fn frame_pts(&self, timestamps: &[f64], frame: u32) -> f64 {
+        if self.variable_frame_rate {
+            vfr::frame_time(timestamps, frame, self.frame_rate)
         } else {
-            ((index as u32 * self.segment_size - 1) as f32 / self.frame_rate).to_string()
+            self.start_offset + frame as f64 / self.frame_rate as f64
+        }
+    }
+
+    /// Returns the frame timestamps [`Video::frame_pts`] reads, kept in temp for variable frame
+    /// rate inputs only.
+    fn read_frame_timestamps(&self) -> Result<Vec<f64>, ReveError> {
+        if self.variable_frame_rate {
+            vfr::read_timestamps()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Returns the input frame the export of the segment at `index` starts from.
+    fn segment_first_frame(&self, index: u32) -> u32 {
+        let offset = self
+            .scene_segment(index)
+            .and_then(|segment| segment.start)
+            .unwrap_or(index * self.segment_size);
+        self.start_frame + offset
+    }
+
+    /// Returns an ffmpeg command reading `frames` frames of the input from `first_frame` on,
+    /// once the chain of [`Video::decoder_filter`] is applied.
+    ///
+    /// Script inputs are decoded by their frame server and piped to ffmpeg as y4m.
+    fn decoder(
+        &self,
+        binaries: &Binaries,
+        first_frame: u32,
+        frames: u32,
+    ) -> Result<Command, ReveError> {
+        let mut command = Command::new(&binaries.ffmpeg);
+        match InputKind::from_path(&self.path) {
+            InputKind::Script(script) => {
+                let stdout = script.pipe(&self.path, first_frame, frames)?;
+                command
+                    .args(["-f", "yuv4mpegpipe", "-i", "-"])
+                    .stdin(stdout);
+            }
+            InputKind::Image(image) => {
+                command.args(image.input_args(&self.path, first_frame, self.frame_rate)?);
+            }
+            InputKind::Video => {
+                // Timestamps are kept from the start of the input for the select of
+                // decoder_filter, which picks the frames the seek lands around
+                let timestamps = self.read_frame_timestamps()?;
+                let seek = (self.frame_pts(&timestamps, first_frame) - SEEK_MARGIN).max(0.0);
+                command.args(["-copyts", "-start_at_zero"]).args([
+                    "-ss",
+                    &format!("{:.6}", seek),
+                    "-i",
+                    &self.path,
+                ]);
+            }
+        }
+        Ok(command)
+    }
+
+    /// Returns the `-vf` chain picking the frames read by [`Video::decoder`], then applying
+    /// `filters`.
+    ///
+    /// Video inputs are only seeked to a little before `first_frame`, since `-ss` lands a
+    /// frame early or late on some sources. The frames are then picked by timestamp, between
+    /// the midpoints with the frames around the range, which absorbs rounded timestamps.
+    fn decoder_filter(
+        &self,
+        first_frame: u32,
+        frames: u32,
+        filters: impl IntoIterator<Item = String>,
+    ) -> Result<Option<String>, ReveError> {
+        let select = if InputKind::from_path(&self.path) == InputKind::Video {
+            let timestamps = self.read_frame_timestamps()?;
+            let pts = |frame: u32| self.frame_pts(&timestamps, frame);
+            let last_frame = first_frame + frames.max(1) - 1;
+            let start = match first_frame.checked_sub(1) {
+                Some(previous) => (pts(previous) + pts(first_frame)) / 2.0,
+                None => pts(0) - 0.5 / self.frame_rate as f64,
+            };
+            let end = (pts(last_frame) + pts(last_frame + 1)) / 2.0;
+            Some(format!("select='between(t,{:.6},{:.6})'", start, end))
+        } else {
+            None
         };
-        let segments_index = if self.segments.len() == 1 { 0 } else { 1 };
-        let stderr = Command::new("ffmpeg")
-            .args([
-                "-v",
-                "verbose",
-                "-ss",
-                &start_time,
-                "-i",
-                &self.path.to_string(),
-                "-qscale:v",
-                "1",
-                "-qmin",
-                "1",
-                "-qmax",
-                "1",
-                "-vsync",
-                "0",
-                "-vframes",
-                &self.segments[segments_index].size.to_string(),
-                &output_path,
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-        Ok(BufReader::new(stderr))
-    }
-
-    pub fn upscale_segment(&self, index: usize) -> Result<BufReader<ChildStderr>, Error> {
-        let input_path = format!("temp\\tmp_frames\\{}", index);
-        let output_path = format!("temp\\out_frames\\{}", index);
-        fs::create_dir(&output_path).expect("could not create directory");
-
-        let stderr = Command::new("realesrgan-ncnn-vulkan")
-            .args([
-                "-i",
-                &input_path,
-                "-o",
-                &output_path,
-                "-n",
-                "realesr-animevideov3-x2",
-                "-s",
-                &self.upscale_ratio.to_string(),
-                "-f",
-                "png",
-                "-v",
-            ])
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-        Ok(BufReader::new(stderr))
+        let chain: Vec<String> = select.into_iter().chain(filters).collect();
+        Ok((!chain.is_empty()).then(|| chain.join(",")))
     }
 
-    // TODO: args builder for custom commands
-    pub fn merge_segment(&self, args: Vec<&str>) -> Result<BufReader<ChildStderr>, Error> {
-        let mut stderr = Command::new("ffmpeg");
-        for arg in args {
-            stderr.arg(arg);
-        }
-        let stderr = stderr
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr
-            .ok_or_else(|| Error::new(ErrorKind::Other, "Could not capture standard output."))?;
-
-        Ok(BufReader::new(stderr))
-    }
-
-    pub fn concatenate_segments(&self) {
-        let mut f_content = String::from("file 'video_parts\\0.mp4'");
-        for segment_index in 1..self.segment_count {
-            let video_part_path = format!("video_parts\\{}.mp4", segment_index);
-            f_content = format!("{}\nfile '{}'", f_content, video_part_path);
-        }
-        fs::write("temp\\parts.txt", f_content).unwrap();
-
-        Command::new("ffmpeg")
-            .args([
-                "-f",
-                "concat",
-                "-safe",
-                "0",
-                "-i",
-                "temp\\parts.txt",
-                "-i",
-                &self.path,
-                "-map",
-                "0:v",
-                "-map",
-                "1:a?",
-                "-map",
-                "1:s?",
-                "-map_chapters",
-                "1",
-                "-c",
-                "copy",
-                &self.output_path,
-            ])
-            .output()
-            .unwrap();
-        fs::remove_file("temp\\parts.txt").unwrap();
-    }
-}
-
-#[derive(Parser, Serialize, Deserialize, Debug)]
-#[clap(name = "Real-ESRGAN Video Enhance",
-author = "ONdraid <ondraid.png@gmail.com>",
-about = "Real-ESRGAN video upscaler with resumability",
-long_about = None)]
-pub struct Args {
-    /// input video path (mp4/mkv)
-    #[clap(short = 'i', long, value_parser = input_validation)]
-    pub inputpath: String,
-
-    /// output video path (mp4/mkv)
-    #[clap(value_parser = output_validation)]
-    pub outputpath: String,
-
-    /// upscale ratio (2, 3, 4)
-    #[clap(short = 's', long, value_parser = clap::value_parser!(u8).range(2..5))]
-    pub scale: u8,
-
-    /// segment size (in frames)
-    #[clap(short = 'S', long, value_parser, default_value_t = 1000)]
-    pub segmentsize: u32,
-
-    /// video constant rate factor (crf: 51-0)
-    #[clap(short = 'c', long, value_parser = clap::value_parser!(u8).range(0..52), default_value_t = 15)]
-    pub crf: u8,
-
-    /// video encoding preset
-    #[clap(short = 'p', long, value_parser = preset_validation, default_value = "slow")]
-    pub preset: String,
-
-    /// x265 encoding parameters
-    #[clap(
-    short = 'x',
-    long,
-    value_parser,
-    default_value = "psy-rd=2:aq-strength=1:deblock=0,0:bframes=8"
-    )]
-    pub x265params: String,
-}
-
-fn input_validation(s: &str) -> Result<String, String> {
-    let p = Path::new(s);
-    if !p.exists() {
-        return Err(String::from_str("input path not found").unwrap());
-    }
-    match p.extension().unwrap().to_str().unwrap() {
-        "mp4" | "mkv" => Ok(s.to_string()),
-        _ => Err(String::from_str("valid input formats: mp4/mkv").unwrap()),
-    }
-}
-
-fn output_validation(s: &str) -> Result<String, String> {
-    let p = Path::new(s);
-    if p.exists() {
-        return Err(String::from_str("output path already exists").unwrap());
-    }
-    match p.extension().unwrap().to_str().unwrap() {
-        "mp4" | "mkv" => Ok(s.to_string()),
-        _ => Err(String::from_str("valid output formats: mp4/mkv").unwrap()),
-    }
-}
-
-fn preset_validation(s: &str) -> Result<String, String> {
-    match s {
-        "ultrafast" | "superfast" | "veryfast" | "faster" | "fast" | "medium" | "slow"
-        | "slower" | "veryslow" => Ok(s.to_string()),
-        _ => Err(String::from_str(
-            "valid: ultrafast/superfast/veryfast/faster/fast/medium/slow/slower/veryslow",
+    /// Returns the `-vf` filter chain applied to the frames exported for upscaling.
+    fn export_filter(&self, first_frame: u32, frames: u32) -> Result<Option<String>, ReveError> {
+        self.decoder_filter(
+            first_frame,
+            frames,
+            self.crop.iter().map(|crop| format!("crop={}", crop)),
         )
-            .unwrap()),
+    }
+
+    /// Returns the filter rendering the `--burn-subs` stream, after the crop so subtitles
+    /// placed in the black bars stay visible.
+    ///
+    /// The frames keep their input timestamps through `select`, which the subtitles are
+    /// timed on.
+    fn subtitles_filter(&self) -> Option<String> {
+        let track = self.burn_subs?;
+        Some(format!(
+            "subtitles={}:si={}",
+            paths::filter_value(Path::new(&self.path)),
+            track
+        ))
+    }
+
+    /// Returns the scale filter resizing the upscaled frames to the target size.
+    pub fn scale_filter(&self) -> Option<String> {
+        let (width, height) = self.target_size?;
+        Some(format!("scale={}:{}:flags=lanczos", width, height))
+    }
+
+    /// Returns the pad filter restoring the original frame size around a cropped upscale.
+    pub fn pad_filter(&self) -> Option<String> {
+        let crop = self.crop?;
+        let scale = self.upscale_ratio as u32;
+        Some(format!(
+            "pad={}:{}:{}:{}:black",
+            self.width * scale,
+            self.height * scale,
+            crop.x * scale,
+            crop.y * scale
+        ))
+    }
+
+    pub fn export_segment(
+        &self,
+        binaries: &Binaries,
+        index: usize,
+    ) -> Result<StageProcess, ReveError> {
+        let mut processes = self.export_segment_parts(binaries, index as u32, 1)?;
+        Ok(processes.remove(0))
+    }
+
+    /// Exports the frames of the segment at `index` with up to `parts` ffmpeg processes, each
+    /// writing a consecutive range of the frames.
+    ///
+    /// Encoding the frames, not decoding the input, is what limits a single process. Each part
+    /// seeks to its own first frame, so the parts together decode the input about once.
+    pub fn export_segment_parts(
+        &self,
+        binaries: &Binaries,
+        index: u32,
+        parts: u32,
+    ) -> Result<Vec<StageProcess>, ReveError> {
+        let index_dir = paths::tmp_frames(index);
+        fs::create_dir(&index_dir)?;
+
+        let output_path = paths::frame_pattern(&index_dir, self.frame_format.extension());
+        let first_frame = self.segment_first_frame(index);
+        split_frames(self.segment_frames(index), parts)
+            .into_iter()
+            .map(|(offset, frames)| {
+                let part_first_frame = first_frame + offset;
+                let mut command = self.decoder(binaries, part_first_frame, frames)?;
+                // Subtitles are burned after denoising, which would blur their edges
+                let filters = self
+                    .crop
+                    .iter()
+                    .map(|crop| format!("crop={}", crop))
+                    .chain(self.denoise.map(|denoise| denoise.filter().to_string()))
+                    .chain(self.subtitles_filter());
+                if let Some(filter) = self.decoder_filter(part_first_frame, frames, filters)? {
+                    command.args(["-vf", &filter]);
+                }
+                spawn_stderr(
+                    command
+                        .args(["-v", "verbose"])
+                        .args(self.frame_format.quality_args(self.frame_quality))
+                        .args(["-vsync", "0", "-vframes", &frames.to_string()])
+                        .args(["-start_number", &(offset + 1).to_string()])
+                        .arg(&output_path),
+                    ReveError::Ffmpeg,
+                )
+            })
+            .collect()
+    }
+
+    pub fn upscale_segment(
+        &self,
+        binaries: &Binaries,
+        index: usize,
+    ) -> Result<StageProcess, ReveError> {
+        let mut processes = self.upscale_segment_parts(binaries, index as u32, 1)?;
+        Ok(processes.remove(0))
+    }
+
+    /// Upscales the exported frames of the segment at `index` with up to `parts` realesrgan
+    /// processes, each reading a consecutive range of the frames.
+    ///
+    /// realesrgan reads a whole folder, so the frames of each part are hard linked into a
+    /// folder of their own, or copied where the file system has no links. Every part writes
+    /// to the output folder of the segment under the names of its frames, which keeps their
+    /// order for the merge.
+    pub fn upscale_segment_parts(
+        &self,
+        binaries: &Binaries,
+        index: u32,
+        parts: u32,
+    ) -> Result<Vec<StageProcess>, ReveError> {
+        let input_path = paths::tmp_frames(index);
+        let output_path = paths::out_frames(index);
+        fs::create_dir(&output_path)?;
+        remove_upscale_parts(index)?;
+
+        let mut frames = fs::read_dir(&input_path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if parts <= 1 {
+            let process = spawn_stderr(
+                self.upscaler(binaries, &input_path, &output_path, self.tile_size)
+                    .arg("-v"),
+                ReveError::Upscaler,
+            )?;
+            return Ok(vec![process]);
+        }
+        frames.sort();
+        split_frames(frames.len() as u32, parts)
+            .into_iter()
+            .zip(0..)
+            .map(|((offset, size), part)| {
+                let part_path = paths::tmp_frames_part(index, part);
+                fs::create_dir(&part_path)?;
+                for frame in &frames[offset as usize..(offset + size) as usize] {
+                    let link = part_path.join(frame.file_name().unwrap_or_default());
+                    if fs::hard_link(frame, &link).is_err() {
+                        fs::copy(frame, &link)?;
+                    }
+                }
+                spawn_stderr(
+                    self.upscaler(binaries, &part_path, &output_path, self.tile_size)
+                        .arg("-v"),
+                    ReveError::Upscaler,
+                )
+            })
+            .collect()
+    }
+
+    fn upscaler(
+        &self,
+        binaries: &Binaries,
+        input_path: &Path,
+        output_path: &Path,
+        tile_size: Option<u32>,
+    ) -> Command {
+        let mut command = Command::new(&binaries.realesrgan);
+        command.arg("-i").arg(input_path).arg("-o").arg(output_path);
+        command.args([
+            "-n",
+            &self.model,
+            "-s",
+            &self.upscale_ratio.to_string(),
+            "-f",
+            self.frame_format.extension(),
+        ]);
+        if let Some(tile_size) = tile_size {
+            command.args(["-t", &tile_size.to_string()]);
+        }
+        if let Some(gpu_id) = self.gpu_id {
+            command.args(["-g", &gpu_id.to_string()]);
+        }
+        if let Some(threads) = self.upscaler_threads {
+            command.args(["-j", &threads.to_string()]);
+        }
+        command
+    }
+
+    /// Finds the largest tile size realesrgan can upscale a sample frame with.
+    ///
+    /// Low-VRAM GPUs crash or thrash with the default tile size, so a frame from the start of
+    /// the range is upscaled with decreasing tile sizes until one succeeds.
+    pub fn tune_tile_size(&self, binaries: &Binaries) -> Result<u32, ReveError> {
+        let test_dir = paths::tile_test();
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir)?;
+
+        let extension = self.frame_format.extension();
+        let input_path = test_dir.join(format!("input.{}", extension));
+        let output_path = test_dir.join(format!("output.{}", extension));
+        let mut command = self.decoder(binaries, self.start_frame, 1)?;
+        if let Some(filter) = self.export_filter(self.start_frame, 1)? {
+            command.args(["-vf", &filter]);
+        }
+        let output = run(command
+            .args(["-v", "error", "-frames:v", "1"])
+            .arg(&input_path))?;
+        if !output.status.success() {
+            return Err(ReveError::Ffmpeg(command_failure(
+                "could not extract a sample frame for tile size tuning",
+                &command,
+                &output,
+            )));
+        }
+
+        for tile_size in TILE_SIZES {
+            let _ = fs::remove_file(&output_path);
+            let output =
+                run(&mut self.upscaler(binaries, &input_path, &output_path, Some(tile_size)))?;
+            if output.status.success() && output_path.exists() {
+                fs::remove_dir_all(&test_dir)?;
+                info!("using tile size {}", tile_size);
+                return Ok(tile_size);
+            }
+        }
+        Err(ReveError::Upscaler(String::from(
+            "could not upscale a sample frame with any tile size",
+        )))
+    }
+
+    /// Detects black bars with ffmpeg's cropdetect on a sample from the first third of the range.
+    ///
+    /// Returns `None` when the detected area covers the whole frame.
+    pub fn detect_crop(&self, binaries: &Binaries) -> Result<Option<Crop>, ReveError> {
+        let first_frame = self.start_frame + self.frame_count / 3;
+        let frames = (self.start_frame + self.frame_count - first_frame).min(240);
+        let mut command = self.decoder(binaries, first_frame, frames)?;
+        let filter = self
+            .decoder_filter(first_frame, frames, [String::from("cropdetect=24:2:0")])?
+            .unwrap_or_default();
+        let output = run(command.args([
+            "-frames:v",
+            &frames.to_string(),
+            "-vf",
+            &filter,
+            "-f",
+            "null",
+            "-",
+        ]))?;
+        if !output.status.success() {
+            return Err(ReveError::Ffmpeg(command_failure(
+                "could not detect black bars of the input",
+                &command,
+                &output,
+            )));
+        }
+
+        let crop = parse_cropdetect(&String::from_utf8_lossy(&output.stderr));
+        Ok(crop.filter(|crop| crop.width < self.width || crop.height < self.height))
+    }
+
+    /// Returns the ffmpeg input arguments reading the upscaled frames of the segment at `index`.
+    ///
+    /// Frames of variable frame rate inputs are listed in an ffconcat file with the durations
+    /// they had in the input, so audio stays in sync.
+    pub fn merge_input_args(&self, index: u32) -> Result<Vec<String>, ReveError> {
+        let frames_dir = paths::out_frames(index);
+        if !self.variable_frame_rate {
+            return Ok(vec![
+                String::from("-f"),
+                String::from("image2"),
+                String::from("-framerate"),
+                format!("{}/1", self.frame_rate),
+                String::from("-i"),
+                paths::frame_pattern(&frames_dir, self.frame_format.extension())
+                    .to_string_lossy()
+                    .into_owned(),
+            ]);
+        }
+
+        let mut files: Vec<String> = fs::read_dir(&frames_dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(&format!(".{}", self.frame_format.extension())))
+            .collect();
+        files.sort();
+        let list_path = frames_dir.join("frames.ffconcat");
+        fs::write(
+            &list_path,
+            vfr::ffconcat(
+                &files,
+                &vfr::read_timestamps()?,
+                self.segment_first_frame(index),
+                self.frame_rate,
+            ),
+        )?;
+        Ok(vec![
+            String::from("-f"),
+            String::from("concat"),
+            String::from("-safe"),
+            String::from("0"),
+            String::from("-i"),
+            list_path.to_string_lossy().into_owned(),
+            String::from("-vsync"),
+            String::from("vfr"),
+        ])
+    }
+
+    // TODO: args builder for custom commands
+    pub fn merge_segment(
+        &self,
+        binaries: &Binaries,
+        args: Vec<&OsStr>,
+    ) -> Result<StageProcess, ReveError> {
+        spawn_stderr(Command::new(&binaries.ffmpeg).args(args), ReveError::Ffmpeg)
+    }
+
+    /// Returns the path of the encoded part of the segment at `index`, relative to temp.
+    pub fn part_path(&self, index: u32) -> PathBuf {
+        Path::new("video_parts").join(format!("{}.{}", index, self.codec.part_extension()))
+    }
+
+    /// Returns the ffmpeg muxer of the output, which the `.part` extension of the partial
+    /// output does not give.
+    fn output_muxer(&self) -> &'static str {
+        match Path::new(&self.output_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("mp4") => "mp4",
+            Some("gif") => "gif",
+            Some("webp") => "webp",
+            Some("avif") => "avif",
+            _ => "matroska",
+        }
+    }
+
+    /// Returns the partial output to write, removing the one of an interrupted run.
+    pub(crate) fn start_partial_output(&self) -> Result<PathBuf, ReveError> {
+        let partial = paths::partial_output(&self.output_path);
+        if partial.exists() {
+            info!(
+                "replacing {}, left by an interrupted run",
+                partial.display()
+            );
+            fs::remove_file(&partial)?;
+        }
+        Ok(partial)
+    }
+
+    /// Renames the written partial output to the output once verified, failing with `error`
+    /// if it is empty.
+    pub(crate) fn complete_output(
+        &self,
+        binaries: &Binaries,
+        partial: &Path,
+        error: impl FnOnce() -> ReveError,
+    ) -> Result<(), ReveError> {
+        if fs::metadata(partial).map_or(true, |metadata| metadata.len() == 0) {
+            return Err(error());
+        }
+        self.verify_output(binaries, partial)?;
+        fs::rename(partial, &self.output_path)?;
+        Ok(())
+    }
+
+    /// Writes the output from the encoded segments and the other streams of the source.
+    ///
+    /// A single ffmpeg reads the parts through the concat demuxer and the source as a second
+    /// input, and copies their streams to the partial output next to the output, which is then
+    /// renamed. The video is written to disk once, with no intermediate file of the whole.
+    pub fn concatenate_segments(&self, binaries: &Binaries) -> Result<(), ReveError> {
+        let f_content: Vec<String> = (0..self.segment_count)
+            .map(|segment_index| paths::concat_entry(&self.part_path(segment_index)))
+            .collect();
+        fs::write(paths::parts_list(), f_content.join("\n"))?;
+        if let Some(animation) = Animation::from_path(&self.output_path) {
+            return self.encode_animation(binaries, animation);
+        }
+
+        let is_mp4 = Path::new(&self.output_path)
+            .extension()
+            .is_some_and(|extension| extension == "mp4");
+        let mut command = Command::new(&binaries.ffmpeg);
+        command
+            .args(["-f", "concat", "-safe", "0", "-i"])
+            .arg(paths::parts_list());
+        let mut stream_codecs = Vec::new();
+        // Scripts and images only provide video, other streams come from the source container.
+        if InputKind::from_path(&self.path) == InputKind::Video {
+            // Only mux the audio and subtitles of the upscaled range, or of the ranges kept
+            // by the edit list, which are read as a second input with the source's metadata
+            // taken from a third one.
+            let edited = !self.edl.is_empty();
+            let source = if edited { 2 } else { 1 };
+            if edited {
+                self.write_edl_list()?;
+                command
+                    .args(["-f", "concat", "-safe", "0", "-i"])
+                    .arg(paths::edl_list());
+            } else if !self.range.is_full() {
+                let start_time = self.frame_time(self.start_frame)?;
+                let end_time = self.frame_time(self.start_frame + self.frame_count)?;
+                command.args([
+                    "-ss",
+                    &start_time.to_string(),
+                    "-t",
+                    &(end_time - start_time).to_string(),
+                ]);
+            }
+            command.args(["-i", &self.path, "-map", "0:v"]);
+            let selection = MuxSelection {
+                audio_tracks: &self.audio_tracks,
+                subtitle_tracks: &self.subtitle_tracks,
+                // The burned subtitles would show twice
+                burn_subs: self.burn_subs,
+                mp4: is_mp4,
+            };
+            let plan = selection.plan(
+                &mux::source_streams(binaries, Path::new(&self.path))?,
+                1,
+                source,
+            );
+            for skipped in &plan.skipped {
+                warn!("leaving out the {} of the input", skipped);
+            }
+            command.args(plan.maps);
+            stream_codecs = plan.codecs;
+            // Chapters of the input would point into the cut ranges
+            command.args(["-map_chapters", if edited { "-1" } else { "1" }]);
+            command.args(["-map_metadata", &source.to_string()]);
+        }
+        for (key, value) in &self.metadata {
+            command.args(["-metadata", &format!("{}={}", key, value)]);
+        }
+        if is_mp4 {
+            // mp4 drops tags it has no atom for otherwise
+            let mut movflags = String::from("use_metadata_tags");
+            if self.fragmented {
+                movflags.push_str("+frag_keyframe+empty_moov+default_base_moof");
+            } else if self.faststart {
+                // Moves the moov atom to the start for web playback, with a second pass
+                movflags.push_str("+faststart");
+            }
+            command.args(["-movflags", &movflags]);
+        }
+        let partial = self.start_partial_output()?;
+        command
+            .args(["-c", "copy"])
+            .args(stream_codecs)
+            .args(["-f", self.output_muxer()])
+            .arg(&partial);
+        let output = run(&mut command)?;
+        if !output.status.success() {
+            return Err(ReveError::Ffmpeg(command_failure(
+                "could not concatenate segments",
+                &command,
+                &output,
+            )));
+        }
+        self.complete_output(binaries, &partial, || {
+            ReveError::Ffmpeg(command_failure(
+                "concatenation did not create the output video",
+                &command,
+                &output,
+            ))
+        })?;
+        fs::remove_file(paths::parts_list())?;
+        Ok(())
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Export,
+    Upscale,
+    Merge,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Export => "export",
+            Stage::Upscale => "upscale",
+            Stage::Merge => "merge",
+        })
+    }
+}
+
+/// Progress event emitted as a single json line in `--progress json` mode.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    SegmentStarted {
+        stage: Stage,
+        segment: u32,
+        frames: u32,
+    },
+    Frames {
+        stage: Stage,
+        segment: u32,
+        done: u64,
+        total: u32,
+        fps: f64,
+    },
+    SegmentFinished {
+        stage: Stage,
+        segment: u32,
+        frames: u64,
+        elapsed: f64,
+    },
+    /// The exporter is waiting for free space on the temp volume.
+    LowDiskSpace {
+        required: u64,
+        available: u64,
+    },
+    /// Upscaling waits for the gpu to cool down from `temperature` °C.
+    GpuHot {
+        temperature: u8,
+        max_temperature: u8,
+    },
+    /// Upscaling is paused before `segment`.
+    Paused {
+        segment: u32,
+    },
+    Resumed {
+        segment: u32,
+    },
+    /// Upscaling waits `opens_in` seconds before `segment` for the `--schedule` window.
+    Scheduled {
+        segment: u32,
+        opens_in: u64,
+    },
+    /// The job was stopped and can be resumed.
+    Stopped,
+    /// `stage` of `segment` failed and will be retried.
+    Retry {
+        stage: Stage,
+        segment: u32,
+        attempt: u8,
+        error: String,
+    },
+    Job {
+        segments_done: u32,
+        segments_total: u32,
+        eta: Option<f64>,
+    },
+    /// A segment was upscaled, `eta` is estimated from the upscale speed of the last segments.
+    Eta {
+        fps: f64,
+        eta: f64,
+    },
+    Done {
+        output: String,
+    },
+}
+
+/// Model of plans saved before the model was chosen by scale, which upscaled every scale with
+/// the x2 weights.
+fn default_video_model() -> String {
+    String::from("realesr-animevideov3-x2")
+}
+
+/// Removes the folders the parts of the segment at `index` read their frames from, see
+/// [`Video::upscale_segment_parts`].
+pub(crate) fn remove_upscale_parts(index: u32) -> Result<(), ReveError> {
+    let prefix = format!("{}.", index);
+    let Ok(entries) = fs::read_dir(paths::tmp_frames_root()) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Splits `frames` frames into up to `parts` consecutive ranges of about the same size,
+/// returning the offset and size of each.
+fn split_frames(frames: u32, parts: u32) -> Vec<(u32, u32)> {
+    let parts = parts.clamp(1, frames.max(1));
+    let mut offset = 0;
+    (0..parts)
+        .map(|part| {
+            let size = frames / parts + u32::from(part < frames % parts);
+            offset += size;
+            (offset - size, size)
+        })
+        .collect()
+}
+
 pub fn get_last_segment_size(frame_count: u32, segment_size: u32) -> u32 {
-    let last_segment_size = (frame_count % segment_size) as u32;
+    let last_segment_size = frame_count % segment_size;
     if last_segment_size == 0 {
         segment_size
     } else {
-        last_segment_size - 1
+        last_segment_size
     }
 }
 
-pub fn rebuild_temp(keep_args: bool) {
-    let _ = fs::create_dir("temp");
-    if !keep_args {
-        println!("removing temp");
-        fs::remove_dir_all("temp").expect("could not remove temp. try deleting manually");
+/// Prepares the temp folder of the job, clearing it and its resume state unless `keep_state`
+/// is set, in which case only the frames of the segments in progress are removed.
+pub fn rebuild_temp(keep_state: bool) -> Result<(), ReveError> {
+    let _ = fs::create_dir_all(paths::temp());
+    if !keep_state {
+        debug!("removing temp");
+        fs::remove_dir_all(paths::temp())?;
+        state::remove()?;
+        if paths::frames_dir() != paths::temp() {
+            let _ = fs::remove_dir_all(paths::frames_dir());
+        }
 
-        for dir in ["temp\\tmp_frames", "temp\\out_frames", "temp\\video_parts"] {
-            println!("creating {}", dir);
-            fs::create_dir_all(dir).unwrap();
+        for dir in [
+            paths::tmp_frames_root(),
+            paths::out_frames_root(),
+            paths::video_parts_root(),
+        ] {
+            debug!("creating {}", dir.display());
+            fs::create_dir_all(dir)?;
         }
     } else {
-        for dir in ["temp\\tmp_frames", "temp\\out_frames"] {
-            println!("removing {}", dir);
-            fs::remove_dir_all(dir)
-                .unwrap_or_else(|_| panic!("could not remove {:?}. try deleting manually", dir));
-            println!("creating {}", dir);
-            fs::create_dir_all(dir).unwrap();
-        }
-        println!("removing parts.txt");
-        let _ = fs::remove_file("temp\\parts.txt");
+        // A ram disk is emptied by a reboot, and --ramdisk may give another one on resume
+        for dir in [paths::tmp_frames_root(), paths::out_frames_root()] {
+            debug!("removing {}", dir.display());
+            match fs::remove_dir_all(&dir) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+            debug!("creating {}", dir.display());
+            fs::create_dir_all(dir)?;
+        }
+        debug!("removing parts.txt");
+        let _ = fs::remove_file(paths::parts_list());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upscaler_threads() {
+        let threads: UpscalerThreads = "2:3:8".parse().unwrap();
+        assert_eq!(
+            threads,
+            UpscalerThreads {
+                load: 2,
+                proc: 3,
+                save: 8
+            }
+        );
+        assert_eq!(threads.to_string(), "2:3:8");
+        assert!("2:0:8".parse::<UpscalerThreads>().is_err());
+        assert!("2:2".parse::<UpscalerThreads>().is_err());
+        assert!(UpscalerThreads::auto().save >= 2);
+    }
+
+    #[test]
+    fn test_split_frames() {
+        assert_eq!(split_frames(10, 3), vec![(0, 4), (4, 3), (7, 3)]);
+        assert_eq!(split_frames(2, 4), vec![(0, 1), (1, 1)]);
+        assert_eq!(split_frames(500, 1), vec![(0, 500)]);
+    }
+
+    #[test]
+    fn test_track_selection() {
+        assert_eq!("first".parse(), Ok(TrackSelection::First));
+        let indices = "0, 2".parse::<TrackSelection>().unwrap();
+        assert!(indices.selects(2) && !indices.selects(1));
+        assert!(!TrackSelection::None.selects(0));
+        assert!("commentary".parse::<TrackSelection>().is_err());
+    }
+
+    #[test]
+    fn test_parse_cropdetect() {
+        let stderr = "\
+[Parsed_cropdetect_0 @ 0x1] x1:0 x2:1919 y1:132 y2:947 w:1920 h:816 x:0 y:132 pts:1 t:0.04 crop=1920:816:0:132
+[Parsed_cropdetect_0 @ 0x1] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:2 t:0.08 crop=1920:800:0:140
+[Parsed_cropdetect_0 @ 0x1] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 pts:3 t:0.12 crop=1920:800:0:140
+frame=    3 fps=0.0 q=-0.0 Lsize=N/A time=00:00:00.12 bitrate=N/A speed=1.2x";
+        assert_eq!(
+            parse_cropdetect(stderr),
+            Some(Crop {
+                width: 1920,
+                height: 800,
+                x: 0,
+                y: 140
+            })
+        );
+        assert_eq!(parse_cropdetect("frame=    3 fps=0.0"), None);
     }
 }
@@ -0,0 +1,207 @@
+//! Jellyfin and Plex api of the `[library]` table, read by `reve library` to find the videos
+//! below a resolution.
+//!
+//! Only the urls and the parsing of the json answers are here, the cli sends the requests
+//! with the token in the header of [`Library::token_header`].
+
+use crate::toml_config::{Library, MediaServer};
+use serde_json::Value;
+
+/// Video of a library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LibraryItem {
+    pub title: String,
+    /// Path of the file as the server sees it.
+    pub path: String,
+    /// Height of the video stream, 0 when the server did not analyze the file yet.
+    pub height: u32,
+}
+
+/// Section of a Plex server holding videos.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlexSection {
+    pub key: String,
+    /// Type of the items listed, `1` for movies and `4` for the episodes of shows.
+    pub item_type: u8,
+}
+
+impl Library {
+    /// Returns the header holding the token.
+    pub fn token_header(&self) -> &'static str {
+        match self.server {
+            MediaServer::Jellyfin => "X-Emby-Token",
+            MediaServer::Plex => "X-Plex-Token",
+        }
+    }
+
+    fn base(&self) -> &str {
+        self.url.trim_end_matches('/')
+    }
+
+    /// Returns the url listing the movies and episodes of Jellyfin, of the library `parent`
+    /// or of every library.
+    pub fn jellyfin_items_url(&self, parent: Option<&str>) -> String {
+        let mut url = format!(
+            "{}/Items?Recursive=true&IncludeItemTypes=Movie,Episode&Fields=Path,MediaStreams",
+            self.base()
+        );
+        if let Some(parent) = parent {
+            url.push_str(&format!("&ParentId={}", parent));
+        }
+        url
+    }
+
+    /// Returns the url Jellyfin scans every library on when posted to.
+    pub fn jellyfin_refresh_url(&self) -> String {
+        format!("{}/Library/Refresh", self.base())
+    }
+
+    /// Returns the url listing the sections of Plex.
+    pub fn plex_sections_url(&self) -> String {
+        format!("{}/library/sections", self.base())
+    }
+
+    /// Returns the url listing the videos of a Plex section.
+    pub fn plex_items_url(&self, section: &PlexSection) -> String {
+        format!(
+            "{}/library/sections/{}/all?type={}",
+            self.base(),
+            section.key,
+            section.item_type
+        )
+    }
+
+    /// Returns the url scanning a Plex section.
+    pub fn plex_refresh_url(&self, section: &PlexSection) -> String {
+        format!("{}/library/sections/{}/refresh", self.base(), section.key)
+    }
+}
+
+fn parse(json: &str) -> Result<Value, String> {
+    serde_json::from_str(json).map_err(|err| format!("invalid answer: {}", err))
+}
+
+fn as_u32(value: &Value) -> Option<u32> {
+    value.as_u64().and_then(|value| u32::try_from(value).ok())
+}
+
+/// Parses the items of a Jellyfin `/Items` answer, skipping the ones without a file.
+pub fn parse_jellyfin_items(json: &str) -> Result<Vec<LibraryItem>, String> {
+    let root = parse(json)?;
+    let items = root["Items"].as_array().ok_or("answer without Items")?;
+    Ok(items
+        .iter()
+        .filter_map(|item| {
+            let video_height = item["MediaStreams"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|stream| stream["Type"] == "Video")
+                .and_then(|stream| as_u32(&stream["Height"]));
+            Some(LibraryItem {
+                title: item["Name"].as_str().unwrap_or_default().to_string(),
+                path: item["Path"].as_str()?.to_string(),
+                height: video_height
+                    .or_else(|| as_u32(&item["Height"]))
+                    .unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+/// Parses the movie and show sections of a Plex `/library/sections` answer.
+pub fn parse_plex_sections(json: &str) -> Result<Vec<PlexSection>, String> {
+    let root = parse(json)?;
+    Ok(root["MediaContainer"]["Directory"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|section| {
+            let item_type = match section["type"].as_str()? {
+                "movie" => 1,
+                "show" => 4,
+                _ => return None,
+            };
+            Some(PlexSection {
+                key: section["key"].as_str()?.to_string(),
+                item_type,
+            })
+        })
+        .collect())
+}
+
+/// Parses the videos of a Plex section, the first file of each.
+pub fn parse_plex_items(json: &str) -> Result<Vec<LibraryItem>, String> {
+    let root = parse(json)?;
+    Ok(root["MediaContainer"]["Metadata"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| {
+            let media = &item["Media"][0];
+            let title = item["title"].as_str().unwrap_or_default();
+            Some(LibraryItem {
+                title: match item["grandparentTitle"].as_str() {
+                    Some(show) => format!("{} - {}", show, title),
+                    None => title.to_string(),
+                },
+                path: media["Part"][0]["file"].as_str()?.to_string(),
+                height: as_u32(&media["height"]).unwrap_or(0),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_library() {
+        let library = Library {
+            server: MediaServer::Plex,
+            url: String::from("http://nas:32400/"),
+            token: String::from("abc"),
+            sections: Vec::new(),
+            rescan: false,
+        };
+        let sections = parse_plex_sections(
+            r#"{"MediaContainer": {"Directory": [{"key": "1", "type": "movie"},
+                {"key": "2", "type": "artist"}, {"key": "3", "type": "show"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(
+            library.plex_items_url(&sections[1]),
+            "http://nas:32400/library/sections/3/all?type=4"
+        );
+
+        let items = parse_plex_items(
+            r#"{"MediaContainer": {"Metadata": [{"title": "Pilot", "grandparentTitle": "Show",
+                "Media": [{"height": 480, "Part": [{"file": "/tv/show/s01e01.mkv"}]}]},
+                {"title": "No file", "Media": []}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            items,
+            [LibraryItem {
+                title: String::from("Show - Pilot"),
+                path: String::from("/tv/show/s01e01.mkv"),
+                height: 480
+            }]
+        );
+        assert!(parse_plex_items(r#"{"MediaContainer": {"size": 0}}"#)
+            .unwrap()
+            .is_empty());
+
+        let items = parse_jellyfin_items(
+            r#"{"Items": [{"Name": "Movie", "Path": "/movies/movie.mp4", "Height": 1080,
+                "MediaStreams": [{"Type": "Audio"}, {"Type": "Video", "Height": 720}]},
+                {"Name": "Folder"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].height, 720);
+        assert!(parse_jellyfin_items("<html>").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+//! Quality of the output against the source with ffmpeg's libvmaf, ssim and psnr filters.
+//!
+//! The output is scaled back to the source size and compared frame by frame in one pass, then
+//! the scores are averaged per segment and written next to the output as json and csv.
+
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{paths, ReveError, Video};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// needs an ffmpeg built with libvmaf
+    Vmaf,
+    Ssim,
+    Psnr,
+}
+
+impl Metric {
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Vmaf => "vmaf",
+            Metric::Ssim => "ssim",
+            Metric::Psnr => "psnr",
+        }
+    }
+
+    /// Returns the filter comparing `[distorted][reference]` and logging every frame to `log`.
+    fn filter(self, log: &Path) -> String {
+        let log = paths::filter_value(log);
+        match self {
+            Metric::Vmaf => format!("libvmaf=log_fmt=json:log_path={}", log),
+            Metric::Ssim => format!("ssim=stats_file={}", log),
+            Metric::Psnr => format!("psnr=stats_file={}", log),
+        }
+    }
+
+    /// Returns the score of every frame from the log of the filter.
+    fn parse_log(self, log: &str) -> Result<Vec<f64>, ReveError> {
+        match self {
+            Metric::Vmaf => {
+                let json: serde_json::Value = serde_json::from_str(log)
+                    .map_err(|err| ReveError::Ffmpeg(format!("invalid vmaf log: {}", err)))?;
+                Ok(json["frames"]
+                    .as_array()
+                    .map(|frames| {
+                        frames
+                            .iter()
+                            .filter_map(|frame| frame["metrics"]["vmaf"].as_f64())
+                            .collect()
+                    })
+                    .unwrap_or_default())
+            }
+            Metric::Ssim => Ok(parse_stats(log, "All:")),
+            Metric::Psnr => Ok(parse_stats(log, "psnr_avg:")),
+        }
+    }
+}
+
+/// Returns the value following `key` on every line of an ssim or psnr stats file.
+fn parse_stats(log: &str, key: &str) -> Vec<f64> {
+    log.lines()
+        .filter_map(|line| line.split_once(key))
+        .filter_map(|(_, rest)| rest.split_whitespace().next()?.parse::<f64>().ok())
+        .collect()
+}
+
+/// Averages `values`, leaving out the infinite psnr of identical frames.
+fn mean(values: &[f64]) -> Option<f64> {
+    let finite: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    (!finite.is_empty()).then(|| finite.iter().sum::<f64>() / finite.len() as f64)
+}
+
+/// Scores of a segment or of the whole output, `None` for the metrics not measured.
+#[derive(Serialize, Default, Debug, PartialEq)]
+pub struct Scores {
+    pub vmaf: Option<f64>,
+    pub ssim: Option<f64>,
+    pub psnr: Option<f64>,
+}
+
+impl Scores {
+    fn set(&mut self, metric: Metric, value: Option<f64>) {
+        match metric {
+            Metric::Vmaf => self.vmaf = value,
+            Metric::Ssim => self.ssim = value,
+            Metric::Psnr => self.psnr = value,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SegmentScores {
+    pub index: u32,
+    /// First frame of the segment in the output.
+    pub first_frame: u32,
+    pub frames: u32,
+    #[serde(flatten)]
+    pub scores: Scores,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct QualityReport {
+    pub output: String,
+    pub average: Scores,
+    pub segments: Vec<SegmentScores>,
+}
+
+impl QualityReport {
+    /// Averages the frame scores of every metric over segments of `segment_size` frames.
+    fn new(output: &str, segment_size: u32, frame_scores: &[(Metric, Vec<f64>)]) -> Self {
+        let frames = frame_scores
+            .iter()
+            .map(|(_, scores)| scores.len())
+            .max()
+            .unwrap_or(0) as u32;
+        let mut average = Scores::default();
+        for (metric, scores) in frame_scores {
+            average.set(*metric, mean(scores));
+        }
+        let segments = (0..frames.div_ceil(segment_size))
+            .map(|index| {
+                let first_frame = index * segment_size;
+                let end = (first_frame + segment_size).min(frames);
+                let mut scores = Scores::default();
+                for (metric, values) in frame_scores {
+                    let end = (end as usize).min(values.len());
+                    let start = (first_frame as usize).min(end);
+                    scores.set(*metric, mean(&values[start..end]));
+                }
+                SegmentScores {
+                    index,
+                    first_frame,
+                    frames: end - first_frame,
+                    scores,
+                }
+            })
+            .collect();
+        QualityReport {
+            output: output.to_string(),
+            average,
+            segments,
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let cell = |value: Option<f64>| value.map(|value| format!("{:.4}", value));
+        let mut csv = String::from("segment,first_frame,frames,vmaf,ssim,psnr\n");
+        for segment in &self.segments {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                segment.index,
+                segment.first_frame,
+                segment.frames,
+                cell(segment.scores.vmaf).unwrap_or_default(),
+                cell(segment.scores.ssim).unwrap_or_default(),
+                cell(segment.scores.psnr).unwrap_or_default()
+            ));
+        }
+        csv
+    }
+}
+
+/// Returns the paths of the json and csv reports of `output_path`.
+pub fn report_paths(output_path: &str) -> (PathBuf, PathBuf) {
+    let output_path = Path::new(output_path);
+    (
+        output_path.with_extension("metrics.json"),
+        output_path.with_extension("metrics.csv"),
+    )
+}
+
+impl Video {
+    /// Compares the output with the upscaled range of the source and writes the report.
+    ///
+    /// The output is scaled back to the size of the upscaled frames, `repad` compares the
+    /// whole source frames when the output was padded back to them.
+    pub fn measure_quality(
+        &self,
+        binaries: &Binaries,
+        metrics: &[Metric],
+        repad: bool,
+    ) -> Result<QualityReport, ReveError> {
+        let (width, height, reference_filter) = match self.crop.filter(|_| !repad) {
+            Some(crop) => (
+                crop.width,
+                crop.height,
+                self.export_filter(self.start_frame, self.frame_count)?,
+            ),
+            None => (
+                self.width,
+                self.height,
+                self.decoder_filter(self.start_frame, self.frame_count, [])?,
+            ),
+        };
+        let logs: Vec<PathBuf> = metrics
+            .iter()
+            .map(|metric| paths::temp().join(format!("metrics_{}.log", metric.name())))
+            .collect();
+
+        let count = metrics.len();
+        let mut graph = format!(
+            "[0:v]{}setpts=PTS-STARTPTS,format=yuv420p,split={}{};\
+             [1:v]scale={}:{}:flags=bicubic,setpts=PTS-STARTPTS,format=yuv420p,split={}{}",
+            reference_filter.map_or(String::new(), |filter| format!("{},", filter)),
+            count,
+            (0..count).map(|i| format!("[r{}]", i)).collect::<String>(),
+            width,
+            height,
+            count,
+            (0..count).map(|i| format!("[d{}]", i)).collect::<String>(),
+        );
+        for (i, (metric, log)) in metrics.iter().zip(&logs).enumerate() {
+            graph.push_str(&format!(";[d{}][r{}]{}", i, i, metric.filter(log)));
+        }
+
+        let mut command = self.decoder(binaries, self.start_frame, self.frame_count)?;
+        command
+            .args(["-i", &self.output_path, "-filter_complex", &graph])
+            .args(["-f", "null", "-"]);
+        let output = run(&mut command)?;
+        if !output.status.success() {
+            return Err(ReveError::Ffmpeg(command_failure(
+                "could not measure the quality of the output",
+                &command,
+                &output,
+            )));
+        }
+
+        let mut frame_scores = Vec::new();
+        for (metric, log) in metrics.iter().zip(&logs) {
+            frame_scores.push((*metric, metric.parse_log(&fs::read_to_string(log)?)?));
+        }
+        let report = QualityReport::new(&self.output_path, self.segment_size, &frame_scores);
+        let (json_path, csv_path) = report_paths(&self.output_path);
+        fs::write(json_path, serde_json::to_string_pretty(&report).unwrap())?;
+        fs::write(csv_path, report.to_csv())?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quality_report() {
+        let ssim = "\
+n:1 Y:0.990000 U:0.995000 V:0.995000 All:0.992000 (20.969100)
+n:2 Y:0.980000 U:0.990000 V:0.990000 All:0.984000 (17.958800)
+n:3 Y:1.000000 U:1.000000 V:1.000000 All:1.000000 (inf)";
+        let psnr = "\
+n:1 mse_avg:0.50 mse_y:0.50 mse_u:0.50 mse_v:0.50 psnr_avg:40.00 psnr_y:40.00
+n:2 mse_avg:0.50 mse_y:0.50 mse_u:0.50 mse_v:0.50 psnr_avg:42.00 psnr_y:42.00
+n:3 mse_avg:0.00 mse_y:0.00 mse_u:0.00 mse_v:0.00 psnr_avg:inf psnr_y:inf";
+        let vmaf = r#"{"frames": [{"frameNum": 0, "metrics": {"vmaf": 90.0}},
+            {"frameNum": 1, "metrics": {"vmaf": 94.0}}, {"frameNum": 2, "metrics": {"vmaf": 98.0}}]}"#;
+        assert_eq!(
+            Metric::Ssim.parse_log(ssim).unwrap(),
+            vec![0.992, 0.984, 1.0]
+        );
+        assert_eq!(
+            Metric::Psnr.parse_log(psnr).unwrap(),
+            vec![40.0, 42.0, f64::INFINITY]
+        );
+
+        let frame_scores = vec![
+            (Metric::Vmaf, Metric::Vmaf.parse_log(vmaf).unwrap()),
+            (Metric::Psnr, Metric::Psnr.parse_log(psnr).unwrap()),
+        ];
+        let report = QualityReport::new("out.mkv", 2, &frame_scores);
+        assert_eq!(report.average.vmaf, Some(94.0));
+        assert_eq!(report.average.ssim, None);
+        assert_eq!(report.segments.len(), 2);
+        assert_eq!(report.segments[1].first_frame, 2);
+        assert_eq!(report.segments[1].frames, 1);
+        assert_eq!(report.segments[1].scores.vmaf, Some(98.0));
+        assert_eq!(report.segments[1].scores.psnr, None);
+        assert_eq!(
+            report.to_csv(),
+            "segment,first_frame,frames,vmaf,ssim,psnr\n\
+             0,0,2,92.0000,,41.0000\n\
+             1,2,1,98.0000,,\n"
+        );
+        assert_eq!(
+            report_paths("/videos/out.mkv").0,
+            Path::new("/videos/out.metrics.json")
+        );
+    }
+}
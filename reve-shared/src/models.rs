@@ -0,0 +1,87 @@
+//! Realesrgan models reve can upscale with, by family and scale.
+
+use crate::ReveError;
+
+/// Model family used when none is selected.
+pub const DEFAULT_FAMILY: &str = "realesr-animevideov3";
+
+/// Files of realesrgan-ncnn-vulkan's `models` folder (without `.param`/`.bin`), by family
+/// and the scale their weights were trained for.
+const MODELS: [(&str, u8, &str); 5] = [
+    ("realesr-animevideov3", 2, "realesr-animevideov3-x2"),
+    ("realesr-animevideov3", 3, "realesr-animevideov3-x3"),
+    ("realesr-animevideov3", 4, "realesr-animevideov3-x4"),
+    ("realesrgan-x4plus", 4, "realesrgan-x4plus"),
+    ("realesrgan-x4plus-anime", 4, "realesrgan-x4plus-anime"),
+];
+
+/// Returns the model families, in the order they are offered.
+pub fn families() -> Vec<&'static str> {
+    let mut families: Vec<&str> = MODELS.iter().map(|(family, _, _)| *family).collect();
+    families.dedup();
+    families
+}
+
+/// Returns the scales `family` has weights for.
+pub fn scales(family: &str) -> Vec<u8> {
+    MODELS
+        .iter()
+        .filter(|(name, _, _)| *name == family)
+        .map(|(_, scale, _)| *scale)
+        .collect()
+}
+
+/// Returns the model files of every family and scale.
+pub fn files() -> Vec<&'static str> {
+    MODELS.iter().map(|(_, _, file)| *file).collect()
+}
+
+/// Returns the model file upscaling with `family` by `scale`.
+pub fn model_name(family: &str, scale: u8) -> Result<&'static str, ReveError> {
+    if let Some((_, _, file)) = MODELS
+        .iter()
+        .find(|(name, model_scale, _)| *name == family && *model_scale == scale)
+    {
+        return Ok(file);
+    }
+    let scales = scales(family);
+    if scales.is_empty() {
+        return Err(ReveError::InvalidArgument(format!(
+            "unknown model {:?}, valid: {}",
+            family,
+            families().join("/")
+        )));
+    }
+    Err(ReveError::InvalidArgument(format!(
+        "{} has no {}x model, available scales: {}",
+        family,
+        scale,
+        scales
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<String>>()
+            .join("/")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_name() {
+        assert_eq!(
+            model_name(DEFAULT_FAMILY, 4).unwrap(),
+            "realesr-animevideov3-x4"
+        );
+        assert_eq!(
+            model_name("realesrgan-x4plus", 4).unwrap(),
+            "realesrgan-x4plus"
+        );
+        assert!(model_name("realesrgan-x4plus", 2).is_err());
+        assert!(model_name("realesr-realvideo", 2).is_err());
+        assert_eq!(families()[0], DEFAULT_FAMILY);
+        assert_eq!(families().len(), 3);
+        assert_eq!(files().len(), 5);
+    }
+}
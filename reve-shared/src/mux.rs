@@ -0,0 +1,295 @@
+//! Streams of the source muxed next to the upscaled video.
+//!
+//! The streams are mapped one by one from the source's ffprobe listing, so any layout of
+//! audio, subtitles, data and attachments gives a working command. Streams are copied
+//! unless the output container cannot hold their codec: mp4 gets its text subtitles as
+//! mov_text and uncommon audio as aac, and leaves out bitmap subtitles, attachments and data
+//! streams.
+
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{ReveError, TrackSelection};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+/// Audio codecs mp4 players handle, copied as they are.
+const MP4_AUDIO: [&str; 8] = ["aac", "mp3", "mp2", "ac3", "eac3", "opus", "flac", "alac"];
+
+/// Text subtitle codecs, converted to mov_text in mp4.
+const TEXT_SUBTITLES: [&str; 6] = ["subrip", "ass", "ssa", "webvtt", "mov_text", "text"];
+
+/// Text subtitle codecs whose styling is lost as mov_text.
+const STYLED_SUBTITLES: [&str; 2] = ["ass", "ssa"];
+
+/// A stream of the source.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SourceStream {
+    pub index: u32,
+    /// `video`, `audio`, `subtitle`, `data` or `attachment`.
+    #[serde(default)]
+    pub codec_type: String,
+    #[serde(default)]
+    pub codec_name: String,
+}
+
+#[derive(Deserialize)]
+struct Output {
+    #[serde(default)]
+    streams: Vec<SourceStream>,
+}
+
+/// Lists the streams of `path`.
+pub fn source_streams(binaries: &Binaries, path: &Path) -> Result<Vec<SourceStream>, ReveError> {
+    let mut command = Command::new(&binaries.ffprobe);
+    command.args([
+        "-v",
+        "error",
+        "-show_entries",
+        "stream=index,codec_type,codec_name",
+        "-of",
+        "json",
+    ]);
+    command.arg(path);
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Probe(command_failure(
+            "ffprobe could not list the streams of the input",
+            &command,
+            &output,
+        )));
+    }
+    let output: Output = serde_json::from_slice(&output.stdout)
+        .map_err(|err| ReveError::Probe(format!("invalid ffprobe output: {}", err)))?;
+    Ok(output.streams)
+}
+
+/// Streams selected for the output with the settings of the job.
+pub(crate) struct MuxSelection<'a> {
+    pub audio_tracks: &'a TrackSelection,
+    pub subtitle_tracks: &'a TrackSelection,
+    /// Subtitle track burned into the video, left out.
+    pub burn_subs: Option<u32>,
+    pub mp4: bool,
+}
+
+/// Selected streams of the source an mp4 output could not keep as they are.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Mp4Fit {
+    /// Streams mp4 can not hold, like bitmap subtitles and attachments.
+    pub dropped: Vec<String>,
+    /// Streams converted for mp4, losing quality or styling.
+    pub converted: Vec<String>,
+}
+
+impl Mp4Fit {
+    /// Returns whether mp4 keeps every selected stream as it is.
+    pub fn is_lossless(&self) -> bool {
+        self.dropped.is_empty() && self.converted.is_empty()
+    }
+}
+
+/// Arguments muxing the source streams.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct MuxPlan {
+    /// `-map` arguments, after the one of the upscaled video.
+    pub maps: Vec<String>,
+    /// Codec arguments replacing `-c copy` for some output streams.
+    pub codecs: Vec<String>,
+    /// Streams left out, to be logged.
+    pub skipped: Vec<String>,
+}
+
+impl MuxSelection<'_> {
+    /// Plans the mapping of `streams`, read from input `input` for audio and subtitles and
+    /// from input `source` for attachments.
+    pub fn plan(&self, streams: &[SourceStream], input: u32, source: u32) -> MuxPlan {
+        let mut plan = MuxPlan::default();
+        let (mut audio_out, mut subtitles_out) = (0, 0);
+        for stream in self.selected(streams) {
+            match stream.codec_type.as_str() {
+                "audio" => {
+                    plan.map(input, stream.index);
+                    if self.mp4 && !MP4_AUDIO.contains(&stream.codec_name.as_str()) {
+                        plan.codecs
+                            .extend([format!("-c:a:{}", audio_out), String::from("aac")]);
+                    }
+                    audio_out += 1;
+                }
+                "subtitle" => {
+                    let text = TEXT_SUBTITLES.contains(&stream.codec_name.as_str());
+                    let codec = match (self.mp4, text) {
+                        (true, false) => {
+                            plan.skipped.push(format!(
+                                "{} subtitles (stream {}), mp4 only holds text subtitles",
+                                stream.codec_name, stream.index
+                            ));
+                            continue;
+                        }
+                        (true, true) if stream.codec_name != "mov_text" => Some("mov_text"),
+                        (false, _) if stream.codec_name == "mov_text" => Some("srt"),
+                        _ => None,
+                    };
+                    plan.map(input, stream.index);
+                    if let Some(codec) = codec {
+                        plan.codecs
+                            .extend([format!("-c:s:{}", subtitles_out), codec.to_string()]);
+                    }
+                    subtitles_out += 1;
+                }
+                // Fonts attached to the mkv are needed to render its styled ASS subtitles
+                "attachment" if self.mp4 => plan.skipped.push(format!(
+                    "attachment {} (stream {}), mp4 can not hold attachments",
+                    stream.codec_name, stream.index
+                )),
+                "attachment" => plan.map(source, stream.index),
+                "data" => plan.skipped.push(format!(
+                    "{} data (stream {})",
+                    stream.codec_name, stream.index
+                )),
+                // The upscaled video replaces the first one, others are cover art
+                _ => {}
+            }
+        }
+        plan
+    }
+
+    /// Returns how the selected `streams` fit an mp4 output.
+    pub fn mp4_fit(&self, streams: &[SourceStream]) -> Mp4Fit {
+        let mut fit = Mp4Fit::default();
+        for stream in self.selected(streams) {
+            let codec = stream.codec_name.as_str();
+            match stream.codec_type.as_str() {
+                "audio" if !MP4_AUDIO.contains(&codec) => fit
+                    .converted
+                    .push(format!("{} audio (stream {}) to aac", codec, stream.index)),
+                "subtitle" if STYLED_SUBTITLES.contains(&codec) => fit.converted.push(format!(
+                    "{} subtitles (stream {}) to mov_text without their styling",
+                    codec, stream.index
+                )),
+                "subtitle" if !TEXT_SUBTITLES.contains(&codec) => fit
+                    .dropped
+                    .push(format!("{} subtitles (stream {})", codec, stream.index)),
+                "attachment" => fit
+                    .dropped
+                    .push(format!("attachment {} (stream {})", codec, stream.index)),
+                _ => {}
+            }
+        }
+        fit
+    }
+
+    /// Returns the streams kept by the track selections, without the burned subtitles.
+    fn selected<'s>(&self, streams: &'s [SourceStream]) -> Vec<&'s SourceStream> {
+        let (mut audio, mut subtitles) = (0, 0);
+        streams
+            .iter()
+            .filter(|stream| match stream.codec_type.as_str() {
+                "audio" => {
+                    audio += 1;
+                    self.audio_tracks.selects(audio - 1)
+                }
+                "subtitle" => {
+                    subtitles += 1;
+                    let track = subtitles - 1;
+                    self.subtitle_tracks.selects(track) && self.burn_subs != Some(track)
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+impl MuxPlan {
+    fn map(&mut self, input: u32, index: u32) {
+        self.maps
+            .extend([String::from("-map"), format!("{}:{}", input, index)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streams(layout: &[(&str, &str)]) -> Vec<SourceStream> {
+        layout
+            .iter()
+            .enumerate()
+            .map(|(index, (codec_type, codec_name))| SourceStream {
+                index: index as u32,
+                codec_type: codec_type.to_string(),
+                codec_name: codec_name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_mux_plan() {
+        let all = TrackSelection::All;
+        let mkv = MuxSelection {
+            audio_tracks: &all,
+            subtitle_tracks: &all,
+            burn_subs: None,
+            mp4: false,
+        };
+        let mp4 = MuxSelection { mp4: true, ..mkv };
+
+        // Video only
+        let video = streams(&[("video", "h264")]);
+        assert_eq!(mkv.plan(&video, 1, 1), MuxPlan::default());
+        assert_eq!(mp4.plan(&video, 1, 1), MuxPlan::default());
+
+        // Audio without subtitles, pcm is not played from mp4
+        let audio = streams(&[("video", "h264"), ("audio", "aac"), ("audio", "pcm_s16le")]);
+        let plan = mkv.plan(&audio, 1, 1);
+        assert_eq!(plan.maps, ["-map", "1:1", "-map", "1:2"]);
+        assert!(plan.codecs.is_empty());
+        assert_eq!(mp4.plan(&audio, 1, 1).codecs, ["-c:a:1", "aac"]);
+
+        // Subtitles, fonts and data without audio
+        let anime = streams(&[
+            ("video", "hevc"),
+            ("subtitle", "ass"),
+            ("subtitle", "hdmv_pgs_subtitle"),
+            ("attachment", "ttf"),
+            ("data", "bin_data"),
+        ]);
+        let plan = mkv.plan(&anime, 1, 2);
+        assert_eq!(plan.maps, ["-map", "1:1", "-map", "1:2", "-map", "2:3"]);
+        assert_eq!(plan.skipped.len(), 1);
+        let plan = mp4.plan(&anime, 1, 2);
+        assert_eq!(plan.maps, ["-map", "1:1"]);
+        assert_eq!(plan.codecs, ["-c:s:0", "mov_text"]);
+        assert_eq!(plan.skipped.len(), 3);
+        let fit = mkv.mp4_fit(&anime);
+        assert_eq!(fit.dropped.len(), 2);
+        assert_eq!(fit.converted.len(), 1);
+        assert!(!mkv.mp4_fit(&audio).is_lossless());
+        assert!(mkv.mp4_fit(&video).is_lossless());
+
+        // Selected tracks, without the burned subtitles
+        let first = TrackSelection::First;
+        let indices = TrackSelection::Indices(vec![1]);
+        let selected = MuxSelection {
+            audio_tracks: &indices,
+            subtitle_tracks: &first,
+            burn_subs: Some(0),
+            mp4: false,
+        };
+        let layout = streams(&[
+            ("video", "h264"),
+            ("audio", "aac"),
+            ("subtitle", "subrip"),
+            ("audio", "opus"),
+            ("subtitle", "mov_text"),
+        ]);
+        assert_eq!(selected.plan(&layout, 1, 1).maps, ["-map", "1:3"]);
+        let selected = MuxSelection {
+            subtitle_tracks: &all,
+            ..selected
+        };
+        let plan = selected.plan(&layout, 1, 1);
+        assert_eq!(plan.maps, ["-map", "1:3", "-map", "1:4"]);
+        assert_eq!(plan.codecs, ["-c:s:0", "srt"]);
+    }
+}
@@ -0,0 +1,307 @@
+//! Locations of the temporary files of a job, relative to the working directory.
+//!
+//! Every path is built with [`Path::join`] so the separators match the platform. Paths
+//! written into ffmpeg concat lists and filtergraphs are quoted by [`concat_entry`] and
+//! [`filter_value`], as file names may hold quotes, brackets or colons.
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Name of the image files written and read by ffmpeg, numbered from 1, without extension.
+const FRAME_PATTERN: &str = "frame%08d";
+
+/// Folder of the running job inside `temp`, see [`set_job`].
+static JOB: RwLock<Option<String>> = RwLock::new(None);
+
+/// Ram disk holding the frames, see [`set_frames_dir`].
+static RAMDISK: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Returns the temp folder of the running job, `temp/{job id}` once [`set_job`] was called.
+pub fn temp() -> PathBuf {
+    let root = PathBuf::from("temp");
+    match JOB.read().unwrap().as_deref() {
+        Some(id) => root.join(id),
+        None => root,
+    }
+}
+
+/// Puts the temporary files of the following calls in the folder of the job `id`.
+pub fn set_job(id: &str) {
+    *JOB.write().unwrap() = Some(id.to_string());
+}
+
+/// Returns the id of the job upscaling `input` to `output`, a stable hash of both paths.
+///
+/// Running reve again with the same paths finds the temp folder to resume.
+pub fn job_id(input: &str, output: &str) -> String {
+    // FNV-1a, unlike std's hasher it does not change between Rust releases
+    let hash = [input, "\0", output]
+        .iter()
+        .flat_map(|part| part.bytes())
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Puts the frames of the following calls in `ramdisk`, under `reve/{job id}`, rather than
+/// in the temp folder.
+pub fn set_frames_dir(ramdisk: Option<&str>) {
+    *RAMDISK.write().unwrap() = ramdisk.map(PathBuf::from);
+}
+
+/// Returns the folder holding the exported and upscaled frames of the running job, the
+/// temp folder unless [`set_frames_dir`] gave a ram disk.
+pub fn frames_dir() -> PathBuf {
+    match RAMDISK.read().unwrap().as_deref() {
+        Some(ramdisk) => ramdisk.join("reve").join(job()),
+        None => temp(),
+    }
+}
+
+/// Returns the id given to [`set_job`], empty before.
+pub fn job() -> String {
+    JOB.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Database holding the resume state of every job, see [`crate::state`].
+pub fn database() -> PathBuf {
+    PathBuf::from("reve.db")
+}
+
+/// Timestamps of the input frames of a variable frame rate video.
+pub fn timestamps() -> PathBuf {
+    temp().join("timestamps.txt")
+}
+
+/// Timestamps as written by ffmpeg, before sorting.
+pub fn raw_timestamps() -> PathBuf {
+    temp().join("timestamps.raw.txt")
+}
+
+/// Folder of the frames extracted from the input, one subfolder per segment.
+pub fn tmp_frames_root() -> PathBuf {
+    frames_dir().join("tmp_frames")
+}
+
+pub fn tmp_frames(index: u32) -> PathBuf {
+    tmp_frames_root().join(index.to_string())
+}
+
+/// Folder of the frames of the segment at `index` read by the realesrgan process `part` of
+/// `--upscaler-instances`.
+pub fn tmp_frames_part(index: u32, part: u32) -> PathBuf {
+    tmp_frames_root().join(format!("{}.{}", index, part))
+}
+
+/// Folder of the upscaled frames, one subfolder per segment.
+pub fn out_frames_root() -> PathBuf {
+    frames_dir().join("out_frames")
+}
+
+pub fn out_frames(index: u32) -> PathBuf {
+    out_frames_root().join(index.to_string())
+}
+
+/// Folder of the encoded segments.
+pub fn video_parts_root() -> PathBuf {
+    temp().join("video_parts")
+}
+
+/// Concat list of the encoded segments.
+pub fn parts_list() -> PathBuf {
+    temp().join("parts.txt")
+}
+
+/// Concat list of the input ranges kept with `--edl`.
+pub fn edl_list() -> PathBuf {
+    temp().join("edl.txt")
+}
+
+/// Returns the path the output is written to until it is complete, when it is renamed to
+/// `output_path`. An interrupted run leaves it behind instead of a truncated output.
+pub fn partial_output(output_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.part", output_path))
+}
+
+/// Returns the folder the files of the segment at `index` are copied to with
+/// `--inspect-segment`, next to the output: `movie.segment12` for `movie.mkv`.
+pub fn inspect_dir(output_path: &str, index: u32) -> PathBuf {
+    Path::new(output_path).with_extension(format!("segment{}", index))
+}
+
+/// Folder used to find a tile size realesrgan can handle.
+pub fn tile_test() -> PathBuf {
+    temp().join("tile_test")
+}
+
+/// Returns the ffmpeg image pattern of the `extension` frames in `dir`.
+pub fn frame_pattern(dir: &Path, extension: &str) -> PathBuf {
+    dir.join(format!("{}.{}", FRAME_PATTERN, extension))
+}
+
+/// Returns the `file` line of an ffmpeg concat list reading `path`.
+///
+/// The path is single quoted, in which backslashes are kept, and its own quotes are written
+/// as `'\''`: closing the quote, an escaped quote, then opening it again.
+pub fn concat_entry(path: &Path) -> String {
+    format!("file {}", quote(&path.to_string_lossy()))
+}
+
+/// Returns `path` as a value of a filter option, such as the file of the `subtitles`
+/// filter.
+///
+/// Option values escape `\`, `'` and `:`, then the filtergraph needs the value quoted so
+/// that `[`, `]`, `,` and `;` are not read as its own syntax.
+pub fn filter_value(path: &Path) -> String {
+    let mut value = String::new();
+    for c in path.to_string_lossy().chars() {
+        if matches!(c, '\\' | '\'' | ':') {
+            value.push('\\');
+        }
+        value.push(c);
+    }
+    quote(&value)
+}
+
+/// Longest Windows path, terminating null included, that programs open without the
+/// extended-length prefix.
+const MAX_PATH: usize = 260;
+
+/// Returns `path` in the form ffmpeg and realesrgan can open, see [`extended_length`] on
+/// Windows.
+pub fn native(path: &str) -> Cow<'_, str> {
+    if cfg!(windows) {
+        extended_length(path)
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Returns the extended-length form (`\\?\C:\...` or `\\?\UNC\server\share\...`) of the
+/// absolute Windows `path` when it is too long for `MAX_PATH`, otherwise `path` itself.
+///
+/// Extended-length paths reach the file system as they are, so their `/` separators and `.`
+/// and `..` components are resolved here.
+pub fn extended_length(path: &str) -> Cow<'_, str> {
+    if path.encode_utf16().count() < MAX_PATH
+        || path.starts_with(r"\\?\")
+        || path.starts_with(r"\\.\")
+    {
+        return Cow::Borrowed(path);
+    }
+    let separated = path.replace('/', "\\");
+    let (prefix, rest, root_parts) = if let Some(unc) = separated.strip_prefix(r"\\") {
+        (r"\\?\UNC\", unc, 2)
+    } else if separated.get(1..3) == Some(r":\") {
+        (r"\\?\", separated.as_str(), 1)
+    } else {
+        // Relative paths are left to the caller, which makes them absolute first
+        return Cow::Borrowed(path);
+    };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in rest.split('\\') {
+        match part {
+            "" | "." => {}
+            ".." if parts.len() > root_parts => {
+                parts.pop();
+            }
+            ".." => {}
+            _ => parts.push(part),
+        }
+    }
+    Cow::Owned(format!("{}{}", prefix, parts.join("\\")))
+}
+
+/// Returns whether `path` is on a network share (`\\server\share\...`).
+pub fn is_network(path: &str) -> bool {
+    path.starts_with(r"\\?\UNC\")
+        || (path.starts_with(r"\\") && !path.starts_with(r"\\?\") && !path.starts_with(r"\\.\"))
+}
+
+/// Single quotes `value` the way ffmpeg reads its tokens.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            tmp_frames(3),
+            Path::new("temp").join("tmp_frames").join("3")
+        );
+        assert_eq!(
+            frame_pattern(&out_frames(0), "png"),
+            Path::new("temp")
+                .join("out_frames")
+                .join("0")
+                .join("frame%08d.png")
+        );
+
+        let id = job_id("/videos/ep1.mkv", "/videos/ep1.hevc.mkv");
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, job_id("/videos/ep1.mkv", "/videos/ep1.hevc.mkv"));
+        assert_ne!(id, job_id("/videos/ep2.mkv", "/videos/ep1.hevc.mkv"));
+
+        assert_eq!(
+            partial_output("/videos/ep1.hevc.mkv"),
+            Path::new("/videos/ep1.hevc.mkv.part")
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_quoting() {
+        assert_eq!(
+            concat_entry(Path::new("/videos/Ep 01 [BD] l'été 東京.mkv")),
+            "file '/videos/Ep 01 [BD] l'\\''été 東京.mkv'"
+        );
+        assert_eq!(
+            concat_entry(Path::new(r"C:\Users\José\it's (1).mkv")),
+            r"file 'C:\Users\José\it'\''s (1).mkv'"
+        );
+        assert_eq!(
+            filter_value(Path::new("/subs/a, b; [c].mkv")),
+            "'/subs/a, b; [c].mkv'"
+        );
+        assert_eq!(
+            filter_value(Path::new(r"C:\Anime\it's.mkv")),
+            r"'C\:\\Anime\\it\'\''s.mkv'"
+        );
+    }
+
+    #[test]
+    fn test_windows_paths() {
+        let folder = "a".repeat(100);
+        let long = format!(r"C:\Videos\{}\{}\..\{}\ep1.mkv", folder, folder, folder);
+        assert_eq!(
+            extended_length(&long),
+            format!(r"\\?\C:\Videos\{}\{}\ep1.mkv", folder, folder)
+        );
+        let unc = format!(r"\\nas\anime\{}/./{}\{}\ep1.mkv", folder, folder, folder);
+        assert_eq!(
+            extended_length(&unc),
+            format!(
+                r"\\?\UNC\nas\anime\{}\{}\{}\ep1.mkv",
+                folder, folder, folder
+            )
+        );
+        assert_eq!(extended_length(r"C:\Videos\ep1.mkv"), r"C:\Videos\ep1.mkv");
+        assert_eq!(
+            extended_length(r"\\nas\anime\ep1.mkv"),
+            r"\\nas\anime\ep1.mkv"
+        );
+        let verbatim = format!(r"\\?\C:\{}\{}\{}", folder, folder, folder);
+        assert_eq!(extended_length(&verbatim), verbatim);
+
+        assert!(is_network(r"\\nas\anime\ep1.mkv"));
+        assert!(is_network(r"\\?\UNC\nas\anime\ep1.mkv"));
+        assert!(!is_network(r"\\?\C:\Videos\ep1.mkv"));
+        assert!(!is_network(r"C:\Videos\ep1.mkv"));
+        assert!(!is_network("/mnt/nas/ep1.mkv"));
+    }
+}
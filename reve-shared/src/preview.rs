@@ -0,0 +1,73 @@
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{models, ReveError};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A frame of the input and the same frame upscaled, as png files.
+pub struct Preview {
+    pub original: PathBuf,
+    pub upscaled: PathBuf,
+}
+
+/// Extracts the frame shown at `timestamp` seconds into `path` and upscales it with the
+/// weights of the `model` family for `scale`, writing both images to `dir`.
+///
+/// Lets models and scales be compared on a single frame before starting a long upscale.
+pub fn preview_frame(
+    binaries: &Binaries,
+    path: &str,
+    timestamp: f64,
+    model: &str,
+    scale: u8,
+    dir: &Path,
+) -> Result<Preview, ReveError> {
+    fs::create_dir_all(dir)?;
+    let original = dir.join("original.png");
+    let model = models::model_name(model, scale)?;
+    let upscaled = dir.join(format!("{}.png", model));
+    let _ = fs::remove_file(&original);
+    let _ = fs::remove_file(&upscaled);
+
+    let mut command = Command::new(&binaries.ffmpeg);
+    command
+        .args([
+            "-v",
+            "error",
+            "-y",
+            "-ss",
+            &timestamp.to_string(),
+            "-i",
+            path,
+        ])
+        .args(["-frames:v", "1"])
+        .arg(&original);
+    let output = run(&mut command)?;
+    if !output.status.success() || !original.exists() {
+        return Err(ReveError::Ffmpeg(command_failure(
+            "could not extract the preview frame",
+            &command,
+            &output,
+        )));
+    }
+
+    let mut command = Command::new(&binaries.realesrgan);
+    command
+        .arg("-i")
+        .arg(&original)
+        .arg("-o")
+        .arg(&upscaled)
+        .args(["-n", model])
+        .args(["-s", &scale.to_string(), "-f", "png"]);
+    let output = run(&mut command)?;
+    if !output.status.success() || !upscaled.exists() {
+        return Err(ReveError::Upscaler(command_failure(
+            "could not upscale the preview frame",
+            &command,
+            &output,
+        )));
+    }
+
+    Ok(Preview { original, upscaled })
+}
@@ -0,0 +1,218 @@
+//! Running the external tools and reporting their failures.
+
+use crate::control::JobControl;
+use crate::ReveError;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, ErrorKind};
+use std::process::{Child, ChildStderr, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+/// Set once `--nice` lowered the priority of the tools.
+static LOW_PRIORITY: AtomicBool = AtomicBool::new(false);
+
+/// Runs the tools started from now on below the normal priority, so the computer stays
+/// usable during long jobs.
+///
+/// On unix reve lowers its own cpu priority (and io priority on Linux), which the tools
+/// inherit. Windows children do not inherit it, so they are created in the below normal
+/// priority class instead.
+pub(crate) fn lower_priority() {
+    if LOW_PRIORITY.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(unix)]
+    {
+        let pid = std::process::id().to_string();
+        let mut commands = vec![("renice", vec!["-n", "10", "-p", pid.as_str()])];
+        if cfg!(target_os = "linux") {
+            // Idle class: disk access only when no other program needs it
+            commands.push(("ionice", vec!["-c", "3", "-p", pid.as_str()]));
+        }
+        for (program, args) in commands {
+            match Command::new(program).args(args).output() {
+                Ok(output) if output.status.success() => {}
+                _ => tracing::warn!("could not lower the priority of reve with {}", program),
+            }
+        }
+    }
+}
+
+/// Creates `command` in the below normal priority class on Windows once
+/// [`lower_priority`] was called.
+pub(crate) fn prioritize(command: &mut Command) {
+    #[cfg(windows)]
+    if LOW_PRIORITY.load(Ordering::Relaxed) {
+        use std::os::windows::process::CommandExt;
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        command.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+    }
+    #[cfg(not(windows))]
+    let _ = command;
+}
+
+/// Spawns `command` with its stderr captured, mapping a missing executable to
+/// `ReveError::MissingBinary`.
+///
+/// `error` builds the error returned by [`StageProcess::wait`] when the command fails.
+pub(crate) fn spawn_stderr(
+    command: &mut Command,
+    error: fn(String) -> ReveError,
+) -> Result<StageProcess, ReveError> {
+    let program = command.get_program().to_string_lossy().to_string();
+    debug!("spawning {:?}", command);
+    prioritize(command);
+    let mut child = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| spawn_error(&program, err))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| io::Error::other("Could not capture standard output."))?;
+
+    Ok(StageProcess {
+        command_line: command_line(command),
+        child: Arc::new(Mutex::new(child)),
+        stderr: BufReader::new(stderr),
+        tail: VecDeque::new(),
+        error,
+    })
+}
+
+/// Running command of a segment stage whose stderr reports its progress.
+pub struct StageProcess {
+    command_line: String,
+    child: Arc<Mutex<Child>>,
+    stderr: BufReader<ChildStderr>,
+    tail: VecDeque<String>,
+    error: fn(String) -> ReveError,
+}
+
+impl StageProcess {
+    /// Returns the stderr lines of the command, logging them and keeping the last ones for
+    /// the error of a failed run.
+    pub fn lines(&mut self) -> impl Iterator<Item = String> + '_ {
+        let tail = &mut self.tail;
+        (&mut self.stderr)
+            .lines()
+            .map_while(Result::ok)
+            .inspect(move |line| {
+                debug!("{}", line);
+                if !line.trim().is_empty() {
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line.clone());
+                }
+            })
+    }
+
+    /// Lets `control` kill the command when the job is stopped.
+    pub fn track(&self, control: &JobControl) {
+        control.track(&self.child);
+    }
+
+    /// Waits for the command to exit and fails if it was unsuccessful.
+    pub fn wait(mut self) -> Result<(), ReveError> {
+        // Drain what is left so the command cannot block on a full pipe
+        self.lines().for_each(drop);
+        let status = self.child.lock().unwrap().wait()?;
+        if status.success() {
+            return Ok(());
+        }
+        Err((self.error)(format!(
+            "exited with {}\n\ncommand: {}\n\n{}",
+            status,
+            self.command_line,
+            Vec::from(self.tail).join("\n")
+        )))
+    }
+}
+
+/// Runs `command` to completion, logging its command line and stderr.
+pub(crate) fn run(command: &mut Command) -> Result<Output, ReveError> {
+    let program = command.get_program().to_string_lossy().to_string();
+    debug!("running {:?}", command);
+    prioritize(command);
+    let output = command.output().map_err(|err| spawn_error(&program, err))?;
+    debug!(
+        "{} exited with {}\n{}",
+        program,
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim_end()
+    );
+    Ok(output)
+}
+
+/// Number of trailing stderr lines kept in the error of a failed command.
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Returns the command line of `command` as it could be typed in a shell.
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| {
+            let arg = arg.to_string_lossy();
+            if arg.is_empty() || arg.contains(' ') {
+                format!("\"{}\"", arg)
+            } else {
+                arg.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Returns the last non-empty lines of a command's stderr.
+fn stderr_tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+/// Describes a failed command with its exit status, command line and the end of its stderr.
+pub(crate) fn command_failure(message: &str, command: &Command, output: &Output) -> String {
+    format!(
+        "{} ({})\n\ncommand: {}\n\n{}",
+        message,
+        output.status,
+        command_line(command),
+        stderr_tail(&output.stderr)
+    )
+}
+
+pub(crate) fn spawn_error(program: &str, err: io::Error) -> ReveError {
+    if err.kind() == ErrorKind::NotFound {
+        ReveError::MissingBinary(program.to_string())
+    } else {
+        ReveError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binaries;
+
+    #[test]
+    fn test_command_failure() {
+        let mut command = Command::new(binaries::find(binaries::FFMPEG));
+        command.args(["-i", "my video.mp4", "-c", "copy", "out.mkv"]);
+        assert_eq!(
+            command_line(&command),
+            "ffmpeg -i \"my video.mp4\" -c copy out.mkv"
+        );
+
+        let stderr: String = (1..=15).map(|line| format!("{}\n\n", line)).collect();
+        assert_eq!(
+            stderr_tail(stderr.as_bytes()),
+            "6\n7\n8\n9\n10\n11\n12\n13\n14\n15"
+        );
+    }
+}
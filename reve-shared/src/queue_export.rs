@@ -0,0 +1,234 @@
+//! Json and csv forms of the queue of `reve daemon`, written by `reve db export` and read by
+//! `reve db import` to move it to another machine or a spreadsheet.
+//!
+//! The csv has a header row and one row per file, with the columns of [`QueuedFile`]; an
+//! empty field is an unset one. Fields holding a comma, a quote or a line break are quoted.
+//! The columns added since the first version may be missing, as in the csv it wrote.
+
+use crate::state::{QueueStatus, QueuedFile, UpscaleStats};
+use crate::ReveError;
+use std::str::FromStr;
+
+const HEADER: [&str; 12] = [
+    "path",
+    "output_dir",
+    "profile",
+    "status",
+    "error",
+    "priority",
+    "started",
+    "finished",
+    "duration",
+    "fps",
+    "output",
+    "output_size",
+];
+
+pub fn to_json(files: &[QueuedFile]) -> Result<String, ReveError> {
+    serde_json::to_string_pretty(files).map_err(|err| ReveError::Io(err.into()))
+}
+
+pub fn from_json(json: &str) -> Result<Vec<QueuedFile>, ReveError> {
+    serde_json::from_str(json)
+        .map_err(|err| ReveError::InvalidArgument(format!("invalid queue json: {}", err)))
+}
+
+pub fn to_csv(files: &[QueuedFile]) -> String {
+    let mut csv = HEADER.join(",");
+    csv.push('\n');
+    for file in files {
+        let stats = &file.stats;
+        let fields = [
+            text(Some(&file.path)),
+            text(file.output_dir.as_ref()),
+            text(file.profile.as_ref()),
+            text(Some(file.status.name())),
+            text(file.error.as_ref()),
+            text(Some(file.priority)),
+            text(stats.started),
+            text(stats.finished),
+            text(stats.duration),
+            text(stats.fps),
+            text(stats.output.as_ref()),
+            text(stats.output_size),
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| quote(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Reads the rows of a csv written by [`to_csv`], whose columns may be in any order.
+pub fn from_csv(csv: &str) -> Result<Vec<QueuedFile>, ReveError> {
+    let invalid =
+        |reason: String| ReveError::InvalidArgument(format!("invalid queue csv: {}", reason));
+    let mut records = records(csv).map_err(invalid)?.into_iter();
+    let header = records.next().unwrap_or_default();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| invalid(format!("no {} column", name)))
+    };
+    let [path, output_dir, profile, status, error] = [
+        column(HEADER[0])?,
+        column(HEADER[1])?,
+        column(HEADER[2])?,
+        column(HEADER[3])?,
+        column(HEADER[4])?,
+    ];
+    // Missing from the csv written before they were added
+    let [priority, started, finished, duration, fps, output, output_size] = [5, 6, 7, 8, 9, 10, 11]
+        .map(|index| {
+            let name = HEADER[index];
+            (name, header.iter().position(|column| column == name))
+        });
+    records
+        .filter(|record| record.iter().any(|field| !field.is_empty()))
+        .enumerate()
+        .map(|(row, record)| {
+            let field = |index: usize| record.get(index).filter(|field| !field.is_empty()).cloned();
+            let optional =
+                |(name, index): (&'static str, Option<usize>)| (name, index.and_then(field));
+            let missing = || invalid(format!("row {} has no path", row + 1));
+            Ok(QueuedFile {
+                path: field(path).ok_or_else(missing)?,
+                output_dir: field(output_dir),
+                profile: field(profile),
+                status: QueueStatus::from_name(&field(status).unwrap_or_default()),
+                error: field(error),
+                priority: number(row, optional(priority))?.unwrap_or_default(),
+                stats: UpscaleStats {
+                    started: number(row, optional(started))?,
+                    finished: number(row, optional(finished))?,
+                    duration: number(row, optional(duration))?,
+                    fps: number(row, optional(fps))?,
+                    output: optional(output).1,
+                    output_size: number(row, optional(output_size))?,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Parses the number of the named field of the record at `row`, `None` when it is empty.
+fn number<T: FromStr>(
+    row: usize,
+    (name, field): (&str, Option<String>),
+) -> Result<Option<T>, ReveError> {
+    field
+        .map(|field| {
+            field.parse().map_err(|_| {
+                ReveError::InvalidArgument(format!(
+                    "invalid queue csv: row {} has an invalid {}",
+                    row + 1,
+                    name
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Returns the field of `value`, empty when it is unset.
+fn text(value: Option<impl ToString>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+/// Quotes `field` when it holds a separator.
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Splits `csv` into its records and their fields.
+fn records(csv: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(char) = chars.next() {
+        match (quoted, char) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, char) => field.push(char),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, char) => field.push(char),
+        }
+    }
+    if quoted {
+        return Err(String::from("unterminated quote"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_export() {
+        let files = vec![
+            QueuedFile {
+                path: String::from("/inbox/Movie, the \"cut\".mkv"),
+                output_dir: Some(String::from("/out")),
+                profile: None,
+                status: QueueStatus::Failed,
+                error: Some(String::from("ffmpeg failed:\nno space left")),
+                priority: 2,
+                stats: UpscaleStats {
+                    started: Some(1_700_000_000),
+                    finished: Some(1_700_003_600),
+                    duration: Some(3600.25),
+                    fps: Some(8.5),
+                    output: Some(String::from("/out/Movie, the \"cut\".mkv")),
+                    output_size: Some(4_000_000_000),
+                },
+            },
+            QueuedFile {
+                path: String::from(r"D:\inbox\song.mkv"),
+                output_dir: None,
+                profile: Some(String::from("tv-h264")),
+                status: QueueStatus::NoVideo,
+                error: Some(String::from("no video stream")),
+                priority: 0,
+                stats: UpscaleStats::default(),
+            },
+        ];
+        let csv = to_csv(&files);
+        assert!(csv.starts_with(
+            "path,output_dir,profile,status,error,priority,started,finished,duration,fps,output,\
+             output_size\n\"/inbox/Movie, the \"\"cut\"\".mkv\",/out,,failed,"
+        ));
+        assert_eq!(from_csv(&csv).unwrap(), files);
+        assert_eq!(from_json(&to_json(&files).unwrap()).unwrap(), files);
+
+        // Columns reordered by a spreadsheet
+        let files = from_csv("status,path,error,profile,output_dir\r\ndone,/a.mp4,,,\r\n").unwrap();
+        assert_eq!(files[0].path, "/a.mp4");
+        assert_eq!(files[0].status, QueueStatus::Done);
+        assert_eq!(files[0].priority, 0);
+        assert!(from_csv("path,status\n/a.mp4,done\n").is_err());
+        assert!(
+            from_csv("path,output_dir,profile,status,error,priority\n/a.mp4,,,done,,x\n").is_err()
+        );
+        assert!(from_csv("path,output_dir,profile,status,error\n\"/a.mp4,,,done,\n").is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Upscaling segments on other machines through a shared folder.
+//!
+//! A job started with `--share` publishes its plan to `{share}/jobs/{id}`, where every
+//! pending segment has a marker file in `segments`:
+//!
+//! - `{index}.todo`: waiting for a worker
+//! - `{index}.{worker}.claimed`: renamed by the worker upscaling it, the rename is atomic so
+//!   a segment is only claimed once
+//! - `{index}.done`: its part is in `parts`, ready to be collected
+//! - `{index}.failed`: holds the error the worker hit
+//!
+//! Workers (`reve worker`) export, upscale and encode a segment in their own temp folder,
+//! then copy the part to the share. The job collects the parts and concatenates them as if
+//! they were encoded locally.
+
+use crate::binaries::Binaries;
+use crate::control::JobControl;
+use crate::job::{resolve_tile_size, JobProgress, Stages};
+use crate::scheduler::Merged;
+use crate::{paths, rebuild_temp, Args, ReveError, Video};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Interval at which the share is checked for finished segments and new work.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a worker needs to upscale the segments of a job.
+#[derive(Serialize, Deserialize)]
+struct JobSpec {
+    args: Args,
+    /// The planned video, its input path relative to the share when it is inside it.
+    video: Video,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Marker {
+    Todo,
+    Claimed(String),
+    Done,
+    Failed,
+}
+
+/// Parses a marker file name of the `segments` folder.
+fn parse_marker(name: &str) -> Option<(u32, Marker)> {
+    let (index, status) = name.split_once('.')?;
+    let marker = match status {
+        "todo" => Marker::Todo,
+        "done" => Marker::Done,
+        "failed" => Marker::Failed,
+        _ => Marker::Claimed(status.strip_suffix(".claimed")?.to_string()),
+    };
+    Some((index.parse().ok()?, marker))
+}
+
+/// Checks that a worker name can be part of a marker file name.
+pub fn worker_name_validation(s: &str) -> Result<String, String> {
+    if s.is_empty()
+        || !s
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(String::from(
+            "worker names may only contain letters, digits, - and _",
+        ));
+    }
+    Ok(s.to_string())
+}
+
+/// A job published to a shared folder.
+pub struct RemoteJob {
+    dir: PathBuf,
+}
+
+impl RemoteJob {
+    /// Publishes the pending segments of `video` to `share`.
+    ///
+    /// Segments already claimed or done in a previous run are left to their worker.
+    pub fn publish(share: &Path, args: &Args, video: &Video) -> Result<RemoteJob, ReveError> {
+        let id = Path::new(&video.output_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().replace(['.', ' '], "_"))
+            .unwrap_or_else(|| String::from("job"));
+        let job = RemoteJob {
+            dir: share.join("jobs").join(id),
+        };
+        fs::create_dir_all(job.segments_dir())?;
+        fs::create_dir_all(job.parts_dir())?;
+
+        let mut video = video.clone();
+        if let Ok(relative) = Path::new(&video.path).strip_prefix(share) {
+            video.path = relative.to_string_lossy().into_owned();
+        }
+        if video.variable_frame_rate {
+            fs::copy(paths::timestamps(), job.dir.join("timestamps.txt"))?;
+        }
+        let spec = JobSpec {
+            args: args.clone(),
+            video: video.clone(),
+        };
+        fs::write(
+            job.dir.join("job.json"),
+            serde_json::to_string(&spec).unwrap(),
+        )?;
+
+        let markers = job.markers()?;
+        for segment in &video.segments {
+            if !markers.iter().any(|(index, _)| *index == segment.index) {
+                fs::write(job.marker_path(segment.index, &Marker::Todo), "")?;
+            }
+        }
+        Ok(job)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn segments_dir(&self) -> PathBuf {
+        self.dir.join("segments")
+    }
+
+    fn parts_dir(&self) -> PathBuf {
+        self.dir.join("parts")
+    }
+
+    fn marker_path(&self, index: u32, marker: &Marker) -> PathBuf {
+        let name = match marker {
+            Marker::Todo => format!("{}.todo", index),
+            Marker::Claimed(worker) => format!("{}.{}.claimed", index, worker),
+            Marker::Done => format!("{}.done", index),
+            Marker::Failed => format!("{}.failed", index),
+        };
+        self.segments_dir().join(name)
+    }
+
+    fn markers(&self) -> Result<Vec<(u32, Marker)>, ReveError> {
+        Ok(fs::read_dir(self.segments_dir())?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| parse_marker(&entry.file_name().to_string_lossy()))
+            .collect())
+    }
+
+    /// Returns the segments whose part is ready to be collected.
+    pub fn done(&self) -> Result<Vec<u32>, ReveError> {
+        Ok(self
+            .markers()?
+            .into_iter()
+            .filter(|(_, marker)| *marker == Marker::Done)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    /// Returns a segment a worker failed to upscale and its error.
+    pub fn failure(&self) -> Result<Option<(u32, String)>, ReveError> {
+        let Some((index, _)) = self
+            .markers()?
+            .into_iter()
+            .find(|(_, marker)| *marker == Marker::Failed)
+        else {
+            return Ok(None);
+        };
+        let err = fs::read_to_string(self.marker_path(index, &Marker::Failed))?;
+        Ok(Some((index, err)))
+    }
+
+    /// Copies the part of the segment at `index` into temp.
+    pub fn collect(&self, video: &Video, index: u32) -> Result<(), ReveError> {
+        let part_path = video.part_path(index);
+        fs::copy(
+            self.parts_dir().join(part_path.file_name().unwrap()),
+            paths::temp().join(part_path),
+        )?;
+        Ok(())
+    }
+
+    /// Removes the job from the share, workers drop the segments they are upscaling.
+    pub fn close(&self) -> Result<(), ReveError> {
+        if self.dir.exists() {
+            fs::remove_dir_all(&self.dir)?;
+        }
+        Ok(())
+    }
+
+    /// Claims the first pending segment for `worker`, `None` if every segment is taken.
+    fn claim(&self, worker: &str) -> Option<u32> {
+        let mut pending: Vec<u32> = self
+            .markers()
+            .ok()?
+            .into_iter()
+            .filter(|(_, marker)| *marker == Marker::Todo)
+            .map(|(index, _)| index)
+            .collect();
+        pending.sort_unstable();
+        // Another worker may rename the same marker first
+        pending.into_iter().find(|&index| {
+            fs::rename(
+                self.marker_path(index, &Marker::Todo),
+                self.marker_path(index, &Marker::Claimed(worker.to_string())),
+            )
+            .is_ok()
+        })
+    }
+
+    /// Upscales the claimed segment at `index` in temp and publishes its part.
+    fn upscale<P: JobProgress + ?Sized>(
+        &self,
+        share: &Path,
+        index: u32,
+        binaries: &Binaries,
+        control: &JobControl,
+        progress: &P,
+    ) -> Result<(), ReveError> {
+        let spec_json = fs::read_to_string(self.dir.join("job.json"))?;
+        let JobSpec { args, mut video } =
+            serde_json::from_str(&spec_json).map_err(|err| ReveError::Io(err.into()))?;
+        if Path::new(&video.path).is_relative() {
+            video.path = share.join(&video.path).to_string_lossy().into_owned();
+        }
+
+        rebuild_temp(false)?;
+        if video.variable_frame_rate {
+            fs::copy(self.dir.join("timestamps.txt"), paths::timestamps())?;
+        }
+        if video.tile_size.is_none() {
+            video.tile_size = resolve_tile_size(&video, &args, binaries)?;
+        }
+        let segment = video
+            .segments
+            .iter()
+            .find(|segment| segment.index == index)
+            .cloned()
+            .ok_or_else(|| ReveError::Worker(format!("segment {} is not planned", index)))?;
+
+        let stages = Stages::new(&video, &args, binaries, control);
+        loop {
+            stages.export(&segment, progress)?;
+            stages.upscale(&segment, progress)?;
+            stages.merge(&segment, progress)?;
+            if stages.reconcile(&segment)? == Merged::Done {
+                break;
+            }
+        }
+
+        // The job only sees the part once it is complete
+        let part_path = video.part_path(index);
+        let part = self.parts_dir().join(part_path.file_name().unwrap());
+        let partial = part.with_extension("partial");
+        fs::copy(paths::temp().join(part_path), &partial)?;
+        fs::rename(&partial, part)?;
+        Ok(())
+    }
+}
+
+/// Returns the jobs published to `share`.
+fn jobs(share: &Path) -> Vec<RemoteJob> {
+    let Ok(entries) = fs::read_dir(share.join("jobs")) else {
+        return Vec::new();
+    };
+    let mut jobs: Vec<RemoteJob> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| RemoteJob { dir: entry.path() })
+        .filter(|job| job.dir.join("job.json").exists())
+        .collect();
+    jobs.sort_by(|a, b| a.dir.cmp(&b.dir));
+    jobs
+}
+
+/// Upscales the segments published to `share` as `worker` until `control` is stopped.
+///
+/// Segments are processed in the temp folder of the working directory, which is cleared for
+/// each of them.
+pub fn work<P: JobProgress + ?Sized>(
+    share: &Path,
+    worker: &str,
+    binaries: &Binaries,
+    control: &JobControl,
+    progress: &P,
+) -> Result<(), ReveError> {
+    info!("waiting for segments in {}", share.display());
+    while !control.is_stopped() {
+        let claimed = jobs(share)
+            .into_iter()
+            .find_map(|job| job.claim(worker).map(|index| (job, index)));
+        let Some((job, index)) = claimed else {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        info!("upscaling segment {} of {}", index, job.dir.display());
+        let claim = job.marker_path(index, &Marker::Claimed(worker.to_string()));
+        match job.upscale(share, index, binaries, control, progress) {
+            Ok(()) => fs::rename(&claim, job.marker_path(index, &Marker::Done))?,
+            Err(ReveError::Interrupted) => {
+                // Hand the segment back to the other workers
+                let _ = fs::rename(&claim, job.marker_path(index, &Marker::Todo));
+                return Err(ReveError::Interrupted);
+            }
+            // The job was closed while the segment was upscaled
+            Err(_) if !job.dir.exists() => warn!("{} was closed", job.dir.display()),
+            Err(err) => {
+                warn!("segment {} failed: {}", index, err);
+                fs::write(job.marker_path(index, &Marker::Failed), err.to_string())?;
+                let _ = fs::remove_file(&claim);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_marker() {
+        assert_eq!(parse_marker("3.todo"), Some((3, Marker::Todo)));
+        assert_eq!(
+            parse_marker("12.gpu-box_2.claimed"),
+            Some((12, Marker::Claimed(String::from("gpu-box_2"))))
+        );
+        assert_eq!(parse_marker("0.done"), Some((0, Marker::Done)));
+        assert_eq!(parse_marker("1.failed"), Some((1, Marker::Failed)));
+        assert_eq!(parse_marker("1.mkv.partial"), None);
+        assert_eq!(parse_marker("notes.txt"), None);
+
+        assert!(worker_name_validation("gpu-box_2").is_ok());
+        assert!(worker_name_validation("gpu.box").is_err());
+        assert!(worker_name_validation("").is_err());
+    }
+}
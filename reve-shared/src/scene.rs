@@ -0,0 +1,126 @@
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{ReveError, Segment, Video};
+
+/// Scene change score above which ffmpeg's `select` filter reports a cut.
+const SCENE_THRESHOLD: f32 = 0.3;
+
+/// Returns the input frames starting a new scene, from the `pts_time` of ffmpeg's showinfo
+/// output.
+fn parse_showinfo(stderr: &str, frame_rate: f32) -> Vec<u32> {
+    stderr
+        .lines()
+        .filter(|line| line.contains("Parsed_showinfo"))
+        .filter_map(|line| line.split_once("pts_time:"))
+        .filter_map(|(_, rest)| rest.split_whitespace().next()?.parse::<f64>().ok())
+        .map(|time| (time * frame_rate as f64).round() as u32)
+        .collect()
+}
+
+/// Splits `frame_count` frames into segments of about `segment_size` frames, returning the
+/// first frame and size of each.
+///
+/// A segment ends on the cut closest to `segment_size` frames if one is within a quarter of
+/// it, otherwise it is cut at `segment_size` frames.
+fn split_on_cuts(frame_count: u32, segment_size: u32, cuts: &[u32]) -> Vec<(u32, u32)> {
+    let margin = segment_size / 4;
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < frame_count {
+        let target = start + segment_size;
+        if frame_count <= target + margin {
+            segments.push((start, frame_count - start));
+            break;
+        }
+        let end = cuts
+            .iter()
+            .copied()
+            .filter(|cut| (target - margin..=target + margin).contains(cut))
+            .min_by_key(|cut| cut.abs_diff(target))
+            .unwrap_or(target);
+        segments.push((start, end - start));
+        start = end;
+    }
+    segments
+}
+
+impl Video {
+    /// Returns the pending segment at `index` if the plan was split on scene cuts.
+    pub(crate) fn scene_segment(&self, index: u32) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .find(|segment| segment.index == index && segment.start.is_some())
+    }
+
+    /// Detects the scene cuts of the range with ffmpeg and plans the segments to end on them.
+    ///
+    /// Seams between segments then fall on cuts, where a dropped or repeated frame does not
+    /// show.
+    pub fn split_on_scenecuts(&mut self, binaries: &Binaries) -> Result<(), ReveError> {
+        let mut command = self.decoder(binaries, self.start_frame, self.frame_count)?;
+        let filter = self
+            .decoder_filter(
+                self.start_frame,
+                self.frame_count,
+                [format!("select='gt(scene,{})',showinfo", SCENE_THRESHOLD)],
+            )?
+            .unwrap_or_default();
+        let output = run(command.args([
+            "-frames:v",
+            &self.frame_count.to_string(),
+            "-vf",
+            &filter,
+            "-f",
+            "null",
+            "-",
+        ]))?;
+        if !output.status.success() {
+            return Err(ReveError::Ffmpeg(command_failure(
+                "could not detect the scene cuts of the input",
+                &command,
+                &output,
+            )));
+        }
+
+        let cuts: Vec<u32> =
+            parse_showinfo(&String::from_utf8_lossy(&output.stderr), self.frame_rate)
+                .into_iter()
+                .filter_map(|cut| cut.checked_sub(self.start_frame))
+                .collect();
+        self.segments = split_on_cuts(self.frame_count, self.segment_size, &cuts)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (start, size))| Segment {
+                index: index as u32,
+                size,
+                start: Some(start),
+            })
+            .collect();
+        self.segment_count = self.segments.len() as u32;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_on_cuts() {
+        let stderr = "\
+[Parsed_showinfo_1 @ 0x1] n:   0 pts:  21021 pts_time:21.021  duration:   1001 fmt:yuv420p
+[Parsed_showinfo_1 @ 0x1] n:   1 pts:  40040 pts_time:40.04   duration:   1001 fmt:yuv420p
+frame=    2 fps=0.0 q=-0.0 Lsize=N/A time=00:00:41.00 bitrate=N/A speed=80x";
+        assert_eq!(parse_showinfo(stderr, 23.976), vec![504, 960]);
+
+        assert_eq!(
+            split_on_cuts(2500, 1000, &[504, 960, 1900]),
+            vec![(0, 960), (960, 940), (1900, 600)]
+        );
+        assert_eq!(
+            split_on_cuts(3500, 1000, &[100, 2100]),
+            vec![(0, 1000), (1000, 1100), (2100, 1000), (3100, 400)]
+        );
+        assert_eq!(split_on_cuts(500, 1000, &[]), vec![(0, 500)]);
+    }
+}
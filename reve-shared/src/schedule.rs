@@ -0,0 +1,106 @@
+//! Daily window given with `--schedule`, outside of which no segment is upscaled.
+//!
+//! The window is in local time and may span midnight (`22:00-06:00`). A segment whose
+//! upscale would start outside of it waits for the window to open, while the segments
+//! already upscaled are merged and saved, so the job can also be stopped and resumed later.
+
+use chrono::{Local, Timelike};
+use serde::{Deserialize, Serialize};
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    /// Minute of the day the window opens at.
+    pub start: u16,
+    /// Minute of the day the window closes at.
+    pub end: u16,
+}
+
+impl Schedule {
+    /// Parses a `hh:mm-hh:mm` window.
+    pub fn parse(s: &str) -> Result<Schedule, String> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| String::from("expected a window like 01:00-07:00"))?;
+        let schedule = Schedule {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        if schedule.start == schedule.end {
+            return Err(String::from("the window opens and closes at the same time"));
+        }
+        Ok(schedule)
+    }
+
+    /// Returns whether `minute` of the day is inside the window.
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// Returns the minutes from `minute` of the day until the window opens, 0 inside it.
+    pub fn minutes_until_open(&self, minute: u16) -> u16 {
+        if self.contains(minute) {
+            return 0;
+        }
+        (self.start + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY
+    }
+
+    /// Returns the `hh:mm` the window opens at.
+    pub fn opening(&self) -> String {
+        format!("{:02}:{:02}", self.start / 60, self.start % 60)
+    }
+}
+
+/// Returns the current minute of the day in local time.
+pub fn local_minute() -> u16 {
+    let now = Local::now();
+    (now.hour() * 60 + now.minute()) as u16
+}
+
+fn parse_time(s: &str) -> Result<u16, String> {
+    let invalid = || format!("invalid time {:?}, expected hh:mm", s);
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u16 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let night = Schedule::parse("01:00-07:00").unwrap();
+        assert_eq!(
+            night,
+            Schedule {
+                start: 60,
+                end: 420
+            }
+        );
+        assert!(night.contains(60));
+        assert!(!night.contains(420));
+        assert_eq!(night.minutes_until_open(8 * 60), 17 * 60);
+        assert_eq!(night.minutes_until_open(30), 30);
+        assert_eq!(night.opening(), "01:00");
+
+        let overnight = Schedule::parse("22:30-6:00").unwrap();
+        assert!(overnight.contains(23 * 60));
+        assert!(overnight.contains(5 * 60));
+        assert!(!overnight.contains(12 * 60));
+        assert_eq!(overnight.minutes_until_open(22 * 60), 30);
+
+        assert!(Schedule::parse("01:00").is_err());
+        assert!(Schedule::parse("01:00-24:00").is_err());
+        assert!(Schedule::parse("7:00-07:00").is_err());
+    }
+}
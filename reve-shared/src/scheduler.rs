@@ -0,0 +1,300 @@
+use crate::{ReveError, Segment};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// What the merge of a segment found of its part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Merged {
+    Done,
+    /// The part is incomplete and the segment goes through the stages again.
+    Again,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Task {
+    Export,
+    Upscale,
+    Merge,
+}
+
+/// Queued segments of each stage and the stages running, shared by the workers.
+struct Queues<'s> {
+    /// Segments not exported yet, started while fewer than `max_inflight` are in flight.
+    pending: VecDeque<&'s Segment>,
+    /// Segments to export again, which keep their place in flight.
+    again: VecDeque<&'s Segment>,
+    upscale: VecDeque<&'s Segment>,
+    merge: VecDeque<&'s Segment>,
+    running: Vec<Task>,
+    inflight: usize,
+    left: usize,
+    error: Option<ReveError>,
+    /// A stage panicked, which the worker running it raises again once the others stop.
+    panicked: bool,
+}
+
+impl<'s> Queues<'s> {
+    /// Takes the next task no worker is running, the latest stage first since finishing a
+    /// segment frees its place in flight. After an error only the upscaled segments are merged.
+    fn take(&mut self, max_inflight: usize) -> Option<(Task, &'s Segment)> {
+        let task = if !self.running.contains(&Task::Merge) && !self.merge.is_empty() {
+            Task::Merge
+        } else if self.error.is_some() {
+            return None;
+        } else if !self.running.contains(&Task::Upscale) && !self.upscale.is_empty() {
+            Task::Upscale
+        } else if !self.running.contains(&Task::Export)
+            && (!self.again.is_empty()
+                || (!self.pending.is_empty() && self.inflight < max_inflight))
+        {
+            Task::Export
+        } else {
+            return None;
+        };
+        let segment = match task {
+            Task::Merge => self.merge.pop_front(),
+            Task::Upscale => self.upscale.pop_front(),
+            Task::Export => self.again.pop_front().or_else(|| {
+                self.inflight += 1;
+                self.pending.pop_front()
+            }),
+        }?;
+        self.running.push(task);
+        Some((task, segment))
+    }
+
+    /// Whether no task is left for the workers, for good or after an error.
+    fn finished(&self) -> bool {
+        self.left == 0
+            || self.panicked
+            || (self.error.is_some() && self.running.is_empty() && self.merge.is_empty())
+    }
+}
+
+/// Bounded work-stealing scheduler running the export, upscale and merge of segments.
+///
+/// Every worker takes whichever stage has a segment waiting and is not running, so segment N
+/// can be merged while N + 1 is upscaled and N + 2 is exported. Each stage runs one segment
+/// at a time, in order. At most `max_inflight` segments are between the start of their export
+/// and the end of their merge at any time, which bounds the disk space used by extracted and
+/// upscaled frames. A segment whose merge returns [`Merged::Again`] is queued for export again
+/// without leaving its place in flight.
+pub struct Scheduler {
+    max_inflight: usize,
+}
+
+impl Scheduler {
+    pub fn new(max_inflight: usize) -> Self {
+        Self {
+            max_inflight: max_inflight.max(1),
+        }
+    }
+
+    /// Runs every stage over `segments` and returns the first error encountered.
+    ///
+    /// When a stage fails, the segments already upscaled are merged and the workers stop.
+    pub fn run<E, U, M>(
+        &self,
+        segments: &[Segment],
+        export: E,
+        upscale: U,
+        merge: M,
+    ) -> Result<(), ReveError>
+    where
+        E: Fn(&Segment) -> Result<(), ReveError> + Sync,
+        U: Fn(&Segment) -> Result<(), ReveError> + Sync,
+        M: Fn(&Segment) -> Result<Merged, ReveError> + Sync,
+    {
+        let queues = Mutex::new(Queues {
+            pending: segments.iter().collect(),
+            again: VecDeque::new(),
+            upscale: VecDeque::new(),
+            merge: VecDeque::new(),
+            running: Vec::new(),
+            inflight: 0,
+            left: segments.len(),
+            error: None,
+            panicked: false,
+        });
+        let changed = Condvar::new();
+
+        let work = || loop {
+            let (task, segment) = {
+                let mut queues = queues.lock().unwrap();
+                loop {
+                    if queues.finished() {
+                        return;
+                    }
+                    if let Some(next) = queues.take(self.max_inflight) {
+                        break next;
+                    }
+                    queues = changed.wait(queues).unwrap();
+                }
+            };
+            let result = panic::catch_unwind(AssertUnwindSafe(|| match task {
+                Task::Export => export(segment).map(|_| Some(Task::Upscale)),
+                Task::Upscale => upscale(segment).map(|_| Some(Task::Merge)),
+                Task::Merge => merge(segment).map(|merged| match merged {
+                    Merged::Done => None,
+                    Merged::Again => Some(Task::Export),
+                }),
+            }));
+
+            let mut queues = queues.lock().unwrap();
+            queues.running.retain(|running| *running != task);
+            let result = match result {
+                Ok(result) => result,
+                Err(payload) => {
+                    queues.panicked = true;
+                    changed.notify_all();
+                    drop(queues);
+                    panic::resume_unwind(payload);
+                }
+            };
+            match result {
+                Ok(Some(Task::Export)) => queues.again.push_back(segment),
+                Ok(Some(Task::Upscale)) => queues.upscale.push_back(segment),
+                Ok(Some(Task::Merge)) => queues.merge.push_back(segment),
+                Ok(None) => {
+                    queues.inflight -= 1;
+                    queues.left -= 1;
+                }
+                Err(err) => {
+                    queues.error.get_or_insert(err);
+                }
+            }
+            changed.notify_all();
+        };
+
+        thread::scope(|s| {
+            // One worker per stage keeps every stage busy
+            let workers: Vec<_> = (0..3).map(|_| s.spawn(work)).collect();
+            for worker in workers {
+                worker.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            }
+        });
+        match queues.into_inner().unwrap().error {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    fn segments(count: u32) -> Vec<Segment> {
+        (0..count)
+            .map(|index| Segment {
+                index,
+                size: 10,
+                start: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn runs_every_stage_in_order_within_inflight_bound() {
+        let inflight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let merged = Mutex::new(Vec::new());
+
+        Scheduler::new(2)
+            .run(
+                &segments(6),
+                |_| {
+                    let now = inflight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    Ok(())
+                },
+                |_| Ok(()),
+                |segment| {
+                    merged.lock().unwrap().push(segment.index);
+                    inflight.fetch_sub(1, Ordering::SeqCst);
+                    Ok(Merged::Done)
+                },
+            )
+            .unwrap();
+
+        assert_eq!(*merged.lock().unwrap(), vec![0, 1, 2, 3, 4, 5]);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn stops_after_a_failing_stage() {
+        let merged = AtomicUsize::new(0);
+
+        let result = Scheduler::new(3).run(
+            &segments(6),
+            |_| Ok(()),
+            |segment| {
+                if segment.index == 2 {
+                    Err(ReveError::Ffmpeg(String::from("upscale failed")))
+                } else {
+                    Ok(())
+                }
+            },
+            |_| {
+                merged.fetch_add(1, Ordering::SeqCst);
+                Ok(Merged::Done)
+            },
+        );
+
+        assert!(matches!(result, Err(ReveError::Ffmpeg(_))));
+        assert_eq!(merged.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn processes_a_segment_again_through_the_queues() {
+        let inflight = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let exported = Mutex::new(Vec::new());
+        let upscaled = Mutex::new(Vec::new());
+        let merged = Mutex::new(Vec::new());
+
+        Scheduler::new(2)
+            .run(
+                &segments(4),
+                |segment| {
+                    let mut exported = exported.lock().unwrap();
+                    // A segment exported again is already in flight
+                    if !exported.contains(&segment.index) {
+                        let now = inflight.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                    }
+                    exported.push(segment.index);
+                    Ok(())
+                },
+                |segment| {
+                    upscaled.lock().unwrap().push(segment.index);
+                    Ok(())
+                },
+                |segment| {
+                    let mut merged = merged.lock().unwrap();
+                    let again = segment.index == 1 && !merged.contains(&1);
+                    merged.push(segment.index);
+                    if again {
+                        return Ok(Merged::Again);
+                    }
+                    inflight.fetch_sub(1, Ordering::SeqCst);
+                    Ok(Merged::Done)
+                },
+            )
+            .unwrap();
+
+        for stage in [exported, upscaled, merged] {
+            let mut indexes = stage.into_inner().unwrap();
+            assert_eq!(indexes.iter().filter(|index| **index == 1).count(), 2);
+            indexes.sort();
+            indexes.dedup();
+            assert_eq!(indexes, vec![0, 1, 2, 3]);
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(inflight.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::process::{command_failure, prioritize, run, spawn_error};
+use crate::{ReveError, VideoInfo};
+use std::path::Path;
+use std::process::{ChildStdout, Command, Stdio};
+use tracing::debug;
+
+/// Frame server used to feed a `.vpy`/`.avs` script input to ffmpeg.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptKind {
+    VapourSynth,
+    AviSynth,
+}
+
+impl ScriptKind {
+    pub fn from_path(path: &str) -> Option<ScriptKind> {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("vpy") => Some(ScriptKind::VapourSynth),
+            Some("avs") => Some(ScriptKind::AviSynth),
+            _ => None,
+        }
+    }
+
+    fn binary(&self) -> &'static str {
+        match self {
+            ScriptKind::VapourSynth => "vspipe",
+            ScriptKind::AviSynth => "avs2yuv",
+        }
+    }
+
+    /// Returns the frame count, frame rate and dimensions of the script's output.
+    pub fn probe(&self, path: &str) -> Result<VideoInfo, ReveError> {
+        let mut command = Command::new(self.binary());
+        match self {
+            ScriptKind::VapourSynth => command.args(["--info", path, "-"]),
+            ScriptKind::AviSynth => command.args([path, "-frames", "1", "-o", "-"]),
+        };
+        let output = run(&mut command)?;
+        if !output.status.success() {
+            return Err(ReveError::Probe(command_failure(
+                &format!("{} could not evaluate the script", self.binary()),
+                &command,
+                &output,
+            )));
+        }
+
+        let info = match self {
+            ScriptKind::VapourSynth => parse_vspipe_info(&String::from_utf8_lossy(&output.stdout)),
+            ScriptKind::AviSynth => parse_avs2yuv_info(&String::from_utf8_lossy(&output.stderr)),
+        };
+        info.ok_or_else(|| {
+            ReveError::Probe(format!(
+                "could not read the video properties from {}",
+                self.binary()
+            ))
+        })
+    }
+
+    /// Spawns the frame server writing `frames` frames from `first_frame` to stdout as y4m.
+    pub fn pipe(
+        &self,
+        path: &str,
+        first_frame: u32,
+        frames: u32,
+    ) -> Result<ChildStdout, ReveError> {
+        let mut command = Command::new(self.binary());
+        match self {
+            ScriptKind::VapourSynth => command.args([
+                "-c",
+                "y4m",
+                "-s",
+                &first_frame.to_string(),
+                "-e",
+                &(first_frame + frames.max(1) - 1).to_string(),
+                path,
+                "-",
+            ]),
+            ScriptKind::AviSynth => command.args([
+                "-seek",
+                &first_frame.to_string(),
+                "-frames",
+                &frames.to_string(),
+                path,
+                "-o",
+                "-",
+            ]),
+        };
+        debug!("spawning {:?}", command);
+        prioritize(&mut command);
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| spawn_error(self.binary(), err))?
+            .stdout
+            .ok_or_else(|| {
+                ReveError::Io(std::io::Error::other("Could not capture standard output."))
+            })
+    }
+}
+
+/// Parses a `num/den` or decimal frame rate.
+fn parse_frame_rate(s: &str) -> Option<f32> {
+    match s.split_once('/') {
+        Some((num, den)) => {
+            let den = den.trim().parse::<f32>().ok()?;
+            (den != 0.0).then_some(num.trim().parse::<f32>().ok()? / den)
+        }
+        None => s.trim().parse::<f32>().ok(),
+    }
+}
+
+/// Parses the `Width:`, `Height:`, `Frames:` and `FPS:` lines of `vspipe --info`.
+fn parse_vspipe_info(info: &str) -> Option<VideoInfo> {
+    let mut frames = None;
+    let mut frame_rate = None;
+    let mut width = None;
+    let mut height = None;
+    for line in info.lines() {
+        if let Some(value) = line.strip_prefix("Frames:") {
+            frames = value.trim().parse::<u32>().ok();
+        } else if let Some(value) = line.strip_prefix("FPS:") {
+            frame_rate = value.split_whitespace().next().and_then(parse_frame_rate);
+        } else if let Some(value) = line.strip_prefix("Width:") {
+            width = value.trim().parse::<u32>().ok();
+        } else if let Some(value) = line.strip_prefix("Height:") {
+            height = value.trim().parse::<u32>().ok();
+        }
+    }
+    Some(VideoInfo {
+        frame_count: frames?,
+        frame_rate: frame_rate?,
+        width: width?,
+        height: height?,
+    })
+}
+
+/// Parses the `script.avs: 1920x1080, 24000/1001 fps, 1000 frames` line printed by avs2yuv.
+fn parse_avs2yuv_info(info: &str) -> Option<VideoInfo> {
+    info.lines().find_map(|line| {
+        let mut frames = None;
+        let mut frame_rate = None;
+        let mut dimensions = None;
+        for part in line.split(", ") {
+            if let Some(value) = part.strip_suffix(" frames") {
+                frames = value.trim().parse::<u32>().ok();
+            } else if let Some(value) = part.strip_suffix(" fps") {
+                frame_rate = parse_frame_rate(value);
+            } else if let Some((width, height)) = part.rsplit(' ').next()?.split_once('x') {
+                dimensions = width.parse::<u32>().ok().zip(height.parse::<u32>().ok());
+            }
+        }
+        let (width, height) = dimensions?;
+        Some(VideoInfo {
+            frame_count: frames?,
+            frame_rate: frame_rate?,
+            width,
+            height,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vspipe_info() {
+        let info = "Width: 1920\nHeight: 1080\nFrames: 34046\nFPS: 24000/1001 (23.976 fps)\nFormat Name: YUV420P8\n";
+        let info = parse_vspipe_info(info).unwrap();
+        assert_eq!(info.frame_count, 34046);
+        assert_eq!((info.width, info.height), (1920, 1080));
+        assert!((info.frame_rate - 23.976).abs() < 0.001);
+        assert!(parse_vspipe_info("Width: 1920\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_avs2yuv_info() {
+        let info = "Avisynth+ 3.7.3\nepisode.avs: 720x480, 30000/1001 fps, 1200 frames\n";
+        let info = parse_avs2yuv_info(info).unwrap();
+        assert_eq!(info.frame_count, 1200);
+        assert_eq!((info.width, info.height), (720, 480));
+        assert!((info.frame_rate - 29.97).abs() < 0.001);
+    }
+}
@@ -0,0 +1,226 @@
+//! Videos `reve batch` and `reve daemon` upscale, in the order of `--sort` and up to `--limit`,
+//! skipping the ones outside `--min-height`, `--max-height` and `--max-width` or in a codec of
+//! `--codec-filter`.
+//!
+//! The videos are compared by their size and modification time and, for the duration, the
+//! resolution and the filters, by their [`ffprobe::probe_json`] output. Videos that sort the
+//! same keep the order they were found in.
+
+use crate::ffprobe::{self, ProbeSummary};
+use crate::ReveError;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+/// Order the videos are upscaled in.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// smallest files first
+    Size,
+    /// shortest videos first
+    Duration,
+    /// lowest resolutions first
+    Resolution,
+    /// most recently modified first
+    Newest,
+}
+
+/// Options selecting the videos to upscale among the ones found.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct Selection {
+    /// order to upscale the videos in, instead of the order they were found in
+    #[clap(long, value_enum)]
+    pub sort: Option<SortKey>,
+
+    /// upscale at most this many videos, then exit
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub limit: Option<u32>,
+
+    /// skip the videos less than this many pixels high
+    #[clap(long, value_name = "PIXELS")]
+    pub min_height: Option<u32>,
+
+    /// skip the videos more than this many pixels high
+    #[clap(long, value_name = "PIXELS")]
+    pub max_height: Option<u32>,
+
+    /// skip the videos more than this many pixels wide
+    #[clap(long, value_name = "PIXELS")]
+    pub max_width: Option<u32>,
+
+    /// skip the videos in these codecs, as ffprobe names them, like `hevc,av1`
+    #[clap(long, value_name = "CODECS", value_delimiter = ',')]
+    pub codec_filter: Vec<String>,
+}
+
+/// Video compared by a [`Selection`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Candidate {
+    pub size: u64,
+    /// Modification time, in milliseconds since the epoch.
+    pub modified: i64,
+    /// Video stream and duration, the default when the video was not probed.
+    pub probe: ProbeSummary,
+}
+
+impl Candidate {
+    /// Reads the candidate of a file of `size` modified at `modified` from its
+    /// [`ffprobe::probe_json`] output.
+    pub fn from_probe(size: u64, modified: i64, json: &str) -> Result<Candidate, ReveError> {
+        Ok(Candidate {
+            size,
+            modified,
+            probe: ffprobe::summary(json)?,
+        })
+    }
+}
+
+impl Selection {
+    /// Returns whether selecting needs the ffprobe output of the videos.
+    pub fn needs_probe(&self) -> bool {
+        matches!(self.sort, Some(SortKey::Duration | SortKey::Resolution)) || self.filters()
+    }
+
+    /// Returns whether videos are skipped by their resolution or codec.
+    pub fn filters(&self) -> bool {
+        self.min_height.is_some()
+            || self.max_height.is_some()
+            || self.max_width.is_some()
+            || !self.codec_filter.is_empty()
+    }
+
+    /// Returns why the video of `probe` is skipped, `None` when it is upscaled.
+    pub fn skip_reason(&self, probe: &ProbeSummary) -> Option<String> {
+        if let Some(codec) = probe.codec.as_deref().filter(|codec| {
+            self.codec_filter
+                .iter()
+                .any(|filter| filter.eq_ignore_ascii_case(codec))
+        }) {
+            return Some(format!("{} video, skipped by --codec-filter", codec));
+        }
+        if self.min_height.is_some_and(|min| probe.height < min) {
+            return Some(format!("{} pixels high, under --min-height", probe.height));
+        }
+        if self.max_height.is_some_and(|max| probe.height > max) {
+            return Some(format!("{} pixels high, over --max-height", probe.height));
+        }
+        if self.max_width.is_some_and(|max| probe.width > max) {
+            return Some(format!("{} pixels wide, over --max-width", probe.width));
+        }
+        None
+    }
+
+    /// Returns the indices of `candidates` in the order they are upscaled in, sorted by
+    /// `sort` when given. The limit is left to the caller, which may skip some of them.
+    pub fn order(&self, candidates: &[Candidate]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        let Some(sort) = self.sort else {
+            return order;
+        };
+        // Sorting is stable, equal videos keep the order they were found in
+        match sort {
+            SortKey::Size => order.sort_by_key(|&index| candidates[index].size),
+            SortKey::Duration => order.sort_by(|&a, &b| {
+                candidates[a]
+                    .probe
+                    .duration
+                    .total_cmp(&candidates[b].probe.duration)
+            }),
+            SortKey::Resolution => order.sort_by_key(|&index| {
+                let probe = &candidates[index].probe;
+                probe.width as u64 * probe.height as u64
+            }),
+            SortKey::Newest => order.sort_by_key(|&index| Reverse(candidates[index].modified)),
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let candidate = |size: u64, modified: i64, duration: f64, height: u32| Candidate {
+            size,
+            modified,
+            probe: ProbeSummary {
+                codec: None,
+                width: height * 16 / 9,
+                height,
+                duration,
+            },
+        };
+        let candidates = [
+            candidate(300, 1, 60.0, 1080),
+            candidate(100, 3, 7200.0, 480),
+            candidate(200, 2, 60.0, 720),
+        ];
+        let order = |sort: Option<SortKey>| {
+            Selection {
+                sort,
+                ..Selection::default()
+            }
+            .order(&candidates)
+        };
+        assert_eq!(order(None), [0, 1, 2]);
+        assert_eq!(order(Some(SortKey::Size)), [1, 2, 0]);
+        assert_eq!(order(Some(SortKey::Duration)), [0, 2, 1]);
+        assert_eq!(order(Some(SortKey::Resolution)), [1, 2, 0]);
+        assert_eq!(order(Some(SortKey::Newest)), [1, 2, 0]);
+
+        let selection = Selection {
+            sort: Some(SortKey::Size),
+            ..Selection::default()
+        };
+        assert!(!selection.needs_probe());
+        let json = r#"{"streams": [{"codec_type": "video", "codec_name": "h264", "width": 720,
+            "height": 576}], "format": {"duration": "60.0"}}"#;
+        let candidate = Candidate::from_probe(100, 3, json).unwrap();
+        assert_eq!(
+            (candidate.probe.height, candidate.probe.duration),
+            (576, 60.0)
+        );
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let probe = |codec: &str, width: u32, height: u32| ProbeSummary {
+            codec: Some(codec.to_string()),
+            width,
+            height,
+            duration: 60.0,
+        };
+        let selection = Selection {
+            min_height: Some(480),
+            max_height: Some(1080),
+            max_width: Some(1440),
+            codec_filter: vec!["HEVC".to_string(), "av1".to_string()],
+            ..Selection::default()
+        };
+        assert!(selection.needs_probe());
+        assert_eq!(selection.skip_reason(&probe("h264", 720, 576)), None);
+        assert_eq!(selection.skip_reason(&probe("h264", 1440, 1080)), None);
+        assert_eq!(
+            selection.skip_reason(&probe("hevc", 3840, 2160)).unwrap(),
+            "hevc video, skipped by --codec-filter"
+        );
+        assert_eq!(
+            selection
+                .skip_reason(&probe("mpeg2video", 352, 288))
+                .unwrap(),
+            "288 pixels high, under --min-height"
+        );
+        assert_eq!(
+            selection.skip_reason(&probe("h264", 3840, 2160)).unwrap(),
+            "2160 pixels high, over --max-height"
+        );
+        assert_eq!(
+            selection.skip_reason(&probe("h264", 1920, 800)).unwrap(),
+            "1920 pixels wide, over --max-width"
+        );
+        assert!(!Selection::default().filters());
+    }
+}
@@ -0,0 +1,1386 @@
+//! Resume state of the jobs, kept in the `reve.db` sqlite database of the working directory.
+//!
+//! A `jobs` row holds the arguments a job was started with and its planned video, and a
+//! `segments` row the status of each segment of it. Both are written in one transaction when
+//! the job is planned and a segment is marked merged in its own, so an interrupted write
+//! leaves the previous state rather than a truncated file.
+//!
+//! The `queue` table holds the files found by `reve daemon` in its watch folders, so the
+//! queue outlives a restart of the service. They are upscaled by decreasing `priority`, which
+//! `--sort` sets, then in the order they were found. Files without a video stream or that
+//! ffprobe can not read are kept with the reason in `error`. The statistics of the last
+//! upscale of each file, its start and end, wall-clock duration, average upscale speed and
+//! output, are kept in the `started`, `finished`, `duration`, `fps`, `output` and
+//! `output_size` columns.
+//!
+//! The `probes` table caches the ffprobe output of the files found, or its error, with their
+//! size and modification time, so a scan only probes the files that changed since the last
+//! one. The `scanned` table lists the watch folders a scan went through, so an interrupted
+//! scan resumes with the next folder. The `fingerprints` table caches the content fingerprint
+//! of the queued files the same way, for `reve db duplicates`.
+//!
+//! The `batch_files` table is the manifest of each `reve batch`, identified by its folder and
+//! options: the videos it found in the order they are upscaled, with their status and output,
+//! so an interrupted batch resumes at the video it stopped on and a later run skips the
+//! videos it upscaled.
+//!
+//! A process keeps one connection open for all its calls, in write-ahead log mode so other
+//! instances read the database while it writes.
+
+use crate::selection::{Candidate, Selection, SortKey};
+use crate::{paths, Args, ReveError, Segment, Video};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY,
+    args TEXT NOT NULL,
+    video TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS segments (
+    job TEXT NOT NULL,
+    idx INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    start INTEGER,
+    status TEXT NOT NULL,
+    PRIMARY KEY (job, idx)
+);
+CREATE TABLE IF NOT EXISTS queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    path TEXT NOT NULL UNIQUE,
+    output_dir TEXT,
+    profile TEXT,
+    status TEXT NOT NULL,
+    error TEXT,
+    priority INTEGER NOT NULL DEFAULT 0,
+    started INTEGER,
+    finished INTEGER,
+    duration REAL,
+    fps REAL,
+    output TEXT,
+    output_size INTEGER
+);
+CREATE TABLE IF NOT EXISTS probes (
+    path TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    json TEXT,
+    error TEXT
+);
+CREATE TABLE IF NOT EXISTS scanned (
+    folder TEXT PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS fingerprints (
+    path TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    modified INTEGER NOT NULL,
+    hash TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS batch_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    batch TEXT NOT NULL,
+    path TEXT NOT NULL,
+    status TEXT NOT NULL,
+    output TEXT,
+    UNIQUE (batch, path)
+);";
+
+/// Columns added to the `queue` table since its first version, with their definition, added
+/// to the databases created before them when opened.
+const QUEUE_COLUMNS: [(&str, &str); 7] = [
+    ("priority", "INTEGER NOT NULL DEFAULT 0"),
+    ("started", "INTEGER"),
+    ("finished", "INTEGER"),
+    ("duration", "REAL"),
+    ("fps", "REAL"),
+    ("output", "TEXT"),
+    ("output_size", "INTEGER"),
+];
+
+/// Status of a segment left to upscale.
+const PENDING: &str = "pending";
+/// Status of a segment whose part is encoded and kept until the output is concatenated.
+const MERGED: &str = "merged";
+
+/// Status of a file in the queue of `reve daemon` or the manifest of `reve batch`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    /// Left out when found, having no video stream like an audio file.
+    #[serde(rename = "no_video")]
+    NoVideo,
+    /// Left out when found, ffprobe could not read it.
+    Error,
+}
+
+impl QueueStatus {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            QueueStatus::Pending => "pending",
+            QueueStatus::Running => "running",
+            QueueStatus::Done => "done",
+            QueueStatus::Failed => "failed",
+            QueueStatus::NoVideo => "no_video",
+            QueueStatus::Error => "error",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> QueueStatus {
+        match name {
+            "pending" => QueueStatus::Pending,
+            "running" => QueueStatus::Running,
+            "done" => QueueStatus::Done,
+            "no_video" => QueueStatus::NoVideo,
+            "error" => QueueStatus::Error,
+            _ => QueueStatus::Failed,
+        }
+    }
+}
+
+/// File found in a watch folder of `reve daemon`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QueuedFile {
+    pub path: String,
+    /// Folder of the output, next to the input when unset.
+    pub output_dir: Option<String>,
+    /// Profile given to `--profile`.
+    pub profile: Option<String>,
+    pub status: QueueStatus,
+    /// Why the upscale failed or the file was left out.
+    pub error: Option<String>,
+    /// Files with a higher priority are upscaled first, the ones found since the queue was
+    /// sorted have none.
+    #[serde(default)]
+    pub priority: i64,
+    #[serde(flatten)]
+    pub stats: UpscaleStats,
+}
+
+/// Statistics of the last upscale of a queued file, recorded by `reve daemon`. An upscale
+/// resumed after a restart of the daemon counts from the restart.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct UpscaleStats {
+    /// Start of the upscale, in seconds since the epoch.
+    pub started: Option<i64>,
+    /// End of the upscale, in seconds since the epoch.
+    pub finished: Option<i64>,
+    /// Wall-clock seconds the upscale took, pauses included.
+    pub duration: Option<f64>,
+    /// Frames realesrgan upscaled per second, on average over the segments.
+    pub fps: Option<f64>,
+    pub output: Option<String>,
+    /// Size of the output in bytes.
+    pub output_size: Option<u64>,
+}
+
+/// Video of the manifest of a `reve batch`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchFile {
+    pub path: String,
+    pub status: QueueStatus,
+    /// Output written, once upscaled.
+    pub output: Option<String>,
+}
+
+/// Upscale saved in the database to be resumed, as listed by `reve db`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SavedJob {
+    pub input: String,
+    pub output: String,
+    pub segments: u32,
+    pub merged: u32,
+}
+
+impl From<rusqlite::Error> for ReveError {
+    fn from(err: rusqlite::Error) -> Self {
+        ReveError::Db(err.to_string())
+    }
+}
+
+fn json_error(err: serde_json::Error) -> ReveError {
+    ReveError::Db(format!("invalid saved state: {}", err))
+}
+
+/// Connection to the database last used, with its absolute path.
+static DATABASE: Mutex<Option<(PathBuf, Connection)>> = Mutex::new(None);
+
+/// Opens the database at `path`, creating its tables.
+fn open(path: &Path) -> Result<Connection, ReveError> {
+    let connection = Connection::open(path)?;
+    // Instances upscaling other videos share the database; with the write-ahead log they
+    // read it while one writes, and a virus scanner opening the file blocks fewer writes
+    connection.busy_timeout(Duration::from_secs(30))?;
+    connection.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+    connection.execute_batch(SCHEMA)?;
+    add_queue_columns(&connection)?;
+    Ok(connection)
+}
+
+/// Adds the [`QUEUE_COLUMNS`] a database created by an older version lacks.
+fn add_queue_columns(connection: &Connection) -> Result<(), ReveError> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info('queue')")?;
+    let columns: Vec<String> = statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for (name, definition) in QUEUE_COLUMNS {
+        if !columns.iter().any(|column| column == name) {
+            connection.execute(
+                &format!("ALTER TABLE queue ADD COLUMN {} {}", name, definition),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `f` on the connection to the database at `path`, kept open for the next calls
+/// rather than opened for each of them. Threads take turns on it.
+fn with_connection<T>(
+    path: &Path,
+    f: impl FnOnce(&Connection) -> Result<T, ReveError>,
+) -> Result<T, ReveError> {
+    let path = env::current_dir()?.join(path);
+    let mut database = DATABASE.lock().unwrap_or_else(PoisonError::into_inner);
+    let connection = match &mut *database {
+        Some((open_path, connection)) if *open_path == path => connection,
+        database => &database.insert((path.clone(), open(&path)?)).1,
+    };
+    f(connection)
+}
+
+/// Runs `f` on the connection to the database of the working directory.
+fn with_database<T>(f: impl FnOnce(&Connection) -> Result<T, ReveError>) -> Result<T, ReveError> {
+    with_connection(&paths::database(), f)
+}
+
+/// Saves the job of [`paths::job`] planned from `args`, replacing a previous one.
+pub fn start(args: &Args, video: &Video) -> Result<(), ReveError> {
+    with_database(|connection| insert(connection, &paths::job(), args, video))
+}
+
+/// Replaces the planned video of the running job, keeping the status of its segments.
+pub fn update_video(video: &Video) -> Result<(), ReveError> {
+    let video = planned_json(video)?;
+    with_database(|connection| {
+        connection.execute(
+            "UPDATE jobs SET video = ?1 WHERE id = ?2",
+            params![video, paths::job()],
+        )?;
+        Ok(())
+    })
+}
+
+/// Marks the segment `index` of the running job merged, so a resumed run skips it.
+pub fn mark_merged(index: u32) -> Result<(), ReveError> {
+    with_database(|connection| set_status(connection, &paths::job(), index, MERGED))
+}
+
+/// Returns the arguments the running job was started with, `None` without saved state.
+pub fn load_args() -> Result<Option<Args>, ReveError> {
+    let path = paths::database();
+    if !path.exists() {
+        return Ok(None);
+    }
+    with_connection(&path, |connection| select_args(connection, &paths::job()))
+}
+
+/// Returns the planned video of the running job with the segments left to upscale.
+pub fn load_video() -> Result<Option<Video>, ReveError> {
+    with_database(|connection| select_video(connection, &paths::job()))
+}
+
+/// Removes the state of the running job, once it is done or before planning it again.
+pub fn remove() -> Result<(), ReveError> {
+    let path = paths::database();
+    if !path.exists() {
+        return Ok(());
+    }
+    with_connection(&path, |connection| delete(connection, &paths::job()))
+}
+
+/// Returns the arguments of every job saved in the database of `work_dir`, skipping the ones
+/// this version cannot read.
+pub fn saved_args(work_dir: &Path) -> Result<Vec<Args>, ReveError> {
+    let path = work_dir.join(paths::database());
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    with_connection(&path, |connection| {
+        let mut statement = connection.prepare("SELECT args FROM jobs ORDER BY id")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut jobs = Vec::new();
+        for args in rows {
+            if let Ok(args) = serde_json::from_str(&args?) {
+                jobs.push(args);
+            }
+        }
+        Ok(jobs)
+    })
+}
+
+/// Runs the integrity check of sqlite on the database of `work_dir`, returning the problems
+/// it found, none when the database is sound or does not exist yet.
+pub fn integrity_problems(work_dir: &Path) -> Result<Vec<String>, ReveError> {
+    let path = work_dir.join(paths::database());
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    with_connection(&path, integrity_select)
+}
+
+/// Adds `path` to the queue of the daemon as pending, returning `false` if it was queued
+/// before, unless it was left out.
+pub fn enqueue(
+    path: &str,
+    output_dir: Option<&str>,
+    profile: Option<&str>,
+) -> Result<bool, ReveError> {
+    with_database(|connection| queue_insert(connection, path, output_dir, profile))
+}
+
+/// Adds `path` to the queue of the daemon with the status `status` of a file left out and
+/// its `reason`, returning `false` if it was queued before, unless it was left out for
+/// another reason.
+pub fn leave_out(path: &str, status: QueueStatus, reason: &str) -> Result<bool, ReveError> {
+    with_database(|connection| queue_leave_out(connection, path, status, reason))
+}
+
+/// Returns the cached ffprobe output of `path`, or the error ffprobe gave, if the file still
+/// has the `size` and `modified` time it had when probed.
+pub fn cached_probe(
+    path: &str,
+    size: u64,
+    modified: i64,
+) -> Result<Option<Result<String, String>>, ReveError> {
+    with_database(|connection| probe_select(connection, path, size, modified))
+}
+
+/// Caches the ffprobe output of each path of `probes`, or its error, for the size and
+/// modification time the file had when probed, in a single transaction.
+pub fn save_probes(probes: &[(&str, u64, i64, &Result<String, String>)]) -> Result<(), ReveError> {
+    with_database(|connection| {
+        let transaction = connection.unchecked_transaction()?;
+        for &(path, size, modified, probe) in probes {
+            probe_insert(&transaction, path, size, modified, probe)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    })
+}
+
+/// Returns the cached [`fingerprint`](crate::fingerprint::fingerprint) of `path`, if the file
+/// still has the `size` and `modified` time it had when read.
+pub fn cached_fingerprint(
+    path: &str,
+    size: u64,
+    modified: i64,
+) -> Result<Option<String>, ReveError> {
+    with_database(|connection| fingerprint_select(connection, path, size, modified))
+}
+
+/// Caches the fingerprint `hash` of `path` for the `size` and `modified` time the file had
+/// when read.
+pub fn save_fingerprint(path: &str, size: u64, modified: i64, hash: &str) -> Result<(), ReveError> {
+    with_database(|connection| fingerprint_insert(connection, path, size, modified, hash))
+}
+
+/// Records that the scan went through the watch folder `folder`.
+pub fn mark_scanned(folder: &str) -> Result<(), ReveError> {
+    with_database(|connection| scanned_insert(connection, folder))
+}
+
+/// Returns the watch folders the scan went through, the ones an interrupted scan skips.
+pub fn scanned() -> Result<Vec<String>, ReveError> {
+    with_database(scanned_select)
+}
+
+/// Forgets the watch folders scanned, once the scan went through all of them.
+pub fn finish_scan() -> Result<(), ReveError> {
+    with_database(|connection| {
+        connection.execute("DELETE FROM scanned", [])?;
+        Ok(())
+    })
+}
+
+/// Returns the pending file of the queue with the highest priority, marked running and
+/// started now, its statistics cleared.
+pub fn dequeue() -> Result<Option<QueuedFile>, ReveError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64);
+    with_database(|connection| queue_next(connection, now))
+}
+
+/// Marks the queued file `path` done, or failed with `error`, with the statistics of its
+/// upscale. Its start is the one [`dequeue`] recorded.
+pub fn finish_queued(
+    path: &str,
+    error: Option<&str>,
+    stats: &UpscaleStats,
+) -> Result<(), ReveError> {
+    with_database(|connection| queue_finish(connection, path, error, stats))
+}
+
+/// Marks the files left running by a daemon that was stopped pending again, returning how
+/// many. Their upscale resumes from its temporary files.
+pub fn requeue_running() -> Result<usize, ReveError> {
+    with_database(|connection| {
+        Ok(connection.execute(
+            "UPDATE queue SET status = ?1 WHERE status = ?2",
+            params![QueueStatus::Pending.name(), QueueStatus::Running.name()],
+        )?)
+    })
+}
+
+/// Returns every file of the queue, in the order they are upscaled.
+pub fn queued() -> Result<Vec<QueuedFile>, ReveError> {
+    with_database(queue_select)
+}
+
+/// Adds `files` exported from another queue to the end of the queue, replacing the files
+/// with the same path, and returns how many. Files running there are pending here.
+pub fn import(files: &[QueuedFile]) -> Result<usize, ReveError> {
+    with_database(|connection| queue_import(connection, files))
+}
+
+/// Returns the file of the queue at `path`.
+pub fn queued_file(path: &str) -> Result<Option<QueuedFile>, ReveError> {
+    with_database(|connection| {
+        Ok(queue_select(connection)?
+            .into_iter()
+            .find(|file| file.path == path))
+    })
+}
+
+/// Sets the priority of the pending files of the queue to upscale them in the order of
+/// `sort`, from their cached probes, and returns how many were sorted. Without `sort` they
+/// go back to the order they were found in.
+pub fn sort_queue(sort: Option<SortKey>) -> Result<usize, ReveError> {
+    with_database(|connection| queue_sort(connection, sort))
+}
+
+/// Makes the file of the queue at `path` pending again, clearing its error, and returns
+/// whether it was queued.
+pub fn reset_queued(path: &str) -> Result<bool, ReveError> {
+    with_database(|connection| queue_reset(connection, path))
+}
+
+/// Removes the files of the queue and their cached probes and fingerprints that no longer
+/// exist, returning how many. A file is only taken for removed when its folder is still
+/// there, so a network share that is not mounted keeps its files.
+pub fn prune() -> Result<usize, ReveError> {
+    with_database(|connection| {
+        prune_missing(connection, |path| {
+            let path = Path::new(path);
+            !path.exists() && path.parent().is_some_and(Path::exists)
+        })
+    })
+}
+
+/// Returns the videos of the manifest of the batch `batch` in the database of `work_dir`, in
+/// the order they were added.
+pub fn batch_files(work_dir: &Path, batch: &str) -> Result<Vec<BatchFile>, ReveError> {
+    with_connection(&work_dir.join(paths::database()), |connection| {
+        batch_select(connection, batch)
+    })
+}
+
+/// Adds the `paths` not in the manifest of the batch `batch` to its end, as pending.
+pub fn add_batch_files(work_dir: &Path, batch: &str, paths: &[String]) -> Result<(), ReveError> {
+    with_connection(&work_dir.join(paths::database()), |connection| {
+        batch_insert(connection, batch, paths)
+    })
+}
+
+/// Records the `status` of the video at `path` of the batch `batch`, with its `output`.
+pub fn update_batch_file(
+    work_dir: &Path,
+    batch: &str,
+    path: &str,
+    status: QueueStatus,
+    output: Option<&str>,
+) -> Result<(), ReveError> {
+    with_connection(&work_dir.join(paths::database()), |connection| {
+        batch_update(connection, batch, path, status, output)
+    })
+}
+
+/// Removes the manifest of the batch `batch`, returning whether it had one.
+pub fn remove_batch(work_dir: &Path, batch: &str) -> Result<bool, ReveError> {
+    with_connection(&work_dir.join(paths::database()), |connection| {
+        batch_delete(connection, batch)
+    })
+}
+
+/// Returns the upscales saved to be resumed, skipping the ones this version cannot read.
+pub fn saved_jobs() -> Result<Vec<SavedJob>, ReveError> {
+    with_database(jobs_select)
+}
+
+/// Returns the JSON of `video` without its segments, which have their own rows.
+fn planned_json(video: &Video) -> Result<String, ReveError> {
+    let mut planned = video.clone();
+    planned.segments.clear();
+    serde_json::to_string(&planned).map_err(json_error)
+}
+
+fn insert(connection: &Connection, job: &str, args: &Args, video: &Video) -> Result<(), ReveError> {
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute("DELETE FROM segments WHERE job = ?1", [job])?;
+    transaction.execute(
+        "INSERT OR REPLACE INTO jobs (id, args, video) VALUES (?1, ?2, ?3)",
+        params![
+            job,
+            serde_json::to_string(args).map_err(json_error)?,
+            planned_json(video)?
+        ],
+    )?;
+    for segment in &video.segments {
+        transaction.execute(
+            "INSERT INTO segments (job, idx, size, start, status) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![job, segment.index, segment.size, segment.start, PENDING],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn set_status(
+    connection: &Connection,
+    job: &str,
+    index: u32,
+    status: &str,
+) -> Result<(), ReveError> {
+    connection.execute(
+        "UPDATE segments SET status = ?1 WHERE job = ?2 AND idx = ?3",
+        params![status, job, index],
+    )?;
+    Ok(())
+}
+
+fn select_args(connection: &Connection, job: &str) -> Result<Option<Args>, ReveError> {
+    let args: Option<String> = connection
+        .query_row("SELECT args FROM jobs WHERE id = ?1", [job], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    args.map(|args| serde_json::from_str(&args).map_err(json_error))
+        .transpose()
+}
+
+fn select_video(connection: &Connection, job: &str) -> Result<Option<Video>, ReveError> {
+    let video: Option<String> = connection
+        .query_row("SELECT video FROM jobs WHERE id = ?1", [job], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    let Some(video) = video else {
+        return Ok(None);
+    };
+    let mut video: Video = serde_json::from_str(&video).map_err(json_error)?;
+    let mut statement = connection.prepare(
+        "SELECT idx, size, start FROM segments WHERE job = ?1 AND status = ?2 ORDER BY idx",
+    )?;
+    video.segments = statement
+        .query_map(params![job, PENDING], |row| {
+            Ok(Segment {
+                index: row.get(0)?,
+                size: row.get(1)?,
+                start: row.get(2)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(Some(video))
+}
+
+fn delete(connection: &Connection, job: &str) -> Result<(), ReveError> {
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute("DELETE FROM segments WHERE job = ?1", [job])?;
+    transaction.execute("DELETE FROM jobs WHERE id = ?1", [job])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+fn integrity_select(connection: &Connection) -> Result<Vec<String>, ReveError> {
+    let mut statement = connection.prepare("PRAGMA integrity_check")?;
+    let problems = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .filter(|line| !matches!(line.as_deref(), Ok("ok")))
+        .collect::<Result<_, _>>()?;
+    Ok(problems)
+}
+
+fn queue_insert(
+    connection: &Connection,
+    path: &str,
+    output_dir: Option<&str>,
+    profile: Option<&str>,
+) -> Result<bool, ReveError> {
+    let inserted = connection.execute(
+        "INSERT INTO queue (path, output_dir, profile, status) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (path) DO UPDATE SET output_dir = ?2, profile = ?3, status = ?4,
+         error = NULL WHERE queue.status IN (?5, ?6)",
+        params![
+            path,
+            output_dir,
+            profile,
+            QueueStatus::Pending.name(),
+            QueueStatus::NoVideo.name(),
+            QueueStatus::Error.name()
+        ],
+    )?;
+    Ok(inserted == 1)
+}
+
+fn queue_leave_out(
+    connection: &Connection,
+    path: &str,
+    status: QueueStatus,
+    reason: &str,
+) -> Result<bool, ReveError> {
+    let inserted = connection.execute(
+        "INSERT INTO queue (path, status, error) VALUES (?1, ?2, ?3)
+         ON CONFLICT (path) DO UPDATE SET status = ?2, error = ?3
+         WHERE queue.status IN (?4, ?5) AND (queue.status, queue.error) IS NOT (?2, ?3)",
+        params![
+            path,
+            status.name(),
+            reason,
+            QueueStatus::NoVideo.name(),
+            QueueStatus::Error.name()
+        ],
+    )?;
+    Ok(inserted == 1)
+}
+
+fn scanned_insert(connection: &Connection, folder: &str) -> Result<(), ReveError> {
+    connection.execute(
+        "INSERT OR IGNORE INTO scanned (folder) VALUES (?1)",
+        [folder],
+    )?;
+    Ok(())
+}
+
+fn scanned_select(connection: &Connection) -> Result<Vec<String>, ReveError> {
+    let mut statement = connection.prepare("SELECT folder FROM scanned")?;
+    let folders = statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(folders)
+}
+
+fn probe_select(
+    connection: &Connection,
+    path: &str,
+    size: u64,
+    modified: i64,
+) -> Result<Option<Result<String, String>>, ReveError> {
+    let probe = connection
+        .query_row(
+            "SELECT json, error FROM probes WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            params![path, size as i64, modified],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                ))
+            },
+        )
+        .optional()?;
+    Ok(probe.map(|(json, error)| json.ok_or(error.unwrap_or_default())))
+}
+
+fn probe_insert(
+    connection: &Connection,
+    path: &str,
+    size: u64,
+    modified: i64,
+    probe: &Result<String, String>,
+) -> Result<(), ReveError> {
+    connection.execute(
+        "INSERT OR REPLACE INTO probes (path, size, modified, json, error)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            path,
+            size as i64,
+            modified,
+            probe.as_ref().ok(),
+            probe.as_ref().err()
+        ],
+    )?;
+    Ok(())
+}
+
+fn fingerprint_select(
+    connection: &Connection,
+    path: &str,
+    size: u64,
+    modified: i64,
+) -> Result<Option<String>, ReveError> {
+    Ok(connection
+        .query_row(
+            "SELECT hash FROM fingerprints WHERE path = ?1 AND size = ?2 AND modified = ?3",
+            params![path, size as i64, modified],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn fingerprint_insert(
+    connection: &Connection,
+    path: &str,
+    size: u64,
+    modified: i64,
+    hash: &str,
+) -> Result<(), ReveError> {
+    connection.execute(
+        "INSERT OR REPLACE INTO fingerprints (path, size, modified, hash) VALUES (?1, ?2, ?3, ?4)",
+        params![path, size as i64, modified, hash],
+    )?;
+    Ok(())
+}
+
+fn queue_next(connection: &Connection, now: i64) -> Result<Option<QueuedFile>, ReveError> {
+    let transaction = connection.unchecked_transaction()?;
+    let file = transaction
+        .query_row(
+            "SELECT path, output_dir, profile, priority FROM queue WHERE status = ?1
+             ORDER BY priority DESC, id LIMIT 1",
+            [QueueStatus::Pending.name()],
+            |row| {
+                Ok(QueuedFile {
+                    path: row.get(0)?,
+                    output_dir: row.get(1)?,
+                    profile: row.get(2)?,
+                    status: QueueStatus::Running,
+                    error: None,
+                    priority: row.get(3)?,
+                    stats: UpscaleStats {
+                        started: Some(now),
+                        ..UpscaleStats::default()
+                    },
+                })
+            },
+        )
+        .optional()?;
+    if let Some(file) = &file {
+        transaction.execute(
+            "UPDATE queue SET status = ?1, started = ?2, finished = NULL, duration = NULL,
+             fps = NULL, output = NULL, output_size = NULL WHERE path = ?3",
+            params![QueueStatus::Running.name(), now, file.path],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(file)
+}
+
+fn queue_finish(
+    connection: &Connection,
+    path: &str,
+    error: Option<&str>,
+    stats: &UpscaleStats,
+) -> Result<(), ReveError> {
+    let status = match error {
+        Some(_) => QueueStatus::Failed,
+        None => QueueStatus::Done,
+    };
+    connection.execute(
+        "UPDATE queue SET status = ?1, error = ?2, finished = ?3, duration = ?4, fps = ?5,
+         output = ?6, output_size = ?7 WHERE path = ?8",
+        params![
+            status.name(),
+            error,
+            stats.finished,
+            stats.duration,
+            stats.fps,
+            stats.output,
+            stats.output_size.map(|size| size as i64),
+            path
+        ],
+    )?;
+    Ok(())
+}
+
+fn queue_import(connection: &Connection, files: &[QueuedFile]) -> Result<usize, ReveError> {
+    let transaction = connection.unchecked_transaction()?;
+    for file in files {
+        let status = match file.status {
+            QueueStatus::Running => QueueStatus::Pending,
+            status => status,
+        };
+        transaction.execute("DELETE FROM queue WHERE path = ?1", [&file.path])?;
+        transaction.execute(
+            "INSERT INTO queue (path, output_dir, profile, status, error, priority, started,
+             finished, duration, fps, output, output_size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                file.path,
+                file.output_dir,
+                file.profile,
+                status.name(),
+                file.error,
+                file.priority,
+                file.stats.started,
+                file.stats.finished,
+                file.stats.duration,
+                file.stats.fps,
+                file.stats.output,
+                file.stats.output_size.map(|size| size as i64)
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(files.len())
+}
+
+fn queue_sort(connection: &Connection, sort: Option<SortKey>) -> Result<usize, ReveError> {
+    let mut statement = connection.prepare(
+        "SELECT queue.path, probes.size, probes.modified, probes.json FROM queue
+         JOIN probes ON probes.path = queue.path
+         WHERE queue.status = ?1 AND probes.json IS NOT NULL ORDER BY queue.id",
+    )?;
+    let rows: Vec<(String, i64, i64, String)> = statement
+        .query_map([QueueStatus::Pending.name()], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?
+        .collect::<Result<_, _>>()?;
+    let mut paths = Vec::new();
+    let mut candidates = Vec::new();
+    for (path, size, modified, json) in rows {
+        // A probe this version can not read sorts with the files found since
+        if let Ok(candidate) = Candidate::from_probe(size as u64, modified, &json) {
+            paths.push(path);
+            candidates.push(candidate);
+        }
+    }
+    let order = Selection {
+        sort,
+        ..Selection::default()
+    }
+    .order(&candidates);
+
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute(
+        "UPDATE queue SET priority = 0 WHERE status = ?1",
+        [QueueStatus::Pending.name()],
+    )?;
+    if sort.is_some() {
+        for (rank, index) in order.iter().enumerate() {
+            transaction.execute(
+                "UPDATE queue SET priority = ?1 WHERE path = ?2",
+                params![(order.len() - rank) as i64, paths[*index]],
+            )?;
+        }
+    }
+    transaction.commit()?;
+    Ok(order.len())
+}
+
+fn queue_reset(connection: &Connection, path: &str) -> Result<bool, ReveError> {
+    let changed = connection.execute(
+        "UPDATE queue SET status = ?1, error = NULL WHERE path = ?2",
+        params![QueueStatus::Pending.name(), path],
+    )?;
+    Ok(changed > 0)
+}
+
+fn prune_missing(
+    connection: &Connection,
+    missing: impl Fn(&str) -> bool,
+) -> Result<usize, ReveError> {
+    let mut statement = connection.prepare(
+        "SELECT path FROM queue UNION SELECT path FROM probes UNION SELECT path FROM fingerprints",
+    )?;
+    let paths: Vec<String> = statement
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let paths: Vec<String> = paths.into_iter().filter(|path| missing(path)).collect();
+    let transaction = connection.unchecked_transaction()?;
+    for path in &paths {
+        for table in ["queue", "probes", "fingerprints"] {
+            transaction.execute(&format!("DELETE FROM {} WHERE path = ?1", table), [path])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(paths.len())
+}
+
+fn batch_select(connection: &Connection, batch: &str) -> Result<Vec<BatchFile>, ReveError> {
+    let mut statement = connection
+        .prepare("SELECT path, status, output FROM batch_files WHERE batch = ?1 ORDER BY id")?;
+    let files = statement
+        .query_map([batch], |row| {
+            Ok(BatchFile {
+                path: row.get(0)?,
+                status: QueueStatus::from_name(&row.get::<_, String>(1)?),
+                output: row.get(2)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(files)
+}
+
+fn batch_insert(connection: &Connection, batch: &str, paths: &[String]) -> Result<(), ReveError> {
+    let transaction = connection.unchecked_transaction()?;
+    for path in paths {
+        transaction.execute(
+            "INSERT OR IGNORE INTO batch_files (batch, path, status) VALUES (?1, ?2, ?3)",
+            params![batch, path, QueueStatus::Pending.name()],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn batch_update(
+    connection: &Connection,
+    batch: &str,
+    path: &str,
+    status: QueueStatus,
+    output: Option<&str>,
+) -> Result<(), ReveError> {
+    connection.execute(
+        "UPDATE batch_files SET status = ?1, output = ?2 WHERE batch = ?3 AND path = ?4",
+        params![status.name(), output, batch, path],
+    )?;
+    Ok(())
+}
+
+fn batch_delete(connection: &Connection, batch: &str) -> Result<bool, ReveError> {
+    Ok(connection.execute("DELETE FROM batch_files WHERE batch = ?1", [batch])? > 0)
+}
+
+fn jobs_select(connection: &Connection) -> Result<Vec<SavedJob>, ReveError> {
+    let mut statement = connection.prepare(
+        "SELECT jobs.video, COUNT(segments.idx),
+                COUNT(CASE WHEN segments.status = ?1 THEN 1 END)
+         FROM jobs LEFT JOIN segments ON segments.job = jobs.id
+         GROUP BY jobs.id ORDER BY jobs.id",
+    )?;
+    let rows = statement.query_map([MERGED], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, u32>(1)?,
+            row.get::<_, u32>(2)?,
+        ))
+    })?;
+    let mut jobs = Vec::new();
+    for row in rows {
+        let (video, segments, merged) = row?;
+        if let Ok(video) = serde_json::from_str::<Video>(&video) {
+            jobs.push(SavedJob {
+                input: video.path,
+                output: video.output_path,
+                segments,
+                merged,
+            });
+        }
+    }
+    Ok(jobs)
+}
+
+fn queue_select(connection: &Connection) -> Result<Vec<QueuedFile>, ReveError> {
+    let mut statement = connection.prepare(
+        "SELECT path, output_dir, profile, status, error, priority, started, finished,
+         duration, fps, output, output_size FROM queue ORDER BY priority DESC, id",
+    )?;
+    let files = statement
+        .query_map([], |row| {
+            Ok(QueuedFile {
+                path: row.get(0)?,
+                output_dir: row.get(1)?,
+                profile: row.get(2)?,
+                status: QueueStatus::from_name(&row.get::<_, String>(3)?),
+                error: row.get(4)?,
+                priority: row.get(5)?,
+                stats: UpscaleStats {
+                    started: row.get(6)?,
+                    finished: row.get(7)?,
+                    duration: row.get(8)?,
+                    fps: row.get(9)?,
+                    output: row.get(10)?,
+                    output_size: row.get::<_, Option<i64>>(11)?.map(|size| size as u64),
+                },
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_job_state() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        let args = Args::parse_from(["reve", "-i", "../reve-cli/assets/test.mp4", "-s", "4"]);
+        let mut video: Video = serde_json::from_str(
+            r#"{"path": "input.mkv", "output_path": "output.mkv", "frame_rate": 23.976,
+                "frame_count": 2500, "segment_size": 1000, "segment_count": 3,
+                "upscale_ratio": 4, "segments": [{"index": 0, "size": 1000},
+                {"index": 1, "size": 1000}, {"index": 2, "size": 500, "start": 2000}]}"#,
+        )
+        .unwrap();
+        assert!(select_video(&connection, "job").unwrap().is_none());
+
+        insert(&connection, "job", &args, &video).unwrap();
+        set_status(&connection, "job", 1, MERGED).unwrap();
+        assert_eq!(select_args(&connection, "job").unwrap().unwrap().scale, 4);
+        let saved = select_video(&connection, "job").unwrap().unwrap();
+        let segments: Vec<_> = saved
+            .segments
+            .iter()
+            .map(|segment| (segment.index, segment.size, segment.start))
+            .collect();
+        assert_eq!(segments, vec![(0, 1000, None), (2, 500, Some(2000))]);
+
+        // Planning the job again starts over
+        video.segments.truncate(1);
+        insert(&connection, "job", &args, &video).unwrap();
+        assert_eq!(
+            select_video(&connection, "job")
+                .unwrap()
+                .unwrap()
+                .segments
+                .len(),
+            1
+        );
+
+        set_status(&connection, "job", 0, MERGED).unwrap();
+        assert_eq!(
+            jobs_select(&connection).unwrap(),
+            [SavedJob {
+                input: String::from("input.mkv"),
+                output: String::from("output.mkv"),
+                segments: 1,
+                merged: 1,
+            }]
+        );
+
+        delete(&connection, "job").unwrap();
+        assert!(select_args(&connection, "job").unwrap().is_none());
+        assert!(jobs_select(&connection).unwrap().is_empty());
+        assert!(integrity_select(&connection).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_db_error() {
+        let connection = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            queue_select(&connection),
+            Err(ReveError::Db(message)) if message.contains("no such table")
+        ));
+    }
+
+    #[test]
+    fn test_queue() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        assert!(queue_insert(&connection, "/inbox/ep1.mkv", Some("/out"), None).unwrap());
+        assert!(queue_insert(&connection, "/inbox/ep2.mkv", None, Some("tv-h264")).unwrap());
+        assert!(!queue_insert(&connection, "/inbox/ep1.mkv", None, None).unwrap());
+        assert!(queue_leave_out(
+            &connection,
+            "/inbox/song.mkv",
+            QueueStatus::NoVideo,
+            "no video stream"
+        )
+        .unwrap());
+        assert!(!queue_leave_out(
+            &connection,
+            "/inbox/song.mkv",
+            QueueStatus::NoVideo,
+            "no video stream"
+        )
+        .unwrap());
+        assert!(!queue_leave_out(&connection, "/inbox/ep1.mkv", QueueStatus::Error, "").unwrap());
+
+        let first = queue_next(&connection, 100).unwrap().unwrap();
+        assert_eq!(first.path, "/inbox/ep1.mkv");
+        assert_eq!(first.output_dir.as_deref(), Some("/out"));
+        assert_eq!(first.stats.started, Some(100));
+        let failed = UpscaleStats {
+            finished: Some(160),
+            duration: Some(60.0),
+            ..UpscaleStats::default()
+        };
+        queue_finish(&connection, &first.path, Some("ffmpeg failed"), &failed).unwrap();
+        let second = queue_next(&connection, 200).unwrap().unwrap();
+        assert_eq!(second.profile.as_deref(), Some("tv-h264"));
+        assert!(queue_next(&connection, 200).unwrap().is_none());
+
+        // Probed again after it changed, a file left out is queued
+        let probe = Ok(String::from(r#"{"streams": []}"#));
+        probe_insert(&connection, "/inbox/song.mkv", 10, 1, &probe).unwrap();
+        assert_eq!(
+            probe_select(&connection, "/inbox/song.mkv", 10, 1).unwrap(),
+            Some(probe)
+        );
+        assert_eq!(
+            probe_select(&connection, "/inbox/song.mkv", 12, 2).unwrap(),
+            None
+        );
+        let broken = Err(String::from("invalid header"));
+        probe_insert(&connection, "/inbox/song.mkv", 12, 2, &broken).unwrap();
+        assert_eq!(
+            probe_select(&connection, "/inbox/song.mkv", 12, 2).unwrap(),
+            Some(broken)
+        );
+        assert!(queue_insert(&connection, "/inbox/song.mkv", None, None).unwrap());
+        let song = queue_next(&connection, 300).unwrap().unwrap();
+        let upscaled = UpscaleStats {
+            started: None,
+            finished: Some(3900),
+            duration: Some(3600.5),
+            fps: Some(12.5),
+            output: Some(String::from("/inbox/song.2x.mkv")),
+            output_size: Some(4_000_000_000),
+        };
+        queue_finish(&connection, &song.path, None, &upscaled).unwrap();
+        let stats: Vec<UpscaleStats> = queue_select(&connection)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.stats)
+            .collect();
+        assert_eq!(
+            stats,
+            [
+                UpscaleStats {
+                    started: Some(100),
+                    ..failed.clone()
+                },
+                UpscaleStats {
+                    started: Some(200),
+                    ..UpscaleStats::default()
+                },
+                UpscaleStats {
+                    started: Some(300),
+                    ..upscaled
+                }
+            ]
+        );
+
+        fingerprint_insert(&connection, "/inbox/ep1.mkv", 10, 1, "ab").unwrap();
+        fingerprint_insert(&connection, "/inbox/ep1.mkv", 10, 2, "cd").unwrap();
+        assert_eq!(
+            fingerprint_select(&connection, "/inbox/ep1.mkv", 10, 2).unwrap(),
+            Some(String::from("cd"))
+        );
+        assert_eq!(
+            fingerprint_select(&connection, "/inbox/ep1.mkv", 10, 1).unwrap(),
+            None
+        );
+
+        scanned_insert(&connection, "/inbox").unwrap();
+        scanned_insert(&connection, "/inbox").unwrap();
+        assert_eq!(scanned_select(&connection).unwrap(), ["/inbox"]);
+
+        let mut imported = queue_select(&connection).unwrap();
+        imported.truncate(2);
+        assert_eq!(queue_import(&connection, &imported).unwrap(), 2);
+        let queue = queue_select(&connection).unwrap();
+        assert_eq!(queue[0].path, "/inbox/song.mkv");
+        assert_eq!(queue[1].stats.duration, failed.duration);
+
+        let statuses: Vec<_> = queue_select(&connection)
+            .unwrap()
+            .into_iter()
+            .map(|file| (file.status, file.error))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (QueueStatus::Done, None),
+                (QueueStatus::Failed, Some(String::from("ffmpeg failed"))),
+                (QueueStatus::Pending, None)
+            ]
+        );
+
+        assert!(queue_reset(&connection, "/inbox/ep1.mkv").unwrap());
+        assert!(!queue_reset(&connection, "/inbox/ep3.mkv").unwrap());
+        let reset = queue_select(&connection).unwrap();
+        assert_eq!(
+            (reset[1].status, reset[1].error.as_deref()),
+            (QueueStatus::Pending, None)
+        );
+
+        // The probe of song.mkv and the fingerprint of ep1.mkv go with their files
+        assert_eq!(
+            prune_missing(&connection, |path| path != "/inbox/ep2.mkv").unwrap(),
+            2
+        );
+        let left: Vec<_> = queue_select(&connection)
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert_eq!(left, ["/inbox/ep2.mkv"]);
+        assert_eq!(
+            probe_select(&connection, "/inbox/song.mkv", 12, 2).unwrap(),
+            None
+        );
+        assert_eq!(
+            fingerprint_select(&connection, "/inbox/ep1.mkv", 10, 2).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_queue_sort() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        let probe = |height: u32, duration: f64| {
+            Ok(format!(
+                r#"{{"streams": [{{"codec_type": "video", "width": {}, "height": {}}}],
+                "format": {{"duration": "{}"}}}}"#,
+                height * 16 / 9,
+                height,
+                duration
+            ))
+        };
+        for (path, size, modified, probe) in [
+            ("/inbox/movie.mkv", 9000, 1, probe(1080, 7200.0)),
+            ("/inbox/short.mkv", 100, 3, probe(1080, 60.0)),
+            ("/inbox/dvd.mkv", 4000, 2, probe(480, 5400.0)),
+        ] {
+            queue_insert(&connection, path, None, None).unwrap();
+            probe_insert(&connection, path, size, modified, &probe).unwrap();
+        }
+        // Found since the sort, without a cached probe
+        queue_insert(&connection, "/inbox/new.mkv", None, None).unwrap();
+        let order = || -> Vec<String> {
+            queue_select(&connection)
+                .unwrap()
+                .into_iter()
+                .map(|file| file.path)
+                .collect()
+        };
+
+        assert_eq!(queue_sort(&connection, Some(SortKey::Size)).unwrap(), 3);
+        assert_eq!(
+            order(),
+            [
+                "/inbox/short.mkv",
+                "/inbox/dvd.mkv",
+                "/inbox/movie.mkv",
+                "/inbox/new.mkv"
+            ]
+        );
+        queue_sort(&connection, Some(SortKey::Resolution)).unwrap();
+        assert_eq!(
+            queue_next(&connection, 100).unwrap().unwrap().path,
+            "/inbox/dvd.mkv"
+        );
+        // The file being upscaled keeps its place
+        queue_sort(&connection, Some(SortKey::Newest)).unwrap();
+        assert_eq!(
+            order(),
+            [
+                "/inbox/dvd.mkv",
+                "/inbox/short.mkv",
+                "/inbox/movie.mkv",
+                "/inbox/new.mkv"
+            ]
+        );
+        queue_sort(&connection, None).unwrap();
+        assert_eq!(
+            queue_next(&connection, 100).unwrap().unwrap().path,
+            "/inbox/movie.mkv"
+        );
+    }
+
+    #[test]
+    fn test_add_queue_columns() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE queue (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL \
+                 UNIQUE, output_dir TEXT, profile TEXT, status TEXT NOT NULL, error TEXT);
+                 INSERT INTO queue (path, status) VALUES ('/inbox/ep1.mkv', 'pending');",
+            )
+            .unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        add_queue_columns(&connection).unwrap();
+        add_queue_columns(&connection).unwrap();
+        assert_eq!(queue_select(&connection).unwrap()[0].priority, 0);
+    }
+
+    #[test]
+    fn test_batch_files() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        let paths = |paths: &[&str]| {
+            paths
+                .iter()
+                .map(|path| path.to_string())
+                .collect::<Vec<_>>()
+        };
+        batch_insert(
+            &connection,
+            "a",
+            &paths(&["/videos/ep1.mkv", "/videos/ep2.mkv"]),
+        )
+        .unwrap();
+        batch_insert(&connection, "b", &paths(&["/videos/ep1.mkv"])).unwrap();
+        batch_update(
+            &connection,
+            "a",
+            "/videos/ep1.mkv",
+            QueueStatus::Done,
+            Some("/videos/ep1_2x.mkv"),
+        )
+        .unwrap();
+        batch_update(
+            &connection,
+            "a",
+            "/videos/ep2.mkv",
+            QueueStatus::Running,
+            None,
+        )
+        .unwrap();
+        // A rescan adds the new videos at the end and keeps the status of the others
+        batch_insert(
+            &connection,
+            "a",
+            &paths(&["/videos/ep0.mkv", "/videos/ep1.mkv"]),
+        )
+        .unwrap();
+
+        let files = batch_select(&connection, "a").unwrap();
+        let statuses: Vec<(&str, QueueStatus)> = files
+            .iter()
+            .map(|file| (file.path.as_str(), file.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("/videos/ep1.mkv", QueueStatus::Done),
+                ("/videos/ep2.mkv", QueueStatus::Running),
+                ("/videos/ep0.mkv", QueueStatus::Pending),
+            ]
+        );
+        assert_eq!(files[0].output.as_deref(), Some("/videos/ep1_2x.mkv"));
+        assert_eq!(
+            batch_select(&connection, "b").unwrap()[0].status,
+            QueueStatus::Pending
+        );
+
+        assert!(batch_delete(&connection, "a").unwrap());
+        assert!(!batch_delete(&connection, "a").unwrap());
+        assert!(batch_select(&connection, "a").unwrap().is_empty());
+        assert_eq!(batch_select(&connection, "b").unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,320 @@
+//! Upscaling still images with realesrgan, for `reve image`.
+//!
+//! realesrgan drops the metadata of the images it writes, so the EXIF block of the input is
+//! copied into the upscaled jpg or png afterwards, keeping the camera details and orientation.
+
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{models, ReveError};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::warn;
+
+/// Image formats realesrgan reads and writes.
+pub const FORMATS: [&str; 4] = ["png", "jpg", "jpeg", "webp"];
+
+/// Returns whether `name` matches `pattern`, where `*` matches any run of characters and `?`
+/// a single one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` and the name position it currently matches up to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Expands the inputs given to `reve image`, a file name with `*` or `?` matching the images
+/// of its folder, for shells that leave patterns to the program.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, ReveError> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !file_name.contains(['*', '?']) {
+            if !path.is_file() {
+                return Err(ReveError::InvalidArgument(format!(
+                    "input image {:?} not found",
+                    input
+                )));
+            }
+            paths.push(path.to_path_buf());
+            continue;
+        }
+
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut matches: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| wildcard_match(&file_name, &entry.file_name().to_string_lossy()))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_supported(path))
+            .collect();
+        if matches.is_empty() {
+            return Err(ReveError::InvalidArgument(format!(
+                "no images match {:?}",
+                input
+            )));
+        }
+        matches.sort();
+        paths.append(&mut matches);
+    }
+    Ok(paths)
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
+
+pub fn is_supported(path: &Path) -> bool {
+    extension(path).is_some_and(|extension| FORMATS.contains(&extension.as_str()))
+}
+
+/// Returns the path `input` is upscaled to: `output` itself for a single image, else a file
+/// named after the input in `output` (or next to the input), with the extension of `format`
+/// or of the input.
+pub fn output_path(
+    input: &Path,
+    output: Option<&Path>,
+    single: bool,
+    scale: u8,
+    format: Option<&str>,
+) -> PathBuf {
+    if let (Some(output), true) = (output, single) {
+        return output.to_path_buf();
+    }
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = format
+        .map(str::to_string)
+        .or_else(|| extension(input))
+        .unwrap_or_else(|| String::from("png"));
+    let file_name = format!("{}_x{}.{}", stem, scale, extension);
+    match output {
+        Some(dir) => dir.join(file_name),
+        None => input.with_file_name(file_name),
+    }
+}
+
+/// Upscales the image at `input` by `scale` with the weights of the `model` family and
+/// writes it to `output`, in the format of its extension.
+pub fn upscale_image(
+    binaries: &Binaries,
+    input: &Path,
+    output: &Path,
+    model: &str,
+    scale: u8,
+    tile_size: Option<u32>,
+    gpu_id: Option<u32>,
+) -> Result<(), ReveError> {
+    let model = models::model_name(model, scale)?;
+    let format = match extension(output).as_deref() {
+        Some("jpg" | "jpeg") => "jpg",
+        Some("webp") => "webp",
+        _ => "png",
+    };
+    let mut command = Command::new(&binaries.realesrgan);
+    command
+        .arg("-i")
+        .arg(input)
+        .arg("-o")
+        .arg(output)
+        .args(["-n", model])
+        .args(["-s", &scale.to_string(), "-f", format]);
+    if let Some(tile_size) = tile_size {
+        command.args(["-t", &tile_size.to_string()]);
+    }
+    if let Some(gpu_id) = gpu_id {
+        command.args(["-g", &gpu_id.to_string()]);
+    }
+    let output_status = run(&mut command)?;
+    if !output_status.status.success() || !output.exists() {
+        return Err(ReveError::Upscaler(command_failure(
+            &format!("could not upscale {}", input.display()),
+            &command,
+            &output_status,
+        )));
+    }
+
+    let source = fs::read(input)?;
+    if let Some(exif) = read_exif(&source) {
+        match write_exif(&fs::read(output)?, &exif) {
+            Some(upscaled) => fs::write(output, upscaled)?,
+            None => warn!(
+                "EXIF data of {} cannot be kept in {}",
+                input.display(),
+                format
+            ),
+        }
+    }
+    Ok(())
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Returns the chunks of a png as type and data, `None` if it is not a png.
+fn png_chunks(png: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut rest = png.strip_prefix(PNG_SIGNATURE)?;
+    let mut chunks = Vec::new();
+    while rest.len() >= 12 {
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let data = rest.get(8..8 + length)?;
+        chunks.push((&rest[4..8], data));
+        rest = rest.get(12 + length..)?;
+    }
+    Some(chunks)
+}
+
+/// Returns the marker and payload of the segments of a jpg up to the image data, `None` if it
+/// is not a jpg.
+fn jpeg_segments(jpeg: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut rest = jpeg.strip_prefix(b"\xff\xd8")?;
+    let mut segments = Vec::new();
+    while rest.len() >= 4 && rest[0] == 0xff && rest[1] != 0xda {
+        let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+        segments.push((rest[1], rest.get(4..2 + length)?));
+        rest = &rest[2 + length..];
+    }
+    Some(segments)
+}
+
+/// Returns the EXIF block (the TIFF data, without the `Exif` header) of a jpg, png or webp.
+fn read_exif(image: &[u8]) -> Option<Vec<u8>> {
+    if let Some(segments) = jpeg_segments(image) {
+        return segments
+            .into_iter()
+            .find_map(|(marker, payload)| {
+                payload.strip_prefix(EXIF_HEADER).filter(|_| marker == 0xe1)
+            })
+            .map(<[u8]>::to_vec);
+    }
+    if let Some(chunks) = png_chunks(image) {
+        return chunks
+            .into_iter()
+            .find(|(kind, _)| *kind == b"eXIf")
+            .map(|(_, data)| data.to_vec());
+    }
+    // RIFF chunks of a webp, padded to an even length
+    let mut rest = image.strip_prefix(b"RIFF")?.get(8..)?;
+    while rest.len() >= 8 {
+        let length = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let data = rest.get(8..8 + length)?;
+        if &rest[..4] == b"EXIF" {
+            return Some(data.strip_prefix(EXIF_HEADER).unwrap_or(data).to_vec());
+        }
+        rest = rest.get(8 + length + length % 2..)?;
+    }
+    None
+}
+
+/// Returns `image` with `exif` inserted, `None` for formats other than jpg and png.
+fn write_exif(image: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+    if image.starts_with(b"\xff\xd8") {
+        let length = u16::try_from(EXIF_HEADER.len() + exif.len() + 2).ok()?;
+        let mut jpeg = image[..2].to_vec();
+        jpeg.extend_from_slice(&[0xff, 0xe1]);
+        jpeg.extend_from_slice(&length.to_be_bytes());
+        jpeg.extend_from_slice(EXIF_HEADER);
+        jpeg.extend_from_slice(exif);
+        jpeg.extend_from_slice(&image[2..]);
+        return Some(jpeg);
+    }
+    png_chunks(image)?;
+    // The eXIf chunk goes right after the IHDR chunk, which always takes 25 bytes
+    let ihdr_end = PNG_SIGNATURE.len() + 25;
+    let mut png = image[..ihdr_end].to_vec();
+    png.extend_from_slice(&(exif.len() as u32).to_be_bytes());
+    let chunk_start = png.len();
+    png.extend_from_slice(b"eXIf");
+    png.extend_from_slice(exif);
+    let crc = crc32(&png[chunk_start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+    png.extend_from_slice(&image[ihdr_end..]);
+    Some(png)
+}
+
+/// CRC-32 of a png chunk's type and data.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exif() {
+        let exif = b"II*\0\x08\0\0\0".to_vec();
+        // Signature and an IHDR chunk of a 1x1 png, then IEND
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(b"\0\0\0\x0dIHDR\0\0\0\x01\0\0\0\x01\x08\x02\0\0\0\x90\x77\x53\xde");
+        png.extend_from_slice(b"\0\0\0\0IEND\xae\x42\x60\x82");
+        assert_eq!(read_exif(&png), None);
+        let tagged = write_exif(&png, &exif).unwrap();
+        assert_eq!(read_exif(&tagged), Some(exif.clone()));
+        assert_eq!(png_chunks(&tagged).unwrap().len(), 3);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+
+        let jpeg = b"\xff\xd8\xff\xe0\0\x04JF\xff\xda\0\0".to_vec();
+        let tagged = write_exif(&jpeg, &exif).unwrap();
+        assert_eq!(read_exif(&tagged), Some(exif));
+        assert_eq!(write_exif(b"RIFF\0\0\0\0WEBP", b""), None);
+
+        assert!(wildcard_match("*.jpg", "photo.jpg"));
+        assert!(wildcard_match("img_??.png", "img_01.png"));
+        assert!(!wildcard_match("img_??.png", "img_1.png"));
+        assert!(!wildcard_match("*.jpg", "photo.jpeg"));
+        assert_eq!(
+            output_path(Path::new("/a/photo.jpg"), None, true, 4, None),
+            Path::new("/a/photo_x4.jpg")
+        );
+        assert_eq!(
+            output_path(
+                Path::new("/a/photo.jpg"),
+                Some(Path::new("/b")),
+                false,
+                2,
+                Some("png")
+            ),
+            Path::new("/b/photo_x2.png")
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! GPU temperature readings for `--gpu-max-temp`.
+//!
+//! nvidia-smi is asked first, then the hwmon sensors of the drm cards on Linux, where amdgpu,
+//! i915 and nouveau report. The hottest device is used, as neither numbering matches the
+//! vulkan ids of realesrgan.
+
+use std::fs;
+use std::process::Command;
+
+/// Degrees below `--gpu-max-temp` the gpu cools down to before upscaling resumes, so it does
+/// not flip between the two after every segment.
+pub const COOL_DOWN: u8 = 10;
+
+/// Returns the temperature of the hottest gpu in °C, `None` without a readable sensor.
+pub fn read_temperature() -> Option<u8> {
+    nvidia_smi().or_else(hwmon)
+}
+
+fn nvidia_smi() -> Option<u8> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            "--query-gpu=temperature.gpu",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    parse_nvidia_smi(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Returns the highest of the temperatures nvidia-smi printed, one line per gpu.
+fn parse_nvidia_smi(output: &str) -> Option<u8> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .max()
+}
+
+fn hwmon() -> Option<u8> {
+    let mut temperatures = Vec::new();
+    for card in fs::read_dir("/sys/class/drm").ok()?.flatten() {
+        let Ok(sensors) = fs::read_dir(card.path().join("device").join("hwmon")) else {
+            continue;
+        };
+        for sensor in sensors.flatten() {
+            if let Ok(content) = fs::read_to_string(sensor.path().join("temp1_input")) {
+                temperatures.extend(parse_millidegrees(&content));
+            }
+        }
+    }
+    temperatures.into_iter().max()
+}
+
+/// Parses a `temp*_input` file of hwmon, in thousandths of a degree.
+fn parse_millidegrees(content: &str) -> Option<u8> {
+    let millidegrees: u32 = content.trim().parse().ok()?;
+    u8::try_from(millidegrees / 1000).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_temperatures() {
+        assert_eq!(parse_nvidia_smi("67\n"), Some(67));
+        assert_eq!(parse_nvidia_smi("45\n83\n"), Some(83));
+        assert_eq!(parse_nvidia_smi("[N/A]\n"), None);
+        assert_eq!(parse_millidegrees("71000\n"), Some(71));
+        assert_eq!(parse_millidegrees("-1\n"), None);
+    }
+}
@@ -0,0 +1,568 @@
+//! Defaults of the command line read from a `reve.toml` file.
+//!
+//! Every key is the long name of an option, given the value it takes on the command line.
+//! Flags take `true` or `false` and options given several values take an array:
+//!
+//! ```toml
+//! model = "realesrgan-x4plus-anime"
+//! scale = 4
+//! crf = 18
+//! codec = "libsvtav1"
+//! gpu-id = 1
+//! frame-cache = true
+//! metrics = ["vmaf", "ssim"]
+//! temp-dir = "/scratch"
+//! ffmpeg-path = "/opt/ffmpeg/bin/ffmpeg"
+//! ```
+//!
+//! `temp-dir`, the folder holding the `temp` folder, has no option. The values become the
+//! defaults of their options, so they are checked like given values and the command line
+//! still overrides them.
+//!
+//! Named profiles bundle options used together and are selected with `--profile <NAME>`,
+//! next to the device profiles. Their values replace the ones of the file:
+//!
+//! ```toml
+//! [profile.anime-4k]
+//! model = "realesr-animevideov3"
+//! scale = 4
+//! codec = "x265"
+//! x265params = "psy-rd=1:aq-strength=0.8:bframes=8"
+//!
+//! [profile.vhs-restore]
+//! denoise = "strong"
+//! scale = 2
+//! ```
+//!
+//! The `[smtp]` table holds the mail server `--notify email:<address>` sends with:
+//!
+//! ```toml
+//! [smtp]
+//! host = "smtp.example.com"
+//! port = 587
+//! username = "reve@example.com"
+//! password = "app password"
+//! from = "reve <reve@example.com>"
+//! security = "starttls"
+//! ```
+//!
+//! The `[daemon]` table holds the folders `reve daemon` watches, and the loopback address
+//! `reve daemon status` asks:
+//!
+//! ```toml
+//! [daemon]
+//! interval = 30
+//! control = "127.0.0.1:7431"
+//!
+//! [[daemon.watch]]
+//! folder = "/srv/videos/inbox"
+//! output-dir = "/srv/videos/upscaled"
+//! profile = "anime-4k"
+//! ```
+//!
+//! The `[library]` table holds the Jellyfin or Plex server `reve library` reads, with an api
+//! key of Jellyfin or the `X-Plex-Token` of Plex:
+//!
+//! ```toml
+//! [library]
+//! server = "jellyfin"
+//! url = "http://localhost:8096"
+//! token = "api key"
+//! sections = ["f137a2dd21bbc1b99aa5c0f6bf02a805"]
+//! rescan = true
+//! ```
+//!
+//! The `[arr]` table holds the profile the files imported by Sonarr or Radarr are upscaled
+//! with:
+//!
+//! ```toml
+//! [arr]
+//! profile = "anime-4k"
+//! ```
+
+use crate::encoder::Profile;
+use crate::ReveError;
+use clap::{Command, ValueEnum};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+pub const FILE_NAME: &str = "reve.toml";
+
+/// Folder of the user's configuration directory holding `reve.toml`.
+const CONFIG_FOLDER: &str = "reve";
+
+/// Keys of the file which are not options of the command line.
+const FILE_ONLY_KEYS: [&str; 5] = ["temp-dir", SMTP_KEY, DAEMON_KEY, LIBRARY_KEY, ARR_KEY];
+
+/// Table holding the mail server settings of `--notify email:<address>`.
+const SMTP_KEY: &str = "smtp";
+
+/// Table holding the watch folders of `reve daemon`.
+const DAEMON_KEY: &str = "daemon";
+
+/// Table holding the media server of `reve library`.
+const LIBRARY_KEY: &str = "library";
+
+/// Table holding the settings of `reve arr`.
+const ARR_KEY: &str = "arr";
+
+/// Table holding the named profiles, whose name is also the option selecting them.
+const PROFILE_KEY: &str = "profile";
+
+/// Mail server of the `[smtp]` table.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Smtp {
+    pub host: String,
+    /// Port of the server, the default one of `security` when unset.
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender of the mails, `username` when unset.
+    pub from: Option<String>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// TLS from the start of the connection, port 465.
+    Tls,
+    /// Upgraded to TLS after connecting, port 587.
+    #[default]
+    Starttls,
+    /// Unencrypted, for a relay on the local network, port 25.
+    None,
+}
+
+/// Settings of `reve daemon` in the `[daemon]` table.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Daemon {
+    /// Seconds between two scans of the watch folders, a file is queued once it was not
+    /// modified for as long.
+    #[serde(default = "default_daemon_interval")]
+    pub interval: u64,
+    /// Loopback address the status is served on.
+    #[serde(default = "default_daemon_control")]
+    pub control: String,
+    #[serde(default)]
+    pub watch: Vec<WatchFolder>,
+}
+
+/// Folder whose videos `reve daemon` upscales, a `[[daemon.watch]]` entry.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct WatchFolder {
+    pub folder: String,
+    /// Folder of the outputs, the watch folder when unset.
+    pub output_dir: Option<String>,
+    /// Named or device profile the videos are upscaled with.
+    pub profile: Option<String>,
+}
+
+/// Media server of the `[library]` table.
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Library {
+    pub server: MediaServer,
+    /// Address of the server, like `http://localhost:8096`.
+    pub url: String,
+    pub token: String,
+    /// Libraries read, the ids of Jellyfin or the section keys of Plex, all when empty.
+    #[serde(default)]
+    pub sections: Vec<String>,
+    /// Whether `reve daemon` asks the server to rescan its libraries after every output.
+    #[serde(default)]
+    pub rescan: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaServer {
+    Jellyfin,
+    Plex,
+}
+
+/// Settings of `reve arr` in the `[arr]` table.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Arr {
+    /// Named or device profile the imported files are upscaled with.
+    pub profile: Option<String>,
+}
+
+fn default_daemon_interval() -> u64 {
+    30
+}
+
+fn default_daemon_control() -> String {
+    String::from("127.0.0.1:7431")
+}
+
+/// Content of a `reve.toml` file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TomlConfig {
+    pub path: PathBuf,
+    table: Table,
+}
+
+impl TomlConfig {
+    /// Returns the path of the file, the one next to the executable (for the portable
+    /// release) before the one of the configuration directory.
+    pub fn path(exe_dir: &Path) -> Option<PathBuf> {
+        let next_to_exe = exe_dir.join(FILE_NAME);
+        if next_to_exe.is_file() {
+            return Some(next_to_exe);
+        }
+        Self::user_path().filter(|path| path.is_file())
+    }
+
+    /// Returns an empty file to be saved at `path`.
+    pub fn new(path: PathBuf) -> TomlConfig {
+        TomlConfig {
+            path,
+            table: Table::new(),
+        }
+    }
+
+    /// Returns the path of the file in the user's configuration directory.
+    pub fn user_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_FOLDER).join(FILE_NAME))
+    }
+
+    /// Loads the file, returning `None` if there is none.
+    pub fn load(exe_dir: &Path) -> Result<Option<TomlConfig>, ReveError> {
+        let Some(path) = Self::path(exe_dir) else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(&path)?;
+        Self::parse(&path, &content).map(Some)
+    }
+
+    fn parse(path: &Path, content: &str) -> Result<TomlConfig, ReveError> {
+        let table = content
+            .parse::<Table>()
+            .map_err(|err| invalid(path, err.message()))?;
+        let config = TomlConfig {
+            path: path.to_path_buf(),
+            table,
+        };
+        config.check_profiles()?;
+        Ok(config)
+    }
+
+    /// Checks that the profiles are tables of options not named like a device profile.
+    fn check_profiles(&self) -> Result<(), ReveError> {
+        let Some(Value::Table(profiles)) = self.table.get(PROFILE_KEY) else {
+            return Ok(());
+        };
+        for (name, profile) in profiles {
+            if Profile::from_str(name, false).is_ok() {
+                return Err(invalid(
+                    &self.path,
+                    &format!("profile {:?} has the name of a device profile", name),
+                ));
+            }
+            match profile {
+                Value::Table(profile) if !profile.contains_key(PROFILE_KEY) => {}
+                Value::Table(_) => {
+                    return Err(invalid(
+                        &self.path,
+                        &format!("profile {:?} selects another profile", name),
+                    ))
+                }
+                _ => {
+                    return Err(invalid(
+                        &self.path,
+                        &format!("profile {:?} is not a table of options", name),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the named profiles with their options.
+    pub fn profiles(&self) -> Vec<(&str, &Table)> {
+        let Some(Value::Table(profiles)) = self.table.get(PROFILE_KEY) else {
+            return Vec::new();
+        };
+        profiles
+            .iter()
+            .filter_map(|(name, profile)| Some((name.as_str(), profile.as_table()?)))
+            .collect()
+    }
+
+    /// Returns the options of the named profile `name`.
+    pub fn profile(&self, name: &str) -> Option<&Table> {
+        self.profiles()
+            .into_iter()
+            .find(|(profile, _)| *profile == name)
+            .map(|(_, options)| options)
+    }
+
+    /// Returns the options of the named profile `name` as they are written in the file.
+    pub fn show_profile(&self, name: &str) -> Option<String> {
+        self.profile(name).map(Table::to_string)
+    }
+
+    /// Returns the file with the options of the named profile given to `--profile` in `argv`,
+    /// which is removed from it since clap only knows the device profiles.
+    pub fn select_profile(&self, argv: &mut Vec<OsString>) -> Result<TomlConfig, ReveError> {
+        let mut config = self.clone();
+        if matches!(config.table.get(PROFILE_KEY), Some(Value::Table(_))) {
+            config.table.remove(PROFILE_KEY);
+        }
+        let Some((index, count, name)) = profile_arg(argv) else {
+            return Ok(config);
+        };
+        if let Some(options) = self.profile(&name) {
+            config.table.extend(options.clone());
+            argv.drain(index..index + count);
+        } else if Profile::from_str(&name, false).is_err() {
+            return Err(ReveError::InvalidArgument(format!(
+                "unknown profile {:?}, see reve profile list",
+                name
+            )));
+        }
+        Ok(config)
+    }
+
+    /// Returns the string value of `key`.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.table.get(key).and_then(Value::as_str)
+    }
+
+    /// Sets `key` to `value`, removing it for `None`.
+    pub fn set(&mut self, key: &str, value: Option<impl Into<Value>>) {
+        match value {
+            Some(value) => self.table.insert(key.to_string(), value.into()),
+            None => self.table.remove(key),
+        };
+    }
+
+    /// Writes the file to its path, creating its folder.
+    pub fn save(&self) -> Result<(), ReveError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, self.table.to_string())?;
+        Ok(())
+    }
+
+    /// Returns the mail server of the `[smtp]` table, if there is one.
+    pub fn smtp(&self) -> Result<Option<Smtp>, ReveError> {
+        let Some(smtp) = self.table.get(SMTP_KEY) else {
+            return Ok(None);
+        };
+        smtp.clone()
+            .try_into()
+            .map(Some)
+            .map_err(|err| invalid(&self.path, &format!("[{}]: {}", SMTP_KEY, err)))
+    }
+
+    /// Returns the settings of the `[daemon]` table, if there is one.
+    pub fn daemon(&self) -> Result<Option<Daemon>, ReveError> {
+        let Some(daemon) = self.table.get(DAEMON_KEY) else {
+            return Ok(None);
+        };
+        daemon
+            .clone()
+            .try_into()
+            .map(Some)
+            .map_err(|err| invalid(&self.path, &format!("[{}]: {}", DAEMON_KEY, err)))
+    }
+
+    /// Returns the media server of the `[library]` table, if there is one.
+    pub fn library(&self) -> Result<Option<Library>, ReveError> {
+        let Some(library) = self.table.get(LIBRARY_KEY) else {
+            return Ok(None);
+        };
+        library
+            .clone()
+            .try_into()
+            .map(Some)
+            .map_err(|err| invalid(&self.path, &format!("[{}]: {}", LIBRARY_KEY, err)))
+    }
+
+    /// Returns the settings of the `[arr]` table, if there is one.
+    pub fn arr(&self) -> Result<Option<Arr>, ReveError> {
+        let Some(arr) = self.table.get(ARR_KEY) else {
+            return Ok(None);
+        };
+        arr.clone()
+            .try_into()
+            .map(Some)
+            .map_err(|err| invalid(&self.path, &format!("[{}]: {}", ARR_KEY, err)))
+    }
+
+    /// Returns the integer value of `key`.
+    pub fn get_u32(&self, key: &str) -> Option<u32> {
+        self.table
+            .get(key)
+            .and_then(Value::as_integer)
+            .and_then(|value| u32::try_from(value).ok())
+    }
+
+    /// Sets the values of the file as the defaults of the options of `command`, returning it
+    /// with the ids of the options set.
+    pub fn apply_defaults(&self, command: Command) -> Result<(Command, Vec<String>), ReveError> {
+        let mut command = command;
+        let mut ids = Vec::new();
+        for (key, value) in &self.table {
+            if FILE_ONLY_KEYS.contains(&key.as_str()) || (key == PROFILE_KEY && value.is_table()) {
+                continue;
+            }
+            let id = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()))
+                .map(|arg| arg.get_id().to_string())
+                .ok_or_else(|| invalid(&self.path, &format!("unknown option {:?}", key)))?;
+            let values =
+                values(value).map_err(|err| invalid(&self.path, &format!("{}: {}", key, err)))?;
+            command = command.mut_arg(&id, |arg| arg.default_values(values));
+            ids.push(id);
+        }
+        Ok((command, ids))
+    }
+}
+
+/// Returns the command line values of `value`.
+fn values(value: &Value) -> Result<Vec<String>, String> {
+    match value {
+        Value::String(value) => Ok(vec![value.clone()]),
+        Value::Integer(value) => Ok(vec![value.to_string()]),
+        Value::Float(value) => Ok(vec![value.to_string()]),
+        Value::Boolean(value) => Ok(vec![value.to_string()]),
+        Value::Array(array) => array
+            .iter()
+            .map(|value| match value {
+                Value::Array(_) | Value::Table(_) => Err(String::from("nested values")),
+                value => values(value).map(|mut values| values.remove(0)),
+            })
+            .collect(),
+        Value::Datetime(_) | Value::Table(_) => Err(String::from(
+            "expected a string, a number, a boolean or an array",
+        )),
+    }
+}
+
+/// Returns the index of the `--profile` option of `argv`, the number of arguments it takes
+/// and its value.
+fn profile_arg(argv: &[OsString]) -> Option<(usize, usize, String)> {
+    argv.iter().enumerate().skip(1).find_map(|(index, arg)| {
+        let arg = arg.to_str()?;
+        if arg == "--profile" {
+            Some((index, 2, argv.get(index + 1)?.to_str()?.to_string()))
+        } else {
+            arg.strip_prefix("--profile=")
+                .map(|name| (index, 1, name.to_string()))
+        }
+    })
+}
+
+fn invalid(path: &Path, message: &str) -> ReveError {
+    ReveError::InvalidArgument(format!("invalid {}: {}", path.display(), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::Args;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn test_toml_defaults() {
+        let path = Path::new(FILE_NAME);
+        let config = TomlConfig::parse(
+            path,
+            "crf = 18\ncodec = \"ffv1\"\nframe-cache = true\ngpu-id = 1\n\
+             metrics = [\"vmaf\", \"ssim\"]\ntemp-dir = \"/scratch\"\n\n\
+             [smtp]\nhost = \"smtp.example.com\"\nusername = \"reve@example.com\"\n\n\
+             [[daemon.watch]]\nfolder = \"/srv/inbox\"\nprofile = \"tv-h264\"\n\n\
+             [library]\nserver = \"plex\"\nurl = \"http://nas:32400\"\ntoken = \"abc\"\n\n\
+             [arr]\nprofile = \"anime-4k\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.get_str("temp-dir"), Some("/scratch"));
+        let smtp = config.smtp().unwrap().unwrap();
+        assert_eq!(smtp.host, "smtp.example.com");
+        assert_eq!(smtp.security, SmtpSecurity::Starttls);
+        let missing_host = TomlConfig::parse(path, "[smtp]\nport = 25\n").unwrap();
+        assert!(missing_host.smtp().is_err());
+        let daemon = config.daemon().unwrap().unwrap();
+        assert_eq!(daemon.interval, 30);
+        assert_eq!(daemon.watch[0].folder, "/srv/inbox");
+        assert_eq!(daemon.watch[0].output_dir, None);
+        let library = config.library().unwrap().unwrap();
+        assert_eq!(library.server, MediaServer::Plex);
+        assert!(library.sections.is_empty() && !library.rescan);
+        assert_eq!(
+            config.arr().unwrap().unwrap().profile.as_deref(),
+            Some("anime-4k")
+        );
+        assert_eq!(TomlConfig::parse(path, "").unwrap().arr().unwrap(), None);
+
+        let (command, ids) = config.apply_defaults(Args::command()).unwrap();
+        assert_eq!(ids.len(), 5);
+        let matches = command
+            .try_get_matches_from(["reve", "-i", "../reve-cli/assets/test.mp4", "-c", "20"])
+            .unwrap();
+        let args = Args::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.crf, 20);
+        assert_eq!(args.codec, Codec::Ffv1);
+        assert!(args.frame_cache);
+        assert_eq!(args.gpu_id, Some(1));
+        assert_eq!(args.metrics.len(), 2);
+
+        let dir = std::env::temp_dir().join(format!("reve-toml-{}", std::process::id()));
+        let mut saved = TomlConfig::new(dir.join(FILE_NAME));
+        saved.set("crf", Some(18));
+        saved.set("gpu-id", None::<i64>);
+        saved.set("temp-dir", Some("/scratch"));
+        saved.save().unwrap();
+        assert_eq!(TomlConfig::load(&dir).unwrap(), Some(saved));
+        fs::remove_dir_all(dir).unwrap();
+
+        let unknown = TomlConfig::parse(path, "upscale = 2").unwrap();
+        assert!(unknown.apply_defaults(Args::command()).is_err());
+        assert!(TomlConfig::parse(path, "crf = ").is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let path = Path::new(FILE_NAME);
+        let config = TomlConfig::parse(
+            path,
+            "crf = 18\nscale = 2\n\n[profile.anime-4k]\nscale = 4\ncodec = \"ffv1\"\n\n\
+             [profile.vhs-restore]\ndenoise = \"strong\"\n",
+        )
+        .unwrap();
+        let names: Vec<&str> = config.profiles().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["anime-4k", "vhs-restore"]);
+
+        let mut argv: Vec<OsString> = ["reve", "-i", "in.mp4", "--profile", "anime-4k", "-c", "20"]
+            .map(OsString::from)
+            .to_vec();
+        let selected = config.select_profile(&mut argv).unwrap();
+        assert_eq!(argv, ["reve", "-i", "in.mp4", "-c", "20"]);
+        assert_eq!(selected.get_u32("scale"), Some(4));
+        assert_eq!(selected.get_u32("crf"), Some(18));
+        assert!(selected.apply_defaults(Args::command()).is_ok());
+
+        let mut argv: Vec<OsString> = ["reve", "--profile=tv-h264"].map(OsString::from).to_vec();
+        assert_eq!(
+            config.select_profile(&mut argv).unwrap().get_u32("scale"),
+            Some(2)
+        );
+        assert_eq!(argv.len(), 2);
+        let mut argv: Vec<OsString> = ["reve", "--profile", "anime"].map(OsString::from).to_vec();
+        assert!(config.select_profile(&mut argv).is_err());
+
+        assert!(TomlConfig::parse(path, "[profile.web-av1]\nscale = 4").is_err());
+        assert!(TomlConfig::parse(path, "[profile]\nanime = 4").is_err());
+    }
+}
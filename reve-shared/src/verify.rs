@@ -0,0 +1,124 @@
+//! Checks of the written output against the source before it is renamed to its path.
+//!
+//! A truncated concatenation or a part encoded from missing frames still gives a playable
+//! file, so its frame count (or duration, for animations whose frame rate may be lowered)
+//! is compared with the planned frames, and an audio stream is required when the source has
+//! one to keep.
+
+use crate::animation::Animation;
+use crate::binaries::Binaries;
+use crate::ffprobe::{self, StreamInfo};
+use crate::{InputKind, ReveError, TrackSelection, Video};
+use std::path::Path;
+
+/// Returns the frames by which the output may differ from the plan, as containers round the
+/// duration of their last frame.
+fn frame_tolerance(frames: u32) -> u32 {
+    (frames / 1000).max(1)
+}
+
+/// What the output of a job should hold.
+struct Expected {
+    frames: u32,
+    frame_rate: f32,
+    animation: bool,
+    audio: bool,
+}
+
+/// Returns how the probed output differs from `expected`.
+fn mismatches(expected: &Expected, output: &StreamInfo, audio_streams: usize) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    let expected_seconds = expected.frames as f64 / expected.frame_rate as f64;
+    let seconds = output.info.frame_count as f64 / output.info.frame_rate as f64;
+    if expected.animation {
+        if (seconds - expected_seconds).abs() > 0.1 + expected_seconds / 100.0 {
+            mismatches.push(format!(
+                "lasts {:.2}s instead of {:.2}s",
+                seconds, expected_seconds
+            ));
+        }
+    } else if output.info.frame_count.abs_diff(expected.frames) > frame_tolerance(expected.frames) {
+        mismatches.push(format!(
+            "has {} frames ({:.2}s) instead of {} ({:.2}s)",
+            output.info.frame_count, seconds, expected.frames, expected_seconds
+        ));
+    }
+    if expected.audio && audio_streams == 0 {
+        mismatches.push(String::from("has no audio while the source has some"));
+    }
+    mismatches
+}
+
+impl Video {
+    /// Checks the output written to `path` against the planned frames and the audio of the
+    /// source.
+    pub(crate) fn verify_output(&self, binaries: &Binaries, path: &Path) -> Result<(), ReveError> {
+        let animation = Animation::from_path(&self.output_path).is_some();
+        let audio = !animation
+            && InputKind::from_path(&self.path) == InputKind::Video
+            && self.audio_tracks != TrackSelection::None
+            && ffprobe::count_streams(binaries, &self.path, "a")? > 0;
+        let output = ffprobe::probe_video(binaries, path)?;
+        let audio_streams = if audio {
+            ffprobe::count_streams(binaries, path, "a")?
+        } else {
+            0
+        };
+        let expected = Expected {
+            frames: self.frame_count,
+            frame_rate: self.frame_rate,
+            animation,
+            audio,
+        };
+        let mismatches = mismatches(&expected, &output, audio_streams);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        Err(ReveError::Ffmpeg(format!(
+            "the output does not match the source, it {}\n\noutput kept at {} for inspection, \
+             the segments are kept in temp",
+            mismatches.join(" and "),
+            path.display()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VideoInfo;
+
+    #[test]
+    fn test_mismatches() {
+        let output = |frame_count, frame_rate| StreamInfo {
+            info: VideoInfo {
+                frame_count,
+                frame_rate,
+                width: 1920,
+                height: 1080,
+            },
+            variable_frame_rate: false,
+            start_offset: 0.0,
+        };
+        let expected = Expected {
+            frames: 24000,
+            frame_rate: 24.0,
+            animation: false,
+            audio: true,
+        };
+        assert!(mismatches(&expected, &output(24000, 24.0), 1).is_empty());
+        assert!(mismatches(&expected, &output(23976, 24.0), 1).is_empty());
+        assert_eq!(mismatches(&expected, &output(23000, 24.0), 2).len(), 1);
+        assert_eq!(mismatches(&expected, &output(23000, 24.0), 0).len(), 2);
+
+        // gifs above 50 fps are written at 50 fps
+        let expected = Expected {
+            frames: 600,
+            frame_rate: 60.0,
+            animation: true,
+            audio: false,
+        };
+        assert!(mismatches(&expected, &output(500, 50.0), 0).is_empty());
+        assert_eq!(mismatches(&expected, &output(250, 50.0), 0).len(), 1);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::binaries::Binaries;
+use crate::process::{command_failure, run};
+use crate::{paths, ReveError};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Writes the timestamp of every frame of the first video stream of `path` to temp, in
+/// milliseconds from the first one.
+///
+/// ffmpeg's mkvtimestamp_v2 muxer lists packets in decode order, so they are sorted into
+/// presentation order and made relative to the first frame.
+pub fn extract_timestamps(binaries: &Binaries, path: &str) -> Result<(), ReveError> {
+    let raw_path = paths::raw_timestamps();
+    let mut command = Command::new(&binaries.ffmpeg);
+    command.args([
+        "-v",
+        "error",
+        "-y",
+        "-i",
+        path,
+        "-map",
+        "0:v:0",
+        "-c",
+        "copy",
+        "-f",
+        "mkvtimestamp_v2",
+    ]);
+    command.arg(&raw_path);
+    let output = run(&mut command)?;
+    if !output.status.success() {
+        return Err(ReveError::Ffmpeg(command_failure(
+            "could not read the frame timestamps of the input",
+            &command,
+            &output,
+        )));
+    }
+
+    let mut timestamps = parse_timecodes(&fs::read_to_string(&raw_path)?);
+    fs::remove_file(&raw_path)?;
+    timestamps.sort_by(f64::total_cmp);
+    let first = timestamps.first().copied().unwrap_or(0.0);
+    let timestamps: Vec<String> = timestamps
+        .iter()
+        .map(|timestamp| (timestamp - first).to_string())
+        .collect();
+    fs::write(paths::timestamps(), timestamps.join("\n"))?;
+    Ok(())
+}
+
+/// Reads the timestamps written by [`extract_timestamps`].
+pub fn read_timestamps() -> Result<Vec<f64>, ReveError> {
+    Ok(parse_timecodes(&fs::read_to_string(paths::timestamps())?))
+}
+
+/// Parses a timecode v2 file: one millisecond timestamp per line after a `#` header.
+fn parse_timecodes(timecodes: &str) -> Vec<f64> {
+    timecodes
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.trim().parse::<f64>().ok())
+        .collect()
+}
+
+/// Returns the time in seconds at which `frame` is shown.
+///
+/// Frames past the last timestamp continue at `frame_rate`.
+pub fn frame_time(timestamps: &[f64], frame: u32, frame_rate: f32) -> f64 {
+    match timestamps.get(frame as usize) {
+        Some(timestamp) => timestamp / 1000.0,
+        None => {
+            let last = timestamps.len() as u32;
+            let last_time = timestamps
+                .last()
+                .map_or(0.0, |timestamp| timestamp / 1000.0);
+            last_time + (frame + 1 - last) as f64 / frame_rate as f64
+        }
+    }
+}
+
+/// Builds an ffconcat list showing `files` (one per frame from `first_frame` on) for as long
+/// as the input did.
+pub fn ffconcat(files: &[String], timestamps: &[f64], first_frame: u32, frame_rate: f32) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for (i, file) in files.iter().enumerate() {
+        let frame = first_frame + i as u32;
+        let duration = frame_time(timestamps, frame + 1, frame_rate)
+            - frame_time(timestamps, frame, frame_rate);
+        list.push_str(&format!(
+            "{}\nduration {:.6}\n",
+            paths::concat_entry(Path::new(file)),
+            duration
+        ));
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffconcat() {
+        let timestamps = parse_timecodes("# timecode format v2\n0\n33\n100\n117\n");
+        assert_eq!(timestamps, vec![0.0, 33.0, 100.0, 117.0]);
+        assert_eq!(frame_time(&timestamps, 2, 30.0), 0.1);
+        assert!((frame_time(&timestamps, 5, 30.0) - (0.117 + 2.0 / 30.0)).abs() < 1e-9);
+
+        let files = vec![String::from("a.png"), String::from("b.png")];
+        assert_eq!(
+            ffconcat(&files, &timestamps, 2, 30.0),
+            "ffconcat version 1.0\nfile 'a.png'\nduration 0.017000\nfile 'b.png'\nduration 0.033333\n"
+        );
+    }
+}
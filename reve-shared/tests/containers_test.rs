@@ -0,0 +1,88 @@
+use reve_shared::binaries::Binaries;
+use reve_shared::{ffprobe, paths, rebuild_temp, TimeRange, Video};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Writes the first frames of the test video to `name`, with the codec arguments `codec`.
+/// Returns `None` when ffmpeg lacks the encoder.
+fn convert(binaries: &Binaries, work_dir: &Path, name: &str, codec: &[&str]) -> Option<PathBuf> {
+    let input = Path::new("..")
+        .join("reve-cli")
+        .join("assets")
+        .join("test.mp4");
+    let output = work_dir.join(name);
+    let status = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-y", "-i"])
+        .arg(&input)
+        .args(["-an", "-frames:v", "48"])
+        .args(codec)
+        .arg(&output)
+        .status()
+        .unwrap();
+    status.success().then_some(output)
+}
+
+#[test]
+fn transport_streams_and_webm_export_every_frame() {
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("containers_test");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+    let binaries = Binaries::default();
+    let inputs: [(&str, &[&str]); 4] = [
+        ("test.ts", &["-c:v", "libx264", "-f", "mpegts"]),
+        ("test.m2ts", &["-c:v", "libx264", "-mpegts_m2ts_mode", "1"]),
+        ("vp9.webm", &["-c:v", "libvpx-vp9", "-deadline", "realtime"]),
+        ("av1.webm", &["-c:v", "libaom-av1", "-cpu-used", "8"]),
+    ];
+    let inputs: Vec<PathBuf> = inputs
+        .into_iter()
+        .filter_map(|(name, codec)| {
+            let input = convert(&binaries, &work_dir, name, codec);
+            if input.is_none() {
+                eprintln!("skipped {}, ffmpeg could not encode it", name);
+            }
+            input
+        })
+        .collect();
+    assert!(!inputs.is_empty());
+    env::set_current_dir(&work_dir).unwrap();
+
+    for input in inputs {
+        let input = fs::canonicalize(input).unwrap();
+        let input = input.to_str().unwrap();
+        // The count of a container without one is the packets counted, not a duration
+        let frame_count = ffprobe::probe_video(&binaries, input)
+            .unwrap()
+            .info
+            .frame_count;
+        assert_eq!(frame_count, 48, "{}", input);
+
+        rebuild_temp(false).unwrap();
+        let video = Video::new(
+            &binaries,
+            input,
+            "out.mkv",
+            17,
+            2,
+            TimeRange::default(),
+            None,
+        )
+        .unwrap();
+        let mut exported = 0;
+        for segment in &video.segments {
+            video
+                .export_segment(&binaries, segment.index as usize)
+                .unwrap()
+                .wait()
+                .unwrap();
+            let frames = fs::read_dir(paths::tmp_frames(segment.index))
+                .unwrap()
+                .count() as u32;
+            assert_eq!(frames, segment.size, "{}", input);
+            exported += frames;
+        }
+        assert_eq!(exported, frame_count, "{}", input);
+    }
+}
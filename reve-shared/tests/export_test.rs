@@ -0,0 +1,55 @@
+use reve_shared::binaries::Binaries;
+use reve_shared::{ffprobe, paths, rebuild_temp, TimeRange, Video};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn export_matches_probed_frames() {
+    let input = fs::canonicalize(
+        Path::new("..")
+            .join("reve-cli")
+            .join("assets")
+            .join("test.mp4"),
+    )
+    .unwrap();
+    let input = input.to_str().unwrap();
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("export_test");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+    env::set_current_dir(&work_dir).unwrap();
+    rebuild_temp(false).unwrap();
+
+    let binaries = Binaries::default();
+    let frame_count = ffprobe::probe_video(&binaries, input)
+        .unwrap()
+        .info
+        .frame_count;
+    // An odd size puts the segment boundaries on frames -ss used to miss
+    let segment_size = (frame_count / 3) | 1;
+    let video = Video::new(
+        &binaries,
+        input,
+        "out.mkv",
+        segment_size,
+        2,
+        TimeRange::default(),
+        None,
+    )
+    .unwrap();
+
+    let mut exported = 0;
+    for segment in &video.segments {
+        video
+            .export_segment(&binaries, segment.index as usize)
+            .unwrap()
+            .wait()
+            .unwrap();
+        let frames = fs::read_dir(paths::tmp_frames(segment.index))
+            .unwrap()
+            .count() as u32;
+        assert_eq!(frames, segment.size);
+        exported += frames;
+    }
+    assert_eq!(exported, frame_count);
+}
@@ -0,0 +1,32 @@
+use reve_shared::{paths, rebuild_temp};
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn resume_recreates_wiped_frames_dir() {
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ramdisk_test");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+    env::set_current_dir(&work_dir).unwrap();
+    let ramdisk = work_dir.join("shm");
+    paths::set_frames_dir(Some(ramdisk.to_str().unwrap()));
+
+    rebuild_temp(false).unwrap();
+    fs::create_dir(paths::tmp_frames(0)).unwrap();
+    fs::write(paths::tmp_frames(0).join("000001.png"), "frame").unwrap();
+    fs::write(paths::video_parts_root().join("0.mkv"), "part").unwrap();
+
+    // Rebooted, the ram disk is empty
+    fs::remove_dir_all(&ramdisk).unwrap();
+    rebuild_temp(true).unwrap();
+    assert_eq!(fs::read_dir(paths::tmp_frames_root()).unwrap().count(), 0);
+    assert!(paths::out_frames_root().is_dir());
+    assert!(paths::video_parts_root().join("0.mkv").is_file());
+
+    // Resumed with another --ramdisk
+    paths::set_frames_dir(Some(work_dir.join("other").to_str().unwrap()));
+    rebuild_temp(true).unwrap();
+    assert!(paths::tmp_frames_root().starts_with(work_dir.join("other")));
+    assert!(paths::tmp_frames_root().is_dir());
+}
@@ -0,0 +1,133 @@
+use reve_shared::binaries::Binaries;
+use reve_shared::{paths, rebuild_temp, TimeRange, Video};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+
+/// Returns the md5 of the pixels of the image at `path`, which png compression leaves alone.
+fn pixels_md5(binaries: &Binaries, path: &Path) -> String {
+    let output = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-i"])
+        .arg(path)
+        .args(["-pix_fmt", "rgb24", "-f", "md5", "-"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", path.display());
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Writes frame `frame` of `input`, decoded from the start, to `output`.
+fn reference_frame(binaries: &Binaries, input: &str, frame: u32, output: &Path) {
+    let status = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-y", "-i", input])
+        .args(["-vf", &format!("select='eq(n,{})'", frame)])
+        .args(["-vsync", "0", "-frames:v", "1"])
+        .arg(output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn late_segment_is_exported_without_decoding_the_input() {
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("seek_test");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+    let binaries = Binaries::default();
+    // Five minutes with a keyframe every two seconds
+    let input: PathBuf = work_dir.join("long.mp4");
+    let status = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-f", "lavfi", "-i"])
+        .arg("testsrc2=size=640x360:rate=24:duration=300")
+        .args(["-c:v", "libx264", "-preset", "ultrafast", "-g", "48"])
+        .args(["-pix_fmt", "yuv420p"])
+        .arg(&input)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let input = input.to_str().unwrap();
+    env::set_current_dir(&work_dir).unwrap();
+    rebuild_temp(false).unwrap();
+
+    let started = Instant::now();
+    let status = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-i", input, "-f", "null", "-"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let decode_time = started.elapsed();
+
+    // An odd size puts the segment boundaries between keyframes
+    let video = Video::new(
+        &binaries,
+        input,
+        "out.mkv",
+        25,
+        2,
+        TimeRange::default(),
+        None,
+    )
+    .unwrap();
+    let segment = video.segments.last().unwrap().clone();
+    let started = Instant::now();
+    video
+        .export_segment(&binaries, segment.index as usize)
+        .unwrap()
+        .wait()
+        .unwrap();
+    let export_time = started.elapsed();
+
+    let frames_dir = paths::tmp_frames(segment.index);
+    let mut frames: Vec<PathBuf> = fs::read_dir(&frames_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    frames.sort();
+    assert_eq!(frames.len() as u32, segment.size);
+    assert!(
+        export_time < decode_time / 2,
+        "exporting {} frames took {:?}, decoding the whole input {:?}",
+        segment.size,
+        export_time,
+        decode_time
+    );
+
+    let first_frame = segment.index * video.segment_size;
+    for (frame, exported) in [
+        (first_frame, &frames[0]),
+        (first_frame + segment.size - 1, frames.last().unwrap()),
+    ] {
+        let reference = work_dir.join(format!("reference{}.png", frame));
+        reference_frame(&binaries, input, frame, &reference);
+        assert_eq!(
+            pixels_md5(&binaries, exported),
+            pixels_md5(&binaries, &reference),
+            "frame {}",
+            frame
+        );
+    }
+
+    // Each part of --export-threads seeks to its own frames
+    let single: Vec<(PathBuf, String)> = frames
+        .iter()
+        .map(|frame| (frame.clone(), pixels_md5(&binaries, frame)))
+        .collect();
+    fs::remove_dir_all(&frames_dir).unwrap();
+    for process in video
+        .export_segment_parts(&binaries, segment.index, 3)
+        .unwrap()
+    {
+        process.wait().unwrap();
+    }
+    let mut parts: Vec<PathBuf> = fs::read_dir(&frames_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    parts.sort();
+    assert_eq!(parts, frames);
+    for (frame, md5) in single {
+        assert_eq!(pixels_md5(&binaries, &frame), md5, "{}", frame.display());
+    }
+}
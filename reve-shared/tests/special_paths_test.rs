@@ -0,0 +1,46 @@
+use reve_shared::binaries::Binaries;
+use reve_shared::{ffprobe, mux, paths};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn concat_reads_special_file_names() {
+    let work_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("special_paths_test");
+    let _ = fs::remove_dir_all(&work_dir);
+    fs::create_dir_all(&work_dir).unwrap();
+    // Quotes, brackets, spaces and non-ASCII characters, all valid on Windows and Linux
+    let input = work_dir.join("Ep 01 [BD 1080p] l'été, 東京 (1).mp4");
+    fs::copy(
+        Path::new("..")
+            .join("reve-cli")
+            .join("assets")
+            .join("test.mp4"),
+        &input,
+    )
+    .unwrap();
+
+    let binaries = Binaries::default();
+    let frame_count = ffprobe::probe_video(&binaries, &input)
+        .unwrap()
+        .info
+        .frame_count;
+    assert!(!mux::source_streams(&binaries, &input).unwrap().is_empty());
+
+    let list = work_dir.join("list.txt");
+    fs::write(&list, paths::concat_entry(&input)).unwrap();
+    let output = work_dir.join("[out] it's 出力.mkv");
+    let status = Command::new(&binaries.ffmpeg)
+        .args(["-v", "error", "-f", "concat", "-safe", "0", "-i"])
+        .arg(&list)
+        .args(["-map", "0:v", "-c", "copy"])
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        ffprobe::count_frames(&binaries, &output).unwrap(),
+        frame_count
+    );
+}